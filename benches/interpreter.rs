@@ -0,0 +1,98 @@
+//! Times the interpreter on small loops that each stress one kind of instruction.
+//!
+//! Run with `cargo bench`; pass a name to run only the benchmarks containing it.
+
+use std::time::{Duration, Instant};
+
+use rj::builder::ClassBuilder;
+use rj::parser::AccessFlags;
+use rj::{Value, Vm};
+
+const ITERATIONS: i32 = 100_000;
+const SAMPLES: usize = 30;
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|it| !it.starts_with('-'));
+    let mut vm = Vm::new();
+    vm.define_class(bench_class()).unwrap();
+    println!("{:<10} {:>12} {:>12} {:>12}", "benchmark", "median", "best", "best per loop");
+    for name in ["arithmetic", "static", "fields", "objects"].iter() {
+        if filter.as_ref().is_some_and(|it| !name.contains(it.as_str())) {
+            continue;
+        }
+        let run = |vm: &mut Vm| vm.invoke_static("Bench", name, "(I)I", &[Value::Int(ITERATIONS)]).unwrap();
+        // warm up, so that classes are initialized and code is decoded
+        let expected = run(&mut vm);
+        let mut samples: Vec<Duration> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                assert_eq!(run(&mut vm), expected);
+                start.elapsed()
+            })
+            .collect();
+        samples.sort();
+        // the best sample is the one least disturbed by whatever else the machine is doing
+        let (median, best) = (samples[SAMPLES / 2], samples[0]);
+        println!("{:<10} {:>9.3} ms {:>9.3} ms {:>9.1} ns", name, median.as_secs_f64() * 1e3,
+                 best.as_secs_f64() * 1e3, best.as_secs_f64() * 1e9 / ITERATIONS as f64);
+    }
+}
+
+fn bench_class() -> rj::parser::ClassFile {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Bench");
+    class.field(AccessFlags::PRIVATE as u16 | AccessFlags::STATIC as u16, "total", "I");
+    class.field(AccessFlags::PRIVATE as u16, "value", "I");
+
+    let mut init = class.method(AccessFlags::PUBLIC as u16, "<init>", "()V");
+    init.aload(0).invokespecial("java/lang/Object", "<init>", "()V").r#return();
+    init.finish().unwrap();
+
+    // int sum = 0; for (int i = 0; i < n; i++) sum += i; return sum;
+    let mut arithmetic = class.method(static_flags, "arithmetic", "(I)I");
+    let (body, condition) = (arithmetic.new_label(), arithmetic.new_label());
+    arithmetic.iconst_0().istore(1).iconst_0().istore(2).goto(condition);
+    arithmetic.bind(body).iload(1).iload(2).iadd().istore(1).iinc(2, 1);
+    arithmetic.bind(condition).iload(2).iload(0).if_icmplt(body);
+    arithmetic.iload(1).ireturn();
+    arithmetic.finish().unwrap();
+
+    // for (int i = 0; i < n; i++) total = add(total, 1); return total;
+    let mut add = class.method(static_flags, "add", "(II)I");
+    add.iload(0).iload(1).iadd().ireturn();
+    add.finish().unwrap();
+    let mut calls = class.method(static_flags, "static", "(I)I");
+    let (body, condition) = (calls.new_label(), calls.new_label());
+    calls.iconst_0().putstatic("Bench", "total", "I").iconst_0().istore(1).goto(condition);
+    calls.bind(body).getstatic("Bench", "total", "I").iconst_1().invokestatic("Bench", "add", "(II)I")
+        .putstatic("Bench", "total", "I").iinc(1, 1);
+    calls.bind(condition).iload(1).iload(0).if_icmplt(body);
+    calls.getstatic("Bench", "total", "I").ireturn();
+    calls.finish().unwrap();
+
+    // Bench b = new Bench(); for (int i = 0; i < n; i++) b.value = b.value + 1; return b.value;
+    let mut fields = class.method(static_flags, "fields", "(I)I");
+    let (body, condition) = (fields.new_label(), fields.new_label());
+    fields.new("Bench").dup().invokespecial("Bench", "<init>", "()V").astore(1);
+    fields.iconst_0().istore(2).goto(condition);
+    fields.bind(body).aload(1).aload(1).getfield("Bench", "value", "I").iconst_1().iadd()
+        .putfield("Bench", "value", "I").iinc(2, 1);
+    fields.bind(condition).iload(2).iload(0).if_icmplt(body);
+    fields.aload(1).getfield("Bench", "value", "I").ireturn();
+    fields.finish().unwrap();
+
+    // int count = 0; for (int i = 0; i < n; i++) count += new Bench().get(); return count;
+    let mut get = class.method(AccessFlags::PUBLIC as u16, "get", "()I");
+    get.aload(0).getfield("Bench", "value", "I").iconst_1().iadd().ireturn();
+    get.finish().unwrap();
+    let mut objects = class.method(static_flags, "objects", "(I)I");
+    let (body, condition) = (objects.new_label(), objects.new_label());
+    objects.iconst_0().istore(1).iconst_0().istore(2).goto(condition);
+    objects.bind(body).iload(1).new("Bench").dup().invokespecial("Bench", "<init>", "()V")
+        .invokevirtual("Bench", "get", "()I").iadd().istore(1).iinc(2, 1);
+    objects.bind(condition).iload(2).iload(0).if_icmplt(body);
+    objects.iload(1).ireturn();
+    objects.finish().unwrap();
+
+    class.build()
+}
@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::builder::{ClassBuilder, Label, MethodBuilder};
+use crate::descriptor::MethodDescriptor;
+use crate::parser::{AccessFlags, ClassFile};
+
+/// Source that cannot be assembled, with the 1-based line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T, AsmError> {
+    Err(AsmError { line, message: message.into() })
+}
+
+/// Assembles a Jasmin-like text format into a class file.
+///
+/// A class starts with `.class` (or `.interface`), optionally followed by `.super` and
+/// `.implements`, then any number of `.field` and `.method` ... `.end method` blocks.
+/// Instructions are written with the mnemonics of JVMS §6.5, labels end with `:` and
+/// comments start with `;`.
+///
+/// ```
+/// let class_file = rj::assembler::assemble("
+///     .class public Adder
+///     .method public static add(II)I
+///         iload_0
+///         iload_1
+///         iadd
+///         ireturn
+///     .end method
+/// ").unwrap();
+/// assert_eq!(class_file.this_class(), "Adder");
+/// ```
+pub fn assemble(source: &str) -> Result<ClassFile, AsmError> {
+    let mut lines = source.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, tokenize(line)))
+        .filter(|(_, tokens)| !tokens.is_empty());
+
+    let mut class = match lines.next() {
+        Some((line, tokens)) => match tokens[0] {
+            ".class" | ".interface" => {
+                let (flags, name) = access_flags(line, &tokens[1..], CLASS_FLAGS)?;
+                let flags = if tokens[0] == ".interface" {
+                    flags | AccessFlags::INTERFACE as u16 | AccessFlags::ABSTRACT as u16
+                } else {
+                    flags | AccessFlags::SUPER as u16
+                };
+                let mut class = ClassBuilder::new(one(line, name)?);
+                class.access_flags(flags);
+                class
+            }
+            _ => return error(line, "expected .class or .interface"),
+        },
+        None => return error(1, "expected .class or .interface"),
+    };
+
+    while let Some((line, tokens)) = lines.next() {
+        match tokens[0] {
+            ".super" => {
+                class.super_class(one(line, &tokens[1..])?);
+            }
+            ".implements" => {
+                class.interface(one(line, &tokens[1..])?);
+            }
+            ".field" => {
+                let (flags, rest) = access_flags(line, &tokens[1..], FIELD_FLAGS)?;
+                match rest {
+                    [name, descriptor] => class.field(flags, name, descriptor),
+                    _ => return error(line, "expected .field <flags> <name> <descriptor>"),
+                };
+            }
+            ".method" => {
+                let (flags, signature) = access_flags(line, &tokens[1..], METHOD_FLAGS)?;
+                let (name, descriptor) = match one(line, signature)?.find('(') {
+                    Some(split) if split > 0 => signature[0].split_at(split),
+                    _ => return error(line, "expected .method <flags> <name>(<parameters>)<return type>"),
+                };
+                let mut body = Vec::new();
+                loop {
+                    match lines.next() {
+                        Some((_, tokens)) if tokens[..] == [".end", "method"] => break,
+                        Some(it) => body.push(it),
+                        None => return error(line, "missing .end method"),
+                    }
+                }
+                let no_code = AccessFlags::ABSTRACT as u16 | NATIVE;
+                if flags & no_code != 0 {
+                    if let Some((line, _)) = body.first() {
+                        return error(*line, "abstract and native methods have no code");
+                    }
+                    class.method_without_code(flags, name, descriptor);
+                } else {
+                    let mut method = class.method(flags, name, descriptor);
+                    assemble_code(&mut method, &body)?;
+                    method.finish()
+                        .or_else(|e| error(line, format!("method {}{}: {}", name, descriptor, e)))?;
+                }
+            }
+            directive => return error(line, format!("unexpected {}", directive)),
+        }
+    }
+    Ok(class.build())
+}
+
+const NATIVE: u16 = 0x0100;
+
+const CLASS_FLAGS: &[(&str, u16)] = &[
+    ("public", AccessFlags::PUBLIC as u16),
+    ("final", AccessFlags::FINAL as u16),
+    ("abstract", AccessFlags::ABSTRACT as u16),
+    ("synthetic", AccessFlags::SYNTHETIC as u16),
+    ("annotation", AccessFlags::ANNOTATION as u16),
+    ("enum", AccessFlags::ENUM as u16),
+];
+
+const FIELD_FLAGS: &[(&str, u16)] = &[
+    ("public", AccessFlags::PUBLIC as u16),
+    ("private", AccessFlags::PRIVATE as u16),
+    ("protected", AccessFlags::PROTECTED as u16),
+    ("static", AccessFlags::STATIC as u16),
+    ("final", AccessFlags::FINAL as u16),
+    ("volatile", 0x0040),
+    ("transient", 0x0080),
+    ("synthetic", AccessFlags::SYNTHETIC as u16),
+    ("enum", AccessFlags::ENUM as u16),
+];
+
+const METHOD_FLAGS: &[(&str, u16)] = &[
+    ("public", AccessFlags::PUBLIC as u16),
+    ("private", AccessFlags::PRIVATE as u16),
+    ("protected", AccessFlags::PROTECTED as u16),
+    ("static", AccessFlags::STATIC as u16),
+    ("final", AccessFlags::FINAL as u16),
+    ("synchronized", 0x0020),
+    ("bridge", 0x0040),
+    ("varargs", 0x0080),
+    ("native", NATIVE),
+    ("abstract", AccessFlags::ABSTRACT as u16),
+    ("strict", 0x0800),
+    ("synthetic", AccessFlags::SYNTHETIC as u16),
+];
+
+/// Splits a line into whitespace separated tokens, dropping a `;` comment.
+///
+/// A `;` only starts a comment at the start of a token, since descriptors contain it.
+fn tokenize(line: &str) -> Vec<&str> {
+    line.split_whitespace()
+        .take_while(|token| !token.starts_with(';'))
+        .collect()
+}
+
+/// Reads leading access flag keywords, returning the flags and the remaining tokens.
+fn access_flags<'a, 'b>(
+    line: usize,
+    tokens: &'b [&'a str],
+    names: &[(&str, u16)],
+) -> Result<(u16, &'b [&'a str]), AsmError> {
+    let mut flags = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match names.iter().find(|(name, _)| name == token) {
+            Some((_, flag)) if flags & flag != 0 => return error(line, format!("repeated flag {}", token)),
+            Some((_, flag)) => flags |= flag,
+            None => return Ok((flags, &tokens[i..])),
+        }
+    }
+    error(line, "missing name")
+}
+
+fn one<'a>(line: usize, operands: &[&'a str]) -> Result<&'a str, AsmError> {
+    match operands {
+        [operand] => Ok(operand),
+        _ => error(line, format!("expected 1 operand, found {}", operands.len())),
+    }
+}
+
+fn two<'a>(line: usize, operands: &[&'a str]) -> Result<(&'a str, &'a str), AsmError> {
+    match operands {
+        [first, second] => Ok((first, second)),
+        _ => error(line, format!("expected 2 operands, found {}", operands.len())),
+    }
+}
+
+fn number<T: std::str::FromStr>(line: usize, operand: &str) -> Result<T, AsmError> {
+    operand.parse().or_else(|_| error(line, format!("invalid number {}", operand)))
+}
+
+/// A `"quoted"` string operand, which the tokenizer split at whitespace; each run of
+/// whitespace in it is read as one space.
+fn string(line: usize, operands: &[&str]) -> Result<String, AsmError> {
+    let joined = operands.join(" ");
+    match joined.strip_prefix('"').and_then(|it| it.strip_suffix('"')) {
+        Some(value) => Ok(value.to_string()),
+        None => error(line, format!("unterminated string {}", joined)),
+    }
+}
+
+/// Splits `Class/name` at the last `/`.
+fn member(line: usize, operand: &str) -> Result<(&str, &str), AsmError> {
+    match operand.rfind('/') {
+        Some(split) if split > 0 && split < operand.len() - 1 => Ok((&operand[..split], &operand[split + 1..])),
+        _ => error(line, format!("expected <class>/<name>, found {}", operand)),
+    }
+}
+
+/// `Class/name descriptor`
+fn field_ref<'a>(line: usize, operands: &[&'a str]) -> Result<(&'a str, &'a str, &'a str), AsmError> {
+    let (member_name, descriptor) = two(line, operands)?;
+    let (class, name) = member(line, member_name)?;
+    Ok((class, name, descriptor))
+}
+
+/// `Class/name(parameters)return`
+fn method_ref<'a>(line: usize, operands: &[&'a str]) -> Result<(&'a str, &'a str, &'a str), AsmError> {
+    let operand = one(line, operands)?;
+    let split = operand.find('(')
+        .map_or_else(|| error(line, format!("expected a method descriptor in {}", operand)), Ok)?;
+    let (class, name) = member(line, &operand[..split])?;
+    Ok((class, name, &operand[split..]))
+}
+
+/// The `atype` operand of `newarray`, §6.5.newarray.
+fn array_type(line: usize, operand: &str) -> Result<u8, AsmError> {
+    let atype = match operand {
+        "boolean" => 4,
+        "char" => 5,
+        "float" => 6,
+        "double" => 7,
+        "byte" => 8,
+        "short" => 9,
+        "int" => 10,
+        "long" => 11,
+        _ => return error(line, format!("invalid array type {}", operand)),
+    };
+    Ok(atype)
+}
+
+fn assemble_code(method: &mut MethodBuilder<'_>, body: &[(usize, Vec<&str>)]) -> Result<(), AsmError> {
+    let mut labels = HashMap::new();
+    let mut bound = HashMap::new();
+    let mut label = |method: &mut MethodBuilder<'_>, name: &str| -> Label {
+        *labels.entry(name.to_string()).or_insert_with(|| method.new_label())
+    };
+
+    for (line, tokens) in body {
+        let line = *line;
+        let mut tokens = &tokens[..];
+        if let Some(name) = tokens[0].strip_suffix(':') {
+            if let Some(previous) = bound.insert(name.to_string(), line) {
+                return error(line, format!("label {} is already bound on line {}", name, previous));
+            }
+            let target = label(method, name);
+            method.bind(target);
+            // an instruction can follow the label on the same line
+            tokens = &tokens[1..];
+            if tokens.is_empty() {
+                continue;
+            }
+        }
+        let (mnemonic, operands) = (tokens[0], &tokens[1..]);
+        if NO_OPERANDS.contains(&mnemonic) && !operands.is_empty() {
+            return error(line, format!("{} takes no operands", mnemonic));
+        }
+        match mnemonic {
+            ".limit" => match two(line, operands)? {
+                ("stack", value) => method.limit_stack(number(line, value)?),
+                ("locals", value) => method.limit_locals(number(line, value)?),
+                (kind, _) => return error(line, format!("unknown limit {}", kind)),
+            },
+            ".catch" => match operands {
+                [class, "from", start, "to", end, "using", handler] => {
+                    let (start, end, handler) = (label(method, start), label(method, end), label(method, handler));
+                    let class = if *class == "all" { None } else { Some(*class) };
+                    method.try_catch(start, end, handler, class)
+                }
+                _ => return error(line, "expected .catch <class> from <label> to <label> using <label>"),
+            },
+            "aconst_null" => method.aconst_null(),
+            "aload_0" => method.aload(0),
+            "aload_1" => method.aload(1),
+            "aload_2" => method.aload(2),
+            "aload_3" => method.aload(3),
+            "astore_0" => method.astore(0),
+            "astore_1" => method.astore(1),
+            "astore_2" => method.astore(2),
+            "astore_3" => method.astore(3),
+            "iconst_0" => method.iconst_0(),
+            "iconst_1" => method.iconst_1(),
+            "iload_0" => method.iload(0),
+            "iload_1" => method.iload(1),
+            "iload_2" => method.iload(2),
+            "iload_3" => method.iload(3),
+            "istore_0" => method.istore(0),
+            "istore_1" => method.istore(1),
+            "istore_2" => method.istore(2),
+            "istore_3" => method.istore(3),
+            "iadd" => method.iadd(),
+            "pop" => method.pop(),
+            "dup" => method.dup(),
+            "ireturn" => method.ireturn(),
+            "areturn" => method.areturn(),
+            "return" => method.r#return(),
+            "arraylength" => method.arraylength(),
+            "iaload" => method.iaload(),
+            "iastore" => method.iastore(),
+            "athrow" => method.athrow(),
+            "bipush" => method.bipush(number(line, one(line, operands)?)?),
+            // the builder picks the `_<n>` form or a `wide` one as the index needs
+            "iload" => method.iload(number(line, one(line, operands)?)?),
+            "aload" => method.aload(number(line, one(line, operands)?)?),
+            "istore" => method.istore(number(line, one(line, operands)?)?),
+            "astore" => method.astore(number(line, one(line, operands)?)?),
+            // the constant pool index decides between the two
+            "ldc" | "ldc_w" => match operands.first() {
+                Some(first) if first.starts_with('"') => method.ldc_string(&string(line, operands)?),
+                _ => {
+                    let operand = one(line, operands)?;
+                    if operand.contains('.') {
+                        method.ldc_float(number(line, operand.trim_end_matches('f'))?)
+                    } else {
+                        method.ldc_int(number(line, operand)?)
+                    }
+                }
+            },
+            "iinc" => {
+                let (index, value) = two(line, operands)?;
+                method.iinc(number(line, index)?, number(line, value)?)
+            }
+            "goto" => {
+                let target = label(method, one(line, operands)?);
+                method.goto(target)
+            }
+            "jsr" => {
+                let target = label(method, one(line, operands)?);
+                method.jsr(target)
+            }
+            "ret" => method.ret(number(line, one(line, operands)?)?),
+            "ifeq" => {
+                let target = label(method, one(line, operands)?);
+                method.ifeq(target)
+            }
+            "ifne" => {
+                let target = label(method, one(line, operands)?);
+                method.ifne(target)
+            }
+            "if_icmplt" => {
+                let target = label(method, one(line, operands)?);
+                method.if_icmplt(target)
+            }
+            "getstatic" => {
+                let (class, name, descriptor) = field_ref(line, operands)?;
+                method.getstatic(class, name, descriptor)
+            }
+            "putstatic" => {
+                let (class, name, descriptor) = field_ref(line, operands)?;
+                method.putstatic(class, name, descriptor)
+            }
+            "getfield" => {
+                let (class, name, descriptor) = field_ref(line, operands)?;
+                method.getfield(class, name, descriptor)
+            }
+            "putfield" => {
+                let (class, name, descriptor) = field_ref(line, operands)?;
+                method.putfield(class, name, descriptor)
+            }
+            "invokevirtual" => {
+                let (class, name, descriptor) = method_ref(line, operands)?;
+                method.invokevirtual(class, name, descriptor)
+            }
+            "invokeinterface" => {
+                // the count operand is optional, and checked against the descriptor when given
+                let (class, name, descriptor) = match operands {
+                    [operand, count] => {
+                        let (class, name, descriptor) = method_ref(line, &[operand])?;
+                        let slots = MethodDescriptor::parse(descriptor).map_or(0, |it| it.parameter_slots());
+                        if count.parse() != Ok(slots + 1) {
+                            return error(line, format!("invokeinterface count {} does not match {}", count, descriptor));
+                        }
+                        (class, name, descriptor)
+                    }
+                    _ => method_ref(line, operands)?,
+                };
+                method.invokeinterface(class, name, descriptor)
+            }
+            "invokestatic" => {
+                let (class, name, descriptor) = method_ref(line, operands)?;
+                method.invokestatic(class, name, descriptor)
+            }
+            "invokespecial" => {
+                let (class, name, descriptor) = method_ref(line, operands)?;
+                method.invokespecial(class, name, descriptor)
+            }
+            "new" => method.new(one(line, operands)?),
+            "newarray" => method.newarray(array_type(line, one(line, operands)?)?),
+            "anewarray" => method.anewarray(one(line, operands)?),
+            "checkcast" => method.checkcast(one(line, operands)?),
+            "instanceof" => method.instanceof(one(line, operands)?),
+            _ => return error(line, format!("unknown instruction {}", mnemonic)),
+        };
+    }
+    if let Some((name, _)) = labels.iter().find(|(name, _)| !bound.contains_key(*name)) {
+        return error(body.last().map_or(0, |(line, _)| *line), format!("label {} is never bound", name));
+    }
+    Ok(())
+}
+
+const NO_OPERANDS: &[&str] = &[
+    "aconst_null", "aload_0", "aload_1", "aload_2", "aload_3", "astore_0", "astore_1", "astore_2", "astore_3",
+    "iconst_0", "iconst_1", "iload_0", "iload_1", "iload_2", "iload_3", "istore_0", "istore_1", "istore_2",
+    "istore_3", "iadd", "pop", "dup", "ireturn", "areturn", "return", "arraylength", "iaload",
+    "iastore", "athrow",
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{assemble, AsmError};
+    use crate::parser::{AccessFlags, Attribute};
+
+    #[test]
+    fn assemble_directives_and_instructions() {
+        let class_file = assemble("
+            .class public final Shapes ; a comment
+            .super java/lang/Number
+            .implements java/lang/Runnable
+            .field private static final names [Ljava/lang/String;
+            .method public abstract area()I
+            .end method
+            .method public static pick(Ljava/lang/Object;)I
+                .limit stack 4
+            start: aload_0
+                checkcast java/lang/String
+                pop
+            end:
+                iconst_1
+                ireturn
+            handler:
+                astore_1
+                iconst_0
+                ireturn
+                .catch java/lang/ClassCastException from start to end using handler
+            .end method
+        ").unwrap();
+
+        assert_eq!(class_file.this_class(), "Shapes");
+        assert_eq!(class_file.super_class(), Some("java/lang/Number"));
+        assert_eq!(class_file.interfaces(), ["java/lang/Runnable"]);
+        let flags = AccessFlags::PUBLIC as u16 | AccessFlags::FINAL as u16 | AccessFlags::SUPER as u16;
+        assert_eq!(class_file.access_flags(), flags);
+        assert_eq!(class_file.fields().collect::<Vec<_>>(), vec![("names", "[Ljava/lang/String;")]);
+        assert_eq!(class_file.methods().collect::<Vec<_>>(),
+            vec![("area", "()I"), ("pick", "(Ljava/lang/Object;)I")]);
+        assert!(class_file.methods[0].attributes.is_empty());
+        match &class_file.methods[1].attributes[..] {
+            [Attribute::Code { max_stack, max_locals, codes, exception_table, .. }] => {
+                assert_eq!((*max_stack, *max_locals), (4, 2));
+                assert_eq!(format!("{:?}", codes), "[aload_0, checkcast(14), pop, iconst_1, ireturn, \
+                    astore_1, iconst_0, ireturn]");
+                let entry = &exception_table[0];
+                assert_eq!((entry.start_pc, entry.end_pc, entry.handler_pc), (0, 3, 5));
+            }
+            attributes => panic!("unexpected attributes {:?}", attributes),
+        }
+    }
+
+    #[test]
+    fn report_errors_with_line_numbers() {
+        let error = |source: &str| assemble(source).unwrap_err();
+        assert_eq!(error(".super A"), AsmError { line: 1, message: "expected .class or .interface".to_string() });
+        assert_eq!(error(".class A\n\n.method static f()V\n  iconst_0 1\n.end method").line, 4);
+        assert_eq!(error(".class A\n.method static f()V\n  frobnicate\n.end method").message,
+            "unknown instruction frobnicate");
+        assert_eq!(error(".class A\n.method static f()V\n  goto nowhere\n.end method").message,
+            "label nowhere is never bound");
+        assert_eq!(error(".class A\n.method static f()V\n  return").message, "missing .end method");
+        assert_eq!(error(".class A\n.method static f()I\n  iadd\n  ireturn\n.end method"),
+            AsmError { line: 2, message: "method f()I: operand stack underflow at 0".to_string() });
+    }
+}
@@ -0,0 +1,719 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::const_pool::{ConstPool, CpInfo};
+use crate::descriptor::{FieldType, MethodDescriptor};
+use crate::Opcode;
+use crate::parser::{AccessFlags, Attribute, ClassFile, ExceptionTableEntry, FieldInfo, MethodInfo};
+
+/// Code that cannot be assembled into a valid method.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// A label that is branched to but never bound
+    UnboundLabel,
+    MalformedDescriptor(String),
+    /// An instruction at `pc` that pops more values than are on the operand stack
+    StackUnderflow { pc: usize },
+    /// Two paths reach the instruction at `pc` with different operand stack depths
+    InconsistentStack { pc: usize },
+    /// Execution can run past the last instruction
+    FallsOffEnd,
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::UnboundLabel => write!(f, "label is never bound"),
+            BuildError::MalformedDescriptor(descriptor) => write!(f, "malformed descriptor {}", descriptor),
+            BuildError::StackUnderflow { pc } => write!(f, "operand stack underflow at {}", pc),
+            BuildError::InconsistentStack { pc } => write!(f, "inconsistent operand stack depth at {}", pc),
+            BuildError::FallsOffEnd => write!(f, "execution falls off the end of the code"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+/// A position in the code of a [`MethodBuilder`], for branches and exception handlers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Label(usize);
+
+/// Builds a [`ClassFile`] without a compiler, adding constant pool entries as they are needed.
+///
+/// ```
+/// use rj::builder::ClassBuilder;
+/// use rj::parser::AccessFlags;
+///
+/// let mut class = ClassBuilder::new("Adder");
+/// let mut add = class.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "add", "(II)I");
+/// add.iload(0).iload(1).iadd().ireturn();
+/// add.finish().unwrap();
+/// let class_file = class.build();
+/// assert_eq!(class_file.this_class(), "Adder");
+/// ```
+pub struct ClassBuilder {
+    // entry 0 is the placeholder
+    entries: Vec<CpInfo>,
+    minor: u16,
+    major: u16,
+    access_flags: u16,
+    this_class: String,
+    super_class: Option<String>,
+    interfaces: Vec<String>,
+    fields: Vec<FieldInfo>,
+    methods: Vec<MethodInfo>,
+    attributes: Vec<Attribute>,
+}
+
+impl ClassBuilder {
+    /// A public class `name` extending `java/lang/Object`, in version 49 (Java 5) format.
+    /// Built methods carry no `StackMapTable`, which verification requires from version 50 on.
+    pub fn new(name: &str) -> ClassBuilder {
+        let mut builder = ClassBuilder {
+            entries: vec![CpInfo::Placeholder],
+            minor: 0,
+            major: 49,
+            access_flags: AccessFlags::PUBLIC as u16 | AccessFlags::SUPER as u16,
+            this_class: name.to_string(),
+            super_class: None,
+            interfaces: Vec::new(),
+            fields: Vec::new(),
+            methods: Vec::new(),
+            attributes: Vec::new(),
+        };
+        builder.class(name);
+        builder.super_class("java/lang/Object");
+        builder
+    }
+
+    /// Starts from a parsed class, keeping its constant pool, members and attributes, so it can
+    /// be patched and built again.
+    ///
+    /// ```
+    /// use rj::builder::ClassBuilder;
+    /// use rj::parser::AccessFlags;
+    ///
+    /// let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class")).unwrap();
+    /// let mut class = ClassBuilder::from_class_file(rj::parser::parse_bytes(&bytes).unwrap());
+    /// assert!(class.remove_method("add", "(II)I"));
+    /// let mut add = class.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "add", "(II)I");
+    /// add.iload(0).iload(1).iadd().iconst_1().iadd().ireturn();
+    /// add.finish().unwrap();
+    /// let patched = rj::writer::write(&class.build()).unwrap();
+    /// assert_ne!(patched, bytes);
+    /// ```
+    pub fn from_class_file(class_file: ClassFile) -> ClassBuilder {
+        ClassBuilder {
+            entries: class_file.const_pool.into_entries(),
+            minor: class_file.minor,
+            major: class_file.major,
+            access_flags: class_file.access_flags,
+            this_class: class_file.this_class,
+            super_class: class_file.super_class,
+            interfaces: class_file.interfaces,
+            fields: class_file.fields,
+            methods: class_file.methods,
+            attributes: class_file.attributes,
+        }
+    }
+
+    pub fn version(&mut self, major: u16, minor: u16) -> &mut ClassBuilder {
+        self.major = major;
+        self.minor = minor;
+        self
+    }
+
+    pub fn access_flags(&mut self, access_flags: u16) -> &mut ClassBuilder {
+        self.access_flags = access_flags;
+        self
+    }
+
+    pub fn super_class(&mut self, name: &str) -> &mut ClassBuilder {
+        self.class(name);
+        self.super_class = Some(name.to_string());
+        self
+    }
+
+    pub fn interface(&mut self, name: &str) -> &mut ClassBuilder {
+        self.class(name);
+        self.interfaces.push(name.to_string());
+        self
+    }
+
+    pub fn field(&mut self, access_flags: u16, name: &str, descriptor: &str) -> &mut ClassBuilder {
+        let name_index = self.utf8(name);
+        let descriptor_index = self.utf8(descriptor);
+        self.fields.push(FieldInfo { access_flags, name_index, descriptor_index, attributes: vec![] });
+        self
+    }
+
+    /// Starts a method, which is added to the class by [`MethodBuilder::finish`].
+    pub fn method(&mut self, access_flags: u16, name: &str, descriptor: &str) -> MethodBuilder<'_> {
+        MethodBuilder {
+            class: self,
+            access_flags,
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+            codes: Vec::new(),
+            effects: Vec::new(),
+            error: None,
+            labels: Vec::new(),
+            exception_table: Vec::new(),
+            max_stack: None,
+            max_locals: None,
+        }
+    }
+
+    /// Adds an abstract or native method, which has no code.
+    pub fn method_without_code(&mut self, access_flags: u16, name: &str, descriptor: &str) -> &mut ClassBuilder {
+        let name_index = self.utf8(name);
+        let descriptor_index = self.utf8(descriptor);
+        self.methods.push(MethodInfo { access_flags, name_index, descriptor_index, attributes: vec![] });
+        self
+    }
+
+    /// Removes the field `name` with `descriptor`, returning whether there was one.
+    pub fn remove_field(&mut self, name: &str, descriptor: &str) -> bool {
+        let entries = &self.entries;
+        let length = self.fields.len();
+        self.fields.retain(|it| !is_member(entries, it.name_index, it.descriptor_index, name, descriptor));
+        self.fields.len() != length
+    }
+
+    /// Removes the method `name` with `descriptor`, returning whether there was one, so that a
+    /// method of a parsed class can be replaced.
+    pub fn remove_method(&mut self, name: &str, descriptor: &str) -> bool {
+        let entries = &self.entries;
+        let length = self.methods.len();
+        self.methods.retain(|it| !is_member(entries, it.name_index, it.descriptor_index, name, descriptor));
+        self.methods.len() != length
+    }
+
+    pub fn build(self) -> ClassFile {
+        ClassFile {
+            minor: self.minor,
+            major: self.major,
+            const_pool: ConstPool::from_vec(self.entries),
+            access_flags: self.access_flags,
+            this_class: self.this_class,
+            super_class: self.super_class,
+            interfaces: self.interfaces,
+            fields: self.fields,
+            methods: self.methods,
+            attributes: self.attributes,
+        }
+    }
+
+    // index of an entry equal to `info`, adding it if there is none; floating-point constants
+    // are compared by their bits so that -0.0 and 0.0 stay apart and NaN matches itself
+    fn entry(&mut self, info: CpInfo) -> u16 {
+        let same = |it: &CpInfo| match (it, &info) {
+            (CpInfo::Float(a), CpInfo::Float(b)) => a.to_bits() == b.to_bits(),
+            (CpInfo::Double(a), CpInfo::Double(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        };
+        match self.entries.iter().position(same) {
+            Some(index) => index as u16,
+            None => {
+                let wide = matches!(info, CpInfo::Long(_) | CpInfo::Double(_));
+                self.entries.push(info);
+                let index = self.entries.len() as u16 - 1;
+                if wide {
+                    self.entries.push(CpInfo::Placeholder);
+                }
+                index
+            }
+        }
+    }
+
+    fn utf8(&mut self, value: &str) -> u16 {
+        self.entry(CpInfo::Utf8(value.to_string()))
+    }
+
+    fn class(&mut self, name: &str) -> u16 {
+        let name_index = self.utf8(name);
+        self.entry(CpInfo::Class { name_index })
+    }
+
+    fn name_and_type(&mut self, name: &str, descriptor: &str) -> u16 {
+        let name_index = self.utf8(name);
+        let descriptor_index = self.utf8(descriptor);
+        self.entry(CpInfo::NameAndTuple { name_index, descriptor_index })
+    }
+
+    fn field_ref(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let class_index = self.class(class);
+        let name_and_type_index = self.name_and_type(name, descriptor);
+        self.entry(CpInfo::FieldRef { class_index, name_and_type_index })
+    }
+
+    fn method_ref(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let class_index = self.class(class);
+        let name_and_type_index = self.name_and_type(name, descriptor);
+        self.entry(CpInfo::MethodRef { class_index, name_and_type_index })
+    }
+
+    fn interface_method_ref(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let class_index = self.class(class);
+        let name_and_type_index = self.name_and_type(name, descriptor);
+        self.entry(CpInfo::InterfaceMethodRef { class_index, name_and_type_index })
+    }
+}
+
+/// Builds the code of a method; instructions are appended in order.
+///
+/// `max_stack` and `max_locals` are computed when the method is finished.
+pub struct MethodBuilder<'a> {
+    class: &'a mut ClassBuilder,
+    access_flags: u16,
+    name: String,
+    descriptor: String,
+    codes: Vec<Opcode>,
+    // (slots popped, slots pushed) by each instruction
+    effects: Vec<(usize, usize)>,
+    // the first invalid descriptor passed to an instruction, reported by `finish`
+    error: Option<BuildError>,
+    // instruction index each label is bound to
+    labels: Vec<Option<usize>>,
+    // (start, end, handler, catch type)
+    exception_table: Vec<(Label, Label, Label, u16)>,
+    // explicit limits that replace the computed ones
+    max_stack: Option<u16>,
+    max_locals: Option<u16>,
+}
+
+impl MethodBuilder<'_> {
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds `label` to the next instruction.
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        self.labels[label.0] = Some(self.codes.len());
+        self
+    }
+
+    /// Adds an exception handler for `[start, end)` catching `class`, or anything if `None`.
+    pub fn try_catch(&mut self, start: Label, end: Label, handler: Label, class: Option<&str>) -> &mut Self {
+        let catch_type = class.map_or(0, |it| self.class.class(it));
+        self.exception_table.push((start, end, handler, catch_type));
+        self
+    }
+
+    /// Uses `max_stack` instead of the computed operand stack size.
+    pub fn limit_stack(&mut self, max_stack: u16) -> &mut Self {
+        self.max_stack = Some(max_stack);
+        self
+    }
+
+    /// Uses `max_locals` instead of the computed number of local variables.
+    pub fn limit_locals(&mut self, max_locals: u16) -> &mut Self {
+        self.max_locals = Some(max_locals);
+        self
+    }
+
+    fn emit(&mut self, code: Opcode, pop: usize, push: usize) -> &mut Self {
+        self.codes.push(code);
+        self.effects.push((pop, push));
+        self
+    }
+
+    // branch targets hold a label until `finish` replaces it with an instruction index
+    fn branch(&mut self, code: fn(usize) -> Opcode, label: Label, pop: usize) -> &mut Self {
+        self.emit(code(label.0), pop, 0)
+    }
+
+    fn invoke(&mut self, code: Opcode, descriptor: &str, receiver: usize) -> &mut Self {
+        match MethodDescriptor::parse(descriptor) {
+            Some(it) => {
+                let push = it.return_type.as_ref().map_or(0, FieldType::slot_width);
+                self.emit(code, receiver + it.parameter_slots(), push)
+            }
+            None => {
+                self.error.get_or_insert(BuildError::MalformedDescriptor(descriptor.to_string()));
+                self.emit(code, receiver, 0)
+            }
+        }
+    }
+
+    pub fn aconst_null(&mut self) -> &mut Self { self.emit(Opcode::aconst_null, 0, 1) }
+    pub fn iconst_0(&mut self) -> &mut Self { self.emit(Opcode::iconst_0, 0, 1) }
+    pub fn iconst_1(&mut self) -> &mut Self { self.emit(Opcode::iconst_1, 0, 1) }
+    pub fn bipush(&mut self, value: i8) -> &mut Self { self.emit(Opcode::bipush(value as i32), 0, 1) }
+
+    /// `ldc` of an `int` constant, or `ldc_w` once the constant pool has more than 255 entries.
+    pub fn ldc_int(&mut self, value: i32) -> &mut Self {
+        let index = self.class.entry(CpInfo::Integer(value));
+        self.emit(Opcode::ldc(index), 0, 1)
+    }
+
+    pub fn ldc_float(&mut self, value: f32) -> &mut Self {
+        let index = self.class.entry(CpInfo::Float(value));
+        self.emit(Opcode::ldc(index), 0, 1)
+    }
+
+    pub fn ldc_string(&mut self, value: &str) -> &mut Self {
+        let string_index = self.class.utf8(value);
+        let index = self.class.entry(CpInfo::String { string_index });
+        self.emit(Opcode::ldc(index), 0, 1)
+    }
+
+    /// `iload_<n>` for the first four locals, `iload` up to 255 and `wide iload` beyond.
+    pub fn iload(&mut self, index: u16) -> &mut Self {
+        let code = match index {
+            0 => Opcode::iload_0,
+            1 => Opcode::iload_1,
+            2 => Opcode::iload_2,
+            3 => Opcode::iload_3,
+            _ => Opcode::iload(index as usize),
+        };
+        self.emit(code, 0, 1)
+    }
+
+    pub fn aload(&mut self, index: u16) -> &mut Self {
+        let code = match index {
+            0 => Opcode::aload_0,
+            1 => Opcode::aload_1,
+            2 => Opcode::aload_2,
+            3 => Opcode::aload_3,
+            _ => Opcode::aload(index as usize),
+        };
+        self.emit(code, 0, 1)
+    }
+
+    pub fn istore(&mut self, index: u16) -> &mut Self {
+        let code = match index {
+            0 => Opcode::istore_0,
+            1 => Opcode::istore_1,
+            2 => Opcode::istore_2,
+            3 => Opcode::istore_3,
+            _ => Opcode::istore(index as usize),
+        };
+        self.emit(code, 1, 0)
+    }
+
+    pub fn astore(&mut self, index: u16) -> &mut Self {
+        let code = match index {
+            0 => Opcode::astore_0,
+            1 => Opcode::astore_1,
+            2 => Opcode::astore_2,
+            3 => Opcode::astore_3,
+            _ => Opcode::astore(index as usize),
+        };
+        self.emit(code, 1, 0)
+    }
+
+    /// `iinc`, or `wide iinc` when the local or increment doesn't fit in a byte.
+    pub fn iinc(&mut self, index: u16, value: i16) -> &mut Self {
+        self.emit(Opcode::iinc(index as usize, value as i32), 0, 0)
+    }
+
+    pub fn iadd(&mut self) -> &mut Self { self.emit(Opcode::iadd, 2, 1) }
+    pub fn pop(&mut self) -> &mut Self { self.emit(Opcode::pop, 1, 0) }
+    pub fn dup(&mut self) -> &mut Self { self.emit(Opcode::dup, 1, 2) }
+    pub fn ifeq(&mut self, label: Label) -> &mut Self { self.branch(Opcode::ifeq, label, 1) }
+    pub fn ifne(&mut self, label: Label) -> &mut Self { self.branch(Opcode::ifne, label, 1) }
+    pub fn if_icmplt(&mut self, label: Label) -> &mut Self { self.branch(Opcode::if_icmplt, label, 2) }
+    pub fn goto(&mut self, label: Label) -> &mut Self { self.branch(Opcode::goto, label, 0) }
+    /// Calls the subroutine at `label`, which starts with its return address on the stack.
+    pub fn jsr(&mut self, label: Label) -> &mut Self { self.emit(Opcode::jsr(label.0), 0, 1) }
+    pub fn ret(&mut self, index: u16) -> &mut Self { self.emit(Opcode::ret(index as usize), 0, 0) }
+    pub fn ireturn(&mut self) -> &mut Self { self.emit(Opcode::ireturn, 1, 0) }
+    pub fn areturn(&mut self) -> &mut Self { self.emit(Opcode::areturn, 1, 0) }
+    pub fn r#return(&mut self) -> &mut Self { self.emit(Opcode::r#return, 0, 0) }
+
+    pub fn getstatic(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.field_ref(class, name, descriptor);
+        self.emit(Opcode::getstatic(index), 0, slot_width(descriptor))
+    }
+
+    pub fn putstatic(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.field_ref(class, name, descriptor);
+        self.emit(Opcode::putstatic(index), slot_width(descriptor), 0)
+    }
+
+    pub fn getfield(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.field_ref(class, name, descriptor);
+        self.emit(Opcode::getfield(index), 1, slot_width(descriptor))
+    }
+
+    pub fn putfield(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.field_ref(class, name, descriptor);
+        self.emit(Opcode::putfield(index), 1 + slot_width(descriptor), 0)
+    }
+
+    pub fn invokevirtual(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.method_ref(class, name, descriptor);
+        self.invoke(Opcode::invokevirtual(index), descriptor, 1)
+    }
+
+    pub fn invokeinterface(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.interface_method_ref(class, name, descriptor);
+        // the count is the slots the arguments take, with the receiver
+        let count = MethodDescriptor::parse(descriptor).map_or(1, |it| it.parameter_slots() + 1);
+        self.invoke(Opcode::invokeinterface(index, count as u8), descriptor, 1)
+    }
+
+    pub fn invokespecial(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.method_ref(class, name, descriptor);
+        self.invoke(Opcode::invokespecial(index), descriptor, 1)
+    }
+
+    pub fn invokestatic(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+        let index = self.class.method_ref(class, name, descriptor);
+        self.invoke(Opcode::invokestatic(index as usize), descriptor, 0)
+    }
+
+    pub fn new(&mut self, class: &str) -> &mut Self {
+        let index = self.class.class(class);
+        self.emit(Opcode::new(index), 0, 1)
+    }
+
+    /// `atype` is one of the array type codes of §6.5 `newarray`, e.g. 10 for `int`.
+    pub fn newarray(&mut self, atype: u8) -> &mut Self { self.emit(Opcode::newarray(atype), 1, 1) }
+
+    pub fn anewarray(&mut self, class: &str) -> &mut Self {
+        let index = self.class.class(class);
+        self.emit(Opcode::anewarray(index), 1, 1)
+    }
+
+    pub fn arraylength(&mut self) -> &mut Self { self.emit(Opcode::arraylength, 1, 1) }
+
+    pub fn iaload(&mut self) -> &mut Self { self.emit(Opcode::iaload, 2, 1) }
+
+    pub fn iastore(&mut self) -> &mut Self { self.emit(Opcode::iastore, 3, 0) }
+
+    pub fn athrow(&mut self) -> &mut Self { self.emit(Opcode::athrow, 1, 0) }
+
+    pub fn checkcast(&mut self, class: &str) -> &mut Self {
+        let index = self.class.class(class);
+        self.emit(Opcode::checkcast(index), 1, 1)
+    }
+
+    pub fn instanceof(&mut self, class: &str) -> &mut Self {
+        let index = self.class.class(class);
+        self.emit(Opcode::instanceof(index), 1, 1)
+    }
+
+    /// Resolves labels, computes `max_stack` and `max_locals` and adds the method to the class.
+    pub fn finish(mut self) -> Result<(), BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let labels = &self.labels;
+        let resolve = |label: usize| labels[label].ok_or(BuildError::UnboundLabel);
+        for code in self.codes.iter_mut() {
+            match code {
+                Opcode::goto(target) | Opcode::ifeq(target) | Opcode::ifne(target) | Opcode::if_icmplt(target)
+                | Opcode::jsr(target) => {
+                    *target = resolve(*target)?;
+                }
+                _ => {}
+            }
+        }
+        let mut exception_table = Vec::with_capacity(self.exception_table.len());
+        for (start, end, handler, catch_type) in self.exception_table.iter() {
+            exception_table.push(ExceptionTableEntry {
+                start_pc: resolve(start.0)?,
+                end_pc: resolve(end.0)?,
+                handler_pc: resolve(handler.0)?,
+                catch_type: *catch_type,
+            });
+        }
+
+        let descriptor = MethodDescriptor::parse(&self.descriptor)
+            .ok_or_else(|| BuildError::MalformedDescriptor(self.descriptor.clone()))?;
+        let is_static = self.access_flags & AccessFlags::STATIC as u16 != 0;
+        let max_locals = (!is_static as usize + descriptor.parameter_slots()).max(max_local(&self.codes));
+        let max_stack = max_stack(&self.codes, &self.effects, &exception_table)?;
+
+        // the writer looks the attribute name up in the constant pool
+        self.class.utf8("Code");
+        let name_index = self.class.utf8(&self.name);
+        let descriptor_index = self.class.utf8(&self.descriptor);
+        self.class.methods.push(MethodInfo {
+            access_flags: self.access_flags,
+            name_index,
+            descriptor_index,
+            attributes: vec![Attribute::Code {
+                max_stack: self.max_stack.unwrap_or(max_stack as u16),
+                max_locals: self.max_locals.unwrap_or(max_locals as u16),
+                codes: self.codes,
+                exception_table,
+                attributes: vec![],
+            }],
+        });
+        Ok(())
+    }
+}
+
+// whether the field or method with these constant pool indices is `name` with `descriptor`
+fn is_member(entries: &[CpInfo], name_index: u16, descriptor_index: u16, name: &str, descriptor: &str) -> bool {
+    let is_utf8 = |index: u16, value: &str| matches!(entries.get(index as usize), Some(CpInfo::Utf8(it)) if it == value);
+    is_utf8(name_index, name) && is_utf8(descriptor_index, descriptor)
+}
+
+fn slot_width(descriptor: &str) -> usize {
+    FieldType::parse(descriptor).map_or(1, |it| it.slot_width())
+}
+
+/// One more than the highest local variable index the code uses.
+fn max_local(codes: &[Opcode]) -> usize {
+    codes.iter()
+        .filter_map(|code| match code {
+            Opcode::iload_0 | Opcode::aload_0 | Opcode::istore_0 | Opcode::astore_0 => Some(0),
+            Opcode::iload_1 | Opcode::aload_1 | Opcode::istore_1 | Opcode::astore_1 => Some(1),
+            Opcode::iload_2 | Opcode::aload_2 | Opcode::istore_2 | Opcode::astore_2 => Some(2),
+            Opcode::iload_3 | Opcode::aload_3 | Opcode::istore_3 | Opcode::astore_3 => Some(3),
+            Opcode::iload(index) | Opcode::aload(index) | Opcode::istore(index) | Opcode::astore(index)
+            | Opcode::iinc(index, _) | Opcode::ret(index) => Some(*index),
+            _ => None,
+        })
+        .map(|it| it + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Follows every path through the code, tracking the operand stack depth at each instruction.
+fn max_stack(
+    codes: &[Opcode],
+    effects: &[(usize, usize)],
+    exception_table: &[ExceptionTableEntry],
+) -> Result<usize, BuildError> {
+    let mut depths: Vec<Option<usize>> = vec![None; codes.len() + 1];
+    let mut pending = vec![(0, 0)];
+    // a handler starts with just the exception on the stack
+    pending.extend(exception_table.iter().map(|it| (it.handler_pc, 1)));
+    let mut max = 0;
+
+    while let Some((pc, depth)) = pending.pop() {
+        match depths[pc] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Err(BuildError::InconsistentStack { pc }),
+            None => depths[pc] = Some(depth),
+        }
+        let code = codes.get(pc).ok_or(BuildError::FallsOffEnd)?;
+        let (pop, push) = effects[pc];
+        let after = depth.checked_sub(pop).ok_or(BuildError::StackUnderflow { pc })? + push;
+        max = max.max(after).max(depth);
+        match code {
+            Opcode::goto(target) => pending.push((*target, after)),
+            // the subroutine returns with the stack it was called with
+            Opcode::jsr(target) => {
+                pending.push((*target, after));
+                pending.push((pc + 1, depth));
+            }
+            Opcode::ifeq(target) | Opcode::ifne(target) | Opcode::if_icmplt(target) => {
+                pending.push((*target, after));
+                pending.push((pc + 1, after));
+            }
+            Opcode::ireturn | Opcode::areturn | Opcode::r#return | Opcode::athrow | Opcode::ret(_) => {}
+            _ => pending.push((pc + 1, after)),
+        }
+    }
+    Ok(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{BuildError, ClassBuilder};
+    use crate::parser::{AccessFlags, Attribute, ClassFile};
+
+    const PUBLIC_STATIC: u16 = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+
+    fn code(class_file: &ClassFile, name: &str) -> String {
+        let index = class_file.methods().position(|(it, _)| it == name).unwrap();
+        match &class_file.methods[index].attributes[..] {
+            [Attribute::Code { max_stack, max_locals, codes, .. }] => {
+                format!("stack={} locals={} {:?}", max_stack, max_locals, codes)
+            }
+            attributes => panic!("unexpected attributes {:?}", attributes),
+        }
+    }
+
+    #[test]
+    fn resolve_labels_and_compute_limits() {
+        let mut class = ClassBuilder::new("Sum");
+        let mut sum = class.method(PUBLIC_STATIC, "sum", "(I)I");
+        let (body, condition) = (sum.new_label(), sum.new_label());
+        sum.iconst_0().istore(1).iconst_0().istore(2).goto(condition);
+        sum.bind(body).iload(1).iload(2).iadd().istore(1).iinc(2, 1);
+        sum.bind(condition).iload(2).iload(0).if_icmplt(body);
+        sum.iload(1).ireturn();
+        sum.finish().unwrap();
+
+        let mut init = class.method(AccessFlags::PUBLIC as u16, "<init>", "()V");
+        init.aload(0).invokespecial("java/lang/Object", "<init>", "()V").r#return();
+        init.finish().unwrap();
+
+        let class_file = class.build();
+        assert_eq!(code(&class_file, "sum"), "stack=2 locals=3 [iconst_0, istore_1, iconst_0, istore_2, goto(10), \
+            iload_1, iload_2, iadd, istore_1, iinc(2, 1), iload_2, iload_0, if_icmplt(5), iload_1, ireturn]");
+        assert_eq!(code(&class_file, "<init>"), "stack=1 locals=1 [aload_0, invokespecial(11), return]");
+    }
+
+    #[test]
+    fn share_constant_pool_entries() {
+        let mut class = ClassBuilder::new("Fields");
+        class.field(AccessFlags::STATIC as u16, "count", "I");
+        let mut bump = class.method(PUBLIC_STATIC, "bump", "()V");
+        bump.getstatic("Fields", "count", "I").iconst_1().iadd().putstatic("Fields", "count", "I").r#return();
+        bump.finish().unwrap();
+        let class_file = class.build();
+        assert_eq!(code(&class_file, "bump"), "stack=2 locals=0 [getstatic(8), iconst_1, iadd, putstatic(8), return]");
+        assert_eq!(class_file.const_pool.find_class("Fields"), Some(2));
+    }
+
+    #[test]
+    fn keep_floating_point_constants_with_different_bits_apart() {
+        let mut class = ClassBuilder::new("Floats");
+        let mut method = class.method(PUBLIC_STATIC, "floats", "()V");
+        method.ldc_float(0.0).pop().ldc_float(-0.0).pop().ldc_float(f32::NAN).pop().ldc_float(f32::NAN).pop()
+            .r#return();
+        method.finish().unwrap();
+        let class_file = class.build();
+        assert_eq!(code(&class_file, "floats"), "stack=1 locals=0 \
+            [ldc(5), pop, ldc(6), pop, ldc(7), pop, ldc(7), pop, return]");
+    }
+
+    #[test]
+    fn handler_starts_with_exception_on_stack() {
+        let mut class = ClassBuilder::new("Catch");
+        let mut method = class.method(PUBLIC_STATIC, "run", "(Ljava/lang/Object;)I");
+        let (start, end, handler) = (method.new_label(), method.new_label(), method.new_label());
+        method.bind(start).aload(0).checkcast("java/lang/String").pop().bind(end).iconst_1().ireturn();
+        method.bind(handler).astore(1).iconst_0().ireturn();
+        method.try_catch(start, end, handler, Some("java/lang/ClassCastException"));
+        method.finish().unwrap();
+        let class_file = class.build();
+        assert_eq!(code(&class_file, "run"), "stack=1 locals=2 \
+            [aload_0, checkcast(6), pop, iconst_1, ireturn, astore_1, iconst_0, ireturn]");
+    }
+
+    #[test]
+    fn reject_invalid_code() {
+        let mut class = ClassBuilder::new("Invalid");
+        let mut method = class.method(PUBLIC_STATIC, "unbound", "()V");
+        let label = method.new_label();
+        method.goto(label);
+        assert_eq!(method.finish(), Err(BuildError::UnboundLabel));
+
+        let mut method = class.method(PUBLIC_STATIC, "underflow", "()I");
+        method.iadd().ireturn();
+        assert_eq!(method.finish(), Err(BuildError::StackUnderflow { pc: 0 }));
+
+        let mut method = class.method(PUBLIC_STATIC, "falls", "()V");
+        method.iconst_0().pop();
+        assert_eq!(method.finish(), Err(BuildError::FallsOffEnd));
+
+        let mut method = class.method(PUBLIC_STATIC, "merge", "(I)V");
+        let label = method.new_label();
+        method.iload(0).ifeq(label).iconst_1().bind(label).r#return();
+        assert_eq!(method.finish(), Err(BuildError::InconsistentStack { pc: 3 }));
+
+        let mut method = class.method(PUBLIC_STATIC, "descriptor", "()V");
+        method.invokestatic("A", "b", "(V)V").r#return();
+        assert_eq!(method.finish(), Err(BuildError::MalformedDescriptor("(V)V".to_string())));
+    }
+}
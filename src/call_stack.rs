@@ -1,164 +1,972 @@
-use std::collections::HashMap;
+use std::convert::TryFrom;
 
-use crate::const_pool::ConstPool;
-use crate::method_area::{Class, Method, MethodArea};
+use crate::const_pool::{ConstPool, CpInfo, MemberRef};
+use crate::heap::{Heap, NULL, Object, OBJECT_HEADER_BYTES, Reference, slot_width};
+use crate::instruction::Instruction;
+use crate::jit::JitThresholds;
+use crate::limits::{Budget, Limit};
+use crate::method_area::{external_name, Class, Method, MethodArea};
 use crate::Opcode;
 
 // §2.5.2
 pub(crate) struct JvmStack<'a> {
     frames: Vec<Frame<'a>>,
-    method_table: &'a MethodArea<'a>,
+    // the locals and operand stacks of all frames, each frame's locals starting where the
+    // arguments were on its invoker's operand stack, so that invoking copies nothing
+    slots: Vec<i32>,
+    // frames that may be on the stack at once, invoking more throws `StackOverflowError`
+    max_depth: usize,
+    budget: Budget,
+    // when methods are compiled, None if they aren't
+    jit: Option<JitThresholds>,
+    // reused to look up the method an `invokevirtual` selects without allocating
+    virtual_key: String,
+    method_table: &'a MethodArea,
+    heap: &'a mut Heap,
 }
 
-// §2.6
-pub(crate) struct Frame<'a> {
+/// Why a run ended before its bottom frame completed.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Stop {
+    /// An exception that no frame caught
+    Thrown(Reference),
+    /// The frames are left as they were, to be [suspended](JvmStack::suspend)
+    Limit(Limit),
+}
+
+/// A frame of a run that stopped at a limit, no longer borrowing the method area.
+#[derive(Debug)]
+pub(crate) struct SuspendedFrame {
+    // key of the method in the method area
+    method: String,
+    // whether the frame runs the method's optimized code, which `pc` is into
+    optimized: bool,
     pc: usize,
-    // §2.6.1
     locals: Vec<i32>,
-    // §2.6.2
     operand_stack: Vec<i32>,
+}
+
+// §2.6, with its locals and operand stack in the slots of the `JvmStack`
+pub(crate) struct Frame<'a> {
+    pc: usize,
+    // §2.6.1, index of local 0
+    locals: usize,
+    // §2.6.2, index of the bottom of the operand stack, after the locals
+    stack: usize,
+    // index of the first free slot above the operand stack
+    sp: usize,
     // §2.5.5
     constant_pool: &'a ConstPool,
-    method: &'a Method<'a>,
+    method: &'a Method,
 }
 
 #[derive(Debug, PartialEq)]
 enum FrameResult<'a> {
     End,
     ReturnValue(i32),
-    Invoke(&'a str),
+    // with the slots the arguments take, including the receiver, which is on the operand
+    // stack below the other arguments
+    InvokeStatic(&'a MemberRef, usize),
+    InvokeSpecial(&'a MemberRef, usize),
+    // an invokevirtual or invokeinterface whose inline cache missed, with the index of its
+    // call site
+    InvokeVirtual(u16, usize),
+    // a quickened invokestatic or invokespecial, with the index in the method area of a method
+    // whose class is initialized
+    Invoke(usize, usize),
+    // §2.10
+    Throw(Reference),
+    // the instruction at `pc` is run again when the frame resumes
+    Limit(Limit),
+    // §5.5, the instruction that needs the class is retried once it is initialized
+    Initialize(String),
 }
 
-impl JvmStack<'_> {
-    pub(crate) fn new<'a>(
-        max_size: usize,
-        main: Frame<'a>,
-        method_table: &'a MethodArea<'a>,
+impl<'a> JvmStack<'a> {
+    pub(crate) fn new(
+        max_depth: usize,
+        method_table: &'a MethodArea,
+        heap: &'a mut Heap,
     ) -> JvmStack<'a> {
-        let mut stack = Vec::with_capacity(max_size);
-        stack.push(main);
+        JvmStack {
+            frames: Vec::new(),
+            slots: Vec::new(),
+            max_depth,
+            budget: Budget::default(),
+            jit: None,
+            virtual_key: String::new(),
+            method_table,
+            heap,
+        }
+    }
+
+    /// Continues a run from the frames it was suspended with.
+    pub(crate) fn resume(
+        max_depth: usize,
+        suspended: Vec<SuspendedFrame>,
+        method_table: &'a MethodArea,
+        heap: &'a mut Heap,
+    ) -> JvmStack<'a> {
+        let mut stack = JvmStack::new(max_depth, method_table, heap);
+        for SuspendedFrame { method, optimized, pc, locals, operand_stack } in suspended {
+            let mut method = method_table.resolve_method(&method);
+            let constant_pool = &method_table.resolve_class(&method.class).const_pool;
+            if optimized {
+                method = method.optimized(constant_pool).expect("optimized before it was suspended");
+            }
+            stack.push_frame(method, constant_pool, &locals);
+            let frame = stack.frames.last_mut().unwrap();
+            frame.pc = pc;
+            stack.slots[frame.stack..frame.stack + operand_stack.len()].copy_from_slice(&operand_stack);
+            frame.sp += operand_stack.len();
+        }
+        stack
+    }
 
-        JvmStack { frames: stack, method_table }
+    /// Pushes a frame for `method` on top of the others, with `arguments` in its first locals
+    /// and the rest zero.
+    pub(crate) fn push_frame(&mut self, method: &'a Method, constant_pool: &'a ConstPool, arguments: &[i32]) {
+        let locals = self.frames.last().map_or(0, |it| it.sp);
+        let frame = Frame::new(locals, arguments.len().max(method.local_size), method, constant_pool);
+        self.reserve(&frame);
+        self.slots[locals..locals + arguments.len()].copy_from_slice(arguments);
+        self.slots[locals + arguments.len()..frame.stack].fill(0);
+        self.frames.push(frame);
     }
 
-    pub(crate) fn run(&mut self) {
-        while !self.frames.is_empty() {
-            let mut frame = self.frames.pop().unwrap();
-            match frame.run() {
-                FrameResult::End => {}
-                FrameResult::ReturnValue(v) => {
-                    // last() does not gain ownership
-                    let mut invoker = self.frames.pop().unwrap();
-                    invoker.push(v);
-                    self.frames.push(invoker);
+    // grows the slots to hold `frame`'s operand stack, which happens rarely once a run is under way
+    fn reserve(&mut self, frame: &Frame) {
+        let top = frame.stack + frame.method.stack_size + 2;
+        if self.slots.len() < top {
+            self.slots.resize(top.max(self.slots.len() * 2), 0);
+        }
+    }
+
+    pub(crate) fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    pub(crate) fn set_jit(&mut self, jit: Option<JitThresholds>) {
+        self.jit = jit;
+    }
+
+    /// Frees the frames of a run that stopped at a limit from the method area so it can be
+    /// resumed later, bottom frame first.
+    pub(crate) fn suspend(self) -> Vec<SuspendedFrame> {
+        let slots = self.slots;
+        self.frames.into_iter()
+            .map(|it| SuspendedFrame {
+                method: it.method.key(),
+                optimized: it.method.is_optimized,
+                pc: it.pc,
+                locals: slots[it.locals..it.stack].to_vec(),
+                operand_stack: slots[it.stack..it.sp].to_vec(),
+            })
+            .collect()
+    }
+
+    /// Pushes frames for the `<clinit>` of `class` and any of its uninitialized
+    /// superclasses, so that they run, superclasses first, before the current frame resumes.
+    pub(crate) fn initialize(&mut self, class: &str) {
+        let mut current = Some(class);
+        while let Some(name) = current {
+            if self.heap.is_initialized(name) {
+                break;
+            }
+            self.heap.init_statics(name, self.method_table.static_slot_count(name));
+            self.init_constants(name);
+            let clinit = format!("{}.<clinit>:()V", name);
+            let c = self.method_table.resolve_class(name);
+            if let Some(method) = self.method_table.find_method(&clinit).filter(|it| it.class == name) {
+                self.push_frame(method, &c.const_pool, &[]);
+            }
+            current = c.super_class.as_deref();
+        }
+    }
+
+    /// Sets static fields with a `ConstantValue` attribute, before `<clinit>` runs (§5.5).
+    fn init_constants(&mut self, class: &str) {
+        for (slot, value) in self.method_table.constant_values(class) {
+            let slots = match value {
+                CpInfo::Integer(v) => vec![*v],
+                CpInfo::Float(v) => vec![v.to_bits() as i32],
+                CpInfo::Long(v) => vec![(v >> 32) as i32, *v as i32],
+                CpInfo::Double(v) => vec![(v.to_bits() >> 32) as i32, v.to_bits() as i32],
+                CpInfo::String { string_index } => {
+                    let c = self.method_table.resolve_class(class);
+                    vec![self.heap.intern(c.const_pool.utf8(*string_index).unwrap_or(""))]
                 }
-                FrameResult::Invoke(method_name) => {
-                    let method: &Method = self.method_table.resolve_method(method_name);
-                    let class: &Class = self.method_table.resolve_class(method.class);
-                    let mut locals = Vec::with_capacity(method.local_size);
+                _ => continue,
+            };
+            self.heap.statics_mut(class)[slot..slot + slots.len()].copy_from_slice(&slots);
+        }
+    }
 
-                    for i in 0..method.local_size {
-                        let arg = frame.pop();
-                        locals.push(arg);
+    /// Runs until the bottom frame completes, returning its return value if it has one,
+    /// or why it stopped before that.
+    pub(crate) fn run(&mut self) -> Result<Option<i32>, Stop> {
+        while let Some(mut frame) = self.frames.pop() {
+            match frame.run(&mut self.slots, self.method_table, self.heap, &mut self.budget, self.jit) {
+                FrameResult::End => {}
+                FrameResult::ReturnValue(v) => match self.frames.last_mut() {
+                    Some(invoker) => {
+                        self.slots[invoker.sp] = v;
+                        invoker.sp += 1;
                     }
-
-                    let invoked = Frame::new(locals, method, &class.const_pool);
+                    None => return Ok(Some(v)),
+                },
+                FrameResult::InvokeStatic(method_ref, arguments) => {
+                    self.invoke_resolved(frame, method_ref, arguments, true)?;
+                }
+                FrameResult::InvokeSpecial(method_ref, arguments) => {
+                    self.invoke_resolved(frame, method_ref, arguments, false)?;
+                }
+                FrameResult::Invoke(method, arguments) => self.invoke(frame, self.method_table.method(method), arguments)?,
+                FrameResult::InvokeVirtual(site, arguments) => {
+                    // §5.4.6, select the method from the receiver's class
+                    let site = &frame.method.call_sites(frame.constant_pool)[site as usize];
+                    let method_ref = match frame.constant_pool.resolve_method_ref(site.index) {
+                        Some(method_ref) => method_ref,
+                        None => {
+                            let exception = invalid_constant(self.heap, self.method_table, site.index);
+                            self.unwind(frame, exception)?;
+                            continue;
+                        }
+                    };
+                    let MemberRef { name, descriptor, .. } = method_ref;
+                    let receiver = self.slots[frame.sp - arguments];
+                    if receiver == NULL {
+                        let exception = self.heap.new_throwable(
+                            self.method_table, "java/lang/NullPointerException", name);
+                        self.unwind(frame, exception)?;
+                        continue;
+                    }
+                    let key = &mut self.virtual_key;
+                    key.clear();
+                    key.push_str(self.heap.get(receiver).class_name());
+                    key.push('.');
+                    key.push_str(name);
+                    key.push(':');
+                    key.push_str(descriptor);
+                    // the receiver's class and so the class declaring the method are initialized
+                    let index = match self.method_table.find_method_index(key) {
+                        Some(index) if !self.method_table.method(index).is_static() => index,
+                        found => {
+                            let exception = match found {
+                                Some(_) => incompatible_method(self.heap, self.method_table, method_ref, false),
+                                None => unresolved_method(self.heap, self.method_table, method_ref),
+                            };
+                            self.unwind(frame, exception)?;
+                            continue;
+                        }
+                    };
+                    site.record(self.heap.get(receiver).class_name(), index);
+                    self.invoke(frame, self.method_table.method(index), arguments)?;
+                }
+                FrameResult::Throw(exception) => self.unwind(frame, exception)?,
+                FrameResult::Limit(limit) => {
                     self.frames.push(frame);
-                    self.frames.push(invoked);
+                    return Err(Stop::Limit(limit));
                 }
+                FrameResult::Initialize(class) => {
+                    self.frames.push(frame);
+                    self.initialize(&class);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resolves the method an `invokestatic` or `invokespecial` calls (§5.4.3.3), then invokes
+    /// it once its class is initialized and quickens the instruction to call it directly, or
+    /// throws the error resolving it does.
+    fn invoke_resolved(
+        &mut self, mut frame: Frame<'a>, method_ref: &MemberRef, arguments: usize, is_static: bool,
+    ) -> Result<(), Stop> {
+        let index = match self.method_table.find_method_index(&method_ref.key) {
+            Some(index) => index,
+            None => {
+                let exception = unresolved_method(self.heap, self.method_table, method_ref);
+                return self.unwind(frame, exception);
+            }
+        };
+        let method: &Method = self.method_table.method(index);
+        if method.is_static() != is_static {
+            let exception = incompatible_method(self.heap, self.method_table, method_ref, is_static);
+            return self.unwind(frame, exception);
+        }
+        if !self.heap.is_initialized(&method.class) {
+            // retry the invoke instruction after running the static initializer
+            frame.pc -= 1;
+            self.frames.push(frame);
+            self.initialize(&method.class);
+            return Ok(());
+        }
+        if let (Ok(method), Ok(arguments)) = (u32::try_from(index), u16::try_from(arguments)) {
+            frame.quicken(Instruction::InvokeQuick { method, arguments });
+        }
+        self.invoke(frame, method, arguments)
+    }
+
+    /// Pushes a frame for `method`, whose class is initialized, with as first locals the top
+    /// `argument_slots` values of `frame`'s operand stack, `this` first if there is one (§2.6.1).
+    fn invoke(&mut self, mut frame: Frame<'a>, method: &'a Method, argument_slots: usize) -> Result<(), Stop> {
+        // the invoking frame was popped, so it and the invoked one make two
+        let depth = self.frames.len() + 2;
+        if let Err(limit) = self.budget.check_depth(depth) {
+            // the invoke instruction runs again when the call resumes
+            frame.pc -= 1;
+            self.frames.push(frame);
+            return Err(Stop::Limit(limit));
+        }
+        if depth > self.max_depth {
+            let exception = self.heap.new_instance(self.method_table, "java/lang/StackOverflowError");
+            return self.unwind(frame, exception);
+        }
+        if argument_slots > method.local_size {
+            let message = format!("arguments of {}.{}{} exceed max_locals", method.class, method.name, method.descriptor);
+            let exception = self.heap.new_throwable(self.method_table, "java/lang/VerifyError", &message);
+            return self.unwind(frame, exception);
+        }
+        let class: &Class = self.method_table.resolve_class(&method.class);
+        // once the method got hot enough to be compiled, calls run its optimized code if it has any
+        let method = match self.jit {
+            Some(jit) if jit.optimize && method.native.get().is_some() => {
+                method.optimized(&class.const_pool).unwrap_or(method)
             }
+            _ => method,
+        };
+        // the arguments become the first locals where they are
+        frame.sp -= argument_slots;
+        let invoked = Frame::new(frame.sp, method.local_size, method, &class.const_pool);
+        self.reserve(&invoked);
+        self.slots[frame.sp + argument_slots..invoked.stack].fill(0);
+        self.frames.push(frame);
+        self.frames.push(invoked);
+        Ok(())
+    }
+
+    /// Pops frames until one has a handler for `exception` and resumes it there (§2.10).
+    fn unwind(&mut self, frame: Frame<'a>, exception: Reference) -> Result<(), Stop> {
+        let mut current = Some(frame);
+        while let Some(mut frame) = current {
+            if let Some(handler_pc) = frame.find_handler(exception, self.method_table, self.heap) {
+                self.slots[frame.stack] = exception;
+                frame.sp = frame.stack + 1;
+                frame.pc = handler_pc;
+                self.frames.push(frame);
+                return Ok(());
+            }
+            current = self.frames.pop();
         }
+        Err(Stop::Thrown(exception))
     }
 }
 
-impl Frame<'_> {
-    pub(crate) fn new<'a>(locals: Vec<i32>,
-                          method: &'a Method<'a>,
-                          constant_pool: &'a ConstPool,
+impl<'a> Frame<'a> {
+    // `local_count` is at least `max_locals`, and more if malformed code passes more arguments
+    fn new(locals: usize,
+           local_count: usize,
+           method: &'a Method,
+           constant_pool: &'a ConstPool,
     ) -> Frame<'a> {
         Frame {
             pc: 0,
             locals,
-            operand_stack: Vec::with_capacity(method.stack_size),
+            stack: locals + local_count,
+            sp: locals + local_count,
             constant_pool,
             method,
         }
     }
-    fn push(&mut self, value: i32) {
-        self.operand_stack.push(value);
+
+    // replaces the instruction that just ran by `quick`
+    fn quicken(&self, quick: Instruction) {
+        self.method.instructions(self.constant_pool)[self.pc - 1].set(quick);
     }
 
-    fn pop(&mut self) -> i32 {
-        self.operand_stack.pop().unwrap()
+    fn find_handler(&self, exception: Reference, method_area: &MethodArea, heap: &Heap) -> Option<usize> {
+        // pc has already moved past the instruction that threw
+        let pc = self.pc - 1;
+        let class = heap.get(exception).class_name();
+        self.method.exception_table.iter()
+            .find(|it| it.start_pc <= pc && pc < it.end_pc && (
+                it.catch_type == 0
+                    || self.constant_pool.resolve_class(it.catch_type).is_some_and(|t| method_area.is_assignable(class, t))
+            ))
+            .map(|it| it.handler_pc)
     }
 
-    fn run(&mut self) -> FrameResult {
-        let mut stack: &mut Vec<i32> = &mut self.operand_stack;
+    /// Runs until the frame returns, invokes a method or stops, with `slots` those of the `JvmStack`,
+    /// in native code where the method has been compiled.
+    fn run(
+        &mut self, slots: &mut [i32], method_area: &MethodArea, heap: &mut Heap, budget: &mut Budget,
+        jit: Option<JitThresholds>,
+    ) -> FrameResult<'a> {
+        let method = self.method;
+        let constant_pool = self.constant_pool;
+        let code = method.instructions(constant_pool);
+        let call_sites = method.call_sites(constant_pool);
+        let locals = self.locals;
+        let stack = self.stack;
+        // no instruction pushes more than two slots and the slots are reserved with two to spare,
+        // so checking the depth before each instruction keeps even unverified code within them
+        let max_sp = self.stack + method.stack_size;
+        // registers, written back to the frame when it is left
+        let mut pc = self.pc;
+        let mut sp = self.sp;
+        // instructions taken from the budget that may still run
+        let mut fuel = 0;
+        let mut native = jit.and(method.native.get().and_then(Option::as_ref));
+        macro_rules! push {
+            ($value:expr) => {{
+                let value = $value;
+                slots[sp] = value;
+                sp += 1;
+            }};
+        }
+        macro_rules! pop {
+            () => {{
+                sp -= 1;
+                slots[sp]
+            }};
+        }
+        // carries on in native code if it can be entered at `pc`, or until the method is compiled,
+        // counts towards the threshold for `counter` and compiles it once that is reached
+        macro_rules! hot {
+            ($counter:ident) => {
+                match native {
+                    Some(native) => if native.can_enter(pc) {
+                        if fuel == 0 {
+                            fuel = budget.take().unwrap_or(0);
+                        }
+                        if let Some((exit, top, left)) = native.run(slots, locals, stack, pc, sp - stack, fuel) {
+                            pc = exit;
+                            sp = top;
+                            fuel = left;
+                        }
+                    },
+                    None => if let Some(jit) = jit {
+                        native = method.count(&method.$counter, jit.$counter, constant_pool);
+                    },
+                }
+            };
+        }
+        // the constant at `$index` as `$resolve` makes of it, which the verifier checked it to be,
+        // throwing rather than trusting that
+        macro_rules! constant {
+            ($resolve:ident, $index:expr) => {
+                match constant_pool.$resolve($index) {
+                    Some(constant) => constant,
+                    None => break FrameResult::Throw(invalid_constant(heap, method_area, $index)),
+                }
+            };
+        }
+        // §5.4.3.2, the error resolving a field of `$class` throws if it or the class is missing
+        macro_rules! resolve_field {
+            ($found:expr, $class:expr, $member:expr) => {
+                match $found {
+                    Some(found) => found,
+                    None => break FrameResult::Throw(
+                        unresolved(heap, method_area, $class, "java/lang/NoSuchFieldError", $member)),
+                }
+            };
+        }
+        // §5.4.3.1, a class reference resolves if the class, or the element class of an array
+        // class, is loaded
+        macro_rules! resolve_class {
+            ($index:expr) => {{
+                let class = constant!(resolve_class, $index);
+                if let Some(missing) = missing_class(method_area, class) {
+                    break FrameResult::Throw(
+                        heap.new_throwable(method_area, "java/lang/NoClassDefFoundError", missing));
+                }
+                class
+            }};
+        }
+        // the native code is entered where the frame starts and where loops go back
+        macro_rules! branch {
+            ($target:expr) => {{
+                let target = $target as usize;
+                let backward = target < pc;
+                pc = target;
+                if backward {
+                    hot!(backedges);
+                }
+            }};
+        }
+
+        if pc == 0 {
+            hot!(invocations);
+        }
 
-        loop {
-            let code = match self.method.codes.get(self.pc) {
-                Some(v) => v,
-                None => break,
+        let result = loop {
+            let instruction = match code.get(pc) {
+                Some(instruction) => instruction.get(),
+                None => break FrameResult::End,
             };
+            if fuel == 0 {
+                match budget.take() {
+                    Ok(taken) => fuel = taken,
+                    Err(limit) => break FrameResult::Limit(limit),
+                }
+            }
+            fuel -= 1;
+            // no instruction pushes more than it is allowed to, but nothing stops malformed code
+            // from trying when it isn't verified
+            if sp > max_sp {
+                let message = format!("operand stack overflow in {}.{}{}", method.class, method.name, method.descriptor);
+                break FrameResult::Throw(heap.new_throwable(method_area, "java/lang/VerifyError", &message));
+            }
 
-            dbg!(code);
-
-            self.pc += 1;
-            match code {
-                Opcode::iconst_0 => stack.push(0),
-                Opcode::iconst_1 => stack.push(1),
-                Opcode::istore_0 => self.locals[0] = stack.pop().unwrap(),
-                Opcode::istore_1 => self.locals[1] = stack.pop().unwrap(),
-                Opcode::goto(index) => self.pc = *index,
-                Opcode::iinc(index, number) => self.locals[*index] += number,
-                Opcode::iload_1 => stack.push(self.locals[1]),
-                Opcode::bipush(number) => stack.push(*number),
-                Opcode::if_icmplt(index) => {
-                    let value2 = stack.pop();
-                    let value1 = stack.pop();
+            pc += 1;
+            match instruction {
+                Instruction::Push(value) => push!(value),
+                Instruction::Load(index) => push!(slots[locals + index as usize]),
+                Instruction::Store(index) => slots[locals + index as usize] = pop!(),
+                Instruction::Increment(index, value) => {
+                    let local = &mut slots[locals + index as usize];
+                    *local = local.wrapping_add(value);
+                }
+                Instruction::Add => {
+                    let value2 = pop!();
+                    let value1 = pop!();
+                    push!(value1.wrapping_add(value2));
+                }
+                Instruction::Pop => sp -= 1,
+                Instruction::Dup => push!(slots[sp - 1]),
+                Instruction::Goto(target) => branch!(target),
+                Instruction::IfZero(target) => if pop!() == 0 {
+                    branch!(target);
+                },
+                Instruction::IfNonZero(target) => if pop!() != 0 {
+                    branch!(target);
+                },
+                Instruction::IfLess(target) => {
+                    let value2 = pop!();
+                    let value1 = pop!();
                     if value1 < value2 {
-                        self.pc = *index;
+                        branch!(target);
+                    }
+                }
+                Instruction::Jsr(target) => {
+                    push!(pc as i32);
+                    pc = target as usize;
+                }
+                Instruction::Ret(index) => pc = slots[locals + index as usize] as usize,
+                Instruction::Return => break FrameResult::End,
+                Instruction::ReturnValue => break FrameResult::ReturnValue(pop!()),
+                Instruction::InvokeStatic { index, arguments } => {
+                    break FrameResult::InvokeStatic(constant!(resolve_method_ref, index), arguments as usize);
+                }
+                Instruction::InvokeSpecial { index, arguments } => {
+                    break FrameResult::InvokeSpecial(constant!(resolve_method_ref, index), arguments as usize);
+                }
+                Instruction::InvokeVirtual { site, arguments } => {
+                    let receiver = slots[sp - arguments as usize];
+                    if receiver != NULL {
+                        if let Some(method) = call_sites[site as usize].lookup(heap.get(receiver).class_name()) {
+                            break FrameResult::Invoke(method, arguments as usize);
+                        }
                     }
+                    break FrameResult::InvokeVirtual(site, arguments as usize);
+                }
+                Instruction::InvokeQuick { method, arguments } => {
+                    break FrameResult::Invoke(method as usize, arguments as usize);
                 }
-                Opcode::r#return => break,
-                Opcode::invokestatic(index) => {
-                    let method = self.constant_pool.resolve_utf8(*index as u16);
-                    return FrameResult::Invoke(method);
+                Instruction::Ldc(index) => {
+                    let value = match constant_pool.get(index) {
+                        Some(CpInfo::String { string_index }) => heap.intern(constant!(utf8, *string_index)),
+                        _ => break FrameResult::Throw(invalid_constant(heap, method_area, index)),
+                    };
+                    code[pc - 1].set(Instruction::Push(value));
+                    push!(value);
                 }
-                Opcode::iload_0 => stack.push(self.locals[0]),
-                Opcode::iadd => {
-                    let result = stack.pop().unwrap() + stack.pop().unwrap();
-                    stack.push(result);
+                Instruction::New(index) => {
+                    let class = resolve_class!(index);
+                    if !method_area.is_instantiable(class) {
+                        break FrameResult::Throw(
+                            heap.new_throwable(method_area, "java/lang/InstantiationError", &external_name(class)));
+                    }
+                    if !heap.is_initialized(class) {
+                        pc -= 1;
+                        break FrameResult::Initialize(class.to_string());
+                    }
+                    let field_slots = method_area.instance_slot_count(class);
+                    if let Ok(field_slots) = u16::try_from(field_slots) {
+                        code[pc - 1].set(Instruction::NewQuick { index, slots: field_slots });
+                    }
+                    match new_instance(heap, budget, class, field_slots) {
+                        Ok(object) => push!(object),
+                        Err(limit) => {
+                            pc -= 1;
+                            break FrameResult::Limit(limit);
+                        }
+                    }
+                }
+                Instruction::NewQuick { index, slots: field_slots } => {
+                    let class = constant!(resolve_class, index);
+                    match new_instance(heap, budget, class, field_slots as usize) {
+                        Ok(object) => push!(object),
+                        Err(limit) => {
+                            pc -= 1;
+                            break FrameResult::Limit(limit);
+                        }
+                    }
+                }
+                Instruction::GetField { index, width } => {
+                    let MemberRef { class, name, .. } = constant!(resolve_field_ref, index);
+                    let (slot, _) = resolve_field!(method_area.instance_field(class, name), class, name);
+                    let object = pop!();
+                    if object == NULL {
+                        break FrameResult::Throw(
+                            heap.new_throwable(method_area, "java/lang/NullPointerException", name));
+                    }
+                    if let Ok(slot) = u16::try_from(slot) {
+                        code[pc - 1].set(Instruction::GetFieldQuick { slot, width });
+                    }
+                    if let Object::Instance { fields, .. } = heap.get(object) {
+                        let width = width as usize;
+                        slots[sp..sp + width].copy_from_slice(&fields[slot..slot + width]);
+                        sp += width;
+                    }
+                }
+                Instruction::GetFieldQuick { slot, width } => {
+                    let object = pop!();
+                    if object == NULL {
+                        let name = field_name(method, constant_pool, pc - 1);
+                        break FrameResult::Throw(
+                            heap.new_throwable(method_area, "java/lang/NullPointerException", name));
+                    }
+                    if let Object::Instance { fields, .. } = heap.get(object) {
+                        let (slot, width) = (slot as usize, width as usize);
+                        slots[sp..sp + width].copy_from_slice(&fields[slot..slot + width]);
+                        sp += width;
+                    }
+                }
+                Instruction::PutField { index, width } => {
+                    let MemberRef { class, name, .. } = constant!(resolve_field_ref, index);
+                    let (slot, _) = resolve_field!(method_area.instance_field(class, name), class, name);
+                    let width = width as usize;
+                    sp -= width;
+                    let value = sp;
+                    let object = pop!();
+                    if object == NULL {
+                        break FrameResult::Throw(
+                            heap.new_throwable(method_area, "java/lang/NullPointerException", name));
+                    }
+                    if let Ok(slot) = u16::try_from(slot) {
+                        code[pc - 1].set(Instruction::PutFieldQuick { slot, width: width as u8 });
+                    }
+                    if let Object::Instance { fields, .. } = heap.get_mut(object) {
+                        fields[slot..slot + width].copy_from_slice(&slots[value..value + width]);
+                    }
+                }
+                Instruction::PutFieldQuick { slot, width } => {
+                    let (slot, width) = (slot as usize, width as usize);
+                    sp -= width;
+                    let value = sp;
+                    let object = pop!();
+                    if object == NULL {
+                        let name = field_name(method, constant_pool, pc - 1);
+                        break FrameResult::Throw(
+                            heap.new_throwable(method_area, "java/lang/NullPointerException", name));
+                    }
+                    if let Object::Instance { fields, .. } = heap.get_mut(object) {
+                        fields[slot..slot + width].copy_from_slice(&slots[value..value + width]);
+                    }
+                }
+                Instruction::GetStatic { index, width } => {
+                    let MemberRef { class, name, .. } = constant!(resolve_field_ref, index);
+                    let (owner, slot, _) = resolve_field!(method_area.static_field(class, name), class, name);
+                    let statics = match heap.statics_index(owner) {
+                        Some(statics) => statics,
+                        None => {
+                            pc -= 1;
+                            break FrameResult::Initialize(owner.to_string());
+                        }
+                    };
+                    if let (Ok(statics), Ok(slot)) = (u32::try_from(statics), u16::try_from(slot)) {
+                        code[pc - 1].set(Instruction::GetStaticQuick { statics, slot, width });
+                    }
+                    let width = width as usize;
+                    slots[sp..sp + width].copy_from_slice(&heap.statics_at(statics)[slot..slot + width]);
+                    sp += width;
+                }
+                Instruction::GetStaticQuick { statics, slot, width } => {
+                    let (slot, width) = (slot as usize, width as usize);
+                    slots[sp..sp + width].copy_from_slice(&heap.statics_at(statics as usize)[slot..slot + width]);
+                    sp += width;
+                }
+                Instruction::PutStatic { index, width } => {
+                    let MemberRef { class, name, .. } = constant!(resolve_field_ref, index);
+                    let (owner, slot, _) = resolve_field!(method_area.static_field(class, name), class, name);
+                    let statics = match heap.statics_index(owner) {
+                        Some(statics) => statics,
+                        None => {
+                            pc -= 1;
+                            break FrameResult::Initialize(owner.to_string());
+                        }
+                    };
+                    if let (Ok(statics), Ok(slot)) = (u32::try_from(statics), u16::try_from(slot)) {
+                        code[pc - 1].set(Instruction::PutStaticQuick { statics, slot, width });
+                    }
+                    let width = width as usize;
+                    sp -= width;
+                    heap.statics_at_mut(statics)[slot..slot + width].copy_from_slice(&slots[sp..sp + width]);
+                }
+                Instruction::PutStaticQuick { statics, slot, width } => {
+                    let (slot, width) = (slot as usize, width as usize);
+                    sp -= width;
+                    heap.statics_at_mut(statics as usize)[slot..slot + width].copy_from_slice(&slots[sp..sp + width]);
+                }
+                Instruction::NewArray(atype) => {
+                    let component = match atype {
+                        4 => "Z",
+                        5 => "C",
+                        6 => "F",
+                        7 => "D",
+                        8 => "B",
+                        9 => "S",
+                        10 => "I",
+                        11 => "J",
+                        _ => {
+                            let message = format!("invalid newarray type {}", atype);
+                            break FrameResult::Throw(heap.new_throwable(method_area, "java/lang/VerifyError", &message));
+                        }
+                    };
+                    match new_array(heap, method_area, budget, component, slots[sp - 1]) {
+                        Ok(array) => slots[sp - 1] = array,
+                        Err(result) => {
+                            if let FrameResult::Limit(_) = result {
+                                pc -= 1;
+                            }
+                            break result;
+                        }
+                    }
+                }
+                Instruction::ANewArray(index) => {
+                    let class = resolve_class!(index);
+                    let component = if class.starts_with('[') {
+                        class.to_string()
+                    } else {
+                        format!("L{};", class)
+                    };
+                    match new_array(heap, method_area, budget, &component, slots[sp - 1]) {
+                        Ok(array) => slots[sp - 1] = array,
+                        Err(result) => {
+                            if let FrameResult::Limit(_) = result {
+                                pc -= 1;
+                            }
+                            break result;
+                        }
+                    }
+                }
+                Instruction::ArrayLength => {
+                    let array = pop!();
+                    if array == NULL {
+                        break FrameResult::Throw(
+                            heap.new_throwable(method_area, "java/lang/NullPointerException", "array is null"));
+                    }
+                    push!(heap.get(array).array_length().unwrap() as i32);
+                }
+                Instruction::ArrayLoad => {
+                    let index = pop!();
+                    let array = pop!();
+                    match element(heap, method_area, array, index) {
+                        Ok(element) => match heap.get(array) {
+                            Object::Array { elements, .. } => push!(elements[element]),
+                            _ => unreachable!(),
+                        },
+                        Err(exception) => break FrameResult::Throw(exception),
+                    }
+                }
+                Instruction::ArrayStore => {
+                    let value = pop!();
+                    let index = pop!();
+                    let array = pop!();
+                    match element(heap, method_area, array, index) {
+                        Ok(element) => if let Object::Array { elements, .. } = heap.get_mut(array) {
+                            elements[element] = value;
+                        },
+                        Err(exception) => break FrameResult::Throw(exception),
+                    }
+                }
+                // the array is known not to be null, as its length was read before
+                Instruction::ArrayLoadUnchecked => {
+                    let index = pop!();
+                    let array = pop!();
+                    match heap.get(array) {
+                        Object::Array { elements, .. } => push!(elements[index as usize]),
+                        _ => unreachable!(),
+                    }
+                }
+                Instruction::ArrayStoreUnchecked => {
+                    let value = pop!();
+                    let index = pop!();
+                    let array = pop!();
+                    if let Object::Array { elements, .. } = heap.get_mut(array) {
+                        elements[index as usize] = value;
+                    }
+                }
+                Instruction::Throw => {
+                    let exception = pop!();
+                    if exception == NULL {
+                        break FrameResult::Throw(
+                            heap.new_throwable(method_area, "java/lang/NullPointerException", "exception is null"));
+                    }
+                    break FrameResult::Throw(exception);
+                }
+                Instruction::CheckCast(index) => {
+                    let object = slots[sp - 1];
+                    if object != NULL {
+                        let t = resolve_class!(index);
+                        let s = heap.get(object).class_name();
+                        if !method_area.is_assignable(s, t) {
+                            let message = method_area.class_cast_message(s, t);
+                            break FrameResult::Throw(
+                                heap.new_throwable(method_area, "java/lang/ClassCastException", &message));
+                        }
+                    }
+                }
+                Instruction::InstanceOf(index) => {
+                    let object = pop!();
+                    let result = object != NULL && {
+                        let t = resolve_class!(index);
+                        method_area.is_assignable(heap.get(object).class_name(), t)
+                    };
+                    push!(result as i32);
                 }
-                Opcode::ireturn => return FrameResult::ReturnValue(stack.pop().unwrap()),
-                _ => unimplemented!("opcode {:?}", code)
             }
+        };
+        self.pc = pc;
+        self.sp = sp;
+        budget.put_back(fuel);
+        result
+    }
+}
+
+fn new_instance(heap: &mut Heap, budget: &Budget, class: &str, field_slots: usize) -> Result<Reference, Limit> {
+    budget.check_heap(heap, OBJECT_HEADER_BYTES + field_slots * 4)?;
+    Ok(heap.allocate(Object::Instance { class: class.to_string(), fields: vec![0; field_slots] }))
+}
+
+// the error a method, field or class reference throws when the constant it refers to is of
+// another kind, which only unverified code gets to
+fn invalid_constant(heap: &mut Heap, method_area: &MethodArea, index: u16) -> Reference {
+    let message = format!("invalid constant pool reference #{}", index);
+    heap.new_throwable(method_area, "java/lang/VerifyError", &message)
+}
+
+// §5.4.3, `error` naming `member` of `class`, or `NoClassDefFoundError` if `class` itself is missing
+fn unresolved(heap: &mut Heap, method_area: &MethodArea, class: &str, error: &str, member: &str) -> Reference {
+    match missing_class(method_area, class) {
+        Some(missing) => heap.new_throwable(method_area, "java/lang/NoClassDefFoundError", missing),
+        None => heap.new_throwable(method_area, error, member),
+    }
+}
+
+fn unresolved_method(heap: &mut Heap, method_area: &MethodArea, method_ref: &MemberRef) -> Reference {
+    let member = format!("{}.{}{}", method_ref.class.replace('/', "."), method_ref.name, method_ref.descriptor);
+    unresolved(heap, method_area, &method_ref.class, "java/lang/NoSuchMethodError", &member)
+}
+
+// §6.5.invokestatic, invokespecial and invokevirtual each only call one kind of method
+fn incompatible_method(heap: &mut Heap, method_area: &MethodArea, method_ref: &MemberRef, is_static: bool) -> Reference {
+    let message = format!(
+        "Expected {} method {}.{}{}", if is_static { "static" } else { "non-static" },
+        method_ref.class.replace('/', "."), method_ref.name, method_ref.descriptor,
+    );
+    heap.new_throwable(method_area, "java/lang/IncompatibleClassChangeError", &message)
+}
+
+// the class `class` needs loaded that isn't, the element class for an array class
+fn missing_class<'c>(method_area: &MethodArea, class: &'c str) -> Option<&'c str> {
+    let element = class.trim_start_matches('[');
+    let element = if element.len() < class.len() {
+        element.strip_prefix('L').and_then(|it| it.strip_suffix(';'))?
+    } else {
+        element
+    };
+    Some(element).filter(|it| !method_area.contains_class(it))
+}
+
+// the name of the field that the quickened field instruction at `pc` accesses, which only the
+// opcode it was decoded from still refers to
+fn field_name<'c>(method: &Method, constant_pool: &'c ConstPool, pc: usize) -> &'c str {
+    match method.codes[pc] {
+        Opcode::getfield(index) | Opcode::putfield(index) => {
+            // the field resolved before the instruction was quickened
+            &constant_pool.resolve_field_ref(index).expect("not a field reference").name
         }
-        FrameResult::End
+        _ => unreachable!("not a field instruction"),
+    }
+}
+
+// the slot `index` of the int array `array` is at, or the exception that accessing it throws
+fn element(heap: &mut Heap, method_area: &MethodArea, array: Reference, index: i32) -> Result<usize, Reference> {
+    if array == NULL {
+        return Err(heap.new_throwable(method_area, "java/lang/NullPointerException", "array is null"));
+    }
+    let length = heap.get(array).array_length().unwrap();
+    if index < 0 || index as usize >= length {
+        let message = format!("Index {} out of bounds for length {}", index, length);
+        return Err(heap.new_throwable(method_area, "java/lang/ArrayIndexOutOfBoundsException", &message));
+    }
+    Ok(index as usize)
+}
+
+fn new_array(
+    heap: &mut Heap, method_area: &MethodArea, budget: &Budget, component: &str, count: i32,
+) -> Result<Reference, FrameResult<'static>> {
+    if count < 0 {
+        let exception = heap.new_throwable(method_area, "java/lang/NegativeArraySizeException", &count.to_string());
+        return Err(FrameResult::Throw(exception));
     }
+    let slots = count as usize * slot_width(component);
+    // checked first so that a huge array fails cleanly instead of exhausting the host's memory
+    budget.check_heap(heap, OBJECT_HEADER_BYTES + slots * 4).map_err(FrameResult::Limit)?;
+    let class = format!("[{}", component);
+    let elements = vec![0; slots];
+    Ok(heap.allocate(Object::Array { class, elements }))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::call_stack::{Frame, FrameResult, JvmStack};
-    use crate::const_pool::ConstPool;
-    use crate::const_pool::CpInfo::Class as CpClass;
-    use crate::const_pool::tests::{sample_const_pool};
-    use crate::method_area::{Class, Method, MethodArea};
+    use crate::call_stack::{Frame, FrameResult, JvmStack, Stop};
+    use crate::const_pool::{ConstPool, CpInfo};
+    use crate::const_pool::tests::sample_const_pool;
+    use crate::heap::Heap;
+    use crate::instruction::Instruction;
+    use crate::jit::JitThresholds;
+    use crate::limits::{Budget, Limit, Limits};
+    use crate::method_area::{Class, ClassLoader, Method, MethodArea};
     use crate::Opcode;
+    use crate::parser::{AccessFlags, ExceptionTableEntry};
+
+    // runs `method` as the only frame, returning as soon as it invokes anything
+    fn run_frame<'a>(
+        method: &'a Method, const_pool: &'a ConstPool, method_area: &MethodArea, heap: &mut Heap,
+    ) -> FrameResult<'a> {
+        let mut frame = Frame::new(0, method.local_size, method, const_pool);
+        let mut slots = vec![0; frame.stack + method.stack_size + 2];
+        frame.run(&mut slots, method_area, heap, &mut Budget::default(), None)
+    }
 
     #[test]
     fn create_new_stack() {
         let method_table = MethodArea::new();
+        let mut heap = Heap::new();
         let const_pool = &sample_const_pool();
         let method = &Method {
             codes: vec![],
             stack_size: 2,
             local_size: 0,
-            class: "",
+            class: "".to_string(),
+            ..Default::default()
         };
-        let frame = Frame::new(Vec::new(), method, const_pool);
-        let stack = JvmStack::new(128, frame, &method_table);
+        let mut stack = JvmStack::new(128, &method_table, &mut heap);
+        stack.push_frame(method, const_pool, &[]);
     }
 
     #[test]
@@ -166,7 +974,7 @@ mod tests {
         let const_pool = &sample_const_pool();
         let method = &Method {
             stack_size: 3,
-            local_size: 0,
+            local_size: 3,
             codes: vec![
                 Opcode::iconst_0,     // 0
                 Opcode::istore_1,     // 1
@@ -176,10 +984,10 @@ mod tests {
                 Opcode::bipush(10),   // 5
                 Opcode::if_icmplt(3), // 6
             ],
-            class: "",
+            class: "".to_string(),
+            ..Default::default()
         };
-        let mut frame = Frame::new(vec![0; 3], method, const_pool);
-        assert_eq!(frame.run(), FrameResult::End);
+        assert_eq!(run_frame(method, const_pool, &MethodArea::new(), &mut Heap::new()), FrameResult::End);
     }
 
     ///```java
@@ -206,7 +1014,8 @@ mod tests {
                 Opcode::istore_0,
                 Opcode::r#return
             ],
-            class: "Adder",
+            class: "Adder".to_string(),
+            ..Default::default()
         };
         let add_method = Method {
             stack_size: 2,
@@ -217,18 +1026,383 @@ mod tests {
                 Opcode::iadd,
                 Opcode::ireturn,
             ],
-            class: "Adder",
+            class: "Adder".to_string(),
+            access_flags: AccessFlags::STATIC as u16,
+            ..Default::default()
         };
 
-        let mut main_frame = Frame::new(vec![0; 1], main_method, const_pool);
         let mut method_area = MethodArea::new();
+        let mut heap = Heap::new();
         method_area.put("Adder.add:(II)I", add_method);
-        method_area.put_class("Adder", crate::method_area::Class {
-            super_class: &None,
+        method_area.put_class(Class {
+            name: "Adder".to_string(),
+            access_flags: 0,
+            super_class: Some("java/lang/Object".to_string()),
+            interfaces: vec![],
+            fields: vec![],
+            const_pool: sample_const_pool(),
+            loader: ClassLoader::App,
+            major: 52,
+        });
+        let mut jvm_stack = JvmStack::new(256, &method_area, &mut heap);
+        jvm_stack.push_frame(main_method, const_pool, &[]);
+        assert_eq!(jvm_stack.run(), Ok(None));
+
+        // the invoke was quickened and runs as well the second time
+        let method = method_area.find_method_index("Adder.add:(II)I").unwrap() as u32;
+        assert_eq!(main_method.instructions(const_pool)[2].get(), Instruction::InvokeQuick { method, arguments: 2 });
+        let mut jvm_stack = JvmStack::new(256, &method_area, &mut heap);
+        jvm_stack.push_frame(main_method, const_pool, &[]);
+        assert_eq!(jvm_stack.run(), Ok(None));
+    }
+
+    #[test]
+    fn invoke_moves_only_the_arguments_in_order() {
+        let const_pool = &sample_const_pool();
+        // 7 + (0 < 1 ? 1 : 0), the 7 must stay on the stack across the call
+        let main_method = &Method {
+            stack_size: 3,
+            local_size: 0,
+            codes: vec![
+                Opcode::bipush(7),
+                Opcode::iconst_0,
+                Opcode::iconst_1,
+                Opcode::invokestatic(2),
+                Opcode::iadd,
+                Opcode::ireturn,
+            ],
+            class: "Adder".to_string(),
+            ..Default::default()
+        };
+        // has a local beyond its parameters
+        let less_method = Method {
+            stack_size: 2,
+            local_size: 3,
+            codes: vec![
+                Opcode::iload_0,
+                Opcode::iload_1,
+                Opcode::if_icmplt(5),
+                Opcode::iconst_0,
+                Opcode::ireturn,
+                Opcode::iconst_1,
+                Opcode::ireturn,
+            ],
+            class: "Adder".to_string(),
+            access_flags: AccessFlags::STATIC as u16,
+            ..Default::default()
+        };
+        let mut method_area = MethodArea::new();
+        let mut heap = Heap::new();
+        method_area.put("Adder.add:(II)I", less_method);
+        method_area.put_class(Class {
+            name: "Adder".to_string(),
+            access_flags: 0,
+            super_class: Some("java/lang/Object".to_string()),
+            interfaces: vec![],
+            fields: vec![],
             const_pool: sample_const_pool(),
-            methods: Default::default(),
+            loader: ClassLoader::App,
+            major: 52,
         });
-        let mut jvm_stack = JvmStack::new(256, main_frame, &method_area);
-        jvm_stack.run();
+        let mut jvm_stack = JvmStack::new(256, &method_area, &mut heap);
+        jvm_stack.push_frame(main_method, const_pool, &[]);
+        assert_eq!(jvm_stack.run(), Ok(Some(8)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn calls_of_hot_method_run_its_optimized_code() {
+        let const_pool = &sample_const_pool();
+        let main_method = &Method {
+            stack_size: 2,
+            local_size: 0,
+            codes: vec![
+                Opcode::iconst_1,
+                Opcode::bipush(2),
+                Opcode::invokestatic(2),
+                Opcode::ireturn,
+            ],
+            class: "Adder".to_string(),
+            ..Default::default()
+        };
+        // x + (1 + 2), which optimizing folds to x + 3
+        let add_method = Method {
+            stack_size: 3,
+            local_size: 2,
+            codes: vec![
+                Opcode::iload_0,
+                Opcode::iconst_1,
+                Opcode::bipush(2),
+                Opcode::iadd,
+                Opcode::iadd,
+                Opcode::ireturn,
+            ],
+            class: "Adder".to_string(),
+            name: "add".to_string(),
+            descriptor: "(II)I".to_string(),
+            access_flags: AccessFlags::STATIC as u16,
+            ..Default::default()
+        };
+        let mut method_area = MethodArea::new();
+        let mut heap = Heap::new();
+        method_area.put("Adder.add:(II)I", add_method);
+        method_area.put_class(Class {
+            name: "Adder".to_string(),
+            access_flags: 0,
+            super_class: Some("java/lang/Object".to_string()),
+            interfaces: vec![],
+            fields: vec![],
+            const_pool: sample_const_pool(),
+            loader: ClassLoader::App,
+            major: 52,
+        });
+        for _ in 0..2 {
+            let mut jvm_stack = JvmStack::new(256, &method_area, &mut heap);
+            jvm_stack.set_jit(Some(JitThresholds { invocations: 1, backedges: 1, optimize: true }));
+            jvm_stack.push_frame(main_method, const_pool, &[]);
+            assert_eq!(jvm_stack.run(), Ok(Some(4)));
+        }
+        // the first call made the method hot, the second ran the optimized code
+        let optimized = method_area.find_method("Adder.add:(II)I").unwrap().optimized.get().unwrap().as_ref().unwrap();
+        assert!(optimized.decoded.get().is_some());
+        assert_eq!(optimized.codes, vec![
+            Opcode::bipush(3),
+            Opcode::istore(2),
+            Opcode::iload(0),
+            Opcode::iload(2),
+            Opcode::iadd,
+            Opcode::istore(3),
+            Opcode::iload(3),
+            Opcode::ireturn,
+        ]);
+    }
+
+    fn cast_const_pool() -> ConstPool {
+        ConstPool::from_vec(vec![
+            CpInfo::Placeholder,
+            CpInfo::Class { name_index: 2 },
+            CpInfo::Utf8("[I".to_string()),
+            CpInfo::Class { name_index: 4 },
+            CpInfo::Utf8("java/lang/String".to_string()),
+            CpInfo::Class { name_index: 6 },
+            CpInfo::Utf8("java/lang/ClassCastException".to_string()),
+            CpInfo::Class { name_index: 8 },
+            CpInfo::Utf8("java/lang/Object".to_string()),
+        ])
+    }
+
+    fn cast_method(codes: Vec<Opcode>) -> Method {
+        Method {
+            stack_size: 2,
+            local_size: 1,
+            codes,
+            class: "".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn checkcast_leaves_assignable_reference() {
+        let const_pool = &cast_const_pool();
+        let method = &cast_method(vec![
+            Opcode::iconst_1,
+            Opcode::newarray(10),
+            Opcode::checkcast(7),
+            Opcode::checkcast(1),
+            Opcode::arraylength,
+            Opcode::ireturn,
+        ]);
+        assert_eq!(run_frame(method, const_pool, &MethodArea::new(), &mut Heap::new()), FrameResult::ReturnValue(1));
+    }
+
+    #[test]
+    fn checkcast_passes_null() {
+        let const_pool = &cast_const_pool();
+        let method = &cast_method(vec![
+            Opcode::aconst_null,
+            Opcode::checkcast(3),
+            Opcode::areturn,
+        ]);
+        assert_eq!(run_frame(method, const_pool, &MethodArea::new(), &mut Heap::new()), FrameResult::ReturnValue(0));
+    }
+
+    #[test]
+    fn checkcast_throws_class_cast_exception() {
+        let const_pool = &cast_const_pool();
+        let method = &cast_method(vec![
+            Opcode::iconst_1,
+            Opcode::newarray(10),
+            Opcode::checkcast(3),
+            Opcode::areturn,
+        ]);
+        let method_area = MethodArea::new();
+        let mut heap = Heap::new();
+        let exception = match run_frame(method, const_pool, &method_area, &mut heap) {
+            FrameResult::Throw(exception) => exception,
+            result => panic!("expected exception, got {:?}", result),
+        };
+        assert_eq!(heap.get(exception).class_name(), "java/lang/ClassCastException");
+        assert_eq!(
+            heap.throwable_message(&method_area, exception),
+            Some("class [I cannot be cast to class java.lang.String \
+            ([I and java.lang.String are in module java.base of loader 'bootstrap')")
+        );
+    }
+
+    #[test]
+    fn instanceof_tests_assignability() {
+        let const_pool = &cast_const_pool();
+        let method_area = MethodArea::new();
+        for (class_index, expected) in [(1, 1), (3, 0), (7, 1)].iter() {
+            let method = &cast_method(vec![
+                Opcode::iconst_1,
+                Opcode::newarray(10),
+                Opcode::instanceof(*class_index),
+                Opcode::ireturn,
+            ]);
+            assert_eq!(run_frame(method, const_pool, &method_area, &mut Heap::new()), FrameResult::ReturnValue(*expected));
+        }
+        let method = &cast_method(vec![
+            Opcode::aconst_null,
+            Opcode::instanceof(7),
+            Opcode::ireturn,
+        ]);
+        assert_eq!(run_frame(method, const_pool, &method_area, &mut Heap::new()), FrameResult::ReturnValue(0));
+    }
+
+    #[test]
+    fn int_array_elements_are_bounds_checked() {
+        let const_pool = &cast_const_pool();
+        let method_area = MethodArea::new();
+        let elements = |index| Method {
+            stack_size: 4,
+            codes: vec![
+                Opcode::iconst_1,
+                Opcode::newarray(10),
+                Opcode::dup,
+                Opcode::iconst_0,
+                Opcode::bipush(42),
+                Opcode::iastore,
+                Opcode::bipush(index),
+                Opcode::iaload,
+                Opcode::ireturn,
+            ],
+            ..Default::default()
+        };
+        assert_eq!(run_frame(&elements(0), const_pool, &method_area, &mut Heap::new()), FrameResult::ReturnValue(42));
+        let mut heap = Heap::new();
+        let exception = match run_frame(&elements(1), const_pool, &method_area, &mut heap) {
+            FrameResult::Throw(exception) => exception,
+            result => panic!("expected exception, got {:?}", result),
+        };
+        assert_eq!(heap.get(exception).class_name(), "java/lang/ArrayIndexOutOfBoundsException");
+        assert_eq!(heap.throwable_message(&method_area, exception), Some("Index 1 out of bounds for length 1"));
+    }
+
+    ///```java
+    /// try {
+    ///     return ((String) (Object) new int[1]).length();
+    /// } catch (ClassCastException e) {
+    ///     return 0;
+    /// }
+    /// ```
+    #[test]
+    fn class_cast_exception_is_caught_by_handler() {
+        let const_pool = &cast_const_pool();
+        let mut method = cast_method(vec![
+            Opcode::iconst_1,     // 0
+            Opcode::newarray(10), // 1
+            Opcode::checkcast(3), // 2
+            Opcode::areturn,      // 3
+            Opcode::astore_0,     // 4
+            Opcode::iconst_0,     // 5
+            Opcode::ireturn,      // 6
+        ]);
+        method.exception_table.push(ExceptionTableEntry {
+            start_pc: 0,
+            end_pc: 4,
+            handler_pc: 4,
+            catch_type: 5,
+        });
+        let method_area = MethodArea::new();
+        let mut heap = Heap::new();
+        let mut jvm_stack = JvmStack::new(16, &method_area, &mut heap);
+        jvm_stack.push_frame(&method, const_pool, &[]);
+        assert_eq!(jvm_stack.run(), Ok(Some(0)));
+    }
+
+    #[test]
+    fn uncaught_exception_is_returned() {
+        let const_pool = &cast_const_pool();
+        let method = &cast_method(vec![
+            Opcode::aconst_null,
+            Opcode::athrow,
+        ]);
+        let method_area = MethodArea::new();
+        let mut heap = Heap::new();
+        let mut jvm_stack = JvmStack::new(16, &method_area, &mut heap);
+        jvm_stack.push_frame(method, const_pool, &[]);
+        let exception = match jvm_stack.run() {
+            Err(Stop::Thrown(exception)) => exception,
+            result => panic!("expected an exception, got {:?}", result),
+        };
+        assert_eq!(heap.get(exception).class_name(), "java/lang/NullPointerException");
+    }
+
+    #[test]
+    fn exceeding_max_stack_throws_verify_error() {
+        let const_pool = &cast_const_pool();
+        // `cast_method` allows two values on the stack
+        let method = &cast_method(vec![
+            Opcode::iconst_0,
+            Opcode::iconst_1,
+            Opcode::iconst_1,
+            Opcode::iadd,
+            Opcode::iadd,
+            Opcode::ireturn,
+        ]);
+        let method_area = MethodArea::new();
+        let mut heap = Heap::new();
+        let mut jvm_stack = JvmStack::new(16, &method_area, &mut heap);
+        jvm_stack.push_frame(method, const_pool, &[]);
+        let exception = match jvm_stack.run() {
+            Err(Stop::Thrown(exception)) => exception,
+            result => panic!("expected an exception, got {:?}", result),
+        };
+        assert_eq!(heap.get(exception).class_name(), "java/lang/VerifyError");
+    }
+
+    #[test]
+    fn stop_at_limit_and_resume() {
+        let const_pool = &cast_const_pool();
+        let method = cast_method(vec![Opcode::iinc(0, 1), Opcode::goto(0)]);
+        let mut method_area = MethodArea::new();
+        method_area.put_class(Class {
+            name: "Spin".to_string(),
+            access_flags: 0,
+            super_class: Some("java/lang/Object".to_string()),
+            interfaces: vec![],
+            fields: vec![],
+            const_pool: cast_const_pool(),
+            loader: ClassLoader::App,
+            major: 52,
+        });
+        method_area.put("Spin.spin:()V", Method {
+            class: "Spin".to_string(),
+            name: "spin".to_string(),
+            descriptor: "()V".to_string(),
+            ..method
+        });
+        let mut heap = Heap::new();
+        let mut jvm_stack = JvmStack::new(16, &method_area, &mut heap);
+        jvm_stack.push_frame(method_area.resolve_method("Spin.spin:()V"), const_pool, &[]);
+        jvm_stack.set_budget(Budget::new(&Limits { instructions: Some(5), ..Limits::default() }));
+        assert_eq!(jvm_stack.run(), Err(Stop::Limit(Limit::Instructions)));
+        let suspended = jvm_stack.suspend();
+        assert_eq!((suspended[0].pc, &suspended[0].locals), (1, &vec![3]));
+
+        let mut jvm_stack = JvmStack::resume(16, suspended, &method_area, &mut heap);
+        jvm_stack.set_budget(Budget::new(&Limits { instructions: Some(2), ..Limits::default() }));
+        assert_eq!(jvm_stack.run(), Err(Stop::Limit(Limit::Instructions)));
+        assert_eq!(jvm_stack.suspend()[0].locals, vec![4]);
+    }
+}
@@ -1,15 +1,11 @@
-use std::collections::HashMap;
-use std::convert::TryInto;
-use std::ops::Add;
-
-use crate::const_pool::CpInfo::Utf8;
-use crate::method_area::{Class, Method};
+use std::cell::OnceCell;
 
 /// const pool table entry
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum CpInfo {
     /// Const pool items start with index 1.
-    /// We insert an empty Placeholder at the head.
+    /// We insert an empty Placeholder at the head, and after each Long and Double
+    /// since those take up two entries, §4.4.5.
     Placeholder,
     Class { name_index: u16 },
     FieldRef { class_index: u16, name_and_type_index: u16 },
@@ -22,45 +18,178 @@ pub enum CpInfo {
     Double(f64),
     NameAndTuple { name_index: u16, descriptor_index: u16 },
     Utf8(String),
-    MethodHandle,
-    MethodType,
-    Dynamic,
-    InvokeDynamic,
-    Module,
-    Package,
+    MethodHandle { reference_kind: u8, reference_index: u16 },
+    MethodType { descriptor_index: u16 },
+    Dynamic { bootstrap_method_attr_index: u16, name_and_type_index: u16 },
+    InvokeDynamic { bootstrap_method_attr_index: u16, name_and_type_index: u16 },
+    Module { name_index: u16 },
+    Package { name_index: u16 },
+}
+
+/// Symbolic reference to a field or method, §5.1.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MemberRef {
+    /// Name of the class or interface in internal form
+    pub(crate) class: String,
+    pub(crate) name: String,
+    pub(crate) descriptor: String,
+    /// `Class.name:descriptor`, the key methods are stored under in the method area
+    pub(crate) key: String,
+}
+
+// §4.4.6
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct NameAndType {
+    pub(crate) name: String,
+    pub(crate) descriptor: String,
 }
 
-// todo: convert vec to array?
+// result of resolving a constant pool entry
 #[derive(Debug)]
-pub(crate) struct ConstPool {
+enum Resolved {
+    Member(MemberRef),
+    NameAndType(NameAndType),
+}
+
+/// §4.4, the constant pool of a class, indexed from 1.
+#[derive(Debug)]
+pub struct ConstPool {
     value: Vec<CpInfo>,
-    cache: HashMap<u16, CpInfo>,
+    // §5.1, the run-time constant pool; each entry is resolved at most once
+    cache: Vec<OnceCell<Resolved>>,
 }
 
 impl ConstPool {
-    pub(crate) fn resolve_utf8(&self, index: u16) -> &str {
-        return match self.value.get(index as usize).unwrap() {
-            CpInfo::Utf8(v) => v,
-            CpInfo::Class { name_index } => self.resolve_utf8(*name_index),
-            CpInfo::NameAndTuple { name_index, descriptor_index } => Box::leak(format!(
-                "{}:{}",// https://stackoverflow.com/a/51286293/6627776
-                self.resolve_utf8(*name_index), self.resolve_utf8(*descriptor_index),
-            ).into_boxed_str()),
-            CpInfo::MethodRef { class_index, name_and_type_index } => Box::leak(format!(
-                "{}.{}",
-                self.resolve_utf8(*class_index), self.resolve_utf8(*name_and_type_index),
-            ).into_boxed_str()),
-            _ => panic!("not supported")
+    /// Name of the class referenced by the `CONSTANT_Class` entry at `index`, §5.4.3.1.
+    pub(crate) fn resolve_class(&self, index: u16) -> Option<&str> {
+        self.class_name(index)
+    }
+
+    /// Method referenced by the `CONSTANT_Methodref` or `CONSTANT_InterfaceMethodref` entry
+    /// at `index`, §5.4.3.3 and §5.4.3.4.
+    pub(crate) fn resolve_method_ref(&self, index: u16) -> Option<&MemberRef> {
+        match self.get(index)? {
+            CpInfo::MethodRef { .. } | CpInfo::InterfaceMethodRef { .. } => self.resolve_member(index),
+            _ => None,
+        }
+    }
+
+    /// Field referenced by the `CONSTANT_Fieldref` entry at `index`, §5.4.3.2.
+    pub(crate) fn resolve_field_ref(&self, index: u16) -> Option<&MemberRef> {
+        match self.get(index)? {
+            CpInfo::FieldRef { .. } => self.resolve_member(index),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn resolve_name_and_type(&self, index: u16) -> Option<&NameAndType> {
+        let (name_index, descriptor_index) = match self.get(index)? {
+            CpInfo::NameAndTuple { name_index, descriptor_index } => (*name_index, *descriptor_index),
+            _ => return None,
+        };
+        let resolved = self.cached(index, || Some(Resolved::NameAndType(NameAndType {
+            name: self.utf8(name_index)?.to_string(),
+            descriptor: self.utf8(descriptor_index)?.to_string(),
+        })))?;
+        match resolved {
+            Resolved::NameAndType(name_and_type) => Some(name_and_type),
+            _ => None,
+        }
+    }
+
+    fn resolve_member(&self, index: u16) -> Option<&MemberRef> {
+        let (class_index, name_and_type_index) = match self.get(index)? {
+            CpInfo::FieldRef { class_index, name_and_type_index }
+            | CpInfo::MethodRef { class_index, name_and_type_index }
+            | CpInfo::InterfaceMethodRef { class_index, name_and_type_index } => (*class_index, *name_and_type_index),
+            _ => return None,
         };
+        let resolved = self.cached(index, || {
+            let class = self.resolve_class(class_index)?;
+            let NameAndType { name, descriptor } = self.resolve_name_and_type(name_and_type_index)?;
+            Some(Resolved::Member(MemberRef {
+                class: class.to_string(),
+                name: name.clone(),
+                descriptor: descriptor.clone(),
+                key: format!("{}.{}:{}", class, name, descriptor),
+            }))
+        })?;
+        match resolved {
+            Resolved::Member(member) => Some(member),
+            _ => None,
+        }
+    }
+
+    // a failed resolution is not cached, it fails the same way every time
+    fn cached(&self, index: u16, resolve: impl FnOnce() -> Option<Resolved>) -> Option<&Resolved> {
+        let cell = self.cache.get(index as usize)?;
+        if let Some(resolved) = cell.get() {
+            return Some(resolved);
+        }
+        let resolved = resolve()?;
+        Some(cell.get_or_init(|| resolved))
+    }
+
+    /// The entry at `index`, `None` for index 0, the second slot of a `Long` or `Double` and
+    /// indices past the end.
+    pub fn get(&self, index: u16) -> Option<&CpInfo> {
+        match self.value.get(index as usize) {
+            Some(CpInfo::Placeholder) | None => None,
+            info => info,
+        }
+    }
+
+    pub fn utf8(&self, index: u16) -> Option<&str> {
+        match self.get(index) {
+            Some(CpInfo::Utf8(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Name of the class referenced by the `CONSTANT_Class` entry at `index`.
+    pub fn class_name(&self, index: u16) -> Option<&str> {
+        match self.get(index) {
+            Some(CpInfo::Class { name_index }) => self.utf8(*name_index),
+            _ => None,
+        }
+    }
+
+    /// All entries including the placeholders, in index order.
+    pub fn entries(&self) -> &[CpInfo] {
+        &self.value
+    }
+
+    /// Index of the first `CONSTANT_Utf8` entry equal to `value`.
+    pub(crate) fn find_utf8(&self, value: &str) -> Option<u16> {
+        self.value.iter().position(|it| matches!(it, CpInfo::Utf8(v) if v == value)).map(|it| it as u16)
+    }
+
+    /// Index of the first `CONSTANT_Class` entry naming `name`.
+    pub(crate) fn find_class(&self, name: &str) -> Option<u16> {
+        (1..self.value.len() as u16).find(|it| self.class_name(*it) == Some(name))
+    }
+
+    /// Names of all classes referenced by `CONSTANT_Class` entries.
+    pub(crate) fn class_names(&self) -> Vec<&str> {
+        self.value.iter()
+            .filter_map(|it| match it {
+                CpInfo::Class { name_index } => self.utf8(*name_index),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn into_entries(self) -> Vec<CpInfo> {
+        self.value
     }
 
     pub(crate) fn from_vec(
         data: Vec<CpInfo>
     ) -> ConstPool {
-        return ConstPool {
+        ConstPool {
+            cache: data.iter().map(|_| OnceCell::new()).collect(),
             value: data,
-            cache: Default::default(),
-        };
+        }
     }
 }
 
@@ -68,8 +197,6 @@ impl ConstPool {
 #[cfg(test)]
 pub mod tests {
     use crate::const_pool::{ConstPool, CpInfo};
-    use crate::method_area::Method;
-    use crate::Opcode;
 
     pub(crate) fn sample_const_pool() -> ConstPool {
         let items = vec![
@@ -94,7 +221,29 @@ pub mod tests {
         ];
         ConstPool::from_vec(items)
     }
-}
 
+    #[test]
+    fn resolve_typed_references() {
+        let const_pool = sample_const_pool();
+        assert_eq!(const_pool.resolve_class(3), Some("Adder"));
+        assert_eq!(const_pool.resolve_class(5), None);
+
+        let method = const_pool.resolve_method_ref(2).unwrap();
+        assert_eq!((method.class.as_str(), method.name.as_str(), method.descriptor.as_str()), ("Adder", "add", "(II)I"));
+        assert_eq!(method.key, "Adder.add:(II)I");
+        assert_eq!(const_pool.resolve_field_ref(2), None);
 
+        let name_and_type = const_pool.resolve_name_and_type(14).unwrap();
+        assert_eq!((name_and_type.name.as_str(), name_and_type.descriptor.as_str()), ("<init>", "()V"));
+        assert_eq!(const_pool.resolve_name_and_type(99), None);
+    }
 
+    #[test]
+    fn resolved_references_are_cached() {
+        let const_pool = sample_const_pool();
+        let first = const_pool.resolve_method_ref(1).unwrap();
+        let second = const_pool.resolve_method_ref(1).unwrap();
+        assert!(std::ptr::eq(first, second));
+        assert_eq!(first.key, "java/lang/Object.<init>:()V");
+    }
+}
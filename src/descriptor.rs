@@ -0,0 +1,177 @@
+use std::fmt::{Display, Formatter};
+use std::str::Chars;
+
+// §4.3.2
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FieldType {
+    Byte,
+    Char,
+    Double,
+    Float,
+    Int,
+    Long,
+    Short,
+    Boolean,
+    // class name in internal form
+    Object(String),
+    Array(Box<FieldType>),
+}
+
+impl FieldType {
+    pub(crate) fn parse(descriptor: &str) -> Option<FieldType> {
+        let mut chars = descriptor.chars();
+        let field_type = read_field_type(&mut chars)?;
+        match chars.next() {
+            None => Some(field_type),
+            Some(_) => None,
+        }
+    }
+
+    /// Number of local variable or operand stack slots a value of this type occupies.
+    pub(crate) fn slot_width(&self) -> usize {
+        match self {
+            FieldType::Long | FieldType::Double => 2,
+            _ => 1,
+        }
+    }
+
+    pub(crate) fn is_reference(&self) -> bool {
+        matches!(self, FieldType::Object(_) | FieldType::Array(_))
+    }
+
+    /// Name of the class of values of this type, as used by `checkcast`.
+    pub(crate) fn class_name(&self) -> Option<String> {
+        match self {
+            FieldType::Object(name) => Some(name.clone()),
+            FieldType::Array(_) => Some(self.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FieldType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldType::Byte => write!(f, "B"),
+            FieldType::Char => write!(f, "C"),
+            FieldType::Double => write!(f, "D"),
+            FieldType::Float => write!(f, "F"),
+            FieldType::Int => write!(f, "I"),
+            FieldType::Long => write!(f, "J"),
+            FieldType::Short => write!(f, "S"),
+            FieldType::Boolean => write!(f, "Z"),
+            FieldType::Object(name) => write!(f, "L{};", name),
+            FieldType::Array(component) => write!(f, "[{}", component),
+        }
+    }
+}
+
+// §4.3.3
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MethodDescriptor {
+    pub(crate) parameters: Vec<FieldType>,
+    // `None` for `V`
+    pub(crate) return_type: Option<FieldType>,
+}
+
+impl MethodDescriptor {
+    pub(crate) fn parse(descriptor: &str) -> Option<MethodDescriptor> {
+        let mut chars = descriptor.chars();
+        if chars.next() != Some('(') {
+            return None;
+        }
+        let mut parameters = Vec::new();
+        loop {
+            if chars.as_str().starts_with(')') {
+                chars.next();
+                break;
+            }
+            parameters.push(read_field_type(&mut chars)?);
+        }
+        let return_type = if chars.as_str() == "V" {
+            None
+        } else {
+            Some(FieldType::parse(chars.as_str())?)
+        };
+        Some(MethodDescriptor { parameters, return_type })
+    }
+
+    /// Slots the parameters take up, not counting `this`.
+    pub(crate) fn parameter_slots(&self) -> usize {
+        self.parameters.iter().map(|it| it.slot_width()).sum()
+    }
+}
+
+fn read_field_type(chars: &mut Chars) -> Option<FieldType> {
+    let field_type = match chars.next()? {
+        'B' => FieldType::Byte,
+        'C' => FieldType::Char,
+        'D' => FieldType::Double,
+        'F' => FieldType::Float,
+        'I' => FieldType::Int,
+        'J' => FieldType::Long,
+        'S' => FieldType::Short,
+        'Z' => FieldType::Boolean,
+        'L' => {
+            let rest = chars.as_str();
+            let end = rest.find(';')?;
+            if end == 0 {
+                return None;
+            }
+            let name = rest[..end].to_string();
+            *chars = rest[end + 1..].chars();
+            FieldType::Object(name)
+        }
+        '[' => FieldType::Array(Box::new(read_field_type(chars)?)),
+        _ => return None,
+    };
+    Some(field_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::descriptor::{FieldType, MethodDescriptor};
+
+    #[test]
+    fn parse_field_types() {
+        assert_eq!(FieldType::parse("I"), Some(FieldType::Int));
+        assert_eq!(FieldType::parse("Ljava/lang/String;"), Some(FieldType::Object("java/lang/String".to_string())));
+        assert_eq!(FieldType::parse("[[J"), Some(FieldType::Array(Box::new(FieldType::Array(Box::new(FieldType::Long))))));
+        assert_eq!(FieldType::parse("II"), None);
+        assert_eq!(FieldType::parse("L;"), None);
+        assert_eq!(FieldType::parse("Ljava/lang/String"), None);
+        assert_eq!(FieldType::parse("V"), None);
+    }
+
+    #[test]
+    fn field_type_round_trips_to_descriptor() {
+        for descriptor in ["Z", "[Ljava/lang/Object;", "[[D"].iter() {
+            assert_eq!(FieldType::parse(descriptor).unwrap().to_string(), *descriptor);
+        }
+    }
+
+    #[test]
+    fn parse_method_descriptor() {
+        let descriptor = MethodDescriptor::parse("(IDLjava/lang/Thread;)Ljava/lang/Object;").unwrap();
+        assert_eq!(descriptor.parameters, vec![
+            FieldType::Int,
+            FieldType::Double,
+            FieldType::Object("java/lang/Thread".to_string()),
+        ]);
+        assert_eq!(descriptor.return_type, Some(FieldType::Object("java/lang/Object".to_string())));
+        assert_eq!(descriptor.parameter_slots(), 4);
+
+        let descriptor = MethodDescriptor::parse("()V").unwrap();
+        assert!(descriptor.parameters.is_empty());
+        assert_eq!(descriptor.return_type, None);
+    }
+
+    #[test]
+    fn reject_malformed_method_descriptors() {
+        assert_eq!(MethodDescriptor::parse("I)V"), None);
+        assert_eq!(MethodDescriptor::parse("(I"), None);
+        assert_eq!(MethodDescriptor::parse("(V)V"), None);
+        assert_eq!(MethodDescriptor::parse("()"), None);
+        assert_eq!(MethodDescriptor::parse("()VV"), None);
+    }
+}
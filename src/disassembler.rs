@@ -0,0 +1,1093 @@
+use std::fmt::Display;
+
+use crate::const_pool::{ConstPool, CpInfo};
+use crate::descriptor::{FieldType, MethodDescriptor};
+use crate::Opcode;
+use crate::parser::{Attribute, ClassFile, ExceptionTableEntry, FieldInfo, MethodInfo};
+use crate::parser::attribute::{
+    Annotation, ElementValue, LocalVariable, StackMapFrame, TargetInfo, TypeAnnotation, VerificationTypeInfo,
+};
+use crate::writer::CodeOffsets;
+
+/// Renders a class file in the format of `javap -c -v`, so the two can be diffed.
+///
+/// The output starts at the class declaration; the file name, size and checksum header
+/// javap prints first is left to the caller. Declarations use the erased descriptor types
+/// even when a `Signature` attribute is present.
+///
+/// ```
+/// let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class")).unwrap();
+/// let text = rj::disassembler::disassemble(&rj::parser::parse_bytes(&bytes).unwrap());
+/// assert!(text.contains("         2: invokestatic  #7                  // Method add:(II)I\n"));
+/// ```
+pub fn disassemble(class_file: &ClassFile) -> String {
+    let mut printer = Printer { out: String::new(), const_pool: &class_file.const_pool, this_class: &class_file.this_class };
+    printer.class_file(class_file);
+    printer.out
+}
+
+const CLASS_FLAGS: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0020, "ACC_SUPER"),
+    (0x0200, "ACC_INTERFACE"),
+    (0x0400, "ACC_ABSTRACT"),
+    (0x1000, "ACC_SYNTHETIC"),
+    (0x2000, "ACC_ANNOTATION"),
+    (0x4000, "ACC_ENUM"),
+    (0x8000, "ACC_MODULE"),
+];
+
+const FIELD_FLAGS: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0002, "ACC_PRIVATE"),
+    (0x0004, "ACC_PROTECTED"),
+    (0x0008, "ACC_STATIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0040, "ACC_VOLATILE"),
+    (0x0080, "ACC_TRANSIENT"),
+    (0x1000, "ACC_SYNTHETIC"),
+    (0x4000, "ACC_ENUM"),
+];
+
+const METHOD_FLAGS: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0002, "ACC_PRIVATE"),
+    (0x0004, "ACC_PROTECTED"),
+    (0x0008, "ACC_STATIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0020, "ACC_SYNCHRONIZED"),
+    (0x0040, "ACC_BRIDGE"),
+    (0x0080, "ACC_VARARGS"),
+    (0x0100, "ACC_NATIVE"),
+    (0x0400, "ACC_ABSTRACT"),
+    (0x0800, "ACC_STRICT"),
+    (0x1000, "ACC_SYNTHETIC"),
+];
+
+// the flags javap spells as modifiers in declarations
+const CLASS_MODIFIERS: &[(u16, &str)] = &[(0x0001, "public"), (0x0010, "final"), (0x0400, "abstract")];
+
+const FIELD_MODIFIERS: &[(u16, &str)] = &[
+    (0x0001, "public"),
+    (0x0002, "private"),
+    (0x0004, "protected"),
+    (0x0008, "static"),
+    (0x0010, "final"),
+    (0x0040, "volatile"),
+    (0x0080, "transient"),
+];
+
+const METHOD_MODIFIERS: &[(u16, &str)] = &[
+    (0x0001, "public"),
+    (0x0002, "private"),
+    (0x0004, "protected"),
+    (0x0008, "static"),
+    (0x0010, "final"),
+    (0x0020, "synchronized"),
+    (0x0100, "native"),
+    (0x0400, "abstract"),
+    (0x0800, "strictfp"),
+];
+
+const INNER_CLASS_MODIFIERS: &[(u16, &str)] = &[
+    (0x0001, "public"),
+    (0x0002, "private"),
+    (0x0004, "protected"),
+    (0x0008, "static"),
+    (0x0010, "final"),
+    (0x0200, "interface"),
+    (0x0400, "abstract"),
+];
+
+const PARAMETER_FLAGS: &[(u16, &str)] = &[(0x0010, "final"), (0x1000, "synthetic"), (0x8000, "mandated")];
+
+fn flag_names(flags: u16, names: &[(u16, &'static str)]) -> Vec<&'static str> {
+    names.iter().filter(|(flag, _)| flags & flag != 0).map(|(_, name)| *name).collect()
+}
+
+// `flags: (0x0021) ACC_PUBLIC, ACC_SUPER`
+fn flags_line(flags: u16, names: &[(u16, &'static str)]) -> String {
+    let names = flag_names(flags, names);
+    if names.is_empty() {
+        format!("flags: (0x{:04x})", flags)
+    } else {
+        format!("flags: (0x{:04x}) {}", flags, names.join(", "))
+    }
+}
+
+fn modifiers(flags: u16, names: &[(u16, &'static str)]) -> String {
+    flag_names(flags, names).iter().map(|it| format!("{} ", it)).collect()
+}
+
+/// `java.lang.String[]` for `[Ljava/lang/String;`
+fn java_type(field_type: &FieldType) -> String {
+    match field_type {
+        FieldType::Byte => "byte".to_string(),
+        FieldType::Char => "char".to_string(),
+        FieldType::Double => "double".to_string(),
+        FieldType::Float => "float".to_string(),
+        FieldType::Int => "int".to_string(),
+        FieldType::Long => "long".to_string(),
+        FieldType::Short => "short".to_string(),
+        FieldType::Boolean => "boolean".to_string(),
+        FieldType::Object(name) => name.replace('/', "."),
+        FieldType::Array(component) => format!("{}[]", java_type(component)),
+    }
+}
+
+fn java_field_type(descriptor: &str) -> String {
+    FieldType::parse(descriptor).map_or_else(|| descriptor.to_string(), |it| java_type(&it))
+}
+
+/// Quotes names that are not plain identifiers, such as `"<init>"` and `"[I"`.
+fn quote(name: &str) -> String {
+    let plain = name.chars().next().is_some_and(|it| !it.is_ascii_digit())
+        && name.chars().all(|it| it.is_alphanumeric() || it == '_' || it == '$' || it == '/');
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+/// Escapes a string constant the way javap prints it.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats like `Double.toString`, which switches to scientific notation outside `[1e-3, 1e7)`.
+fn java_double(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(1e-3..1e7).contains(&magnitude) {
+        let formatted = format!("{:e}", value);
+        let (mantissa, exponent) = formatted.split_at(formatted.find('e').unwrap());
+        let mantissa = if mantissa.contains('.') { mantissa.to_string() } else { format!("{}.0", mantissa) };
+        format!("{}E{}", mantissa, &exponent[1..])
+    } else if value.fract() == 0.0 {
+        format!("{:.1}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+fn java_float(value: f32) -> String {
+    // shortest representation of the f32, not of its widened f64
+    let shortest: f64 = value.to_string().parse().unwrap_or(value as f64);
+    java_double(shortest)
+}
+
+fn reference_kind(kind: u8) -> &'static str {
+    match kind {
+        1 => "REF_getField",
+        2 => "REF_getStatic",
+        3 => "REF_putField",
+        4 => "REF_putStatic",
+        5 => "REF_invokeVirtual",
+        6 => "REF_invokeStatic",
+        7 => "REF_invokeSpecial",
+        8 => "REF_newInvokeSpecial",
+        9 => "REF_invokeInterface",
+        _ => "REF_unknown",
+    }
+}
+
+fn target_type(target_type: u8) -> &'static str {
+    match target_type {
+        0x00 => "CLASS_TYPE_PARAMETER",
+        0x01 => "METHOD_TYPE_PARAMETER",
+        0x10 => "CLASS_EXTENDS",
+        0x11 => "CLASS_TYPE_PARAMETER_BOUND",
+        0x12 => "METHOD_TYPE_PARAMETER_BOUND",
+        0x13 => "FIELD",
+        0x14 => "METHOD_RETURN",
+        0x15 => "METHOD_RECEIVER",
+        0x16 => "METHOD_FORMAL_PARAMETER",
+        0x17 => "THROWS",
+        0x40 => "LOCAL_VARIABLE",
+        0x41 => "RESOURCE_VARIABLE",
+        0x42 => "EXCEPTION_PARAMETER",
+        0x43 => "INSTANCEOF",
+        0x44 => "NEW",
+        0x45 => "CONSTRUCTOR_REFERENCE",
+        0x46 => "METHOD_REFERENCE",
+        0x47 => "CAST",
+        0x48 => "CONSTRUCTOR_INVOCATION_TYPE_ARGUMENT",
+        0x49 => "METHOD_INVOCATION_TYPE_ARGUMENT",
+        0x4A => "CONSTRUCTOR_REFERENCE_TYPE_ARGUMENT",
+        0x4B => "METHOD_REFERENCE_TYPE_ARGUMENT",
+        _ => "UNKNOWN",
+    }
+}
+
+pub(crate) fn array_type(atype: u8) -> &'static str {
+    match atype {
+        4 => "boolean",
+        5 => "char",
+        6 => "float",
+        7 => "double",
+        8 => "byte",
+        9 => "short",
+        10 => "int",
+        11 => "long",
+        _ => "unknown",
+    }
+}
+
+struct Printer<'a> {
+    out: String,
+    const_pool: &'a ConstPool,
+    this_class: &'a str,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, indent: usize, text: impl Display) {
+        let text = text.to_string();
+        for _ in 0..indent {
+            self.out.push(' ');
+        }
+        self.out.push_str(&text);
+        self.out.push('\n');
+    }
+
+    // javap lines up `//` comments at column 40 past the indent
+    fn commented(&mut self, indent: usize, text: impl Display, comment: impl Display) {
+        self.line(indent, format!("{:<40}// {}", text.to_string(), comment));
+    }
+
+    fn utf8(&self, index: u16) -> &str {
+        self.const_pool.utf8(index).unwrap_or("")
+    }
+
+    fn class_name(&self, index: u16) -> &str {
+        self.const_pool.class_name(index).unwrap_or("")
+    }
+
+    fn class_file(&mut self, class_file: &ClassFile) {
+        self.line(0, self.declaration(class_file));
+        self.line(2, format!("minor version: {}", class_file.minor));
+        self.line(2, format!("major version: {}", class_file.major));
+        self.line(2, flags_line(class_file.access_flags, CLASS_FLAGS));
+        let this_index = self.const_pool.find_class(&class_file.this_class).unwrap_or(0);
+        self.commented(2, format!("this_class: #{}", this_index), quote(&class_file.this_class));
+        match &class_file.super_class {
+            Some(name) => {
+                let super_index = self.const_pool.find_class(name).unwrap_or(0);
+                self.commented(2, format!("super_class: #{}", super_index), quote(name));
+            }
+            None => self.line(2, "super_class: #0"),
+        }
+        self.line(2, format!(
+            "interfaces: {}, fields: {}, methods: {}, attributes: {}",
+            class_file.interfaces.len(), class_file.fields.len(), class_file.methods.len(), class_file.attributes.len(),
+        ));
+        self.constant_pool();
+
+        self.line(0, "{");
+        let mut first = true;
+        for field in &class_file.fields {
+            if !first {
+                self.line(0, "");
+            }
+            first = false;
+            self.field(field);
+        }
+        for method in &class_file.methods {
+            if !first {
+                self.line(0, "");
+            }
+            first = false;
+            self.method(method);
+        }
+        self.line(0, "}");
+        self.attributes(0, &class_file.attributes, None);
+    }
+
+    fn declaration(&self, class_file: &ClassFile) -> String {
+        let is_interface = class_file.access_flags & 0x0200 != 0;
+        let name = class_file.this_class.replace('/', ".");
+        let interfaces = class_file.interfaces.iter().map(|it| it.replace('/', ".")).collect::<Vec<_>>().join(",");
+        if is_interface {
+            let mut declaration = format!("{}interface {}", modifiers(class_file.access_flags & 0x0001, CLASS_MODIFIERS), name);
+            if !interfaces.is_empty() {
+                declaration.push_str(&format!(" extends {}", interfaces));
+            }
+            declaration
+        } else {
+            let mut declaration = format!("{}class {}", modifiers(class_file.access_flags, CLASS_MODIFIERS), name);
+            match class_file.super_class.as_deref() {
+                Some("java/lang/Object") | None => {}
+                Some(name) => declaration.push_str(&format!(" extends {}", name.replace('/', "."))),
+            }
+            if !interfaces.is_empty() {
+                declaration.push_str(&format!(" implements {}", interfaces));
+            }
+            declaration
+        }
+    }
+
+    fn constant_pool(&mut self) {
+        self.line(0, "Constant pool:");
+        for (index, entry) in self.const_pool.entries().iter().enumerate() {
+            let (kind, value, comment) = match self.constant(entry) {
+                Some(it) => it,
+                None => continue,
+            };
+            let number = format!("#{}", index);
+            match comment {
+                Some(comment) => self.line(0, format!("{:>5} = {:<18} {:<14} // {}", number, kind, value, comment)),
+                None => self.line(0, format!("{:>5} = {:<18} {}", number, kind, value)),
+            }
+        }
+    }
+
+    // (kind, value, comment) of a constant pool entry
+    fn constant(&self, entry: &CpInfo) -> Option<(&'static str, String, Option<String>)> {
+        let constant = match entry {
+            CpInfo::Placeholder => return None,
+            CpInfo::Class { name_index } => ("Class", format!("#{}", name_index), Some(quote(self.utf8(*name_index)))),
+            CpInfo::FieldRef { class_index, name_and_type_index } => {
+                ("Fieldref", format!("#{}.#{}", class_index, name_and_type_index), Some(self.member(*class_index, *name_and_type_index)))
+            }
+            CpInfo::MethodRef { class_index, name_and_type_index } => {
+                ("Methodref", format!("#{}.#{}", class_index, name_and_type_index), Some(self.member(*class_index, *name_and_type_index)))
+            }
+            CpInfo::InterfaceMethodRef { class_index, name_and_type_index } => (
+                "InterfaceMethodref",
+                format!("#{}.#{}", class_index, name_and_type_index),
+                Some(self.member(*class_index, *name_and_type_index)),
+            ),
+            CpInfo::String { string_index } => ("String", format!("#{}", string_index), Some(escape(self.utf8(*string_index)))),
+            CpInfo::Integer(value) => ("Integer", value.to_string(), None),
+            CpInfo::Float(value) => ("Float", format!("{}f", java_float(*value)), None),
+            CpInfo::Long(value) => ("Long", format!("{}l", value), None),
+            CpInfo::Double(value) => ("Double", format!("{}d", java_double(*value)), None),
+            CpInfo::NameAndTuple { name_index, descriptor_index } => (
+                "NameAndType",
+                format!("#{}:#{}", name_index, descriptor_index),
+                Some(format!("{}:{}", quote(self.utf8(*name_index)), self.utf8(*descriptor_index))),
+            ),
+            CpInfo::Utf8(value) => ("Utf8", escape(value), None),
+            CpInfo::MethodHandle { reference_kind: kind, reference_index } => (
+                "MethodHandle",
+                format!("{}:#{}", kind, reference_index),
+                Some(format!("{} {}", reference_kind(*kind), self.reference(*reference_index))),
+            ),
+            CpInfo::MethodType { descriptor_index } => {
+                ("MethodType", format!("#{}", descriptor_index), Some(self.utf8(*descriptor_index).to_string()))
+            }
+            CpInfo::Dynamic { bootstrap_method_attr_index, name_and_type_index } => (
+                "Dynamic",
+                format!("#{}:#{}", bootstrap_method_attr_index, name_and_type_index),
+                Some(format!("#{}:{}", bootstrap_method_attr_index, self.name_and_type(*name_and_type_index))),
+            ),
+            CpInfo::InvokeDynamic { bootstrap_method_attr_index, name_and_type_index } => (
+                "InvokeDynamic",
+                format!("#{}:#{}", bootstrap_method_attr_index, name_and_type_index),
+                Some(format!("#{}:{}", bootstrap_method_attr_index, self.name_and_type(*name_and_type_index))),
+            ),
+            CpInfo::Module { name_index } => ("Module", format!("#{}", name_index), Some(quote(self.utf8(*name_index)))),
+            CpInfo::Package { name_index } => ("Package", format!("#{}", name_index), Some(quote(self.utf8(*name_index)))),
+        };
+        Some(constant)
+    }
+
+    fn name_and_type(&self, index: u16) -> String {
+        match self.const_pool.resolve_name_and_type(index) {
+            Some(it) => format!("{}:{}", quote(&it.name), it.descriptor),
+            None => format!("#{}", index),
+        }
+    }
+
+    // `java/lang/Object."<init>":()V`
+    fn member(&self, class_index: u16, name_and_type_index: u16) -> String {
+        format!("{}.{}", quote(self.class_name(class_index)), self.name_and_type(name_and_type_index))
+    }
+
+    fn reference(&self, index: u16) -> String {
+        match self.const_pool.get(index) {
+            Some(CpInfo::FieldRef { class_index, name_and_type_index })
+            | Some(CpInfo::MethodRef { class_index, name_and_type_index })
+            | Some(CpInfo::InterfaceMethodRef { class_index, name_and_type_index }) => {
+                self.member(*class_index, *name_and_type_index)
+            }
+            _ => format!("#{}", index),
+        }
+    }
+
+    // the comment after an instruction operand, which leaves out the class for own members
+    fn operand_comment(&self, index: u16) -> String {
+        let member = |kind: &str, class_index: u16, name_and_type_index: u16| {
+            let class = self.class_name(class_index);
+            if class == self.this_class {
+                format!("{} {}", kind, self.name_and_type(name_and_type_index))
+            } else {
+                format!("{} {}", kind, self.member(class_index, name_and_type_index))
+            }
+        };
+        match self.const_pool.get(index) {
+            Some(CpInfo::FieldRef { class_index, name_and_type_index }) => member("Field", *class_index, *name_and_type_index),
+            Some(CpInfo::MethodRef { class_index, name_and_type_index }) => member("Method", *class_index, *name_and_type_index),
+            Some(CpInfo::InterfaceMethodRef { class_index, name_and_type_index }) => {
+                member("InterfaceMethod", *class_index, *name_and_type_index)
+            }
+            Some(CpInfo::Class { name_index }) => format!("class {}", quote(self.utf8(*name_index))),
+            Some(entry) => match self.constant(entry) {
+                // javap names loaded constants by the type they push
+                Some(("Integer", value, _)) => format!("int {}", value),
+                Some(("String", value, comment)) => format!("String {}", comment.unwrap_or(value)),
+                Some((kind, value, comment)) => format!("{} {}", kind.to_lowercase(), comment.unwrap_or(value)),
+                None => String::new(),
+            },
+            None => String::new(),
+        }
+    }
+
+    fn field(&mut self, field: &FieldInfo) {
+        let name = self.utf8(field.name_index).to_string();
+        let descriptor = self.utf8(field.descriptor_index).to_string();
+        self.line(2, format!("{}{} {};", modifiers(field.access_flags, FIELD_MODIFIERS), java_field_type(&descriptor), name));
+        self.line(4, format!("descriptor: {}", descriptor));
+        self.line(4, flags_line(field.access_flags, FIELD_FLAGS));
+        self.attributes(4, &field.attributes, None);
+    }
+
+    fn method(&mut self, method: &MethodInfo) {
+        let name = self.utf8(method.name_index).to_string();
+        let descriptor = self.utf8(method.descriptor_index).to_string();
+        let parsed = MethodDescriptor::parse(&descriptor);
+        let declaration = if name == "<clinit>" {
+            "static {}".to_string()
+        } else {
+            let parameters = parsed.as_ref()
+                .map(|it| it.parameters.iter().map(java_type).collect::<Vec<_>>().join(", "))
+                .unwrap_or_default();
+            let mut declaration = modifiers(method.access_flags, METHOD_MODIFIERS);
+            if name == "<init>" {
+                declaration.push_str(&self.this_class.replace('/', "."));
+            } else {
+                let return_type = parsed.as_ref()
+                    .and_then(|it| it.return_type.as_ref())
+                    .map_or_else(|| "void".to_string(), java_type);
+                declaration.push_str(&format!("{} {}", return_type, name));
+            }
+            declaration.push_str(&format!("({})", parameters));
+            let throws = method.attributes.iter().find_map(|it| match it {
+                Attribute::Exceptions(indices) => Some(indices),
+                _ => None,
+            });
+            if let Some(indices) = throws {
+                let names = indices.iter().map(|it| self.class_name(*it).replace('/', ".")).collect::<Vec<_>>();
+                declaration.push_str(&format!(" throws {}", names.join(", ")));
+            }
+            declaration
+        };
+        self.line(2, format!("{};", declaration));
+        self.line(4, format!("descriptor: {}", descriptor));
+        self.line(4, flags_line(method.access_flags, METHOD_FLAGS));
+
+        let is_static = method.access_flags & 0x0008 != 0;
+        // javap counts parameters here, not the slots they take
+        let args_size = !is_static as usize + parsed.map_or(0, |it| it.parameters.len());
+        for attribute in &method.attributes {
+            match attribute {
+                Attribute::Code { max_stack, max_locals, codes, exception_table, attributes } => {
+                    self.line(4, "Code:");
+                    self.line(6, format!("stack={}, locals={}, args_size={}", max_stack, max_locals, args_size));
+                    self.code(codes, exception_table, attributes);
+                }
+                attribute => self.attribute(4, attribute, None),
+            }
+        }
+    }
+
+    fn code(&mut self, codes: &[Opcode], exception_table: &[ExceptionTableEntry], attributes: &[Attribute]) {
+        let offsets = CodeOffsets::of(codes);
+        for (index, code) in codes.iter().enumerate() {
+            let pc = offsets.pc(index);
+            let indexed = |name: &str, operand: u16| {
+                format!("{:<34}// {}", format!("{:<13} #{}", name, operand), self.operand_comment(operand))
+            };
+            let text = match code {
+                Opcode::bipush(value) => format!("{:<13} {}", "bipush", value),
+                Opcode::ldc(index) if *index <= u8::MAX as u16 => indexed("ldc", *index),
+                Opcode::ldc(index) => indexed("ldc_w", *index),
+                Opcode::iload(local) => format!("{:<13} {}", "iload", local),
+                Opcode::aload(local) => format!("{:<13} {}", "aload", local),
+                Opcode::istore(local) => format!("{:<13} {}", "istore", local),
+                Opcode::astore(local) => format!("{:<13} {}", "astore", local),
+                Opcode::iinc(local, value) => format!("{:<13} {}, {}", "iinc", local, value),
+                Opcode::goto(target) => format!("{:<13} {}", "goto", offsets.pc(*target)),
+                Opcode::jsr(target) => format!("{:<13} {}", "jsr", offsets.pc(*target)),
+                Opcode::ret(local) => format!("{:<13} {}", "ret", local),
+                Opcode::ifeq(target) => format!("{:<13} {}", "ifeq", offsets.pc(*target)),
+                Opcode::ifne(target) => format!("{:<13} {}", "ifne", offsets.pc(*target)),
+                Opcode::if_icmplt(target) => format!("{:<13} {}", "if_icmplt", offsets.pc(*target)),
+                Opcode::getstatic(index) => indexed("getstatic", *index),
+                Opcode::putstatic(index) => indexed("putstatic", *index),
+                Opcode::getfield(index) => indexed("getfield", *index),
+                Opcode::putfield(index) => indexed("putfield", *index),
+                Opcode::invokevirtual(index) => indexed("invokevirtual", *index),
+                Opcode::invokespecial(index) => indexed("invokespecial", *index),
+                Opcode::invokestatic(index) => indexed("invokestatic", *index as u16),
+                Opcode::invokeinterface(index, count) => format!(
+                    "{:<34}// {}", format!("{:<13} #{},  {}", "invokeinterface", index, count), self.operand_comment(*index)),
+                Opcode::new(index) => indexed("new", *index),
+                Opcode::anewarray(index) => indexed("anewarray", *index),
+                Opcode::checkcast(index) => indexed("checkcast", *index),
+                Opcode::instanceof(index) => indexed("instanceof", *index),
+                // javap pads this one operand a column further
+                Opcode::newarray(atype) => format!("{:<14} {}", "newarray", array_type(*atype)),
+                Opcode::r#return => "return".to_string(),
+                code => format!("{:?}", code),
+            };
+            self.line(0, format!("{:>10}: {}", pc, text));
+        }
+
+        if !exception_table.is_empty() {
+            self.line(6, "Exception table:");
+            self.line(9, "from    to  target type");
+            for entry in exception_table {
+                let catch_type = match entry.catch_type {
+                    0 => "any".to_string(),
+                    index => format!("Class {}", self.class_name(index)),
+                };
+                self.line(0, format!(
+                    "{:>14}{:>6}{:>6}   {}",
+                    offsets.pc(entry.start_pc), offsets.pc(entry.end_pc), offsets.pc(entry.handler_pc), catch_type,
+                ));
+            }
+        }
+        self.attributes(6, attributes, Some(&offsets));
+    }
+
+    fn stack_map_frame(&mut self, indent: usize, frame: &StackMapFrame, delta: u16, code: &CodeOffsets) {
+        let frame_type = frame.frame_type(delta);
+        let kind = match frame_type {
+            0..=63 => "same",
+            64..=127 => "same_locals_1_stack_item",
+            247 => "same_locals_1_stack_item_frame_extended",
+            248..=250 => "chop",
+            251 => "same_frame_extended",
+            252..=254 => "append",
+            _ => "full_frame",
+        };
+        self.line(indent, format!("frame_type = {} /* {} */", frame_type, kind));
+        if frame_type >= 247 {
+            self.line(indent + 2, format!("offset_delta = {}", delta));
+        }
+        match frame {
+            StackMapFrame::Same { .. } | StackMapFrame::Chop { .. } => {}
+            StackMapFrame::SameLocals1StackItem { stack, .. } => {
+                self.line(indent + 2, format!("stack = {}", self.verification_types(&[*stack], code)));
+            }
+            StackMapFrame::Append { locals, .. } => {
+                self.line(indent + 2, format!("locals = {}", self.verification_types(locals, code)));
+            }
+            StackMapFrame::Full { locals, stack, .. } => {
+                self.line(indent + 2, format!("locals = {}", self.verification_types(locals, code)));
+                self.line(indent + 2, format!("stack = {}", self.verification_types(stack, code)));
+            }
+        }
+    }
+
+    fn verification_types(&self, types: &[VerificationTypeInfo], code: &CodeOffsets) -> String {
+        let names = types.iter().map(|info| match info {
+            VerificationTypeInfo::Top => "top".to_string(),
+            VerificationTypeInfo::Integer => "int".to_string(),
+            VerificationTypeInfo::Float => "float".to_string(),
+            VerificationTypeInfo::Double => "double".to_string(),
+            VerificationTypeInfo::Long => "long".to_string(),
+            VerificationTypeInfo::Null => "null".to_string(),
+            VerificationTypeInfo::UninitializedThis => "this".to_string(),
+            VerificationTypeInfo::Object(index) => format!("class {}", quote(self.class_name(*index))),
+            VerificationTypeInfo::Uninitialized(offset) => format!("uninitialized {}", code.pc(*offset)),
+        }).collect::<Vec<_>>();
+        if names.is_empty() {
+            return "[]".to_string();
+        }
+        format!("[ {} ]", names.join(", "))
+    }
+
+    fn attributes(&mut self, indent: usize, attributes: &[Attribute], code: Option<&CodeOffsets>) {
+        for attribute in attributes {
+            self.attribute(indent, attribute, code);
+        }
+    }
+
+    fn attribute(&mut self, indent: usize, attribute: &Attribute, code: Option<&CodeOffsets>) {
+        match attribute {
+            Attribute::ConstantValue { constantvalue_index } => {
+                let value = match self.const_pool.get(*constantvalue_index) {
+                    Some(CpInfo::Integer(value)) => format!("int {}", value),
+                    Some(CpInfo::Float(value)) => format!("float {}f", java_float(*value)),
+                    Some(CpInfo::Long(value)) => format!("long {}l", value),
+                    Some(CpInfo::Double(value)) => format!("double {}d", java_double(*value)),
+                    Some(CpInfo::String { string_index }) => format!("String {}", escape(self.utf8(*string_index))),
+                    _ => format!("#{}", constantvalue_index),
+                };
+                self.line(indent, format!("ConstantValue: {}", value));
+            }
+            Attribute::Code { max_stack, max_locals, codes, exception_table, attributes } => {
+                // only reached for a Code attribute outside of a method
+                self.line(indent, "Code:");
+                self.line(indent + 2, format!("stack={}, locals={}", max_stack, max_locals));
+                self.code(codes, exception_table, attributes);
+            }
+            Attribute::StackMapTable(frames) => {
+                let code = code.expect("StackMapTable outside of Code");
+                self.line(indent, format!("StackMapTable: number_of_entries = {}", frames.len()));
+                let mut previous = None;
+                for frame in frames {
+                    let pc = code.pc(frame.offset());
+                    let delta = previous.map_or(pc, |previous| pc - previous - 1);
+                    previous = Some(pc);
+                    self.stack_map_frame(indent + 2, frame, delta, code);
+                }
+            }
+            Attribute::Exceptions(indices) => {
+                self.line(indent, "Exceptions:");
+                let names = indices.iter().map(|it| self.class_name(*it).replace('/', ".")).collect::<Vec<_>>();
+                self.line(indent + 2, format!("throws {}", names.join(", ")));
+            }
+            Attribute::InnerClasses(classes) => {
+                self.line(indent, "InnerClasses:");
+                for class in classes {
+                    let mut text = modifiers(class.inner_class_access_flags, INNER_CLASS_MODIFIERS);
+                    let mut comment = String::new();
+                    if class.inner_name_index != 0 {
+                        text.push_str(&format!("#{}= ", class.inner_name_index));
+                        comment.push_str(&format!("{}=", self.utf8(class.inner_name_index)));
+                    }
+                    text.push_str(&format!("#{}", class.inner_class_info_index));
+                    comment.push_str(&format!("class {}", quote(self.class_name(class.inner_class_info_index))));
+                    if class.outer_class_info_index != 0 {
+                        text.push_str(&format!(" of #{}", class.outer_class_info_index));
+                        comment.push_str(&format!(" of class {}", quote(self.class_name(class.outer_class_info_index))));
+                    }
+                    text.push(';');
+                    self.commented(indent + 2, text, comment);
+                }
+            }
+            Attribute::EnclosingMethod { class_index, method_index } => {
+                let mut comment = quote(self.class_name(*class_index));
+                if *method_index != 0 {
+                    comment.push_str(&format!(".{}", self.name_and_type(*method_index)));
+                }
+                self.commented(indent, format!("EnclosingMethod: #{}.#{}", class_index, method_index), comment);
+            }
+            Attribute::Synthetic => self.line(indent, "Synthetic: true"),
+            Attribute::Deprecated => self.line(indent, "Deprecated: true"),
+            Attribute::Signature { signature_index } => {
+                let signature = self.utf8(*signature_index).to_string();
+                self.commented(indent, format!("Signature: #{}", signature_index), signature);
+            }
+            Attribute::SourceFile { sourcefile_index } => {
+                self.line(indent, format!("SourceFile: \"{}\"", self.utf8(*sourcefile_index)));
+            }
+            Attribute::SourceDebugExtension(info) => {
+                self.line(indent, "SourceDebugExtension:");
+                for line in String::from_utf8_lossy(info).lines() {
+                    self.line(indent + 2, line);
+                }
+            }
+            Attribute::LineNumberTable(lines) => {
+                self.line(indent, "LineNumberTable:");
+                for line in lines {
+                    let pc = code.map_or(line.start_pc as u16, |it| it.pc(line.start_pc));
+                    self.line(indent + 2, format!("line {}: {}", line.line_number, pc));
+                }
+            }
+            Attribute::LocalVariableTable(variables) => self.local_variables(indent, "LocalVariableTable", variables, code),
+            Attribute::LocalVariableTypeTable(variables) => {
+                self.local_variables(indent, "LocalVariableTypeTable", variables, code)
+            }
+            Attribute::RuntimeVisibleAnnotations(annotations) => {
+                self.annotations(indent, "RuntimeVisibleAnnotations", annotations)
+            }
+            Attribute::RuntimeInvisibleAnnotations(annotations) => {
+                self.annotations(indent, "RuntimeInvisibleAnnotations", annotations)
+            }
+            Attribute::RuntimeVisibleParameterAnnotations(parameters) => {
+                self.parameter_annotations(indent, "RuntimeVisibleParameterAnnotations", parameters)
+            }
+            Attribute::RuntimeInvisibleParameterAnnotations(parameters) => {
+                self.parameter_annotations(indent, "RuntimeInvisibleParameterAnnotations", parameters)
+            }
+            Attribute::RuntimeVisibleTypeAnnotations(annotations) => {
+                self.type_annotations(indent, "RuntimeVisibleTypeAnnotations", annotations, code)
+            }
+            Attribute::RuntimeInvisibleTypeAnnotations(annotations) => {
+                self.type_annotations(indent, "RuntimeInvisibleTypeAnnotations", annotations, code)
+            }
+            Attribute::AnnotationDefault(value) => {
+                self.line(indent, "AnnotationDefault:");
+                self.line(indent + 2, format!("default_value: {}", self.raw_element_value(value)));
+                let pretty = self.element_value(value);
+                self.line(indent + 4, pretty);
+            }
+            Attribute::BootstrapMethods(methods) => {
+                self.line(indent, "BootstrapMethods:");
+                for (i, method) in methods.iter().enumerate() {
+                    let handle = match self.const_pool.get(method.bootstrap_method_ref) {
+                        Some(entry) => self.constant(entry).and_then(|(_, _, comment)| comment).unwrap_or_default(),
+                        None => String::new(),
+                    };
+                    self.line(indent + 2, format!("{}: #{} {}", i, method.bootstrap_method_ref, handle));
+                    self.line(indent + 4, "Method arguments:");
+                    for argument in &method.bootstrap_arguments {
+                        let value = match self.const_pool.get(*argument) {
+                            Some(entry) => self.constant(entry).map(|(_, value, comment)| comment.unwrap_or(value)),
+                            None => None,
+                        };
+                        self.line(indent + 6, format!("#{} {}", argument, value.unwrap_or_default()));
+                    }
+                }
+            }
+            Attribute::MethodParameters(parameters) => {
+                self.line(indent, "MethodParameters:");
+                self.line(indent + 2, format!("{:<30} {}", "Name", "Flags"));
+                for parameter in parameters {
+                    let name = match parameter.name_index {
+                        0 => "<no name>",
+                        index => self.utf8(index),
+                    };
+                    let flags = flag_names(parameter.access_flags, PARAMETER_FLAGS).join(" ");
+                    self.line(indent + 2, format!("{:<30} {}", name, flags).trim_end());
+                }
+            }
+            Attribute::Module(module) => {
+                self.line(indent, "Module:");
+                self.commented(indent + 2, format!("#{},{:x}", module.module_name_index, module.module_flags),
+                    quote(self.utf8(module.module_name_index)));
+                self.line(indent + 2, format!("#{}", module.module_version_index));
+                self.line(indent + 2, module.requires.len());
+                for requires in &module.requires {
+                    self.commented(indent + 4, format!("#{},{:x}", requires.requires_index, requires.requires_flags),
+                        quote(self.utf8_of(requires.requires_index)));
+                }
+                for exports in [&module.exports, &module.opens].iter() {
+                    self.line(indent + 2, exports.len());
+                    for export in exports.iter() {
+                        self.commented(indent + 4, format!("#{},{:x}", export.index, export.flags),
+                            quote(self.utf8_of(export.index)));
+                    }
+                }
+                self.line(indent + 2, module.uses.len());
+                for uses in &module.uses {
+                    self.commented(indent + 4, format!("#{}", uses), quote(self.class_name(*uses)));
+                }
+                self.line(indent + 2, module.provides.len());
+                for provides in &module.provides {
+                    self.commented(indent + 4, format!("#{}", provides.provides_index),
+                        quote(self.class_name(provides.provides_index)));
+                }
+            }
+            Attribute::ModulePackages(indices) => {
+                self.line(indent, "ModulePackages:");
+                for index in indices {
+                    self.commented(indent + 2, format!("#{}", index), quote(self.utf8_of(*index)));
+                }
+            }
+            Attribute::ModuleMainClass { main_class_index } => {
+                let name = quote(self.class_name(*main_class_index));
+                self.commented(indent, format!("ModuleMainClass: #{}", main_class_index), name);
+            }
+            Attribute::NestHost { host_class_index } => {
+                self.line(indent, format!("NestHost: class {}", self.class_name(*host_class_index)));
+            }
+            Attribute::NestMembers(indices) => self.class_list(indent, "NestMembers", indices),
+            Attribute::PermittedSubclasses(indices) => self.class_list(indent, "PermittedSubclasses", indices),
+            Attribute::Record(components) => {
+                self.line(indent, "Record:");
+                for component in components {
+                    let descriptor = self.utf8(component.descriptor_index).to_string();
+                    self.line(indent + 2, format!("{} {};", java_field_type(&descriptor), self.utf8(component.name_index)));
+                    self.line(indent + 4, format!("descriptor: {}", descriptor));
+                    self.attributes(indent + 4, &component.attributes, None);
+                }
+            }
+            Attribute::Unknown { name_index, info } => {
+                self.line(indent, format!("Unknown attribute {}: length = 0x{:x}", self.utf8(*name_index), info.len()));
+                let bytes = info.iter().map(|it| format!("{:02x}", it)).collect::<Vec<_>>();
+                for chunk in bytes.chunks(16) {
+                    self.line(indent + 2, chunk.join(" "));
+                }
+            }
+        }
+    }
+
+    // the name of a CONSTANT_Module or CONSTANT_Package entry
+    fn utf8_of(&self, index: u16) -> &str {
+        match self.const_pool.get(index) {
+            Some(CpInfo::Module { name_index }) | Some(CpInfo::Package { name_index }) => self.utf8(*name_index),
+            _ => "",
+        }
+    }
+
+    fn class_list(&mut self, indent: usize, name: &str, indices: &[u16]) {
+        self.line(indent, format!("{}:", name));
+        for index in indices {
+            let class = self.class_name(*index).to_string();
+            self.line(indent + 2, class);
+        }
+    }
+
+    fn local_variables(&mut self, indent: usize, name: &str, variables: &[LocalVariable], code: Option<&CodeOffsets>) {
+        self.line(indent, format!("{}:", name));
+        self.line(indent + 2, "Start  Length  Slot  Name   Signature");
+        for variable in variables {
+            let (start, length) = match code {
+                Some(offsets) => (offsets.pc(variable.start_pc), offsets.length(variable.start_pc, variable.end_pc)),
+                None => (variable.start_pc as u16, (variable.end_pc - variable.start_pc) as u16),
+            };
+            let text = format!(
+                "{:>5} {:>7} {:>5} {:>5}   {}",
+                start, length, variable.index, self.utf8(variable.name_index), self.utf8(variable.descriptor_index),
+            );
+            self.line(indent + 2, text);
+        }
+    }
+
+    fn annotations(&mut self, indent: usize, name: &str, annotations: &[Annotation]) {
+        self.line(indent, format!("{}:", name));
+        for (i, annotation) in annotations.iter().enumerate() {
+            self.line(indent + 2, format!("{}: {}", i, self.raw_annotation(annotation)));
+            self.pretty_annotation(indent + 4, annotation);
+        }
+    }
+
+    fn parameter_annotations(&mut self, indent: usize, name: &str, parameters: &[Vec<Annotation>]) {
+        self.line(indent, format!("{}:", name));
+        for (parameter, annotations) in parameters.iter().enumerate() {
+            self.line(indent + 2, format!("parameter {}:", parameter));
+            for (i, annotation) in annotations.iter().enumerate() {
+                self.line(indent + 4, format!("{}: {}", i, self.raw_annotation(annotation)));
+                self.pretty_annotation(indent + 6, annotation);
+            }
+        }
+    }
+
+    fn type_annotations(
+        &mut self,
+        indent: usize,
+        name: &str,
+        annotations: &[TypeAnnotation],
+        code: Option<&CodeOffsets>,
+    ) {
+        self.line(indent, format!("{}:", name));
+        for (i, annotation) in annotations.iter().enumerate() {
+            let pc = |index: usize| code.map_or(index as u16, |it| it.pc(index));
+            let target = match &annotation.target_info {
+                TargetInfo::TypeParameter { type_parameter_index } => format!(", param_index={}", type_parameter_index),
+                TargetInfo::Supertype { supertype_index } => format!(", type_index={}", supertype_index),
+                TargetInfo::TypeParameterBound { type_parameter_index, bound_index } => {
+                    format!(", param_index={}, bound_index={}", type_parameter_index, bound_index)
+                }
+                TargetInfo::Empty => String::new(),
+                TargetInfo::FormalParameter { formal_parameter_index } => format!(", param_index={}", formal_parameter_index),
+                TargetInfo::Throws { throws_type_index } => format!(", type_index={}", throws_type_index),
+                TargetInfo::LocalVar(ranges) => {
+                    let ranges = ranges.iter()
+                        .map(|it| {
+                            let length = code.map_or((it.end_pc - it.start_pc) as u16, |offsets| offsets.length(it.start_pc, it.end_pc));
+                            format!("[start_pc={}, length={}, index={}]", pc(it.start_pc), length, it.index)
+                        })
+                        .collect::<Vec<_>>();
+                    format!(", {{{}}}", ranges.join(","))
+                }
+                TargetInfo::Catch { exception_table_index } => format!(", exception_index={}", exception_table_index),
+                TargetInfo::Offset(offset) => format!(", offset={}", pc(*offset)),
+                TargetInfo::TypeArgument { offset, type_argument_index } => {
+                    format!(", offset={}, type_index={}", pc(*offset), type_argument_index)
+                }
+            };
+            let path = if annotation.target_path.is_empty() {
+                String::new()
+            } else {
+                let path = annotation.target_path.iter()
+                    .map(|(kind, argument)| match kind {
+                        0 => "ARRAY".to_string(),
+                        1 => "INNER_TYPE".to_string(),
+                        2 => "WILDCARD".to_string(),
+                        _ => format!("TYPE_ARGUMENT({})", argument),
+                    })
+                    .collect::<Vec<_>>();
+                format!(", location=[{}]", path.join(", "))
+            };
+            self.line(indent + 2, format!(
+                "{}: {}: {}{}{}",
+                i, self.raw_annotation(&annotation.annotation), target_type(annotation.target_type), target, path,
+            ));
+            self.pretty_annotation(indent + 4, &annotation.annotation);
+        }
+    }
+
+    // `#24(#19=s#25)`
+    fn raw_annotation(&self, annotation: &Annotation) -> String {
+        let pairs = annotation.element_value_pairs.iter()
+            .map(|(name_index, value)| format!("#{}={}", name_index, self.raw_element_value(value)))
+            .collect::<Vec<_>>();
+        format!("#{}({})", annotation.type_index, pairs.join(","))
+    }
+
+    fn raw_element_value(&self, value: &ElementValue) -> String {
+        match value {
+            ElementValue::Const { tag, const_value_index } => format!("{}#{}", *tag as char, const_value_index),
+            ElementValue::Enum { type_name_index, const_name_index } => format!("e#{}.#{}", type_name_index, const_name_index),
+            ElementValue::Class { class_info_index } => format!("c#{}", class_info_index),
+            ElementValue::Annotation(annotation) => format!("@{}", self.raw_annotation(annotation)),
+            ElementValue::Array(values) => {
+                format!("[{}]", values.iter().map(|it| self.raw_element_value(it)).collect::<Vec<_>>().join(","))
+            }
+        }
+    }
+
+    fn pretty_annotation(&mut self, indent: usize, annotation: &Annotation) {
+        let name = java_field_type(self.utf8(annotation.type_index));
+        if annotation.element_value_pairs.is_empty() {
+            self.line(indent, name);
+            return;
+        }
+        self.line(indent, format!("{}(", name));
+        for (name_index, value) in &annotation.element_value_pairs {
+            let value = self.element_value(value);
+            self.line(indent + 2, format!("{}={}", self.utf8(*name_index), value));
+        }
+        self.line(indent, ")");
+    }
+
+    fn element_value(&self, value: &ElementValue) -> String {
+        match value {
+            ElementValue::Const { tag, const_value_index } => {
+                let entry = self.const_pool.get(*const_value_index);
+                match (tag, entry) {
+                    (b's', _) => format!("\"{}\"", escape(self.utf8(*const_value_index))),
+                    (b'Z', Some(CpInfo::Integer(value))) => (*value != 0).to_string(),
+                    (b'C', Some(CpInfo::Integer(value))) => {
+                        format!("'{}'", std::char::from_u32(*value as u32).unwrap_or(std::char::REPLACEMENT_CHARACTER))
+                    }
+                    (b'B', Some(CpInfo::Integer(value))) => format!("(byte) {}", value),
+                    (b'S', Some(CpInfo::Integer(value))) => format!("(short) {}", value),
+                    (_, Some(CpInfo::Integer(value))) => value.to_string(),
+                    (_, Some(CpInfo::Float(value))) => format!("{}f", java_float(*value)),
+                    (_, Some(CpInfo::Long(value))) => format!("{}l", value),
+                    (_, Some(CpInfo::Double(value))) => format!("{}d", java_double(*value)),
+                    _ => format!("#{}", const_value_index),
+                }
+            }
+            ElementValue::Enum { type_name_index, const_name_index } => {
+                format!("{}.{}", self.utf8(*type_name_index), self.utf8(*const_name_index))
+            }
+            ElementValue::Class { class_info_index } => {
+                format!("class {}", java_field_type(self.utf8(*class_info_index)))
+            }
+            ElementValue::Annotation(annotation) => {
+                let pairs = annotation.element_value_pairs.iter()
+                    .map(|(name_index, value)| format!("{}={}", self.utf8(*name_index), self.element_value(value)))
+                    .collect::<Vec<_>>();
+                format!("@{}({})", java_field_type(self.utf8(annotation.type_index)), pairs.join(","))
+            }
+            ElementValue::Array(values) => {
+                format!("[{}]", values.iter().map(|it| self.element_value(it)).collect::<Vec<_>>().join(","))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::ClassBuilder;
+    use crate::disassembler::{disassemble, java_double, java_float};
+    use crate::parser::{AccessFlags, parse_bytes};
+
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        disassemble(&parse_bytes(&std::fs::read(path).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn match_javap_output() {
+        // generated with `javap -c -v`, without the file header
+        for name in ["Adder", "Counter"].iter() {
+            let path = format!("{}/tests/fixtures/{}.javap", env!("CARGO_MANIFEST_DIR"), name);
+            assert_eq!(fixture(&format!("{}.class", name)), std::fs::read_to_string(path).unwrap());
+        }
+    }
+
+    #[test]
+    fn print_attributes() {
+        let text = fixture("Attributes.class");
+        for line in [
+            "    ConstantValue: int 42\n",
+            "    Signature: #22                          // TT;\n",
+            "      0: #24(#19=s#40,#41=[I#42,I#43])\n        Tag(\n          value=\"method\"\n          ids=[1,2]\n        )\n",
+            "            0       4     1 other   LAttributes;\n",
+            "    Exceptions:\n      throws java.lang.IllegalStateException\n",
+            "      other                          synthetic\n",
+            "InnerClasses:\n  #54= #51 of #7;                         // Inner=class Attributes$Inner of class Attributes\n",
+        ].iter() {
+            assert!(text.contains(line), "missing {:?}", line);
+        }
+        assert!(fixture("Tag.class").contains("    AnnotationDefault:\n      default_value: s#10\n        \"none\"\n"));
+    }
+
+    #[test]
+    fn print_branch_targets_and_exception_table() {
+        let mut class = ClassBuilder::new("Branches");
+        let mut method = class.method(AccessFlags::STATIC as u16, "run", "(I)I");
+        let (start, end, handler, zero) = (method.new_label(), method.new_label(), method.new_label(), method.new_label());
+        method.bind(start).iload(0).ifeq(zero).bipush(10).newarray(10).arraylength().bind(end).ireturn();
+        method.bind(zero).iconst_0().ireturn();
+        method.bind(handler).athrow();
+        method.try_catch(start, end, handler, None);
+        method.finish().unwrap();
+        let text = disassemble(&class.build());
+        assert!(text.contains("  static int run(int);\n    descriptor: (I)I\n    flags: (0x0008) ACC_STATIC\n"));
+        assert!(text.contains("\
+         0: iload_0
+         1: ifeq          10
+         4: bipush        10
+         6: newarray       int
+         8: arraylength
+         9: ireturn
+        10: iconst_0
+        11: ireturn
+        12: athrow
+      Exception table:
+         from    to  target type
+             0     9    12   any
+"));
+    }
+
+    #[test]
+    fn format_floating_point_like_java() {
+        assert_eq!(java_double(1.0), "1.0");
+        assert_eq!(java_double(1.5), "1.5");
+        assert_eq!(java_double(1e10), "1.0E10");
+        assert_eq!(java_double(-2.5e-5), "-2.5E-5");
+        assert_eq!(java_double(f64::NAN), "NaN");
+        assert_eq!(java_float(0.1), "0.1");
+        assert_eq!(java_float(f32::NEG_INFINITY), "-Infinity");
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use crate::method_area::MethodArea;
+
+/// Index into the heap, offset by one so that `0` is `null`.
+pub(crate) type Reference = i32;
+
+pub(crate) const NULL: Reference = 0;
+
+// §2.4
+#[derive(Debug, PartialEq)]
+pub(crate) enum Object {
+    Instance {
+        class: String,
+        fields: Vec<i32>,
+    },
+    // `class` is the array's own class name, e.g. `[I` or `[Ljava/lang/String;`
+    Array {
+        class: String,
+        elements: Vec<i32>,
+    },
+    String(String),
+}
+
+impl Object {
+    pub(crate) fn class_name(&self) -> &str {
+        match self {
+            Object::Instance { class, .. } => class,
+            Object::Array { class, .. } => class,
+            Object::String(_) => "java/lang/String",
+        }
+    }
+
+    /// Bytes the object takes up as far as `Limits::heap_bytes` is concerned.
+    pub(crate) fn size(&self) -> usize {
+        OBJECT_HEADER_BYTES + match self {
+            Object::Instance { fields, .. } => fields.len() * 4,
+            Object::Array { elements, .. } => elements.len() * 4,
+            Object::String(s) => s.encode_utf16().count() * 2,
+        }
+    }
+
+    pub(crate) fn array_length(&self) -> Option<usize> {
+        match self {
+            Object::Array { class, elements } => Some(elements.len() / slot_width(&class[1..])),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) const OBJECT_HEADER_BYTES: usize = 16;
+
+/// Number of 32-bit slots a value of the given field descriptor occupies, see §2.6.1.
+pub(crate) fn slot_width(descriptor: &str) -> usize {
+    match descriptor.as_bytes().first() {
+        Some(b'J') | Some(b'D') => 2,
+        _ => 1,
+    }
+}
+
+// §2.5.3
+#[derive(Debug, Default)]
+pub(crate) struct Heap {
+    objects: Vec<Object>,
+    // sum of the `size` of `objects`
+    bytes: usize,
+    // static fields of each class that has started initialization, §5.5, by the index
+    // `statics_index` gives the class, which doesn't change once given
+    statics: Vec<Vec<i32>>,
+    statics_index: HashMap<String, usize>,
+    // §5.1, the `String` of each string literal resolved so far, so that equal literals are
+    // the same object
+    interned: HashMap<String, Reference>,
+}
+
+impl Heap {
+    pub(crate) fn new() -> Heap {
+        Heap::default()
+    }
+
+    pub(crate) fn is_initialized(&self, class: &str) -> bool {
+        self.statics_index.contains_key(class)
+    }
+
+    /// Marks `class` as initialized, with all static fields at their default value.
+    pub(crate) fn init_statics(&mut self, class: &str, slot_count: usize) {
+        self.statics_index.insert(class.to_string(), self.statics.len());
+        self.statics.push(vec![0; slot_count]);
+    }
+
+    /// Index of the static fields of `class`, once it is initialized.
+    pub(crate) fn statics_index(&self, class: &str) -> Option<usize> {
+        self.statics_index.get(class).copied()
+    }
+
+    pub(crate) fn statics(&self, class: &str) -> &[i32] {
+        &self.statics[self.statics_index[class]]
+    }
+
+    pub(crate) fn statics_mut(&mut self, class: &str) -> &mut [i32] {
+        &mut self.statics[self.statics_index[class]]
+    }
+
+    pub(crate) fn statics_at(&self, index: usize) -> &[i32] {
+        &self.statics[index]
+    }
+
+    pub(crate) fn statics_at_mut(&mut self, index: usize) -> &mut [i32] {
+        &mut self.statics[index]
+    }
+
+    /// The `String` for the string literal `value`, §5.1.
+    pub(crate) fn intern(&mut self, value: &str) -> Reference {
+        match self.interned.get(value) {
+            Some(string) => *string,
+            None => {
+                let string = self.new_string(value);
+                self.interned.insert(value.to_string(), string);
+                string
+            }
+        }
+    }
+
+    /// A heap with `objects` at the references they had, each class of `statics` initialized
+    /// with those static fields, and the strings of `interned` as the literals they were.
+    pub(crate) fn restore(
+        objects: Vec<Object>, statics: Vec<(String, Vec<i32>)>, interned: Vec<(String, Reference)>,
+    ) -> Heap {
+        let mut heap = Heap::new();
+        heap.bytes = objects.iter().map(Object::size).sum();
+        heap.objects = objects;
+        for (class, fields) in statics {
+            heap.statics_index.insert(class, heap.statics.len());
+            heap.statics.push(fields);
+        }
+        heap.interned = interned.into_iter().collect();
+        heap
+    }
+
+    pub(crate) fn objects(&self) -> &[Object] {
+        &self.objects
+    }
+
+    /// The initialized classes with their static fields, in the order they started initializing.
+    pub(crate) fn initialized(&self) -> Vec<(&str, &[i32])> {
+        let mut classes: Vec<_> = self.statics_index.iter().map(|(class, index)| (*index, class.as_str())).collect();
+        classes.sort_unstable();
+        classes.into_iter().map(|(index, class)| (class, self.statics[index].as_slice())).collect()
+    }
+
+    /// The string literals resolved so far with their `String`s, sorted.
+    pub(crate) fn interned(&self) -> Vec<(&str, Reference)> {
+        let mut interned: Vec<_> = self.interned.iter().map(|(value, string)| (value.as_str(), *string)).collect();
+        interned.sort_unstable();
+        interned
+    }
+
+    pub(crate) fn allocate(&mut self, object: Object) -> Reference {
+        self.bytes += object.size();
+        self.objects.push(object);
+        self.objects.len() as Reference
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub(crate) fn get(&self, reference: Reference) -> &Object {
+        self.find(reference).unwrap_or_else(|| panic!("invalid reference {}", reference))
+    }
+
+    /// The object `reference` refers to, `None` if it is null or not one of this heap's.
+    pub(crate) fn find(&self, reference: Reference) -> Option<&Object> {
+        match reference {
+            NULL => None,
+            _ => self.objects.get((reference as usize).wrapping_sub(1)),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, reference: Reference) -> &mut Object {
+        match self.objects.get_mut((reference - 1) as usize) {
+            Some(object) if reference != NULL => object,
+            _ => panic!("invalid reference {}", reference),
+        }
+    }
+
+    pub(crate) fn new_instance(&mut self, method_area: &MethodArea, class: &str) -> Reference {
+        let fields = vec![0; method_area.instance_slot_count(class)];
+        self.allocate(Object::Instance { class: class.to_string(), fields })
+    }
+
+    pub(crate) fn new_string(&mut self, value: &str) -> Reference {
+        self.allocate(Object::String(value.to_string()))
+    }
+
+    /// Allocates a `Throwable` of `class` with `detailMessage` already set,
+    /// for exceptions raised by the VM itself rather than by `athrow`.
+    pub(crate) fn new_throwable(&mut self, method_area: &MethodArea, class: &str, message: &str) -> Reference {
+        let message = self.new_string(message);
+        let exception = self.new_instance(method_area, class);
+        let slot = method_area.field_slot(class, "detailMessage")
+            .expect("throwable without detailMessage");
+        if let Object::Instance { fields, .. } = self.get_mut(exception) {
+            fields[slot] = message;
+        }
+        exception
+    }
+
+    pub(crate) fn throwable_message(&self, method_area: &MethodArea, exception: Reference) -> Option<&str> {
+        let object = self.get(exception);
+        match (object, method_area.field_slot(object.class_name(), "detailMessage")) {
+            (Object::Instance { fields, .. }, Some(slot)) if fields[slot] != NULL => {
+                match self.get(fields[slot]) {
+                    Object::String(s) => Some(s.as_str()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heap::{Heap, NULL, Object};
+
+    #[test]
+    fn references_are_non_null() {
+        let mut heap = Heap::new();
+        let reference = heap.new_string("foo");
+        assert_ne!(reference, NULL);
+        assert_eq!(heap.get(reference).class_name(), "java/lang/String");
+    }
+
+    #[test]
+    fn array_length_accounts_for_wide_elements() {
+        let array = Object::Array { class: "[J".to_string(), elements: vec![0; 6] };
+        assert_eq!(array.array_length(), Some(3));
+    }
+}
@@ -0,0 +1,133 @@
+use std::cell::{Cell, RefCell};
+
+// receiver classes a call site caches before it is megamorphic, as in HotSpot's bimorphic and
+// polymorphic caches
+const POLYMORPHIC_LIMIT: usize = 4;
+
+/// How many receiver classes a call site's inline cache holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheState {
+    /// The call site hasn't dispatched since it was decoded or its cache was flushed
+    Empty,
+    /// One receiver class
+    Monomorphic,
+    /// Two receiver classes or more, up to a limit
+    Polymorphic,
+    /// More receiver classes than the cache holds, so the others are looked up every time
+    Megamorphic,
+}
+
+/// Hit and miss counts of the inline cache of an `invokevirtual` or `invokeinterface`, see
+/// [`Vm::call_sites`](crate::Vm::call_sites).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSiteStats {
+    /// The method the call site is in, as `Class.name:descriptor`
+    pub method: String,
+    /// The index of the instruction in the method's code
+    pub pc: usize,
+    /// The method the instruction refers to, as `Class.name:descriptor`
+    pub target: String,
+    pub state: CacheState,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CallSiteStats {
+    /// The share of dispatches the cache answered, 0 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// The inline cache of an `invokevirtual` or `invokeinterface` in a decoded method, mapping the
+/// classes of the receivers seen there to the methods they select (§5.4.6).
+///
+/// The entries only hold while the classes they were found with are all that are loaded, so the
+/// method area flushes them whenever that changes.
+#[derive(Debug, Default)]
+pub(crate) struct CallSite {
+    // the index of the instruction in `codes`
+    pub pc: usize,
+    // the index of its `CONSTANT_Methodref` or `CONSTANT_InterfaceMethodref`
+    pub index: u16,
+    // receiver classes and the index in the method area of the method each selects
+    entries: RefCell<Vec<(Box<str>, usize)>>,
+    megamorphic: Cell<bool>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl CallSite {
+    pub(crate) fn new(pc: usize, index: u16) -> CallSite {
+        CallSite { pc, index, ..Default::default() }
+    }
+
+    /// The method that receivers of `class` select, if cached, counting a hit or miss.
+    pub(crate) fn lookup(&self, class: &str) -> Option<usize> {
+        let method = self.entries.borrow().iter().find(|(it, _)| **it == *class).map(|(_, method)| *method);
+        let counter = if method.is_some() { &self.hits } else { &self.misses };
+        counter.set(counter.get() + 1);
+        method
+    }
+
+    /// Caches that receivers of `class` select `method`, unless the cache is full.
+    pub(crate) fn record(&self, class: &str, method: usize) {
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() < POLYMORPHIC_LIMIT {
+            entries.push((class.into(), method));
+        } else {
+            self.megamorphic.set(true);
+        }
+    }
+
+    /// Empties the cache, keeping the counts.
+    pub(crate) fn flush(&self) {
+        self.entries.borrow_mut().clear();
+        self.megamorphic.set(false);
+    }
+
+    pub(crate) fn state(&self) -> CacheState {
+        match self.entries.borrow().len() {
+            _ if self.megamorphic.get() => CacheState::Megamorphic,
+            0 => CacheState::Empty,
+            1 => CacheState::Monomorphic,
+            _ => CacheState::Polymorphic,
+        }
+    }
+
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    pub(crate) fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inline_cache::{CacheState, CallSite};
+
+    #[test]
+    fn cache_goes_megamorphic_past_the_limit() {
+        let site = CallSite::new(0, 1);
+        assert_eq!((site.lookup("A"), site.state()), (None, CacheState::Empty));
+        site.record("A", 7);
+        assert_eq!((site.lookup("A"), site.state()), (Some(7), CacheState::Monomorphic));
+        for (method, class) in ["B", "C", "D"].iter().enumerate() {
+            site.record(class, method);
+        }
+        assert_eq!((site.lookup("D"), site.state()), (Some(2), CacheState::Polymorphic));
+        site.record("E", 3);
+        assert_eq!((site.lookup("E"), site.state()), (None, CacheState::Megamorphic));
+        assert_eq!(site.lookup("A"), Some(7));
+        assert_eq!((site.hits(), site.misses()), (3, 2));
+
+        site.flush();
+        assert_eq!((site.lookup("A"), site.state()), (None, CacheState::Empty));
+        assert_eq!((site.hits(), site.misses()), (3, 3));
+    }
+}
@@ -0,0 +1,193 @@
+use std::cell::Cell;
+
+use crate::const_pool::{ConstPool, CpInfo};
+use crate::descriptor::MethodDescriptor;
+use crate::heap::slot_width;
+use crate::inline_cache::CallSite;
+use crate::Opcode;
+
+/// An instruction as the interpreter runs it, decoded once per method from its [`Opcode`]s.
+///
+/// The forms of an opcode that only differ in an operand, such as `iload_0` to `iload_3`, share a
+/// variant, and whatever can be worked out from the constant pool alone is worked out while
+/// decoding, so running an instruction never parses a descriptor. Indices stay those of `codes`,
+/// so branch targets and exception tables apply unchanged. Variants are at most 8 bytes so that a
+/// method's instructions are a dense array the interpreter's `match` jumps through.
+///
+/// What needs the method area or heap is resolved the first time the instruction runs, after
+/// which the interpreter quickens it: it replaces the instruction in place by a `*Quick` form
+/// holding the result, like HotSpot's `_fast_` bytecodes. A quick form is only written once
+/// the class it depends on is initialized, so it runs without checking that again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Instruction {
+    // aconst_null, iconst_<i>, bipush, push, and ldc of a number or once the string is resolved
+    Push(i32),
+    // ldc of a string
+    Ldc(u16),
+    // iload, aload and their `_<n>` forms
+    Load(u16),
+    // istore, astore and their `_<n>` forms
+    Store(u16),
+    Increment(u16, i32),
+    Add,
+    Pop,
+    Dup,
+    Goto(u32),
+    IfZero(u32),
+    IfNonZero(u32),
+    IfLess(u32),
+    Jsr(u32),
+    Ret(u16),
+    Return,
+    // ireturn and areturn
+    ReturnValue,
+    // `width` is the number of slots the field's value takes
+    GetStatic { index: u16, width: u8 },
+    PutStatic { index: u16, width: u8 },
+    GetField { index: u16, width: u8 },
+    PutField { index: u16, width: u8 },
+    // `arguments` is the number of slots taken by the arguments, including the receiver if any
+    InvokeStatic { index: u16, arguments: u16 },
+    InvokeSpecial { index: u16, arguments: u16 },
+    // invokevirtual and invokeinterface, which select the method the same way, with the index
+    // of their call site
+    InvokeVirtual { site: u16, arguments: u16 },
+    New(u16),
+    NewArray(u8),
+    ANewArray(u16),
+    ArrayLength,
+    // iaload and iastore
+    ArrayLoad,
+    ArrayStore,
+    // their unchecked forms in optimized methods
+    ArrayLoadUnchecked,
+    ArrayStoreUnchecked,
+    Throw,
+    CheckCast(u16),
+    InstanceOf(u16),
+
+    // index of the method in the method area
+    InvokeQuick { method: u32, arguments: u16 },
+    // index of the class's statics in the heap
+    GetStaticQuick { statics: u32, slot: u16, width: u8 },
+    PutStaticQuick { statics: u32, slot: u16, width: u8 },
+    GetFieldQuick { slot: u16, width: u8 },
+    PutFieldQuick { slot: u16, width: u8 },
+    // `slots` is the number of slots the instance's fields take
+    NewQuick { index: u16, slots: u16 },
+}
+
+/// A method's code as the interpreter runs it.
+pub(crate) struct Decoded {
+    pub instructions: Box<[Cell<Instruction>]>,
+    // the inline caches of its `invokevirtual` and `invokeinterface` instructions, in order
+    pub call_sites: Box<[CallSite]>,
+}
+
+/// Decodes `codes` of a method whose class has `constant_pool`.
+pub(crate) fn decode(codes: &[Opcode], constant_pool: &ConstPool) -> Decoded {
+    // constants of the wrong kind are left for the instructions to throw on when they run
+    let field_width = |index: u16| {
+        constant_pool.resolve_field_ref(index).map_or(1, |it| slot_width(&it.descriptor) as u8)
+    };
+    let argument_slots = |index: u16| {
+        constant_pool.resolve_method_ref(index)
+            .and_then(|it| MethodDescriptor::parse(&it.descriptor))
+            .map_or(0, |it| it.parameter_slots() as u16)
+    };
+    let mut call_sites = Vec::new();
+    let mut call_site = |pc: usize, index: u16| {
+        call_sites.push(CallSite::new(pc, index));
+        Instruction::InvokeVirtual { site: (call_sites.len() - 1) as u16, arguments: argument_slots(index) + 1 }
+    };
+    let instructions = codes.iter()
+        .enumerate()
+        .map(|(pc, code)| match *code {
+            Opcode::aconst_null => Instruction::Push(0),
+            Opcode::iconst_0 => Instruction::Push(0),
+            Opcode::iconst_1 => Instruction::Push(1),
+            Opcode::bipush(value) | Opcode::push(value) => Instruction::Push(value),
+            Opcode::ldc(index) => match constant_pool.get(index) {
+                Some(CpInfo::Integer(value)) => Instruction::Push(*value),
+                Some(CpInfo::Float(value)) => Instruction::Push(value.to_bits() as i32),
+                _ => Instruction::Ldc(index),
+            },
+            Opcode::iload(index) | Opcode::aload(index) => Instruction::Load(index as u16),
+            Opcode::iload_0 | Opcode::aload_0 => Instruction::Load(0),
+            Opcode::iload_1 | Opcode::aload_1 => Instruction::Load(1),
+            Opcode::iload_2 | Opcode::aload_2 => Instruction::Load(2),
+            Opcode::iload_3 | Opcode::aload_3 => Instruction::Load(3),
+            Opcode::istore(index) | Opcode::astore(index) => Instruction::Store(index as u16),
+            Opcode::istore_0 | Opcode::astore_0 => Instruction::Store(0),
+            Opcode::istore_1 | Opcode::astore_1 => Instruction::Store(1),
+            Opcode::istore_2 | Opcode::astore_2 => Instruction::Store(2),
+            Opcode::istore_3 | Opcode::astore_3 => Instruction::Store(3),
+            Opcode::iinc(index, value) => Instruction::Increment(index as u16, value),
+            Opcode::iadd => Instruction::Add,
+            Opcode::pop => Instruction::Pop,
+            Opcode::dup => Instruction::Dup,
+            Opcode::goto(target) => Instruction::Goto(target as u32),
+            Opcode::ifeq(target) => Instruction::IfZero(target as u32),
+            Opcode::ifne(target) => Instruction::IfNonZero(target as u32),
+            Opcode::if_icmplt(target) => Instruction::IfLess(target as u32),
+            Opcode::jsr(target) => Instruction::Jsr(target as u32),
+            Opcode::ret(index) => Instruction::Ret(index as u16),
+            Opcode::r#return => Instruction::Return,
+            Opcode::ireturn | Opcode::areturn => Instruction::ReturnValue,
+            Opcode::getstatic(index) => Instruction::GetStatic { index, width: field_width(index) },
+            Opcode::putstatic(index) => Instruction::PutStatic { index, width: field_width(index) },
+            Opcode::getfield(index) => Instruction::GetField { index, width: field_width(index) },
+            Opcode::putfield(index) => Instruction::PutField { index, width: field_width(index) },
+            Opcode::invokestatic(index) => {
+                let index = index as u16;
+                Instruction::InvokeStatic { index, arguments: argument_slots(index) }
+            }
+            Opcode::invokespecial(index) => Instruction::InvokeSpecial { index, arguments: argument_slots(index) + 1 },
+            Opcode::invokevirtual(index) | Opcode::invokeinterface(index, _) => call_site(pc, index),
+            Opcode::new(index) => Instruction::New(index),
+            Opcode::newarray(atype) => Instruction::NewArray(atype),
+            Opcode::anewarray(index) => Instruction::ANewArray(index),
+            Opcode::arraylength => Instruction::ArrayLength,
+            Opcode::iaload => Instruction::ArrayLoad,
+            Opcode::iastore => Instruction::ArrayStore,
+            Opcode::iaload_unchecked => Instruction::ArrayLoadUnchecked,
+            Opcode::iastore_unchecked => Instruction::ArrayStoreUnchecked,
+            Opcode::athrow => Instruction::Throw,
+            Opcode::checkcast(index) => Instruction::CheckCast(index),
+            Opcode::instanceof(index) => Instruction::InstanceOf(index),
+        })
+        .map(Cell::new)
+        .collect();
+    Decoded { instructions, call_sites: call_sites.into_boxed_slice() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::mem::size_of;
+
+    use crate::const_pool::tests::sample_const_pool;
+    use crate::instruction::{decode, Instruction};
+    use crate::Opcode;
+
+    #[test]
+    fn decode_resolves_operands() {
+        assert_eq!(size_of::<Instruction>(), 8);
+        let codes = [
+            Opcode::aload_1,
+            Opcode::istore_2,
+            Opcode::bipush(-3),
+            Opcode::invokestatic(2),
+            Opcode::if_icmplt(0),
+        ];
+        let decoded: Vec<_> = decode(&codes, &sample_const_pool()).instructions.iter().map(Cell::get).collect();
+        assert_eq!(decoded, vec![
+            Instruction::Load(1),
+            Instruction::Store(2),
+            Instruction::Push(-3),
+            // Adder.add:(II)I
+            Instruction::InvokeStatic { index: 2, arguments: 2 },
+            Instruction::IfLess(0),
+        ]);
+    }
+}
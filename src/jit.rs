@@ -0,0 +1,611 @@
+use crate::const_pool::{ConstPool, CpInfo};
+use crate::descriptor::{FieldType, MethodDescriptor};
+use crate::heap::slot_width;
+use crate::{branch_target, Opcode};
+
+/// How hot a method gets before it is compiled to native code, see
+/// [`Vm::set_jit`](crate::Vm::set_jit).
+///
+/// ```
+/// let thresholds = rj::JitThresholds::default();
+/// assert_eq!((thresholds.invocations, thresholds.backedges), (1000, 10_000));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitThresholds {
+    /// Calls of the method
+    pub invocations: u32,
+    /// Branches back to an earlier instruction, counted over all calls of the method
+    pub backedges: u32,
+    /// Whether later calls of a method compiled for reaching either threshold run it optimized
+    /// through its SSA form, and compiled from that
+    pub optimize: bool,
+}
+
+impl Default for JitThresholds {
+    fn default() -> Self {
+        JitThresholds { invocations: 1000, backedges: 10_000, optimize: true }
+    }
+}
+
+/// A method compiled by a template JIT: each instruction is translated on its own into x86-64
+/// code doing what the interpreter does for it, on the same slots the `Frame` uses.
+///
+/// Since the locals and operand stack stay in the frame's slots, the native code can be entered
+/// at the start of any basic block and left at any instruction, which is then all that
+/// deoptimizing back to the interpreter takes. It leaves at instructions it doesn't support, at
+/// the end of the code, and when the instructions taken from the budget run out, which are
+/// counted a basic block at a time so that limits stop it where they would stop the interpreter.
+pub(crate) struct Native {
+    code: imp::Code,
+    // operand stack depth before each instruction and at the end of the code, if the native
+    // code can get there
+    depths: Box<[Option<u16>]>,
+    // whether the native code can be entered at each instruction, where a block starts that
+    // leads into a loop
+    entries: Box<[bool]>,
+    local_size: usize,
+    stack_size: usize,
+}
+
+impl Native {
+    #[inline]
+    pub(crate) fn can_enter(&self, pc: usize) -> bool {
+        self.entries.get(pc).copied().unwrap_or(false)
+    }
+
+    /// Runs from `pc` with the frame's locals at `locals` and `depth` values on its operand
+    /// stack at `stack`, if it can be entered there. Returns the instruction and operand
+    /// stack depth the interpreter carries on with, and the `fuel` that is left.
+    // kept out of the interpreter loop, which only enters it on a branch back
+    #[inline(never)]
+    pub(crate) fn run(
+        &self, slots: &mut [i32], locals: usize, stack: usize, pc: usize, depth: usize, fuel: u64,
+    ) -> Option<(usize, usize, u64)> {
+        if !self.can_enter(pc) || self.depths[pc] != Some(depth as u16) {
+            return None;
+        }
+        // the native code only touches these slots
+        assert!(locals + self.local_size <= stack && stack + self.stack_size <= slots.len());
+        let base = slots.as_mut_ptr();
+        // SAFETY: the code was compiled for `local_size` locals and `stack_size` operand stack
+        // slots, which the assert keeps within `slots`, and is entered at the start of a block
+        // with the depth it was compiled for
+        let (pc, fuel) = unsafe { self.code.call(base.add(locals), base.add(stack), pc, fuel) };
+        let depth = self.depths[pc].expect("native code left where it can't get") as usize;
+        Some((pc, stack + depth, fuel))
+    }
+}
+
+/// Compiles `codes` of a method with `local_size` locals and `stack_size` operand stack slots,
+/// whose exception handlers are at `handlers`, unless it does something the native code
+/// couldn't keep within those, or the platform isn't x86-64 Linux.
+pub(crate) fn compile(
+    codes: &[Opcode], constant_pool: &ConstPool, local_size: usize, stack_size: usize, handlers: &[usize],
+) -> Option<Native> {
+    let depths = depths(codes, constant_pool, local_size, stack_size, handlers)?;
+    // a block starts where the code starts and wherever it can be branched to
+    let mut leaders = vec![false; codes.len() + 1];
+    leaders[0] = true;
+    for &handler in handlers {
+        leaders[handler] = true;
+    }
+    for (pc, code) in codes.iter().enumerate() {
+        if let Some(target) = branch_target(code) {
+            leaders[target] = true;
+            leaders[pc + 1] = true;
+        }
+    }
+    let supported = |pc: usize| depths[pc].is_some() && codes.get(pc).is_some_and(|it| template(it, constant_pool).is_some());
+    // entering only pays off where the native code loops before it is left again
+    let loops = |pc: usize| {
+        let mut pc = pc;
+        for _ in 0..codes.len() {
+            if !supported(pc) {
+                return false;
+            }
+            match codes[pc] {
+                Opcode::goto(target) if target > pc => pc = target,
+                ref code => match branch_target(code) {
+                    Some(target) if target <= pc => return true,
+                    _ => pc += 1,
+                },
+            }
+        }
+        false
+    };
+    let entries: Box<[bool]> = (0..codes.len()).map(|pc| leaders[pc] && supported(pc) && loops(pc)).collect();
+
+    let mut assembler = Assembler::default();
+    assembler.prologue();
+    let mut labels = Vec::with_capacity(codes.len() + 1);
+    for (pc, code) in codes.iter().enumerate() {
+        labels.push(assembler.offset());
+        if !supported(pc) {
+            assembler.exit(pc);
+            continue;
+        }
+        if leaders[pc] {
+            // charge the block's instructions up to its branch, or up to where the native code
+            // is left or the next block starts
+            let mut end = pc + 1;
+            while branch_target(&codes[end - 1]).is_none() && end < codes.len() && !leaders[end] && supported(end) {
+                end += 1;
+            }
+            assembler.charge(pc, end - pc);
+        }
+        let depth = depths[pc].unwrap() as i32;
+        match template(code, constant_pool).unwrap() {
+            Template::Push(value) => assembler.store_imm(Base::Stack, depth, value),
+            Template::Load(index) => {
+                assembler.load(Base::Locals, index as i32);
+                assembler.store(Base::Stack, depth);
+            }
+            Template::Store(index) => {
+                assembler.load(Base::Stack, depth - 1);
+                assembler.store(Base::Locals, index as i32);
+            }
+            Template::Increment(index, value) => assembler.add_imm(Base::Locals, index as i32, value),
+            Template::Add => {
+                assembler.load(Base::Stack, depth - 2);
+                assembler.add(Base::Stack, depth - 1);
+                assembler.store(Base::Stack, depth - 2);
+            }
+            Template::Pop => {}
+            Template::Dup => {
+                assembler.load(Base::Stack, depth - 1);
+                assembler.store(Base::Stack, depth);
+            }
+            Template::Goto(target) => assembler.jump(None, target),
+            Template::IfZero(target) => {
+                assembler.compare_zero(Base::Stack, depth - 1);
+                assembler.jump(Some(Condition::Equal), target);
+            }
+            Template::IfNonZero(target) => {
+                assembler.compare_zero(Base::Stack, depth - 1);
+                assembler.jump(Some(Condition::NotEqual), target);
+            }
+            Template::IfLess(target) => {
+                assembler.load(Base::Stack, depth - 2);
+                assembler.compare(Base::Stack, depth - 1);
+                assembler.jump(Some(Condition::Less), target);
+            }
+        }
+    }
+    labels.push(assembler.offset());
+    assembler.exit(codes.len());
+    let bytes = assembler.finish(&labels, &entries);
+    Some(Native { code: imp::Code::new(&bytes)?, depths, entries, local_size, stack_size })
+}
+
+// what an instruction the native code supports does
+enum Template {
+    Push(i32),
+    Load(usize),
+    Store(usize),
+    Increment(usize, i32),
+    Add,
+    Pop,
+    Dup,
+    Goto(usize),
+    IfZero(usize),
+    IfNonZero(usize),
+    IfLess(usize),
+}
+
+fn template(code: &Opcode, constant_pool: &ConstPool) -> Option<Template> {
+    let template = match *code {
+        Opcode::aconst_null | Opcode::iconst_0 => Template::Push(0),
+        Opcode::iconst_1 => Template::Push(1),
+        Opcode::bipush(value) | Opcode::push(value) => Template::Push(value),
+        Opcode::ldc(index) => match constant_pool.get(index)? {
+            CpInfo::Integer(value) => Template::Push(*value),
+            CpInfo::Float(value) => Template::Push(value.to_bits() as i32),
+            _ => return None,
+        },
+        Opcode::iload(index) | Opcode::aload(index) => Template::Load(index),
+        Opcode::istore(index) | Opcode::astore(index) => Template::Store(index),
+        Opcode::iload_0 | Opcode::aload_0 => Template::Load(0),
+        Opcode::iload_1 | Opcode::aload_1 => Template::Load(1),
+        Opcode::iload_2 | Opcode::aload_2 => Template::Load(2),
+        Opcode::iload_3 | Opcode::aload_3 => Template::Load(3),
+        Opcode::istore_0 | Opcode::astore_0 => Template::Store(0),
+        Opcode::istore_1 | Opcode::astore_1 => Template::Store(1),
+        Opcode::istore_2 | Opcode::astore_2 => Template::Store(2),
+        Opcode::istore_3 | Opcode::astore_3 => Template::Store(3),
+        Opcode::iinc(index, value) => Template::Increment(index, value),
+        Opcode::iadd => Template::Add,
+        Opcode::pop => Template::Pop,
+        Opcode::dup => Template::Dup,
+        Opcode::goto(target) => Template::Goto(target),
+        Opcode::ifeq(target) => Template::IfZero(target),
+        Opcode::ifne(target) => Template::IfNonZero(target),
+        Opcode::if_icmplt(target) => Template::IfLess(target),
+        _ => return None,
+    };
+    Some(template)
+}
+
+// the operand stack depth before each instruction, following the code from the start and from
+// the handlers; None if the depths don't agree, or an instruction the native code runs would go
+// past the locals or operand stack
+fn depths(
+    codes: &[Opcode], constant_pool: &ConstPool, local_size: usize, stack_size: usize, handlers: &[usize],
+) -> Option<Box<[Option<u16>]>> {
+    let mut depths = vec![None; codes.len() + 1];
+    let mut pending: Vec<(usize, u16)> = vec![(0, 0)];
+    pending.extend(handlers.iter().map(|&it| (it, 1)));
+    while let Some((pc, depth)) = pending.pop() {
+        match depths.get(pc)? {
+            Some(known) if *known == depth => continue,
+            Some(_) => return None,
+            None => depths[pc] = Some(depth),
+        }
+        let code = match codes.get(pc) {
+            Some(code) => code,
+            None => continue,
+        };
+        let (pops, pushes, local) = match template(code, constant_pool) {
+            Some(Template::Push(_)) => (0, 1, None),
+            Some(Template::Load(index)) => (0, 1, Some(index)),
+            Some(Template::Store(index)) => (1, 0, Some(index)),
+            Some(Template::Increment(index, _)) => (0, 0, Some(index)),
+            Some(Template::Add) => (2, 1, None),
+            Some(Template::Pop) => (1, 0, None),
+            Some(Template::Dup) => (1, 2, None),
+            Some(Template::Goto(_)) => (0, 0, None),
+            Some(Template::IfZero(_)) | Some(Template::IfNonZero(_)) => (1, 0, None),
+            Some(Template::IfLess(_)) => (2, 0, None),
+            // the interpreter runs it, and the code after it is only entered where it loops
+            None => match effect(code, constant_pool) {
+                Some((pops, pushes)) => (pops, pushes, None),
+                None => continue,
+            },
+        };
+        let depth = depth.checked_sub(pops)? + pushes;
+        if depth as usize > stack_size || local.is_some_and(|it| it >= local_size) {
+            return None;
+        }
+        if let Some(target) = branch_target(code) {
+            pending.push((target, depth));
+        }
+        if !matches!(code, Opcode::goto(_)) {
+            pending.push((pc + 1, depth));
+        }
+    }
+    Some(depths.into_boxed_slice())
+}
+
+// the slots an instruction the native code doesn't run pops and pushes, None if the code after it
+// isn't run next or can't be told
+fn effect(code: &Opcode, constant_pool: &ConstPool) -> Option<(u16, u16)> {
+    let field_width = |index: u16| constant_pool.resolve_field_ref(index).map(|it| slot_width(&it.descriptor) as u16);
+    let invoke = |index: u16, receiver: u16| {
+        let method = constant_pool.resolve_method_ref(index)?;
+        let descriptor = MethodDescriptor::parse(&method.descriptor)?;
+        let returned = descriptor.return_type.as_ref().map_or(0, FieldType::slot_width);
+        Some((receiver + descriptor.parameter_slots() as u16, returned as u16))
+    };
+    match *code {
+        Opcode::ldc(_) | Opcode::new(_) => Some((0, 1)),
+        Opcode::getstatic(index) => Some((0, field_width(index)?)),
+        Opcode::putstatic(index) => Some((field_width(index)?, 0)),
+        Opcode::getfield(index) => Some((1, field_width(index)?)),
+        Opcode::putfield(index) => Some((1 + field_width(index)?, 0)),
+        Opcode::invokestatic(index) => invoke(index as u16, 0),
+        Opcode::invokevirtual(index) | Opcode::invokespecial(index) | Opcode::invokeinterface(index, _) => invoke(index, 1),
+        Opcode::newarray(_) | Opcode::anewarray(_) | Opcode::arraylength | Opcode::checkcast(_) | Opcode::instanceof(_) => {
+            Some((1, 1))
+        }
+        Opcode::iaload | Opcode::iaload_unchecked => Some((2, 1)),
+        Opcode::iastore | Opcode::iastore_unchecked => Some((3, 0)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Base {
+    // rdi
+    Locals,
+    // rsi
+    Stack,
+}
+
+enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+}
+
+// x86-64 machine code for the templates, with `eax` as the only scratch register
+#[derive(Default)]
+struct Assembler {
+    bytes: Vec<u8>,
+    // where a jump's 32-bit offset is and the instruction it goes to
+    jumps: Vec<(usize, usize)>,
+    // where the offset of the entry table is
+    table: usize,
+}
+
+impl Assembler {
+    fn offset(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, value: i32) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    // the ModRM byte and displacement of the slot at `index` from `base`, with `reg` as the
+    // register operand or opcode extension
+    fn slot(&mut self, reg: u8, base: Base, index: i32) {
+        let rm = match base {
+            Base::Locals => 7,
+            Base::Stack => 6,
+        };
+        self.emit(&[0x80 | (reg << 3) | rm]);
+        self.imm32(index * 4);
+    }
+
+    // called as `extern "sysv64" fn(locals, stack, pc, fuel) -> (pc, fuel)`, goes to the code of
+    // instruction `pc` through the entry table, with the fuel in rdx from then on
+    fn prologue(&mut self) {
+        // mov rax, rdx; mov rdx, rcx
+        self.emit(&[0x48, 0x89, 0xd0, 0x48, 0x89, 0xca]);
+        // lea rcx, [rip + table]
+        self.emit(&[0x48, 0x8d, 0x0d]);
+        self.table = self.offset();
+        self.imm32(0);
+        // movsxd rax, dword [rcx + rax * 4]; add rax, rcx; jmp rax
+        self.emit(&[0x48, 0x63, 0x04, 0x81, 0x48, 0x01, 0xc8, 0xff, 0xe0]);
+    }
+
+    // leaves the native code at instruction `pc`
+    fn exit(&mut self, pc: usize) {
+        // mov eax, pc; ret
+        self.emit(&[0xb8]);
+        self.imm32(pc as i32);
+        self.emit(&[0xc3]);
+    }
+
+    // takes `count` fuel, leaving at `pc` with it untouched if there isn't as much
+    fn charge(&mut self, pc: usize, count: usize) {
+        // sub rdx, count; jae over the exit
+        self.emit(&[0x48, 0x81, 0xea]);
+        self.imm32(count as i32);
+        self.emit(&[0x73, 13]);
+        // add rdx, count
+        self.emit(&[0x48, 0x81, 0xc2]);
+        self.imm32(count as i32);
+        self.exit(pc);
+    }
+
+    // mov eax, [slot]
+    fn load(&mut self, base: Base, index: i32) {
+        self.emit(&[0x8b]);
+        self.slot(0, base, index);
+    }
+
+    // mov [slot], eax
+    fn store(&mut self, base: Base, index: i32) {
+        self.emit(&[0x89]);
+        self.slot(0, base, index);
+    }
+
+    // mov dword [slot], value
+    fn store_imm(&mut self, base: Base, index: i32, value: i32) {
+        self.emit(&[0xc7]);
+        self.slot(0, base, index);
+        self.imm32(value);
+    }
+
+    // add dword [slot], value
+    fn add_imm(&mut self, base: Base, index: i32, value: i32) {
+        self.emit(&[0x81]);
+        self.slot(0, base, index);
+        self.imm32(value);
+    }
+
+    // add eax, [slot]
+    fn add(&mut self, base: Base, index: i32) {
+        self.emit(&[0x03]);
+        self.slot(0, base, index);
+    }
+
+    // cmp eax, [slot]
+    fn compare(&mut self, base: Base, index: i32) {
+        self.emit(&[0x3b]);
+        self.slot(0, base, index);
+    }
+
+    // cmp dword [slot], 0
+    fn compare_zero(&mut self, base: Base, index: i32) {
+        self.emit(&[0x83]);
+        self.slot(7, base, index);
+        self.emit(&[0]);
+    }
+
+    // jmp or jcc to the code of instruction `target`
+    fn jump(&mut self, condition: Option<Condition>, target: usize) {
+        match condition {
+            None => self.emit(&[0xe9]),
+            Some(Condition::Equal) => self.emit(&[0x0f, 0x84]),
+            Some(Condition::NotEqual) => self.emit(&[0x0f, 0x85]),
+            Some(Condition::Less) => self.emit(&[0x0f, 0x8c]),
+        }
+        self.jumps.push((self.offset(), target));
+        self.imm32(0);
+    }
+
+    // resolves the jumps to `labels`, the offset of each instruction's code, and appends the
+    // entry table, whose entries where the code can't be entered go to a trap
+    fn finish(mut self, labels: &[usize], entries: &[bool]) -> Vec<u8> {
+        for &(at, target) in &self.jumps {
+            let relative = labels[target] as i32 - (at + 4) as i32;
+            self.bytes[at..at + 4].copy_from_slice(&relative.to_le_bytes());
+        }
+        // ud2
+        let trap = self.offset();
+        self.emit(&[0x0f, 0x0b]);
+        while self.offset() % 4 != 0 {
+            self.emit(&[0xcc]);
+        }
+        let table = self.offset();
+        let relative = table as i32 - (self.table + 4) as i32;
+        self.bytes[self.table..self.table + 4].copy_from_slice(&relative.to_le_bytes());
+        for (pc, entry) in entries.iter().enumerate() {
+            let target = if *entry { labels[pc] } else { trap };
+            self.imm32(target as i32 - table as i32);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod imp {
+    use std::os::raw::{c_int, c_long, c_void};
+    use std::ptr;
+
+    const PROT_READ: c_int = 1;
+    const PROT_WRITE: c_int = 2;
+    const PROT_EXEC: c_int = 4;
+    const MAP_PRIVATE: c_int = 2;
+    const MAP_ANONYMOUS: c_int = 0x20;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: c_long) -> *mut c_void;
+        fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    #[repr(C)]
+    struct Exit {
+        pc: u64,
+        fuel: u64,
+    }
+
+    /// Machine code in memory of its own, mapped executable but not writable.
+    pub(super) struct Code {
+        memory: *mut c_void,
+        len: usize,
+    }
+
+    // the mapping is only ever read and executed, and is owned by the `Code`
+    unsafe impl Send for Code {}
+
+    impl Code {
+        pub(super) fn new(bytes: &[u8]) -> Option<Code> {
+            let len = bytes.len();
+            // SAFETY: a fresh anonymous mapping, written before it is made executable
+            unsafe {
+                let memory = mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+                if memory as isize == -1 {
+                    return None;
+                }
+                let code = Code { memory, len };
+                ptr::copy_nonoverlapping(bytes.as_ptr(), memory as *mut u8, len);
+                if mprotect(memory, len, PROT_READ | PROT_EXEC) != 0 {
+                    return None;
+                }
+                Some(code)
+            }
+        }
+
+        pub(super) unsafe fn call(&self, locals: *mut i32, stack: *mut i32, pc: usize, fuel: u64) -> (usize, u64) {
+            let entry: unsafe extern "sysv64" fn(*mut i32, *mut i32, u64, u64) -> Exit = std::mem::transmute(self.memory);
+            let exit = entry(locals, stack, pc as u64, fuel);
+            (exit.pc as usize, exit.fuel)
+        }
+    }
+
+    impl Drop for Code {
+        fn drop(&mut self) {
+            // SAFETY: the mapping is no longer used
+            unsafe {
+                munmap(self.memory, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+mod imp {
+    // there is nothing to run the machine code on, so nothing is compiled
+    pub(super) enum Code {}
+
+    impl Code {
+        pub(super) fn new(_: &[u8]) -> Option<Code> {
+            None
+        }
+
+        pub(super) unsafe fn call(&self, _: *mut i32, _: *mut i32, _: usize, _: u64) -> (usize, u64) {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64", target_os = "linux"))]
+mod tests {
+    use crate::const_pool::tests::sample_const_pool;
+    use crate::jit::compile;
+    use crate::Opcode;
+
+    // sum of 0 to n - 1 for n in local 0
+    fn sum() -> Vec<Opcode> {
+        vec![
+            Opcode::iconst_0,     // 0
+            Opcode::istore_1,     // 1
+            Opcode::iconst_0,     // 2
+            Opcode::istore_2,     // 3
+            Opcode::goto(10),     // 4
+            Opcode::iload_1,      // 5
+            Opcode::iload_2,      // 6
+            Opcode::iadd,         // 7
+            Opcode::istore_1,     // 8
+            Opcode::iinc(2, 1),   // 9
+            Opcode::iload_2,      // 10
+            Opcode::iload_0,      // 11
+            Opcode::if_icmplt(5), // 12
+            Opcode::iload_1,      // 13
+            Opcode::ireturn,      // 14
+        ]
+    }
+
+    #[test]
+    fn run_compiled_loop_and_leave_at_unsupported_instruction() {
+        let native = compile(&sum(), &sample_const_pool(), 3, 2, &[]).unwrap();
+        let mut slots = vec![10, 0, 0, 0, 0];
+        // 5 instructions up to the loop, 10 times the body and 11 times the condition, then the
+        // iload_1 before the ireturn
+        let (pc, sp, fuel) = native.run(&mut slots, 0, 3, 0, 0, 1000).unwrap();
+        assert_eq!((pc, sp, fuel), (14, 4, 1000 - (5 + 5 * 10 + 3 * 11 + 1)));
+        assert_eq!(&slots[..4], &[10, 45, 10, 45]);
+        // the code can only be entered where blocks start, with the depth it expects
+        assert!(native.run(&mut slots, 0, 3, 6, 1, 1000).is_none());
+        assert!(native.run(&mut slots, 0, 3, 5, 1, 1000).is_none());
+        // and only where it gets to a loop before it is left
+        let native = compile(&[Opcode::iconst_1, Opcode::ireturn], &sample_const_pool(), 0, 1, &[]).unwrap();
+        assert!(native.run(&mut slots, 0, 0, 0, 0, 1000).is_none());
+    }
+
+    #[test]
+    fn leave_when_fuel_runs_out() {
+        let native = compile(&sum(), &sample_const_pool(), 3, 2, &[]).unwrap();
+        let mut slots = vec![10, 0, 0, 0, 0];
+        // 5 runs up to the loop condition, which takes 3 more
+        assert_eq!(native.run(&mut slots, 0, 3, 0, 0, 7).unwrap(), (10, 3, 2));
+        // the fuel left is handed back and the loop carries on where it was left
+        assert_eq!(native.run(&mut slots, 0, 3, 10, 0, 3).unwrap(), (5, 3, 0));
+        assert_eq!(&slots[..3], &[10, 0, 0]);
+    }
+
+    #[test]
+    fn refuse_code_that_leaves_its_slots() {
+        assert!(compile(&[Opcode::iload_3, Opcode::ireturn], &sample_const_pool(), 3, 1, &[]).is_none());
+        assert!(compile(&[Opcode::iconst_0, Opcode::dup], &sample_const_pool(), 0, 1, &[]).is_none());
+        assert!(compile(&[Opcode::pop], &sample_const_pool(), 0, 1, &[]).is_none());
+    }
+}
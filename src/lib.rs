@@ -1,74 +1,124 @@
-use std::fs;
+use std::path::Path;
 
-use crate::call_stack::{Frame, JvmStack};
-use crate::const_pool::ConstPool;
-use crate::method_area::{Class, Method, MethodArea};
-use crate::parser::Attribute;
+pub use crate::inline_cache::{CacheState, CallSiteStats};
+pub use crate::jit::JitThresholds;
+pub use crate::limits::{Limit, Limits};
+pub use crate::parser::{ClassFormatError, ClassFormatErrorKind};
+pub use crate::verifier::{VerifyError, VerifyErrorKind};
+pub use crate::vm::{JavaException, ObjectRef, Value, Vm, VmError};
 
-mod tests;
+pub mod assembler;
+pub mod builder;
 mod const_pool;
 mod call_stack;
+mod descriptor;
+pub mod disassembler;
+mod heap;
+mod inline_cache;
+mod instruction;
+mod jit;
+mod limits;
 mod method_area;
-mod parser;
+pub mod parser;
+mod snapshot;
+pub mod ssa;
+mod verifier;
+mod vm;
+pub mod writer;
 
+/// A JVM instruction, §6.5, as the `codes` of a [`Code`](parser::Attribute::Code) attribute
+/// hold them. Branch targets and the ranges in the attributes of the code are indices into
+/// `codes` rather than byte offsets.
 #[allow(non_camel_case_types)]
-#[derive(Debug)]
-enum Opcode {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Opcode {
+    aconst_null,
     aload_0,
+    aload_1,
+    aload_2,
+    aload_3,
+    astore_0,
+    astore_1,
+    astore_2,
+    astore_3,
     iconst_0,
     iconst_1,
+    // §6.5.wide, the indexed loads and stores, `iinc` and `ret` are written with a `wide`
+    // prefix when their operands don't fit in a byte
+    iload(usize),
+    aload(usize),
+    istore(usize),
+    astore(usize),
     iload_0,
     iload_1,
+    iload_2,
+    iload_3,
     istore_0,
     istore_1,
+    istore_2,
+    istore_3,
     iadd,
+    pop,
+    dup,
     ireturn,
+    areturn,
     goto(usize),
     iinc(usize, i32),
     bipush(i32),
+    // §6.5.ldc, written as `ldc_w` when the index doesn't fit in a byte
+    ldc(u16),
+    ifeq(usize),
+    ifne(usize),
     if_icmplt(usize),
+    // §6.5.jsr, the return address pushed is the index of the next instruction
+    jsr(usize),
+    ret(usize),
     r#return,
+    getstatic(u16),
+    putstatic(u16),
+    getfield(u16),
+    putfield(u16),
+    invokevirtual(u16),
     invokestatic(usize),
     invokespecial(u16),
+    // §6.5.invokeinterface, with its `count` operand
+    invokeinterface(u16, u8),
+    new(u16),
+    newarray(u8),
+    anewarray(u16),
+    arraylength,
+    iaload,
+    iastore,
+    athrow,
+    checkcast(u16),
+    instanceof(u16),
+    // Not in class files: optimized methods are lowered to these where no instruction does the
+    // same, and the writer and verifier reject them.
+    // pushes any int, where bipush only pushes those that fit in a byte
+    push(i32),
+    // iaload and iastore of an index known to be within the array, which they don't check
+    iaload_unchecked,
+    iastore_unchecked,
 }
 
-pub fn run(class_path: &str) {
-    let mut file = fs::File::open(class_path).unwrap();
-    let class_file = parser::parse(&mut file);
-    let const_pool = class_file.const_pool;
-    let mut method_area = MethodArea::new();
-    let class_name = &class_file.this_class;
-    for info in class_file.methods {
-        let mut method: Option<Method> = None;
-        for attribute in info.attributes {
-            match attribute {
-                Attribute::Code { max_stack, max_locals, codes } => {
-                    method = Some(Method::new(max_stack as usize, max_locals as usize, codes, class_name))
-                }
-                _ => {}
-            }
-        }
-        let method = method.unwrap();
-        let name = const_pool.resolve_utf8(info.name_index);
-        let descriptor = const_pool.resolve_utf8(info.descriptor_index);
-        let key = String::from(&class_file.this_class) + "." + name + ":" + descriptor;
-        method_area.put(Box::leak(key.into_boxed_str()), method);
+/// The instruction `code` may jump to besides the next one.
+pub(crate) fn branch_target(code: &Opcode) -> Option<usize> {
+    match *code {
+        Opcode::goto(target) | Opcode::ifeq(target) | Opcode::ifne(target) | Opcode::if_icmplt(target)
+        | Opcode::jsr(target) => Some(target),
+        _ => None,
     }
-    method_area.put_class(&class_file.this_class, Class {
-        super_class: &None,
-        const_pool,
-        methods: Default::default(),
-    });
-    let main_method = method_area.resolve_method(&*(
-        String::from(&class_file.this_class)
-            + "."
-            + "main:([Ljava/lang/String;)V"
-    ));
-    dbg!(&method_area);
-    let const_pool = &method_area.resolve_class(class_name).const_pool;
-    let mut local = Vec::with_capacity(main_method.local_size);
-    local.resize(main_method.local_size, 0);
-    let main_frame = Frame::new(local, main_method, const_pool);
-    let mut jvm_stack = JvmStack::new(256, main_frame, &method_area);
-    jvm_stack.run()
+}
+
+/// Runs the `main` method of the class in the given class file, with the directory it is in
+/// as the classpath. An exception `main` doesn't catch is returned as [`VmError::Exception`].
+pub fn run(class_path: &str) -> Result<(), VmError> {
+    let mut vm = Vm::new();
+    if let Some(dir) = Path::new(class_path).parent() {
+        vm.add_classpath(dir);
+    }
+    let class_name = vm.load_class_file(class_path)?;
+    let args = vm.new_string_array(&[]);
+    vm.invoke_static(&class_name, "main", "([Ljava/lang/String;)V", &[Value::Object(args)])?;
+    Ok(())
 }
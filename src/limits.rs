@@ -0,0 +1,160 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::heap::Heap;
+
+/// Bounds on the Java code a single call into the [`Vm`](crate::Vm) runs, for running code
+/// that isn't trusted. Reaching one stops the call with [`VmError::LimitExceeded`](crate::VmError),
+/// after which it can be [resumed](crate::Vm::resume). Unlike the `StackOverflowError` that
+/// [`Vm::set_stack_depth`](crate::Vm::set_stack_depth) bounds call depth with, the Java code
+/// can't catch any of these.
+///
+/// ```
+/// use std::time::Duration;
+/// use rj::Limits;
+///
+/// let limits = Limits { instructions: Some(1_000_000), time: Some(Duration::from_millis(50)), ..Limits::default() };
+/// assert_eq!(limits.heap_bytes, None);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Instructions executed per call
+    pub instructions: Option<u64>,
+    /// Wall-clock time per call, checked every 1024 instructions
+    pub time: Option<Duration>,
+    /// Size the heap may grow to, counting 16 bytes per object, 4 per field or array slot and
+    /// 2 per character of a string
+    pub heap_bytes: Option<usize>,
+    /// Frames that may be on the stack at once; the invoke that would go deeper is run again
+    /// when the call resumes
+    pub stack_depth: Option<usize>,
+}
+
+// reading the clock costs far more than an instruction
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// The limit a call stopped at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    Time,
+    HeapBytes,
+    StackDepth,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Instructions => write!(f, "instruction limit exceeded"),
+            Limit::Time => write!(f, "time limit exceeded"),
+            Limit::HeapBytes => write!(f, "heap limit exceeded"),
+            Limit::StackDepth => write!(f, "stack depth limit exceeded"),
+        }
+    }
+}
+
+/// What is left of the [`Limits`] of the current call; the default is unlimited.
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    // instructions that may run before the limits are checked again
+    countdown: u64,
+    // instructions the countdown started from at the last check
+    window: u64,
+    executed: u64,
+    instructions: Option<u64>,
+    deadline: Option<Instant>,
+    heap_bytes: Option<usize>,
+    stack_depth: Option<usize>,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: &Limits) -> Budget {
+        Budget {
+            instructions: limits.instructions,
+            deadline: limits.time.map(|it| Instant::now() + it),
+            heap_bytes: limits.heap_bytes,
+            stack_depth: limits.stack_depth,
+            ..Budget::default()
+        }
+    }
+
+    /// Takes the instructions that may run before the limits have to be checked again, at least
+    /// one, so that the interpreter can count them down in a register. Instructions are counted
+    /// before they run, so that a stopped frame resumes at the instruction that didn't run.
+    pub(crate) fn take(&mut self) -> Result<u64, Limit> {
+        if self.countdown == 0 {
+            self.check()?;
+        }
+        Ok(std::mem::take(&mut self.countdown))
+    }
+
+    /// Returns the instructions of the last [`take`](Budget::take) that didn't run.
+    pub(crate) fn put_back(&mut self, instructions: u64) {
+        self.countdown = instructions;
+    }
+
+    fn check(&mut self) -> Result<(), Limit> {
+        self.executed = self.executed.saturating_add(self.window);
+        let remaining = match self.instructions {
+            Some(limit) if self.executed >= limit => return Err(Limit::Instructions),
+            Some(limit) => limit - self.executed,
+            None => u64::MAX,
+        };
+        if self.deadline.is_some_and(|it| Instant::now() >= it) {
+            return Err(Limit::Time);
+        }
+        self.window = if self.deadline.is_some() { remaining.min(TIME_CHECK_INTERVAL) } else { remaining };
+        self.countdown = self.window;
+        Ok(())
+    }
+
+    /// Checks that `frames` may be on the stack, before invoking what makes them that many. The
+    /// invoke instruction is given back, since it runs again once the call resumes.
+    pub(crate) fn check_depth(&mut self, frames: usize) -> Result<(), Limit> {
+        match self.stack_depth {
+            Some(limit) if frames > limit => {
+                self.countdown += 1;
+                Err(Limit::StackDepth)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that `bytes` more can be allocated, before allocating anything that large.
+    pub(crate) fn check_heap(&self, heap: &Heap, bytes: usize) -> Result<(), Limit> {
+        match self.heap_bytes {
+            Some(limit) if heap.bytes().saturating_add(bytes) > limit => Err(Limit::HeapBytes),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::heap::Heap;
+    use crate::limits::{Budget, Limit, Limits};
+
+    #[test]
+    fn budget_runs_out() {
+        let heap = Heap::new();
+        let mut budget = Budget::new(&Limits { instructions: Some(2), ..Limits::default() });
+        assert_eq!(budget.take(), Ok(2));
+        budget.put_back(1);
+        assert_eq!(budget.take(), Ok(1));
+        assert_eq!(budget.take(), Err(Limit::Instructions));
+
+        let mut budget = Budget::new(&Limits { time: Some(Duration::from_secs(0)), ..Limits::default() });
+        assert_eq!(budget.take(), Err(Limit::Time));
+
+        let budget = Budget::new(&Limits { heap_bytes: Some(64), ..Limits::default() });
+        assert_eq!(budget.check_heap(&heap, 64), Ok(()));
+        assert_eq!(budget.check_heap(&heap, 65), Err(Limit::HeapBytes));
+        assert_eq!(Budget::default().check_heap(&heap, usize::MAX), Ok(()));
+
+        let mut budget = Budget::new(&Limits { stack_depth: Some(3), ..Limits::default() });
+        assert_eq!(budget.check_depth(3), Ok(()));
+        assert_eq!(budget.check_depth(4), Err(Limit::StackDepth));
+    }
+}
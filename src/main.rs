@@ -1,8 +1,69 @@
-use std::{env, fs};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
 
+use rj::VmError;
+
+const USAGE: &str = "usage: rj <main.class>
+       rj asm <input.j> [output.class]
+       rj disasm [--dump-ir] <input.class>";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let class_path = args.get(1).expect("no main class passed in");
-    rj::run(class_path);
+    match args.get(1).map(String::as_str) {
+        Some("asm") => {
+            let input = args.get(2).unwrap_or_else(|| usage());
+            let output = args.get(3).cloned()
+                .unwrap_or_else(|| Path::new(input).with_extension("class").to_string_lossy().into_owned());
+            if let Err(e) = assemble(input, &output) {
+                eprintln!("{}: {}", input, e);
+                process::exit(1);
+            }
+        }
+        Some("disasm") => {
+            // `--dump-ir` prints the methods in SSA form instead, before and after optimization
+            let dump_ir = args.get(2).is_some_and(|it| it == "--dump-ir");
+            let input = args.get(2 + dump_ir as usize).unwrap_or_else(|| usage());
+            match disassemble(input, dump_ir) {
+                Ok(text) => print!("{}", text),
+                Err(e) => {
+                    eprintln!("{}: {}", input, e);
+                    process::exit(1);
+                }
+            }
+        }
+        Some(class_path) => match rj::run(class_path) {
+            Ok(()) => {}
+            Err(VmError::Exception(exception)) => {
+                eprintln!("Exception in thread \"main\" {}", exception);
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", class_path, e);
+                process::exit(1);
+            }
+        },
+        None => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn assemble(input: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let source = fs::read_to_string(input)?;
+    let class_file = rj::assembler::assemble(&source)?;
+    fs::write(output, rj::writer::write(&class_file)?)?;
+    Ok(())
+}
+
+fn disassemble(input: &str, dump_ir: bool) -> Result<String, Box<dyn std::error::Error>> {
+    let class_file = rj::parser::parse(fs::File::open(input)?)?;
+    if dump_ir {
+        return Ok(rj::ssa::dump(&class_file));
+    }
+    Ok(rj::disassembler::disassemble(&class_file))
 }
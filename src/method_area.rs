@@ -1,20 +1,34 @@
+use std::cell::{Cell, OnceCell};
 use std::collections::HashMap;
+use std::fmt::{Debug, Error, Formatter};
 
-use crate::{const_pool, Opcode};
-use std::fmt::{Debug, Formatter, Error, Display};
+use crate::const_pool::{ConstPool, CpInfo};
+use crate::heap::slot_width;
+use crate::inline_cache::{CallSite, CallSiteStats};
+use crate::instruction::{decode, Decoded, Instruction};
+use crate::jit;
+use crate::jit::Native;
+use crate::parser::{AccessFlags, Attribute, ClassFile, ExceptionTableEntry};
+use crate::parser::attribute::StackMapFrame;
+use crate::ssa::{Function, Lowered};
+use crate::Opcode;
+
+const OBJECT: &str = "java/lang/Object";
+const CLONEABLE: &str = "java/lang/Cloneable";
+const SERIALIZABLE: &str = "java/io/Serializable";
 
 // JLS §12.3.2, JVMS §2.5.4
-pub struct MethodArea<'a> {
-    methods: HashMap<&'a str, Method<'a>>,
-    classes: HashMap<&'a str, Class<'a>>,
+pub struct MethodArea {
+    // methods are only ever added, so that quickened instructions can refer to them by index
+    methods: Vec<Method>,
+    method_index: HashMap<String, usize>,
+    classes: HashMap<String, Class>,
 }
 
-impl Debug for MethodArea<'_> {
+impl Debug for MethodArea {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        // write!(f, "MethodArea[classes:{}, methods{}]", )
-        // let classes = self.classes.keys().collect()
-        let classes = self.classes.keys().fold(String::from(""), |mut s, it| s + it + " ");
-        let methods = self.methods.keys().fold(String::from(""), |s, it| s + it + " ");
+        let classes = self.classes.keys().fold(String::from(""), |s, it| s + it + " ");
+        let methods = self.method_index.keys().fold(String::from(""), |s, it| s + it + " ");
         f.debug_struct("MethodArea")
             .field("classes", &classes)
             .field("methods", &methods)
@@ -22,14 +36,34 @@ impl Debug for MethodArea<'_> {
     }
 }
 
-pub(crate) struct Method<'a> {
+#[derive(Default)]
+pub(crate) struct Method {
     pub stack_size: usize,
     pub local_size: usize,
     pub codes: Vec<Opcode>,
-    pub class: &'a str,
+    pub class: String,
+    // §4.7.3, indices are into `codes`
+    pub exception_table: Vec<ExceptionTableEntry>,
+    pub name: String,
+    pub descriptor: String,
+    pub access_flags: u16,
+    // §4.7.4, checked by the verifier
+    pub stack_map: Vec<StackMapFrame>,
+    // `codes` decoded for the interpreter the first time the method runs, then quickened as it runs
+    pub decoded: OnceCell<Decoded>,
+    // calls and branches back so far, to tell when the method is hot enough to compile
+    pub invocations: Cell<u32>,
+    pub backedges: Cell<u32>,
+    // what the JIT compiled the method to, None if it couldn't
+    pub native: OnceCell<Option<Native>>,
+    // the method optimized through its SSA form, which calls run once it is hot, None if it
+    // couldn't be converted
+    pub optimized: OnceCell<Option<Box<Method>>>,
+    // whether this is the optimized code of the method of the same key
+    pub is_optimized: bool,
 }
 
-impl Method<'_> {
+impl Method {
     pub(crate) fn new(
         stack_size: usize, local_size: usize,
         codes: Vec<Opcode>, class: &str,
@@ -38,53 +72,537 @@ impl Method<'_> {
             stack_size,
             local_size,
             codes,
-            class,
+            class: class.to_string(),
+            ..Default::default()
         }
     }
+
+    /// The instructions the interpreter runs, decoded from `codes` on first use.
+    pub(crate) fn instructions(&self, constant_pool: &ConstPool) -> &[Cell<Instruction>] {
+        &self.decoded.get_or_init(|| decode(&self.codes, constant_pool)).instructions
+    }
+
+    /// The inline caches of the method's call sites, decoded along with its instructions.
+    pub(crate) fn call_sites(&self, constant_pool: &ConstPool) -> &[CallSite] {
+        &self.decoded.get_or_init(|| decode(&self.codes, constant_pool)).call_sites
+    }
+
+    /// The method compiled to native code, compiling it on first use.
+    pub(crate) fn native(&self, constant_pool: &ConstPool) -> Option<&Native> {
+        self.native.get_or_init(|| {
+            let handlers: Vec<_> = self.exception_table.iter().map(|it| it.handler_pc).collect();
+            jit::compile(&self.codes, constant_pool, self.local_size, self.stack_size, &handlers)
+        }).as_ref()
+    }
+
+    /// Counts an invocation or back-edge on `counter`, compiling the method once it reaches
+    /// `threshold`.
+    #[inline(never)]
+    pub(crate) fn count(&self, counter: &Cell<u32>, threshold: u32, constant_pool: &ConstPool) -> Option<&Native> {
+        let count = counter.get().saturating_add(1);
+        counter.set(count);
+        if count < threshold {
+            return None;
+        }
+        self.native(constant_pool)
+    }
+
+    /// The method optimized through its SSA form and lowered back to bytecode, which is also
+    /// compiled right away, as it is only asked for once the method is hot. None for methods
+    /// with exception handlers and those the SSA form can't express.
+    pub(crate) fn optimized(&self, constant_pool: &ConstPool) -> Option<&Method> {
+        self.optimized.get_or_init(|| {
+            if !self.exception_table.is_empty() {
+                return None;
+            }
+            let mut function = Function::build(
+                &self.codes, constant_pool, &self.descriptor, self.is_static(), self.local_size, &[]).ok()?;
+            function.optimize();
+            let Lowered { codes, local_size, stack_size } = function.lower(&self.descriptor, self.is_static()).ok()?;
+            let optimized = Method {
+                name: self.name.clone(),
+                descriptor: self.descriptor.clone(),
+                access_flags: self.access_flags,
+                is_optimized: true,
+                ..Method::new(stack_size, local_size, codes, &self.class)
+            };
+            optimized.native(constant_pool);
+            Some(Box::new(optimized))
+        }).as_deref()
+    }
+
+    pub(crate) fn is_static(&self) -> bool {
+        self.access_flags & AccessFlags::STATIC as u16 != 0
+    }
+
+    /// `Class.name:descriptor`, the key the method is stored under.
+    pub(crate) fn key(&self) -> String {
+        format!("{}.{}:{}", self.class, self.name, self.descriptor)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Field {
+    pub(crate) access_flags: u16,
+    pub(crate) name: String,
+    pub(crate) descriptor: String,
+    // §4.7.2, index of the initial value of a static field in the class's constant pool
+    pub(crate) constant_value: Option<u16>,
+}
+
+// §5.3
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ClassLoader {
+    Bootstrap,
+    App,
+}
+
+pub(crate) struct Class {
+    pub(crate) name: String,
+    pub(crate) access_flags: u16,
+    pub(crate) super_class: Option<String>,
+    pub(crate) interfaces: Vec<String>,
+    pub(crate) fields: Vec<Field>,
+    pub const_pool: ConstPool,
+    pub(crate) loader: ClassLoader,
+    // class file version, which decides how the class is verified
+    pub(crate) major: u16,
 }
 
-pub(crate) struct Class<'a> {
-    pub(crate) super_class: &'a Option<Class<'a>>,
-    pub const_pool: const_pool::ConstPool,
-    pub(crate) methods: HashMap<&'a str, Method<'a>>,
+impl Class {
+    pub(crate) fn is_interface(&self) -> bool {
+        self.access_flags & AccessFlags::INTERFACE as u16 != 0
+    }
+
+    /// Fields declared by this class that are not static, in declaration order.
+    fn instance_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(|it| it.access_flags & AccessFlags::STATIC as u16 == 0)
+    }
 }
 
-impl<'a> MethodArea<'a> {
-    pub(crate) fn new() -> MethodArea<'a> {
-        MethodArea {
+impl MethodArea {
+    /// Creates a method area with the handful of `java.lang` classes the
+    /// interpreter needs to exist, standing in for the bootstrap class loader.
+    pub(crate) fn new() -> MethodArea {
+        let mut method_area = MethodArea {
             methods: Default::default(),
+            method_index: Default::default(),
             classes: Default::default(),
+        };
+        let interface = AccessFlags::PUBLIC as u16 | AccessFlags::INTERFACE as u16 | AccessFlags::ABSTRACT as u16;
+        let class = AccessFlags::PUBLIC as u16 | AccessFlags::SUPER as u16;
+        let bootstrap: [(&str, u16, Option<&str>, &[&str]); 24] = [
+            (OBJECT, class, None, &[]),
+            (CLONEABLE, interface, Some(OBJECT), &[]),
+            (SERIALIZABLE, interface, Some(OBJECT), &[]),
+            ("java/lang/Comparable", interface, Some(OBJECT), &[]),
+            ("java/lang/CharSequence", interface, Some(OBJECT), &[]),
+            ("java/lang/String", class | AccessFlags::FINAL as u16, Some(OBJECT),
+             &[SERIALIZABLE, "java/lang/Comparable", "java/lang/CharSequence"]),
+            ("java/lang/Throwable", class, Some(OBJECT), &[SERIALIZABLE]),
+            ("java/lang/Exception", class, Some("java/lang/Throwable"), &[]),
+            ("java/lang/RuntimeException", class, Some("java/lang/Exception"), &[]),
+            ("java/lang/ClassCastException", class, Some("java/lang/RuntimeException"), &[]),
+            ("java/lang/NullPointerException", class, Some("java/lang/RuntimeException"), &[]),
+            ("java/lang/NegativeArraySizeException", class, Some("java/lang/RuntimeException"), &[]),
+            ("java/lang/IndexOutOfBoundsException", class, Some("java/lang/RuntimeException"), &[]),
+            ("java/lang/ArrayIndexOutOfBoundsException", class, Some("java/lang/IndexOutOfBoundsException"), &[]),
+            ("java/lang/Error", class, Some("java/lang/Throwable"), &[]),
+            ("java/lang/LinkageError", class, Some("java/lang/Error"), &[]),
+            ("java/lang/VerifyError", class, Some("java/lang/LinkageError"), &[]),
+            ("java/lang/NoClassDefFoundError", class, Some("java/lang/LinkageError"), &[]),
+            ("java/lang/IncompatibleClassChangeError", class, Some("java/lang/LinkageError"), &[]),
+            ("java/lang/NoSuchFieldError", class, Some("java/lang/IncompatibleClassChangeError"), &[]),
+            ("java/lang/NoSuchMethodError", class, Some("java/lang/IncompatibleClassChangeError"), &[]),
+            ("java/lang/InstantiationError", class, Some("java/lang/IncompatibleClassChangeError"), &[]),
+            ("java/lang/VirtualMachineError", class | AccessFlags::ABSTRACT as u16, Some("java/lang/Error"), &[]),
+            ("java/lang/StackOverflowError", class, Some("java/lang/VirtualMachineError"), &[]),
+        ];
+        for (name, access_flags, super_class, interfaces) in bootstrap.iter() {
+            let fields = match *name {
+                "java/lang/Throwable" => vec![Field {
+                    access_flags: AccessFlags::PRIVATE as u16,
+                    name: "detailMessage".to_string(),
+                    descriptor: "Ljava/lang/String;".to_string(),
+                    constant_value: None,
+                }],
+                _ => vec![],
+            };
+            method_area.put_class(Class {
+                name: name.to_string(),
+                access_flags: *access_flags,
+                super_class: super_class.map(String::from),
+                interfaces: interfaces.iter().map(|it| it.to_string()).collect(),
+                fields,
+                const_pool: ConstPool::from_vec(vec![]),
+                loader: ClassLoader::Bootstrap,
+                major: 52,
+            });
+            if access_flags & AccessFlags::INTERFACE as u16 == 0 {
+                method_area.put(&format!("{}.<init>:()V", name), Method {
+                    name: "<init>".to_string(),
+                    descriptor: "()V".to_string(),
+                    ..Method::new(0, 1, vec![Opcode::r#return], name)
+                });
+            }
+        }
+        method_area
+    }
+
+    pub(crate) fn put(&mut self, key: &str, method: Method) {
+        self.method_index.insert(key.to_string(), self.methods.len());
+        self.methods.push(method);
+    }
+
+    pub(crate) fn put_class(&mut self, class: Class) {
+        self.classes.insert(class.name.clone(), class);
+        self.flush_inline_caches();
+    }
+
+    // inline caches hold for the classes that were loaded when they were filled
+    fn flush_inline_caches(&self) {
+        for method in &self.methods {
+            if let Some(decoded) = method.decoded.get() {
+                decoded.call_sites.iter().for_each(CallSite::flush);
+            }
+        }
+    }
+
+    /// The inline caches of the call sites of all methods that have run, ordered by method
+    /// and then instruction.
+    pub(crate) fn call_site_stats(&self) -> Vec<CallSiteStats> {
+        let mut stats = Vec::new();
+        for (key, index) in &self.method_index {
+            let method = &self.methods[*index];
+            let (decoded, class) = match (method.decoded.get(), self.classes.get(&method.class)) {
+                (Some(decoded), Some(class)) => (decoded, class),
+                _ => continue,
+            };
+            stats.extend(decoded.call_sites.iter().map(|site| CallSiteStats {
+                method: key.clone(),
+                pc: site.pc,
+                target: class.const_pool.resolve_method_ref(site.index).map_or_else(String::new, |it| it.key.clone()),
+                state: site.state(),
+                hits: site.hits(),
+                misses: site.misses(),
+            }));
         }
+        stats.sort_by(|a, b| (&a.method, a.pc).cmp(&(&b.method, b.pc)));
+        stats
     }
 
-    pub(crate) fn put(&mut self, key: &'a str, method: Method<'a>) {
-        self.methods.insert(key, method);
+    pub(crate) fn contains_class(&self, name: &str) -> bool {
+        self.classes.contains_key(name)
     }
 
-    pub(crate) fn put_class(&mut self, key: &'a str, class: Class<'a>) {
-        self.classes.insert(key, class);
+    /// Defines a class loaded by the application class loader from its parsed form.
+    pub(crate) fn define_class(&mut self, class_file: ClassFile) {
+        let fields = class_file.fields.iter().map(|info| Field {
+            access_flags: info.access_flags,
+            name: class_file.const_pool.utf8(info.name_index).unwrap().to_string(),
+            descriptor: class_file.const_pool.utf8(info.descriptor_index).unwrap().to_string(),
+            // ignored for instance fields
+            constant_value: info.attributes.iter()
+                .filter(|_| info.access_flags & AccessFlags::STATIC as u16 != 0)
+                .find_map(|it| match it {
+                    Attribute::ConstantValue { constantvalue_index } => Some(*constantvalue_index),
+                    _ => None,
+                }),
+        }).collect();
+        let class_name = class_file.this_class;
+        for info in class_file.methods {
+            let name = class_file.const_pool.utf8(info.name_index).unwrap();
+            let descriptor = class_file.const_pool.utf8(info.descriptor_index).unwrap();
+            let key = format!("{}.{}:{}", class_name, name, descriptor);
+            for attribute in info.attributes {
+                if let Attribute::Code { max_stack, max_locals, codes, exception_table, attributes } = attribute {
+                    let mut method = Method::new(max_stack as usize, max_locals as usize, codes, &class_name);
+                    method.exception_table = exception_table;
+                    method.name = name.to_string();
+                    method.descriptor = descriptor.to_string();
+                    method.access_flags = info.access_flags;
+                    method.stack_map = attributes.into_iter()
+                        .find_map(|it| match it {
+                            Attribute::StackMapTable(frames) => Some(frames),
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+                    self.put(&key, method);
+                }
+            }
+        }
+        self.put_class(Class {
+            name: class_name,
+            access_flags: class_file.access_flags,
+            super_class: class_file.super_class,
+            interfaces: class_file.interfaces,
+            fields,
+            const_pool: class_file.const_pool,
+            loader: ClassLoader::App,
+            major: class_file.major,
+        });
     }
 
+    /// Removes a class that failed to link, along with its methods.
+    pub(crate) fn remove_class(&mut self, name: &str) {
+        let prefix = format!("{}.", name);
+        // its methods stay behind, unreachable, since nothing can have been linked to them
+        self.method_index.retain(|key, _| !key.starts_with(&prefix));
+        self.classes.remove(name);
+        self.flush_inline_caches();
+    }
+
+    /// Methods declared by `class` that have code, ordered by key.
+    pub(crate) fn methods_of(&self, class: &str) -> Vec<(&str, &Method)> {
+        let prefix = format!("{}.", class);
+        let mut methods: Vec<_> = self.method_index.iter()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, index)| (key.as_str(), &self.methods[*index]))
+            .collect();
+        methods.sort_by_key(|(key, _)| *key);
+        methods
+    }
+
+    /// Looks up `Class.name:descriptor`, searching superclasses of `Class` as in §5.4.3.3.
+    pub(crate) fn find_method(&self, key: &str) -> Option<&Method> {
+        self.find_method_index(key).map(|it| &self.methods[it])
+    }
+
+    /// Like [`find_method`](MethodArea::find_method), returning the index [`method`](MethodArea::method)
+    /// takes.
+    pub(crate) fn find_method_index(&self, key: &str) -> Option<usize> {
+        if let Some(index) = self.method_index.get(key) {
+            return Some(*index);
+        }
+        let (class, member) = key.split_at(key.find('.').unwrap_or(0));
+        let mut current = self.classes.get(class).and_then(|it| it.super_class.as_deref());
+        while let Some(name) = current {
+            if let Some(index) = self.method_index.get(&format!("{}{}", name, member)) {
+                return Some(*index);
+            }
+            current = self.classes.get(name).and_then(|it| it.super_class.as_deref());
+        }
+        None
+    }
+
+    /// Like [`find_method`](MethodArea::find_method) for methods known to exist, such as those
+    /// of suspended frames.
     pub(crate) fn resolve_method(&self, key: &str) -> &Method {
-        let method = self.methods.get(key);
-        match method {
+        match self.find_method(key) {
             None => panic!("Cannot find method {}", key),
-            Some(m) => return m
+            Some(method) => method
         }
     }
 
+    pub(crate) fn method(&self, index: usize) -> &Method {
+        &self.methods[index]
+    }
+
     pub(crate) fn resolve_class(&self, key: &str) -> &Class {
         let class = self.classes.get(key);
         match class {
             None => panic!("{:?} class not available", key),
-            Some(c) => return c
+            Some(c) => c
+        }
+    }
+
+    /// Whether `new` may create instances of `class`, which interfaces and abstract classes
+    /// can't have, §6.5.new.
+    pub(crate) fn is_instantiable(&self, class: &str) -> bool {
+        let abstract_flags = AccessFlags::INTERFACE as u16 | AccessFlags::ABSTRACT as u16;
+        self.classes.get(class).is_some_and(|it| it.access_flags & abstract_flags == 0)
+    }
+
+    /// Number of 32-bit slots an instance of `class` needs for its own
+    /// and inherited instance fields.
+    pub(crate) fn instance_slot_count(&self, class: &str) -> usize {
+        match self.classes.get(class) {
+            Some(c) => {
+                let own: usize = c.instance_fields().map(|it| slot_width(&it.descriptor)).sum();
+                own + c.super_class.as_ref().map_or(0, |it| self.instance_slot_count(it))
+            }
+            None => 0,
+        }
+    }
+
+    /// Slot of the named instance field, with superclass fields laid out first.
+    pub(crate) fn field_slot(&self, class: &str, name: &str) -> Option<usize> {
+        self.instance_field(class, name).map(|(slot, _)| slot)
+    }
+
+    pub(crate) fn instance_field(&self, class: &str, name: &str) -> Option<(usize, &Field)> {
+        let c = self.classes.get(class)?;
+        // a field may hide one of the same name in a superclass, so the class itself is searched first
+        let mut slot = c.super_class.as_ref().map_or(0, |it| self.instance_slot_count(it));
+        for field in c.instance_fields() {
+            if field.name == name {
+                return Some((slot, field));
+            }
+            slot += slot_width(&field.descriptor);
+        }
+        c.super_class.as_ref().and_then(|it| self.instance_field(it, name))
+    }
+
+    /// Resolves a static field as in §5.4.3.2, returning the class that
+    /// declares it and its slot among that class's statics.
+    pub(crate) fn static_field(&self, class: &str, name: &str) -> Option<(&str, usize, &Field)> {
+        let c = self.classes.get(class)?;
+        let mut slot = 0;
+        for field in c.fields.iter().filter(|it| it.access_flags & AccessFlags::STATIC as u16 != 0) {
+            if field.name == name {
+                return Some((&c.name, slot, field));
+            }
+            slot += slot_width(&field.descriptor);
+        }
+        c.interfaces.iter()
+            .find_map(|it| self.static_field(it, name))
+            .or_else(|| c.super_class.as_ref().and_then(|it| self.static_field(it, name)))
+    }
+
+    /// Slot and constant pool entry of each static field of `class` that has a `ConstantValue`.
+    pub(crate) fn constant_values(&self, class: &str) -> Vec<(usize, &CpInfo)> {
+        let c = match self.classes.get(class) {
+            Some(c) => c,
+            None => return vec![],
+        };
+        let mut constants = Vec::new();
+        let mut slot = 0;
+        for field in c.fields.iter().filter(|it| it.access_flags & AccessFlags::STATIC as u16 != 0) {
+            if let Some(value) = field.constant_value.and_then(|it| c.const_pool.get(it)) {
+                constants.push((slot, value));
+            }
+            slot += slot_width(&field.descriptor);
+        }
+        constants
+    }
+
+    pub(crate) fn static_slot_count(&self, class: &str) -> usize {
+        self.classes.get(class).map_or(0, |c| c.fields.iter()
+            .filter(|it| it.access_flags & AccessFlags::STATIC as u16 != 0)
+            .map(|it| slot_width(&it.descriptor))
+            .sum())
+    }
+
+    /// Slot and type of each field of `class` that holds a reference, among its statics or else
+    /// among its own and inherited instance fields, with types as [`is_assignable`](MethodArea::is_assignable)
+    /// takes them.
+    pub(crate) fn reference_fields(&self, class: &str, statics: bool) -> Vec<(usize, &str)> {
+        let c = match self.classes.get(class) {
+            Some(c) => c,
+            None => return vec![],
+        };
+        let (mut fields, mut slot) = match &c.super_class {
+            Some(super_class) if !statics => (self.reference_fields(super_class, false), self.instance_slot_count(super_class)),
+            _ => (vec![], 0),
+        };
+        for field in c.fields.iter().filter(|it| (it.access_flags & AccessFlags::STATIC as u16 != 0) == statics) {
+            if let Some(t) = component_class(&field.descriptor) {
+                fields.push((slot, t));
+            }
+            slot += slot_width(&field.descriptor);
+        }
+        fields
+    }
+
+    fn is_interface(&self, class: &str) -> bool {
+        self.classes.get(class).is_some_and(|it| it.is_interface())
+    }
+
+    fn is_subclass(&self, class: &str, of: &str) -> bool {
+        let mut current = Some(class);
+        while let Some(name) = current {
+            if name == of {
+                return true;
+            }
+            current = self.classes.get(name).and_then(|it| it.super_class.as_deref());
+        }
+        false
+    }
+
+    fn implements(&self, class: &str, interface: &str) -> bool {
+        let mut current = Some(class);
+        while let Some(name) = current {
+            let c = match self.classes.get(name) {
+                Some(c) => c,
+                None => return false,
+            };
+            if c.interfaces.iter().any(|it| it == interface || self.implements(it, interface)) {
+                return true;
+            }
+            current = c.super_class.as_deref();
+        }
+        false
+    }
+
+    /// Whether a value of runtime type `s` can be assigned to type `t`, following
+    /// the rules of `checkcast` and `instanceof` (§6.5). Both are class names in
+    /// internal form, array classes as descriptors, e.g. `[Ljava/lang/String;`.
+    pub(crate) fn is_assignable(&self, s: &str, t: &str) -> bool {
+        if s == t {
+            return true;
+        }
+        match (s.strip_prefix('['), t.strip_prefix('[')) {
+            (Some(sc), Some(tc)) => match (component_class(sc), component_class(tc)) {
+                (Some(sc), Some(tc)) => self.is_assignable(sc, tc),
+                // primitive component types must be identical
+                _ => false,
+            },
+            (Some(_), None) => t == OBJECT || t == CLONEABLE || t == SERIALIZABLE,
+            (None, Some(_)) => false,
+            (None, None) => if self.is_interface(t) {
+                self.implements(s, t)
+            } else {
+                self.is_subclass(s, t)
+            },
+        }
+    }
+
+    /// Message of the `ClassCastException` thrown when `s` cannot be cast to `t`,
+    /// in the format HotSpot uses.
+    pub(crate) fn class_cast_message(&self, s: &str, t: &str) -> String {
+        let (s_name, t_name) = (external_name(s), external_name(t));
+        let (s_loader, t_loader) = (self.loader_description(s), self.loader_description(t));
+        if s_loader == t_loader {
+            format!("class {} cannot be cast to class {} ({} and {} are in {})",
+                    s_name, t_name, s_name, t_name, s_loader)
+        } else {
+            format!("class {} cannot be cast to class {} ({} is in {}; {} is in {})",
+                    s_name, t_name, s_name, s_loader, t_name, t_loader)
+        }
+    }
+
+    fn loader_description(&self, class: &str) -> &'static str {
+        let element = class.trim_start_matches('[');
+        let loader = match (class.starts_with('['), component_class(element)) {
+            (false, _) => self.classes.get(class).map(|it| it.loader),
+            (true, Some(name)) => self.classes.get(name).map(|it| it.loader),
+            (true, None) => Some(ClassLoader::Bootstrap),
+        };
+        match loader {
+            Some(ClassLoader::Bootstrap) => "module java.base of loader 'bootstrap'",
+            _ => "unnamed module of loader 'app'",
         }
     }
 }
 
+/// Class name of a reference component type, `None` for primitives.
+pub(crate) fn component_class(descriptor: &str) -> Option<&str> {
+    if descriptor.starts_with('[') {
+        Some(descriptor)
+    } else {
+        descriptor.strip_prefix('L').and_then(|it| it.strip_suffix(';'))
+    }
+}
+
+pub(crate) fn external_name(class: &str) -> String {
+    class.replace('/', ".")
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::method_area::{Method, MethodArea};
+    use crate::const_pool::ConstPool;
+    use crate::method_area::{Class, ClassLoader, Method, MethodArea};
+    use crate::parser::AccessFlags;
 
     #[test]
     fn can_put_and_resolve_from_method_table() {
@@ -93,8 +611,107 @@ mod tests {
             codes: vec![],
             stack_size: 2,
             local_size: 0,
-            class: "",
+            class: "".to_string(),
+            ..Default::default()
         });
         table.resolve_method("foo");
     }
+
+    fn put_class(method_area: &mut MethodArea, name: &str, super_class: &str, interfaces: &[&str], interface: bool) {
+        let access_flags = if interface { AccessFlags::INTERFACE as u16 } else { 0 };
+        method_area.put_class(Class {
+            name: name.to_string(),
+            access_flags,
+            super_class: Some(super_class.to_string()),
+            interfaces: interfaces.iter().map(|it| it.to_string()).collect(),
+            fields: vec![],
+            const_pool: ConstPool::from_vec(vec![]),
+            loader: ClassLoader::App,
+            major: 52,
+        });
+    }
+
+    /// ```java
+    /// interface Shape {}
+    /// interface Polygon extends Shape {}
+    /// class Square implements Polygon {}
+    /// class Cube extends Square {}
+    /// class Circle implements Shape {}
+    /// ```
+    fn shapes() -> MethodArea {
+        let mut method_area = MethodArea::new();
+        put_class(&mut method_area, "Shape", "java/lang/Object", &[], true);
+        put_class(&mut method_area, "Polygon", "java/lang/Object", &["Shape"], true);
+        put_class(&mut method_area, "Square", "java/lang/Object", &["Polygon"], false);
+        put_class(&mut method_area, "Cube", "Square", &[], false);
+        put_class(&mut method_area, "Circle", "java/lang/Object", &["Shape"], false);
+        method_area
+    }
+
+    #[test]
+    fn class_assignable_to_superclasses() {
+        let method_area = shapes();
+        assert!(method_area.is_assignable("Cube", "Square"));
+        assert!(method_area.is_assignable("Cube", "java/lang/Object"));
+        assert!(!method_area.is_assignable("Square", "Cube"));
+        assert!(!method_area.is_assignable("Circle", "Square"));
+    }
+
+    #[test]
+    fn class_assignable_to_inherited_interfaces() {
+        let method_area = shapes();
+        assert!(method_area.is_assignable("Cube", "Polygon"));
+        assert!(method_area.is_assignable("Cube", "Shape"));
+        assert!(method_area.is_assignable("Circle", "Shape"));
+        assert!(!method_area.is_assignable("Circle", "Polygon"));
+        assert!(method_area.is_assignable("Polygon", "Shape"));
+        assert!(method_area.is_assignable("Polygon", "java/lang/Object"));
+        assert!(method_area.is_assignable("java/lang/String", "java/lang/CharSequence"));
+    }
+
+    #[test]
+    fn arrays_are_covariant_for_references() {
+        let method_area = shapes();
+        assert!(method_area.is_assignable("[LCube;", "[LShape;"));
+        assert!(method_area.is_assignable("[[LCube;", "[[LSquare;"));
+        assert!(method_area.is_assignable("[[LCube;", "[Ljava/lang/Object;"));
+        assert!(!method_area.is_assignable("[LShape;", "[LCube;"));
+        assert!(!method_area.is_assignable("[LCube;", "[[LCube;"));
+    }
+
+    #[test]
+    fn arrays_assignable_to_object_cloneable_and_serializable() {
+        let method_area = shapes();
+        assert!(method_area.is_assignable("[I", "java/lang/Object"));
+        assert!(method_area.is_assignable("[LCube;", "java/lang/Cloneable"));
+        assert!(method_area.is_assignable("[[D", "java/io/Serializable"));
+        assert!(!method_area.is_assignable("[I", "Shape"));
+        assert!(!method_area.is_assignable("java/lang/Object", "[I"));
+    }
+
+    #[test]
+    fn primitive_arrays_require_identical_components() {
+        let method_area = shapes();
+        assert!(method_area.is_assignable("[I", "[I"));
+        assert!(!method_area.is_assignable("[I", "[J"));
+        assert!(!method_area.is_assignable("[I", "[Ljava/lang/Object;"));
+        assert!(method_area.is_assignable("[[I", "[Ljava/lang/Object;"));
+    }
+
+    #[test]
+    fn class_cast_message_names_loaders() {
+        let method_area = shapes();
+        assert_eq!(
+            method_area.class_cast_message("Circle", "Square"),
+            "class Circle cannot be cast to class Square (Circle and Square are in unnamed module of loader 'app')"
+        );
+        assert_eq!(
+            method_area.class_cast_message("[I", "[Ljava/lang/String;"),
+            "class [I cannot be cast to class [Ljava.lang.String; ([I and [Ljava.lang.String; are in module java.base of loader 'bootstrap')"
+        );
+        assert_eq!(
+            method_area.class_cast_message("Circle", "java/lang/String"),
+            "class Circle cannot be cast to class java.lang.String (Circle is in unnamed module of loader 'app'; java.lang.String is in module java.base of loader 'bootstrap')"
+        );
+    }
 }
@@ -1,74 +1,668 @@
 use crate::const_pool::ConstPool;
-use crate::method_area::Method;
 use crate::Opcode;
-use crate::parser::{Attribute};
+use crate::parser::{Attribute, ClassFormatError, ClassFormatErrorKind, ExceptionTableEntry};
 use crate::parser::Reader;
 
-pub(crate) fn read_attribute(reader: &mut Reader, const_pool: &ConstPool) -> Attribute {
-    let name_index = reader.read_u16();
-    let name = const_pool.resolve_utf8(name_index);
-    match name {
-        "Code" => read_code_attribute(reader),
-        _ => unimplemented!("name: {}", name)
+// §4.7.4, `offset` is the index into `codes` of the instruction the frame applies to
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackMapFrame {
+    Same { offset: usize },
+    SameLocals1StackItem { offset: usize, stack: VerificationTypeInfo },
+    // removes the last `k` locals, 1 to 3
+    Chop { offset: usize, k: u8 },
+    Append { offset: usize, locals: Vec<VerificationTypeInfo> },
+    Full { offset: usize, locals: Vec<VerificationTypeInfo>, stack: Vec<VerificationTypeInfo> },
+}
+
+impl StackMapFrame {
+    pub(crate) fn offset(&self) -> usize {
+        match self {
+            StackMapFrame::Same { offset }
+            | StackMapFrame::SameLocals1StackItem { offset, .. }
+            | StackMapFrame::Chop { offset, .. }
+            | StackMapFrame::Append { offset, .. }
+            | StackMapFrame::Full { offset, .. } => *offset,
+        }
+    }
+
+    /// The most compact `frame_type` that encodes this frame with the given offset delta.
+    pub(crate) fn frame_type(&self, delta: u16) -> u8 {
+        match self {
+            StackMapFrame::Same { .. } if delta < 64 => delta as u8,
+            StackMapFrame::Same { .. } => 251,
+            StackMapFrame::SameLocals1StackItem { .. } if delta < 64 => 64 + delta as u8,
+            StackMapFrame::SameLocals1StackItem { .. } => 247,
+            StackMapFrame::Chop { k, .. } => 251 - k,
+            StackMapFrame::Append { locals, .. } => 251 + locals.len() as u8,
+            StackMapFrame::Full { .. } => 255,
+        }
+    }
+}
+
+// §4.7.4, a long or double is a single entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerificationTypeInfo {
+    Top,
+    Integer,
+    Float,
+    Double,
+    Long,
+    Null,
+    UninitializedThis,
+    Object(u16),
+    // index into `codes` of the `new` instruction that created the object
+    Uninitialized(usize),
+}
+
+// §4.7.6
+#[derive(Debug, Clone, PartialEq)]
+pub struct InnerClass {
+    pub inner_class_info_index: u16,
+    pub outer_class_info_index: u16,
+    pub inner_name_index: u16,
+    pub inner_class_access_flags: u16,
+}
+
+// §4.7.12
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineNumber {
+    pub start_pc: usize,
+    pub line_number: u16,
+}
+
+// §4.7.13 and §4.7.14, the variable has a value in `codes[start_pc..end_pc]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariable {
+    pub start_pc: usize,
+    pub end_pc: usize,
+    pub name_index: u16,
+    // the signature in a LocalVariableTypeTable
+    pub descriptor_index: u16,
+    pub index: u16,
+}
+
+// §4.7.16
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub type_index: u16,
+    // (element_name_index, value)
+    pub element_value_pairs: Vec<(u16, ElementValue)>,
+}
+
+// §4.7.16.1
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementValue {
+    // `tag` is one of `BCDFIJSZs`
+    Const { tag: u8, const_value_index: u16 },
+    Enum { type_name_index: u16, const_name_index: u16 },
+    Class { class_info_index: u16 },
+    Annotation(Annotation),
+    Array(Vec<ElementValue>),
+}
+
+// §4.7.20
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAnnotation {
+    pub target_type: u8,
+    pub target_info: TargetInfo,
+    // (type_path_kind, type_argument_index)
+    pub target_path: Vec<(u8, u8)>,
+    pub annotation: Annotation,
+}
+
+// §4.7.20.1
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetInfo {
+    TypeParameter { type_parameter_index: u8 },
+    Supertype { supertype_index: u16 },
+    TypeParameterBound { type_parameter_index: u8, bound_index: u8 },
+    Empty,
+    FormalParameter { formal_parameter_index: u8 },
+    Throws { throws_type_index: u16 },
+    LocalVar(Vec<LocalVarTarget>),
+    Catch { exception_table_index: u16 },
+    Offset(usize),
+    TypeArgument { offset: usize, type_argument_index: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVarTarget {
+    pub start_pc: usize,
+    pub end_pc: usize,
+    pub index: u16,
+}
+
+// §4.7.23
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapMethod {
+    pub bootstrap_method_ref: u16,
+    pub bootstrap_arguments: Vec<u16>,
+}
+
+// §4.7.24
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodParameter {
+    pub name_index: u16,
+    pub access_flags: u16,
+}
+
+// §4.7.25
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub module_name_index: u16,
+    pub module_flags: u16,
+    pub module_version_index: u16,
+    pub requires: Vec<Requires>,
+    pub exports: Vec<Exports>,
+    pub opens: Vec<Exports>,
+    pub uses: Vec<u16>,
+    pub provides: Vec<Provides>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requires {
+    pub requires_index: u16,
+    pub requires_flags: u16,
+    pub requires_version_index: u16,
+}
+
+// an `exports` or `opens` entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exports {
+    pub index: u16,
+    pub flags: u16,
+    pub to: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provides {
+    pub provides_index: u16,
+    pub with: Vec<u16>,
+}
+
+// §4.7.30
+#[derive(Debug)]
+pub struct RecordComponent {
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub attributes: Vec<Attribute>,
+}
+
+/// Byte offset of each instruction of a `Code` attribute, to map the pcs in its own attributes.
+struct CodeOffsets<'a> {
+    offsets: &'a [usize],
+    length: usize,
+}
+
+pub(crate) fn read_attribute(reader: &mut Reader, const_pool: &ConstPool) -> Result<Attribute, ClassFormatError> {
+    read_attribute_in(reader, const_pool, None)
+}
+
+fn read_attribute_in(
+    reader: &mut Reader,
+    const_pool: &ConstPool,
+    code: Option<&CodeOffsets>,
+) -> Result<Attribute, ClassFormatError> {
+    let name_index = reader.read_u16()?;
+    let name = const_pool.utf8(name_index).ok_or_else(|| ClassFormatError::new(
+        reader.position - 2, ClassFormatErrorKind::InvalidConstantPoolIndex(name_index)))?;
+    let length = reader.read_u32()?;
+    let start = reader.position;
+    let attribute = match name {
+        "ConstantValue" => Attribute::ConstantValue { constantvalue_index: reader.read_u16()? },
+        "Code" => read_code_attribute(reader, const_pool)?,
+        "StackMapTable" => Attribute::StackMapTable(read_stack_map_table(reader, code)?),
+        "Exceptions" => Attribute::Exceptions(read_indices(reader)?),
+        "InnerClasses" => Attribute::InnerClasses(read_table(reader, |reader| Ok(InnerClass {
+            inner_class_info_index: reader.read_u16()?,
+            outer_class_info_index: reader.read_u16()?,
+            inner_name_index: reader.read_u16()?,
+            inner_class_access_flags: reader.read_u16()?,
+        }))?),
+        "EnclosingMethod" => Attribute::EnclosingMethod {
+            class_index: reader.read_u16()?,
+            method_index: reader.read_u16()?,
+        },
+        "Synthetic" => Attribute::Synthetic,
+        "Signature" => Attribute::Signature { signature_index: reader.read_u16()? },
+        "SourceFile" => Attribute::SourceFile { sourcefile_index: reader.read_u16()? },
+        "SourceDebugExtension" => Attribute::SourceDebugExtension(reader.read_bytes(length)?),
+        "LineNumberTable" => Attribute::LineNumberTable(read_table(reader, |reader| Ok(LineNumber {
+            start_pc: read_pc(reader, code)?,
+            line_number: reader.read_u16()?,
+        }))?),
+        "LocalVariableTable" => Attribute::LocalVariableTable(read_table(reader, |reader| read_local_variable(reader, code))?),
+        "LocalVariableTypeTable" => Attribute::LocalVariableTypeTable(read_table(reader, |reader| read_local_variable(reader, code))?),
+        "Deprecated" => Attribute::Deprecated,
+        "RuntimeVisibleAnnotations" => Attribute::RuntimeVisibleAnnotations(read_table(reader, read_annotation)?),
+        "RuntimeInvisibleAnnotations" => Attribute::RuntimeInvisibleAnnotations(read_table(reader, read_annotation)?),
+        "RuntimeVisibleParameterAnnotations" => Attribute::RuntimeVisibleParameterAnnotations(read_parameter_annotations(reader)?),
+        "RuntimeInvisibleParameterAnnotations" => Attribute::RuntimeInvisibleParameterAnnotations(read_parameter_annotations(reader)?),
+        "RuntimeVisibleTypeAnnotations" => Attribute::RuntimeVisibleTypeAnnotations(
+            read_table(reader, |reader| read_type_annotation(reader, code))?),
+        "RuntimeInvisibleTypeAnnotations" => Attribute::RuntimeInvisibleTypeAnnotations(
+            read_table(reader, |reader| read_type_annotation(reader, code))?),
+        "AnnotationDefault" => Attribute::AnnotationDefault(read_element_value(reader)?),
+        "BootstrapMethods" => Attribute::BootstrapMethods(read_table(reader, |reader| Ok(BootstrapMethod {
+            bootstrap_method_ref: reader.read_u16()?,
+            bootstrap_arguments: read_indices(reader)?,
+        }))?),
+        "MethodParameters" => {
+            let parameters_count = reader.read_u8()?;
+            let mut parameters = Vec::with_capacity(parameters_count as usize);
+            for _ in 0..parameters_count {
+                parameters.push(MethodParameter { name_index: reader.read_u16()?, access_flags: reader.read_u16()? });
+            }
+            Attribute::MethodParameters(parameters)
+        }
+        "Module" => Attribute::Module(read_module(reader)?),
+        "ModulePackages" => Attribute::ModulePackages(read_indices(reader)?),
+        "ModuleMainClass" => Attribute::ModuleMainClass { main_class_index: reader.read_u16()? },
+        "NestHost" => Attribute::NestHost { host_class_index: reader.read_u16()? },
+        "NestMembers" => Attribute::NestMembers(read_indices(reader)?),
+        "Record" => Attribute::Record(read_table(reader, |reader| Ok(RecordComponent {
+            name_index: reader.read_u16()?,
+            descriptor_index: reader.read_u16()?,
+            attributes: read_table(reader, |reader| read_attribute(reader, const_pool))?,
+        }))?),
+        "PermittedSubclasses" => Attribute::PermittedSubclasses(read_indices(reader)?),
+        _ => Attribute::Unknown { name_index, info: reader.read_bytes(length)? },
+    };
+    let actual = (reader.position - start) as u32;
+    if actual != length {
+        return Err(ClassFormatError::new(start - 4, ClassFormatErrorKind::AttributeLength {
+            name: name.to_string(),
+            declared: length,
+            actual,
+        }));
+    }
+    Ok(attribute)
+}
+
+/// Reads a u2 count followed by that many entries.
+fn read_table<T>(
+    reader: &mut Reader,
+    mut read_entry: impl FnMut(&mut Reader) -> Result<T, ClassFormatError>,
+) -> Result<Vec<T>, ClassFormatError> {
+    let count = reader.read_u16()?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        entries.push(read_entry(reader)?);
+    }
+    Ok(entries)
+}
+
+fn read_indices(reader: &mut Reader) -> Result<Vec<u16>, ClassFormatError> {
+    read_table(reader, |reader| reader.read_u16())
+}
+
+/// Reads a u2 byte offset into the enclosing code array and maps it to an instruction index.
+fn read_pc(reader: &mut Reader, code: Option<&CodeOffsets>) -> Result<usize, ClassFormatError> {
+    let offset = reader.read_u16()? as usize;
+    code.and_then(|it| code_index(it.offsets, it.length, offset))
+        .ok_or_else(|| ClassFormatError::new(reader.position - 2, ClassFormatErrorKind::InvalidCodeOffset(offset)))
+}
+
+/// Reads a u2 `start_pc` and u2 `length` and maps them to a range of instruction indices.
+fn read_pc_range(reader: &mut Reader, code: Option<&CodeOffsets>) -> Result<(usize, usize), ClassFormatError> {
+    let start_pc = read_pc(reader, code)?;
+    let start = code.map_or(0, |it| it.offsets.get(start_pc).copied().unwrap_or(it.length));
+    let offset = start + reader.read_u16()? as usize;
+    let end_pc = code.and_then(|it| code_index(it.offsets, it.length, offset))
+        .ok_or_else(|| ClassFormatError::new(reader.position - 2, ClassFormatErrorKind::InvalidCodeOffset(offset)))?;
+    Ok((start_pc, end_pc))
+}
+
+/// Reads the frames of a `StackMapTable`, whose offsets are deltas from the previous frame.
+fn read_stack_map_table(reader: &mut Reader, code: Option<&CodeOffsets>) -> Result<Vec<StackMapFrame>, ClassFormatError> {
+    let mut previous: Option<usize> = None;
+    read_table(reader, |reader| {
+        let start = reader.position;
+        let frame_type = reader.read_u8()?;
+        let delta = match frame_type {
+            0..=63 => frame_type as usize,
+            64..=127 => frame_type as usize - 64,
+            247..=255 => reader.read_u16()? as usize,
+            _ => return Err(ClassFormatError::new(start, ClassFormatErrorKind::InvalidFrameType(frame_type))),
+        };
+        let byte_offset = previous.map_or(delta, |previous| previous + delta + 1);
+        previous = Some(byte_offset);
+        let offset = code.and_then(|it| it.offsets.binary_search(&byte_offset).ok())
+            .ok_or_else(|| ClassFormatError::new(start, ClassFormatErrorKind::InvalidCodeOffset(byte_offset)))?;
+        let frame = match frame_type {
+            0..=63 | 251 => StackMapFrame::Same { offset },
+            64..=127 | 247 => StackMapFrame::SameLocals1StackItem { offset, stack: read_verification_type(reader, code)? },
+            248..=250 => StackMapFrame::Chop { offset, k: 251 - frame_type },
+            252..=254 => {
+                let mut locals = Vec::with_capacity(frame_type as usize - 251);
+                for _ in 251..frame_type {
+                    locals.push(read_verification_type(reader, code)?);
+                }
+                StackMapFrame::Append { offset, locals }
+            }
+            _ => StackMapFrame::Full {
+                offset,
+                locals: read_table(reader, |reader| read_verification_type(reader, code))?,
+                stack: read_table(reader, |reader| read_verification_type(reader, code))?,
+            },
+        };
+        Ok(frame)
+    })
+}
+
+fn read_verification_type(reader: &mut Reader, code: Option<&CodeOffsets>) -> Result<VerificationTypeInfo, ClassFormatError> {
+    let tag = reader.read_u8()?;
+    let info = match tag {
+        0 => VerificationTypeInfo::Top,
+        1 => VerificationTypeInfo::Integer,
+        2 => VerificationTypeInfo::Float,
+        3 => VerificationTypeInfo::Double,
+        4 => VerificationTypeInfo::Long,
+        5 => VerificationTypeInfo::Null,
+        6 => VerificationTypeInfo::UninitializedThis,
+        7 => VerificationTypeInfo::Object(reader.read_u16()?),
+        8 => VerificationTypeInfo::Uninitialized(read_pc(reader, code)?),
+        _ => return Err(ClassFormatError::new(
+            reader.position - 1, ClassFormatErrorKind::InvalidVerificationType(tag))),
+    };
+    Ok(info)
+}
+
+fn read_local_variable(reader: &mut Reader, code: Option<&CodeOffsets>) -> Result<LocalVariable, ClassFormatError> {
+    let (start_pc, end_pc) = read_pc_range(reader, code)?;
+    Ok(LocalVariable {
+        start_pc,
+        end_pc,
+        name_index: reader.read_u16()?,
+        descriptor_index: reader.read_u16()?,
+        index: reader.read_u16()?,
+    })
+}
+
+fn read_annotation(reader: &mut Reader) -> Result<Annotation, ClassFormatError> {
+    Ok(Annotation {
+        type_index: reader.read_u16()?,
+        element_value_pairs: read_table(reader, |reader| Ok((reader.read_u16()?, read_element_value(reader)?)))?,
+    })
+}
+
+fn read_element_value(reader: &mut Reader) -> Result<ElementValue, ClassFormatError> {
+    let tag = reader.read_u8()?;
+    let value = match tag {
+        b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' | b's' => {
+            ElementValue::Const { tag, const_value_index: reader.read_u16()? }
+        }
+        b'e' => ElementValue::Enum { type_name_index: reader.read_u16()?, const_name_index: reader.read_u16()? },
+        b'c' => ElementValue::Class { class_info_index: reader.read_u16()? },
+        b'@' => ElementValue::Annotation(read_annotation(reader)?),
+        b'[' => ElementValue::Array(read_table(reader, read_element_value)?),
+        _ => return Err(ClassFormatError::new(
+            reader.position - 1, ClassFormatErrorKind::InvalidElementValueTag(tag))),
+    };
+    Ok(value)
+}
+
+fn read_parameter_annotations(reader: &mut Reader) -> Result<Vec<Vec<Annotation>>, ClassFormatError> {
+    let num_parameters = reader.read_u8()?;
+    let mut parameter_annotations = Vec::with_capacity(num_parameters as usize);
+    for _ in 0..num_parameters {
+        parameter_annotations.push(read_table(reader, read_annotation)?);
+    }
+    Ok(parameter_annotations)
+}
+
+fn read_type_annotation(reader: &mut Reader, code: Option<&CodeOffsets>) -> Result<TypeAnnotation, ClassFormatError> {
+    let target_type = reader.read_u8()?;
+    let target_info = match target_type {
+        0x00 | 0x01 => TargetInfo::TypeParameter { type_parameter_index: reader.read_u8()? },
+        0x10 => TargetInfo::Supertype { supertype_index: reader.read_u16()? },
+        0x11 | 0x12 => TargetInfo::TypeParameterBound {
+            type_parameter_index: reader.read_u8()?,
+            bound_index: reader.read_u8()?,
+        },
+        0x13..=0x15 => TargetInfo::Empty,
+        0x16 => TargetInfo::FormalParameter { formal_parameter_index: reader.read_u8()? },
+        0x17 => TargetInfo::Throws { throws_type_index: reader.read_u16()? },
+        0x40 | 0x41 => TargetInfo::LocalVar(read_table(reader, |reader| {
+            let (start_pc, end_pc) = read_pc_range(reader, code)?;
+            Ok(LocalVarTarget { start_pc, end_pc, index: reader.read_u16()? })
+        })?),
+        0x42 => TargetInfo::Catch { exception_table_index: reader.read_u16()? },
+        0x43..=0x46 => TargetInfo::Offset(read_pc(reader, code)?),
+        0x47..=0x4B => TargetInfo::TypeArgument {
+            offset: read_pc(reader, code)?,
+            type_argument_index: reader.read_u8()?,
+        },
+        _ => return Err(ClassFormatError::new(
+            reader.position - 1, ClassFormatErrorKind::InvalidTargetType(target_type))),
+    };
+    let path_length = reader.read_u8()?;
+    let mut target_path = Vec::with_capacity(path_length as usize);
+    for _ in 0..path_length {
+        target_path.push((reader.read_u8()?, reader.read_u8()?));
     }
+    Ok(TypeAnnotation { target_type, target_info, target_path, annotation: read_annotation(reader)? })
+}
+
+fn read_module(reader: &mut Reader) -> Result<Module, ClassFormatError> {
+    let read_exports = |reader: &mut Reader| Ok(Exports {
+        index: reader.read_u16()?,
+        flags: reader.read_u16()?,
+        to: read_indices(reader)?,
+    });
+    Ok(Module {
+        module_name_index: reader.read_u16()?,
+        module_flags: reader.read_u16()?,
+        module_version_index: reader.read_u16()?,
+        requires: read_table(reader, |reader| Ok(Requires {
+            requires_index: reader.read_u16()?,
+            requires_flags: reader.read_u16()?,
+            requires_version_index: reader.read_u16()?,
+        }))?,
+        exports: read_table(reader, read_exports)?,
+        opens: read_table(reader, read_exports)?,
+        uses: read_indices(reader)?,
+        provides: read_table(reader, |reader| Ok(Provides {
+            provides_index: reader.read_u16()?,
+            with: read_indices(reader)?,
+        }))?,
+    })
 }
 
-fn read_code_attribute(reader: &mut Reader) -> Attribute {
-    let length = reader.read_u32();
-    let max_stack = reader.read_u16();
-    let max_locals = reader.read_u16();
-    let mut code_length = reader.read_u32();
+fn read_code_attribute(reader: &mut Reader, const_pool: &ConstPool) -> Result<Attribute, ClassFormatError> {
+    let max_stack = reader.read_u16()?;
+    let max_locals = reader.read_u16()?;
+    let code_length = reader.read_u32()? as usize;
+    let start = reader.position;
+    // byte offset of each instruction in `codes`
+    let mut offsets: Vec<usize> = Vec::new();
     let mut codes: Vec<Opcode> = Vec::new();
 
-    while code_length > 0 {
-        let opcode = reader.read_u8();
-        code_length -= 1;
+    while reader.position - start < code_length {
+        let pc = reader.position - start;
+        offsets.push(pc);
+        let opcode = reader.read_u8()?;
         let code = match opcode {
+            0x01 => Opcode::aconst_null,
             0x03 => Opcode::iconst_0,
             0x04 => Opcode::iconst_1,
+            0x10 => Opcode::bipush(reader.read_u8()? as i8 as i32),
+            0x12 => Opcode::ldc(reader.read_u8()? as u16),
+            0x13 => Opcode::ldc(reader.read_u16()?),
+            0x15 => Opcode::iload(reader.read_u8()? as usize),
+            0x19 => Opcode::aload(reader.read_u8()? as usize),
             0x1a => Opcode::iload_0,
             0x1b => Opcode::iload_1,
+            0x1c => Opcode::iload_2,
+            0x1d => Opcode::iload_3,
             0x2a => Opcode::aload_0,
+            0x2b => Opcode::aload_1,
+            0x2c => Opcode::aload_2,
+            0x2d => Opcode::aload_3,
+            0x2e => Opcode::iaload,
+            0x36 => Opcode::istore(reader.read_u8()? as usize),
+            0x3a => Opcode::astore(reader.read_u8()? as usize),
             0x3b => Opcode::istore_0,
             0x3c => Opcode::istore_1,
+            0x3d => Opcode::istore_2,
+            0x3e => Opcode::istore_3,
+            0x4b => Opcode::astore_0,
+            0x4c => Opcode::astore_1,
+            0x4d => Opcode::astore_2,
+            0x4e => Opcode::astore_3,
+            0x4f => Opcode::iastore,
+            0x57 => Opcode::pop,
+            0x59 => Opcode::dup,
             0x60 => Opcode::iadd,
-            0xa7 => {
-                code_length -= 2;
-                Opcode::goto(reader.read_u16() as usize)
-            }
-            0xa1 => {
-                code_length -= 2;
-                Opcode::if_icmplt(reader.read_u16() as usize)
-            }
+            0x84 => Opcode::iinc(reader.read_u8()? as usize, reader.read_u8()? as i8 as i32),
+            0x99 => Opcode::ifeq(branch_target(pc, reader.read_u16()?)),
+            0x9a => Opcode::ifne(branch_target(pc, reader.read_u16()?)),
+            0xa7 => Opcode::goto(branch_target(pc, reader.read_u16()?)),
+            0xa1 => Opcode::if_icmplt(branch_target(pc, reader.read_u16()?)),
+            0xa8 => Opcode::jsr(branch_target(pc, reader.read_u16()?)),
+            0xa9 => Opcode::ret(reader.read_u8()? as usize),
             0xac => Opcode::ireturn,
+            0xb0 => Opcode::areturn,
             0xb1 => Opcode::r#return,
-            0xb7 => {
-                code_length -= 2;
-                Opcode::invokespecial(reader.read_u16())
-            }
-            0xb8 => {
-                code_length -= 2;
-                Opcode::invokestatic(reader.read_u16() as usize)
+            0xb2 => Opcode::getstatic(reader.read_u16()?),
+            0xb3 => Opcode::putstatic(reader.read_u16()?),
+            0xb4 => Opcode::getfield(reader.read_u16()?),
+            0xb5 => Opcode::putfield(reader.read_u16()?),
+            0xb6 => Opcode::invokevirtual(reader.read_u16()?),
+            0xb7 => Opcode::invokespecial(reader.read_u16()?),
+            0xb8 => Opcode::invokestatic(reader.read_u16()? as usize),
+            0xb9 => {
+                let code = Opcode::invokeinterface(reader.read_u16()?, reader.read_u8()?);
+                // the fourth byte is always zero
+                reader.read_u8()?;
+                code
             }
-            _ => panic!("cannot parse opcode {:04X?}", opcode)
+            0xbb => Opcode::new(reader.read_u16()?),
+            0xbc => match reader.read_u8()? {
+                atype @ 4..=11 => Opcode::newarray(atype),
+                atype => return Err(ClassFormatError::new(
+                    reader.position - 1, ClassFormatErrorKind::InvalidArrayType(atype))),
+            },
+            0xbd => Opcode::anewarray(reader.read_u16()?),
+            0xbe => Opcode::arraylength,
+            0xbf => Opcode::athrow,
+            0xc0 => Opcode::checkcast(reader.read_u16()?),
+            0xc1 => Opcode::instanceof(reader.read_u16()?),
+            0xc4 => match reader.read_u8()? {
+                0x15 => Opcode::iload(reader.read_u16()? as usize),
+                0x19 => Opcode::aload(reader.read_u16()? as usize),
+                0x36 => Opcode::istore(reader.read_u16()? as usize),
+                0x3a => Opcode::astore(reader.read_u16()? as usize),
+                0x84 => Opcode::iinc(reader.read_u16()? as usize, reader.read_u16()? as i16 as i32),
+                0xa9 => Opcode::ret(reader.read_u16()? as usize),
+                modified => return Err(ClassFormatError::new(
+                    reader.position - 1, ClassFormatErrorKind::InvalidOpcode(modified))),
+            },
+            _ => return Err(ClassFormatError::new(
+                start + pc, ClassFormatErrorKind::InvalidOpcode(opcode))),
         };
         codes.push(code)
     };
 
-    let exception_table_length = reader.read_u16();
-    dbg!(exception_table_length);
+    // branch targets were decoded as byte offsets, the interpreter indexes `codes`
+    for (pc, code) in offsets.iter().zip(codes.iter_mut()) {
+        match code {
+            Opcode::goto(target) | Opcode::if_icmplt(target) | Opcode::jsr(target)
+            | Opcode::ifeq(target) | Opcode::ifne(target) => {
+                *target = code_index(&offsets, code_length, *target)
+                    .ok_or_else(|| ClassFormatError::new(start + pc, ClassFormatErrorKind::InvalidCodeOffset(*target)))?
+            }
+            _ => {}
+        }
+    }
+
+    let exception_table_length = reader.read_u16()?;
+    let mut exception_table = Vec::with_capacity(exception_table_length as usize);
+    for _ in 0..exception_table_length {
+        let mut read_index = || -> Result<usize, ClassFormatError> {
+            let offset = reader.read_u16()? as usize;
+            code_index(&offsets, code_length, offset).ok_or_else(|| ClassFormatError::new(
+                reader.position - 2, ClassFormatErrorKind::InvalidCodeOffset(offset)))
+        };
+        let start_pc = read_index()?;
+        let end_pc = read_index()?;
+        let handler_pc = read_index()?;
+        exception_table.push(ExceptionTableEntry {
+            start_pc,
+            end_pc,
+            handler_pc,
+            catch_type: reader.read_u16()?,
+        });
+    }
+
+    let code = CodeOffsets { offsets: &offsets, length: code_length };
+    let attributes = read_table(reader, |reader| read_attribute_in(reader, const_pool, Some(&code)))?;
 
-    let attributes_count = reader.read_u16();
-    dbg!(attributes_count);
+    Ok(Attribute::Code { max_stack, max_locals, codes, exception_table, attributes })
+}
 
-    for _ in 0..attributes_count {
-        let name_index = reader.read_u16();
-        dbg!(name_index);
-        let length = reader.read_u32();
-        // fixme
-        let content = reader.read_utf8(length as u16);
-        dbg!(length, content);
+fn branch_target(pc: usize, offset: u16) -> usize {
+    // a negative target wraps around to an offset past the end of the code
+    (pc as isize + offset as i16 as isize) as usize
+}
+
+/// Maps a byte offset within the code array to an index into the decoded
+/// instructions. The end of the code array maps to one past the last instruction.
+fn code_index(offsets: &[usize], code_length: usize, offset: usize) -> Option<usize> {
+    if offset == code_length {
+        return Some(offsets.len());
+    }
+    offsets.binary_search(&offset).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::const_pool::{ConstPool, CpInfo};
+    use crate::parser::{Attribute, ClassFormatError, ClassFormatErrorKind, Reader};
+    use crate::parser::attribute::read_attribute;
+
+    fn const_pool() -> ConstPool {
+        ConstPool::from_vec(vec![
+            CpInfo::Placeholder,
+            CpInfo::Utf8("Record".to_string()),
+            CpInfo::Utf8("PermittedSubclasses".to_string()),
+            CpInfo::Utf8("NestHost".to_string()),
+            CpInfo::Utf8("x".to_string()),
+            CpInfo::Utf8("I".to_string()),
+            CpInfo::Utf8("Signature".to_string()),
+            CpInfo::Utf8("RuntimeVisibleAnnotations".to_string()),
+        ])
     }
 
-    Attribute::Code { max_stack, max_locals, codes }
+    #[test]
+    fn read_class_attributes() {
+        let const_pool = const_pool();
+        // record with one component `int x` that has a Signature attribute
+        let bytes = [0, 1, 0, 0, 0, 16, 0, 1, 0, 4, 0, 5, 0, 1, 0, 6, 0, 0, 0, 2, 0, 5];
+        match read_attribute(&mut Reader::new(&bytes), &const_pool).unwrap() {
+            Attribute::Record(components) => {
+                assert_eq!((components[0].name_index, components[0].descriptor_index), (4, 5));
+                assert!(matches!(components[0].attributes[..], [Attribute::Signature { signature_index: 5 }]));
+            }
+            attribute => panic!("expected Record, got {:?}", attribute),
+        }
+
+        let bytes = [0, 2, 0, 0, 0, 6, 0, 2, 0, 9, 0, 10];
+        assert!(matches!(read_attribute(&mut Reader::new(&bytes), &const_pool).unwrap(),
+            Attribute::PermittedSubclasses(classes) if classes == vec![9, 10]));
+
+        let bytes = [0, 3, 0, 0, 0, 2, 0, 11];
+        assert!(matches!(read_attribute(&mut Reader::new(&bytes), &const_pool).unwrap(),
+            Attribute::NestHost { host_class_index: 11 }));
+    }
+
+    #[test]
+    fn reject_invalid_element_value_tag() {
+        // one annotation with one element whose tag is `X`
+        let bytes = [0, 7, 0, 0, 0, 8, 0, 1, 0, 4, 0, 1, 0, 4, b'X', 0];
+        assert_eq!(
+            read_attribute(&mut Reader::new(&bytes), &const_pool()).unwrap_err(),
+            ClassFormatError { offset: 14, kind: ClassFormatErrorKind::InvalidElementValueTag(b'X') }
+        );
+    }
 }
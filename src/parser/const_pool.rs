@@ -1,40 +1,209 @@
-use crate::parser::Reader;
+use crate::parser::{ClassFormatError, ClassFormatErrorKind, Reader};
 use crate::const_pool::{ConstPool, CpInfo};
 
-pub(crate) fn read_const_pool<'a>(reader: &mut Reader) -> ConstPool {
-    let const_pool_count = reader.read_u16();
+pub(crate) fn read_const_pool(reader: &mut Reader) -> Result<ConstPool, ClassFormatError> {
+    let const_pool_count = reader.read_u16()?;
+    if const_pool_count == 0 {
+        return Err(ClassFormatError::new(reader.position - 2, ClassFormatErrorKind::EmptyConstantPool));
+    }
     let mut parsed_items = Vec::with_capacity(const_pool_count as usize);
+    // byte offset of each entry, for reporting invalid references
+    let mut offsets = Vec::with_capacity(const_pool_count as usize);
 
     parsed_items.push(CpInfo::Placeholder);
-    for _ in 0..const_pool_count - 1 {
-        let tag = reader.read_u8();
+    offsets.push(0);
+    while parsed_items.len() < const_pool_count as usize {
+        offsets.push(reader.position);
+        let tag = reader.read_u8()?;
         let info: CpInfo = match tag {
-            7 => CpInfo::Class { name_index: reader.read_u16() },
-            9 => CpInfo::FieldRef { class_index: 0, name_and_type_index: 0 },
-            10 => CpInfo::MethodRef { class_index: reader.read_u16(), name_and_type_index: reader.read_u16() },
-            11 => CpInfo::InterfaceMethodRef { class_index: 0, name_and_type_index: 0 },
-            8 => CpInfo::String { string_index: 0 },
-            3 => CpInfo::Integer(0),
-            4 => CpInfo::Float(0.0),
-            5 => CpInfo::Long(0),
-            6 => CpInfo::Double(0.0),
-            12 => CpInfo::NameAndTuple { name_index: reader.read_u16(), descriptor_index: reader.read_u16() },
+            7 => CpInfo::Class { name_index: reader.read_u16()? },
+            9 => CpInfo::FieldRef { class_index: reader.read_u16()?, name_and_type_index: reader.read_u16()? },
+            10 => CpInfo::MethodRef { class_index: reader.read_u16()?, name_and_type_index: reader.read_u16()? },
+            11 => CpInfo::InterfaceMethodRef { class_index: reader.read_u16()?, name_and_type_index: reader.read_u16()? },
+            8 => CpInfo::String { string_index: reader.read_u16()? },
+            3 => CpInfo::Integer(reader.read_u32()? as i32),
+            4 => CpInfo::Float(f32::from_bits(reader.read_u32()?)),
+            5 => CpInfo::Long(read_u64(reader)? as i64),
+            6 => CpInfo::Double(f64::from_bits(read_u64(reader)?)),
+            12 => CpInfo::NameAndTuple { name_index: reader.read_u16()?, descriptor_index: reader.read_u16()? },
             1 => {
-                let length = reader.read_u16();
-                let string = reader.read_utf8(length);
-                // let string = Box::leak(string.into_boxed_str());
+                let length = reader.read_u16()?;
+                let string = reader.read_utf8(length)?;
                 CpInfo::Utf8(string)
             }
-            15 => CpInfo::MethodHandle,
-            16 => CpInfo::MethodType,
-            17 => CpInfo::Dynamic,
-            18 => CpInfo::InvokeDynamic,
-            19 => CpInfo::Module,
-            20 => CpInfo::Package,
-            _ => unimplemented!("{}", tag)
+            15 => CpInfo::MethodHandle { reference_kind: reader.read_u8()?, reference_index: reader.read_u16()? },
+            16 => CpInfo::MethodType { descriptor_index: reader.read_u16()? },
+            17 => CpInfo::Dynamic {
+                bootstrap_method_attr_index: reader.read_u16()?,
+                name_and_type_index: reader.read_u16()?,
+            },
+            18 => CpInfo::InvokeDynamic {
+                bootstrap_method_attr_index: reader.read_u16()?,
+                name_and_type_index: reader.read_u16()?,
+            },
+            19 => CpInfo::Module { name_index: reader.read_u16()? },
+            20 => CpInfo::Package { name_index: reader.read_u16()? },
+            _ => return Err(ClassFormatError::new(
+                reader.position - 1, ClassFormatErrorKind::InvalidConstantPoolTag(tag))),
         };
+        let wide = matches!(info, CpInfo::Long(_) | CpInfo::Double(_));
         parsed_items.push(info);
+        if wide {
+            // §4.4.5, the following index is valid but unusable
+            if parsed_items.len() == const_pool_count as usize {
+                let index = parsed_items.len() as u16 - 1;
+                return Err(ClassFormatError::new(
+                    offsets[index as usize], ClassFormatErrorKind::InvalidConstantPoolIndex(index)));
+            }
+            parsed_items.push(CpInfo::Placeholder);
+            offsets.push(reader.position);
+        }
+    }
+
+    let const_pool = ConstPool::from_vec(parsed_items);
+    validate(&const_pool, &offsets)?;
+    Ok(const_pool)
+}
+
+fn read_u64(reader: &mut Reader) -> Result<u64, ClassFormatError> {
+    let high = reader.read_u32()? as u64;
+    let low = reader.read_u32()? as u64;
+    Ok(high << 32 | low)
+}
+
+/// Checks that every index inside an entry refers to an entry of the kind §4.4 requires.
+fn validate(const_pool: &ConstPool, offsets: &[usize]) -> Result<(), ClassFormatError> {
+    let is_utf8 = |index| matches!(const_pool.get(index), Some(CpInfo::Utf8(_)));
+    let is_class = |index| matches!(const_pool.get(index), Some(CpInfo::Class { .. }));
+    let is_name_and_type = |index| matches!(const_pool.get(index), Some(CpInfo::NameAndTuple { .. }));
+    let is_field_ref = |index| matches!(const_pool.get(index), Some(CpInfo::FieldRef { .. }));
+    let is_method_ref = |index| matches!(const_pool.get(index), Some(CpInfo::MethodRef { .. }));
+    let is_interface_method_ref = |index| matches!(const_pool.get(index), Some(CpInfo::InterfaceMethodRef { .. }));
+
+    for (index, offset) in offsets.iter().enumerate().skip(1) {
+        let info = match const_pool.get(index as u16) {
+            Some(info) => info,
+            None => continue,
+        };
+        let invalid = |index| Err(ClassFormatError::new(*offset, ClassFormatErrorKind::InvalidConstantPoolIndex(index)));
+        match *info {
+            CpInfo::Class { name_index: index }
+            | CpInfo::String { string_index: index }
+            | CpInfo::MethodType { descriptor_index: index }
+            | CpInfo::Module { name_index: index }
+            | CpInfo::Package { name_index: index } if !is_utf8(index) => return invalid(index),
+            CpInfo::FieldRef { class_index, .. }
+            | CpInfo::MethodRef { class_index, .. }
+            | CpInfo::InterfaceMethodRef { class_index, .. } if !is_class(class_index) => return invalid(class_index),
+            CpInfo::FieldRef { name_and_type_index, .. }
+            | CpInfo::MethodRef { name_and_type_index, .. }
+            | CpInfo::InterfaceMethodRef { name_and_type_index, .. }
+            | CpInfo::Dynamic { name_and_type_index, .. }
+            | CpInfo::InvokeDynamic { name_and_type_index, .. } if !is_name_and_type(name_and_type_index) => {
+                return invalid(name_and_type_index);
+            }
+            CpInfo::NameAndTuple { name_index, .. } if !is_utf8(name_index) => return invalid(name_index),
+            CpInfo::NameAndTuple { descriptor_index, .. } if !is_utf8(descriptor_index) => return invalid(descriptor_index),
+            // §4.4.8
+            CpInfo::MethodHandle { reference_kind, reference_index } => {
+                let valid = match reference_kind {
+                    // REF_getField, REF_getStatic, REF_putField, REF_putStatic
+                    1..=4 => is_field_ref(reference_index),
+                    // REF_invokeVirtual, REF_newInvokeSpecial
+                    5 | 8 => is_method_ref(reference_index),
+                    // REF_invokeStatic, REF_invokeSpecial
+                    6 | 7 => is_method_ref(reference_index) || is_interface_method_ref(reference_index),
+                    // REF_invokeInterface
+                    9 => is_interface_method_ref(reference_index),
+                    _ => return Err(ClassFormatError::new(
+                        *offset + 1, ClassFormatErrorKind::InvalidReferenceKind(reference_kind))),
+                };
+                if !valid {
+                    return invalid(reference_index);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::const_pool::CpInfo;
+    use crate::parser::{ClassFormatError, ClassFormatErrorKind, Reader};
+    use crate::parser::const_pool::read_const_pool;
+
+    #[test]
+    fn long_and_double_take_two_entries() {
+        let bytes = [
+            0, 6,
+            5, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE,
+            6, 0x40, 0x09, 0x21, 0xFB, 0x54, 0x44, 0x2D, 0x18,
+            3, 0x80, 0, 0, 0,
+        ];
+        let const_pool = read_const_pool(&mut Reader::new(&bytes)).unwrap();
+        assert!(matches!(const_pool.get(1), Some(CpInfo::Long(-2))));
+        assert!(const_pool.get(2).is_none());
+        assert!(matches!(const_pool.get(3), Some(CpInfo::Double(d)) if *d == std::f64::consts::PI));
+        assert!(const_pool.get(4).is_none());
+        assert!(matches!(const_pool.get(5), Some(CpInfo::Integer(i32::MIN))));
+    }
+
+    #[test]
+    fn read_every_tag() {
+        let bytes = [
+            0, 15,
+            1, 0, 1, b'A',              // 1 Utf8
+            7, 0, 1,                    // 2 Class
+            12, 0, 1, 0, 1,             // 3 NameAndType
+            9, 0, 2, 0, 3,              // 4 FieldRef
+            10, 0, 2, 0, 3,             // 5 MethodRef
+            11, 0, 2, 0, 3,             // 6 InterfaceMethodRef
+            8, 0, 1,                    // 7 String
+            4, 0x3F, 0x80, 0, 0,        // 8 Float
+            15, 1, 0, 4,                // 9 MethodHandle
+            16, 0, 1,                   // 10 MethodType
+            17, 0, 0, 0, 3,             // 11 Dynamic
+            18, 0, 1, 0, 3,             // 12 InvokeDynamic
+            19, 0, 1,                   // 13 Module
+            20, 0, 1,                   // 14 Package
+        ];
+        let mut reader = Reader::new(&bytes);
+        let const_pool = read_const_pool(&mut reader).unwrap();
+        assert_eq!(reader.position, bytes.len());
+        assert!(matches!(const_pool.get(6), Some(CpInfo::InterfaceMethodRef { class_index: 2, name_and_type_index: 3 })));
+        assert!(matches!(const_pool.get(8), Some(CpInfo::Float(f)) if *f == 1.0));
+        assert!(matches!(const_pool.get(9), Some(CpInfo::MethodHandle { reference_kind: 1, reference_index: 4 })));
+        assert!(matches!(const_pool.get(12), Some(CpInfo::InvokeDynamic { bootstrap_method_attr_index: 1, .. })));
+        assert!(matches!(const_pool.get(14), Some(CpInfo::Package { name_index: 1 })));
     }
 
-    ConstPool::from_vec(parsed_items)
-}
\ No newline at end of file
+    #[test]
+    fn reject_invalid_references() {
+        // a String pointing at a Class
+        let bytes = [0, 4, 1, 0, 1, b'A', 7, 0, 1, 8, 0, 2];
+        assert_eq!(
+            read_const_pool(&mut Reader::new(&bytes)).unwrap_err(),
+            ClassFormatError { offset: 9, kind: ClassFormatErrorKind::InvalidConstantPoolIndex(2) }
+        );
+        // a Class pointing into the second half of a Long
+        let bytes = [0, 4, 5, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 2];
+        assert_eq!(
+            read_const_pool(&mut Reader::new(&bytes)).unwrap_err(),
+            ClassFormatError { offset: 11, kind: ClassFormatErrorKind::InvalidConstantPoolIndex(2) }
+        );
+        // REF_invokeVirtual of a FieldRef
+        let bytes = [0, 6, 1, 0, 1, b'A', 7, 0, 1, 12, 0, 1, 0, 1, 9, 0, 2, 0, 3, 15, 5, 0, 4];
+        assert_eq!(
+            read_const_pool(&mut Reader::new(&bytes)).unwrap_err(),
+            ClassFormatError { offset: 19, kind: ClassFormatErrorKind::InvalidConstantPoolIndex(4) }
+        );
+        // a Long in the last entry
+        let bytes = [0, 2, 5, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            read_const_pool(&mut Reader::new(&bytes)).unwrap_err(),
+            ClassFormatError { offset: 2, kind: ClassFormatErrorKind::InvalidConstantPoolIndex(1) }
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// A class file that could not be parsed, see JVMS §4.8.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassFormatError {
+    /// Byte offset into the class file of the item that is malformed
+    pub offset: usize,
+    pub kind: ClassFormatErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClassFormatErrorKind {
+    /// The input ended in the middle of an item
+    Truncated,
+    Io(io::ErrorKind),
+    BadMagic(u32),
+    /// The JVM's `UnsupportedClassVersionError`, a version outside of what is supported
+    UnsupportedClassVersion { major: u16, minor: u16 },
+    EmptyConstantPool,
+    InvalidConstantPoolTag(u8),
+    /// An index that is out of range or refers to an entry of the wrong kind
+    InvalidConstantPoolIndex(u16),
+    /// A `CONSTANT_MethodHandle_info` whose `reference_kind` is not in 1 to 9
+    InvalidReferenceKind(u8),
+    InvalidUtf8,
+    /// Bytes consumed by an attribute differ from its `attribute_length`
+    AttributeLength { name: String, declared: u32, actual: u32 },
+    InvalidOpcode(u8),
+    InvalidArrayType(u8),
+    /// A branch or exception table offset that is not the start of an instruction
+    InvalidCodeOffset(usize),
+    InvalidElementValueTag(u8),
+    InvalidTargetType(u8),
+    InvalidFrameType(u8),
+    InvalidVerificationType(u8),
+    /// Flags of the class, field or method at the offset that can't be combined, §4.1, §4.5, §4.6
+    InvalidAccessFlags(u16),
+    InvalidDescriptor(String),
+    /// A class, field or method name that is not valid in internal form, §4.2
+    InvalidName(String),
+}
+
+impl ClassFormatError {
+    pub(crate) fn new(offset: usize, kind: ClassFormatErrorKind) -> ClassFormatError {
+        ClassFormatError { offset, kind }
+    }
+}
+
+impl Display for ClassFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "malformed class file at byte {}: ", self.offset)?;
+        match &self.kind {
+            ClassFormatErrorKind::Truncated => write!(f, "unexpected end of file"),
+            ClassFormatErrorKind::Io(kind) => write!(f, "{:?}", kind),
+            ClassFormatErrorKind::BadMagic(magic) => write!(f, "bad magic number {:#010X}", magic),
+            ClassFormatErrorKind::UnsupportedClassVersion { major, minor } => write!(
+                f, "unsupported class file version {}.{}", major, minor),
+            ClassFormatErrorKind::EmptyConstantPool => write!(f, "constant_pool_count is 0"),
+            ClassFormatErrorKind::InvalidConstantPoolTag(tag) => write!(f, "invalid constant pool tag {}", tag),
+            ClassFormatErrorKind::InvalidConstantPoolIndex(index) => write!(f, "invalid constant pool index {}", index),
+            ClassFormatErrorKind::InvalidReferenceKind(kind) => write!(f, "invalid method handle reference kind {}", kind),
+            ClassFormatErrorKind::InvalidUtf8 => write!(f, "invalid modified UTF-8"),
+            ClassFormatErrorKind::AttributeLength { name, declared, actual } => write!(
+                f, "{} attribute declares length {} but has {} bytes", name, declared, actual),
+            ClassFormatErrorKind::InvalidOpcode(opcode) => write!(f, "invalid opcode {:#04X}", opcode),
+            ClassFormatErrorKind::InvalidArrayType(atype) => write!(f, "invalid newarray type {}", atype),
+            ClassFormatErrorKind::InvalidCodeOffset(offset) => write!(f, "code offset {} is not an instruction", offset),
+            ClassFormatErrorKind::InvalidElementValueTag(tag) => write!(f, "invalid element_value tag {}", tag),
+            ClassFormatErrorKind::InvalidTargetType(target) => write!(f, "invalid type annotation target {:#04X}", target),
+            ClassFormatErrorKind::InvalidFrameType(frame_type) => write!(f, "invalid stack map frame type {}", frame_type),
+            ClassFormatErrorKind::InvalidVerificationType(tag) => write!(f, "invalid verification type tag {}", tag),
+            ClassFormatErrorKind::InvalidAccessFlags(flags) => write!(f, "illegal access flags {:#06X}", flags),
+            ClassFormatErrorKind::InvalidDescriptor(descriptor) => write!(f, "invalid descriptor {}", descriptor),
+            ClassFormatErrorKind::InvalidName(name) => write!(f, "invalid name {}", name),
+        }
+    }
+}
+
+impl Error for ClassFormatError {}
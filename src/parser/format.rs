@@ -0,0 +1,258 @@
+use crate::descriptor::{FieldType, MethodDescriptor};
+use crate::parser::AccessFlags;
+use crate::parser::ClassFormatErrorKind;
+
+/// Oldest class file version that is loaded, JDK 1.0.2.
+const MIN_MAJOR: u16 = 45;
+/// Newest class file version that is loaded, Java 17.
+const MAX_MAJOR: u16 = 61;
+
+const PUBLIC: u16 = AccessFlags::PUBLIC as u16;
+const PRIVATE: u16 = AccessFlags::PRIVATE as u16;
+const PROTECTED: u16 = AccessFlags::PROTECTED as u16;
+const STATIC: u16 = AccessFlags::STATIC as u16;
+const FINAL: u16 = AccessFlags::FINAL as u16;
+const SUPER: u16 = AccessFlags::SUPER as u16;
+const INTERFACE: u16 = AccessFlags::INTERFACE as u16;
+const ABSTRACT: u16 = AccessFlags::ABSTRACT as u16;
+const SYNTHETIC: u16 = AccessFlags::SYNTHETIC as u16;
+const ANNOTATION: u16 = AccessFlags::ANNOTATION as u16;
+const ENUM: u16 = AccessFlags::ENUM as u16;
+const MODULE: u16 = AccessFlags::MODULE as u16;
+// flags that share a bit with one of `AccessFlags` but mean something else on fields and methods
+const SYNCHRONIZED: u16 = 0x0020;
+const VOLATILE: u16 = 0x0040;
+const BRIDGE: u16 = 0x0040;
+const VARARGS: u16 = 0x0080;
+const NATIVE: u16 = 0x0100;
+const STRICT: u16 = 0x0800;
+
+/// §4.1, versions from 56 on have a minor version of 0, or 65535 for preview features,
+/// which are not supported.
+pub(crate) fn check_version(major: u16, minor: u16) -> Result<(), ClassFormatErrorKind> {
+    if (MIN_MAJOR..=MAX_MAJOR).contains(&major) && (major < 56 || minor == 0) {
+        Ok(())
+    } else {
+        Err(ClassFormatErrorKind::UnsupportedClassVersion { major, minor })
+    }
+}
+
+/// §4.1, flags a class or interface may not combine. Classes older than Java 5 predate
+/// `ACC_ANNOTATION` and `ACC_ENUM` and may set bits that now mean something else.
+pub(crate) fn check_class_flags(flags: u16, major: u16) -> Result<(), ClassFormatErrorKind> {
+    let illegal = if flags & MODULE != 0 {
+        // `module-info` is not a class that can be loaded
+        true
+    } else if flags & INTERFACE != 0 {
+        flags & ABSTRACT == 0 || flags & FINAL != 0 || (major >= 49 && flags & (SUPER | ENUM) != 0)
+    } else {
+        flags & FINAL != 0 && flags & ABSTRACT != 0 || (major >= 49 && flags & ANNOTATION != 0)
+    };
+    if illegal {
+        Err(ClassFormatErrorKind::InvalidAccessFlags(flags))
+    } else {
+        Ok(())
+    }
+}
+
+/// §4.5, the flags of a field of a class with `class_flags`.
+pub(crate) fn check_field_flags(flags: u16, class_flags: u16) -> Result<(), ClassFormatErrorKind> {
+    let illegal = if class_flags & INTERFACE != 0 {
+        flags & !SYNTHETIC != PUBLIC | STATIC | FINAL
+    } else {
+        !at_most_one_access(flags) || flags & FINAL != 0 && flags & VOLATILE != 0
+    };
+    if illegal {
+        Err(ClassFormatErrorKind::InvalidAccessFlags(flags))
+    } else {
+        Ok(())
+    }
+}
+
+/// §4.6, the flags of method `name` of a class with `class_flags`. The flags of `<clinit>` are
+/// ignored, other than that it is static from version 51 on.
+pub(crate) fn check_method_flags(
+    flags: u16, name: &str, class_flags: u16, major: u16,
+) -> Result<(), ClassFormatErrorKind> {
+    // `ACC_STRICT` is implied from version 61 on, so it no longer conflicts with `ACC_ABSTRACT`
+    let strict = if major < 61 { STRICT } else { 0 };
+    let illegal = if name == "<clinit>" {
+        major >= 51 && flags & STATIC == 0
+    } else if class_flags & INTERFACE != 0 {
+        if major < 52 {
+            flags & !(SYNTHETIC | BRIDGE | VARARGS) != PUBLIC | ABSTRACT
+        } else {
+            // Java 8 added static, private and default methods to interfaces
+            flags & (PUBLIC | PRIVATE) == 0
+                || flags & (PUBLIC | PRIVATE) == PUBLIC | PRIVATE
+                || flags & (PROTECTED | FINAL | SYNCHRONIZED | NATIVE) != 0
+                || name == "<init>"
+                || flags & ABSTRACT != 0 && flags & (PRIVATE | STATIC | strict) != 0
+        }
+    } else if name == "<init>" {
+        !at_most_one_access(flags) || flags & !(PUBLIC | PRIVATE | PROTECTED | VARARGS | STRICT | SYNTHETIC) != 0
+    } else {
+        !at_most_one_access(flags)
+            || flags & ABSTRACT != 0 && flags & (PRIVATE | STATIC | FINAL | SYNCHRONIZED | NATIVE | strict) != 0
+    };
+    if illegal {
+        Err(ClassFormatErrorKind::InvalidAccessFlags(flags))
+    } else {
+        Ok(())
+    }
+}
+
+fn at_most_one_access(flags: u16) -> bool {
+    (flags & (PUBLIC | PRIVATE | PROTECTED)).count_ones() <= 1
+}
+
+/// §4.3.2, also checking the class names and that arrays have at most 255 dimensions.
+pub(crate) fn check_field_descriptor(descriptor: &str) -> Result<(), ClassFormatErrorKind> {
+    match FieldType::parse(descriptor) {
+        Some(field_type) if is_valid_field_type(&field_type) => Ok(()),
+        _ => Err(ClassFormatErrorKind::InvalidDescriptor(descriptor.to_string())),
+    }
+}
+
+/// §4.3.3, the parameters of method `name` may take at most 255 slots, counting `this` unless
+/// the method is `static`. `<init>` and `<clinit>` return `void`, and `<clinit>` takes no arguments
+/// from version 51 on.
+pub(crate) fn check_method_descriptor(
+    descriptor: &str, name: &str, flags: u16, major: u16,
+) -> Result<(), ClassFormatErrorKind> {
+    let valid = match MethodDescriptor::parse(descriptor) {
+        Some(method) => {
+            let this = if flags & STATIC == 0 { 1 } else { 0 };
+            method.parameters.iter().chain(method.return_type.iter()).all(is_valid_field_type)
+                && method.parameter_slots() + this <= 255
+                && match name {
+                    "<init>" => method.return_type.is_none(),
+                    "<clinit>" => method.return_type.is_none() && (major < 51 || method.parameters.is_empty()),
+                    _ => true,
+                }
+        }
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(ClassFormatErrorKind::InvalidDescriptor(descriptor.to_string()))
+    }
+}
+
+fn is_valid_field_type(field_type: &FieldType) -> bool {
+    let mut dimensions = 0;
+    let mut element = field_type;
+    while let FieldType::Array(component) = element {
+        dimensions += 1;
+        element = component;
+    }
+    dimensions <= 255 && match element {
+        FieldType::Object(name) => is_class_name(name),
+        _ => true,
+    }
+}
+
+/// §4.2.1, a binary class or interface name in internal form, e.g. `java/lang/Object`.
+pub(crate) fn check_class_name(name: &str) -> Result<(), ClassFormatErrorKind> {
+    if is_class_name(name) {
+        Ok(())
+    } else {
+        Err(ClassFormatErrorKind::InvalidName(name.to_string()))
+    }
+}
+
+fn is_class_name(name: &str) -> bool {
+    name.split('/').all(is_unqualified_name)
+}
+
+/// §4.2.2, a field name, or a method name when `method` is set, which also can't contain
+/// `<` or `>` unless it is `<init>` or `<clinit>`.
+pub(crate) fn check_member_name(name: &str, method: bool) -> Result<(), ClassFormatErrorKind> {
+    let valid = if method {
+        name == "<init>" || name == "<clinit>" || (is_unqualified_name(name) && !name.contains(&['<', '>'][..]))
+    } else {
+        is_unqualified_name(name)
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(ClassFormatErrorKind::InvalidName(name.to_string()))
+    }
+}
+
+fn is_unqualified_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(&['.', ';', '[', '/'][..])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ClassFormatErrorKind;
+    use crate::parser::format::{
+        check_class_flags, check_class_name, check_field_descriptor, check_field_flags, check_member_name,
+        check_method_descriptor, check_method_flags, check_version,
+    };
+
+    #[test]
+    fn check_versions() {
+        assert_eq!(check_version(45, 3), Ok(()));
+        assert_eq!(check_version(55, 1), Ok(()));
+        assert_eq!(check_version(61, 0), Ok(()));
+        for (major, minor) in [(44, 0), (62, 0), (61, 65535)].iter() {
+            assert_eq!(check_version(*major, *minor),
+                       Err(ClassFormatErrorKind::UnsupportedClassVersion { major: *major, minor: *minor }));
+        }
+    }
+
+    #[test]
+    fn check_flag_combinations() {
+        // public final abstract class
+        assert_eq!(check_class_flags(0x0411, 52), Err(ClassFormatErrorKind::InvalidAccessFlags(0x0411)));
+        // an interface that isn't abstract, then one with ACC_SUPER, which only Java 5 forbids
+        assert!(check_class_flags(0x0201, 52).is_err());
+        assert_eq!(check_class_flags(0x0621, 48), Ok(()));
+        assert!(check_class_flags(0x0621, 49).is_err());
+
+        // public private
+        assert!(check_field_flags(0x0003, 0x0021).is_err());
+        // final volatile
+        assert!(check_field_flags(0x0050, 0x0021).is_err());
+        // interface fields are constants
+        assert_eq!(check_field_flags(0x0019, 0x0601), Ok(()));
+        assert!(check_field_flags(0x0009, 0x0601).is_err());
+
+        // abstract static
+        assert!(check_method_flags(0x0408, "m", 0x0021, 52).is_err());
+        // abstract strict is only allowed once strict is the default
+        assert!(check_method_flags(0x0c01, "m", 0x0021, 60).is_err());
+        assert_eq!(check_method_flags(0x0c01, "m", 0x0021, 61), Ok(()));
+        assert!(check_method_flags(0x0009, "<init>", 0x0021, 52).is_err());
+        assert!(check_method_flags(0x0000, "<clinit>", 0x0021, 52).is_err());
+        // static interface methods came with Java 8
+        assert!(check_method_flags(0x0009, "m", 0x0601, 51).is_err());
+        assert_eq!(check_method_flags(0x0009, "m", 0x0601, 52), Ok(()));
+    }
+
+    #[test]
+    fn check_descriptors_and_names() {
+        assert_eq!(check_field_descriptor("[Ljava/lang/String;"), Ok(()));
+        assert_eq!(check_field_descriptor("Ljava//String;"),
+                   Err(ClassFormatErrorKind::InvalidDescriptor("Ljava//String;".to_string())));
+        assert!(check_field_descriptor(&format!("{}I", "[".repeat(256))).is_err());
+
+        assert_eq!(check_method_descriptor("(IJ)V", "m", 0x0008, 52), Ok(()));
+        assert!(check_method_descriptor("()I", "<init>", 0x0001, 52).is_err());
+        assert!(check_method_descriptor("(I)V", "<clinit>", 0x0008, 51).is_err());
+        // 127 longs and `this` take 255 slots
+        let longs = format!("({})V", "J".repeat(127));
+        assert_eq!(check_method_descriptor(&longs, "m", 0x0001, 52), Ok(()));
+        assert!(check_method_descriptor(&format!("(I{}", &longs[1..]), "m", 0x0001, 52).is_err());
+
+        assert_eq!(check_class_name("java/lang/Object"), Ok(()));
+        assert!(check_class_name("java.lang.Object").is_err());
+        assert!(check_class_name("[I").is_err());
+        assert_eq!(check_member_name("<init>", true), Ok(()));
+        assert!(check_member_name("a<b", true).is_err());
+        assert_eq!(check_member_name("a<b", false), Ok(()));
+    }
+}
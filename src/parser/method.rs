@@ -1,7 +1,3 @@
-use crate::parser::Reader;
-use crate::parser;
-use crate::method_area::Method;
-
 // pub(crate) fn read_method<'a>(reader: &mut Reader) -> Method<'a> {
 //     let access_flag = reader.read_u16();
 //     // dbg!(i, access_flag);
@@ -1,60 +1,191 @@
-use std::fs::File;
 use std::io::Read;
 
 use crate::{Opcode, parser};
-use crate::const_pool::ConstPool;
+use crate::parser::attribute::{
+    Annotation, BootstrapMethod, ElementValue, InnerClass, LineNumber, LocalVariable, MethodParameter, Module,
+    RecordComponent, StackMapFrame, TypeAnnotation,
+};
+pub use crate::const_pool::{ConstPool, CpInfo};
+pub use crate::parser::error::{ClassFormatError, ClassFormatErrorKind};
 
-pub(crate) mod attribute;
+pub mod attribute;
 pub(crate) mod const_pool;
+mod error;
+mod format;
 pub(crate) mod method;
+pub(crate) mod mutf8;
 
 #[derive(Debug)]
-pub struct ClassFile<'a> {
-    minor: u16,
-    major: u16,
+pub struct ClassFile {
+    pub(crate) minor: u16,
+    pub(crate) major: u16,
     pub(crate) const_pool: ConstPool,
+    pub(crate) access_flags: u16,
     pub(crate) this_class: String,
-    pub(crate) super_class: String,
-    pub(crate) interfaces: &'a [u16],
+    pub(crate) super_class: Option<String>,
+    pub(crate) interfaces: Vec<String>,
     pub(crate) fields: Vec<FieldInfo>,
     pub(crate) methods: Vec<MethodInfo>,
     pub(crate) attributes: Vec<Attribute>,
 }
 
+impl ClassFile {
+    pub fn minor_version(&self) -> u16 {
+        self.minor
+    }
+
+    pub fn major_version(&self) -> u16 {
+        self.major
+    }
+
+    pub fn access_flags(&self) -> u16 {
+        self.access_flags
+    }
+
+    /// Name of this class in internal form, e.g. `java/lang/Object`.
+    pub fn this_class(&self) -> &str {
+        &self.this_class
+    }
+
+    /// `None` only for `java/lang/Object`.
+    pub fn super_class(&self) -> Option<&str> {
+        self.super_class.as_deref()
+    }
+
+    pub fn interfaces(&self) -> &[String] {
+        &self.interfaces
+    }
+
+    /// Name and descriptor of each declared field.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(move |it| self.member(it.name_index, it.descriptor_index))
+    }
+
+    /// Name and descriptor of each declared method.
+    pub fn methods(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.methods.iter().map(move |it| self.member(it.name_index, it.descriptor_index))
+    }
+
+    /// §4.4, the constant pool that the indices in fields, methods and attributes refer to.
+    pub fn const_pool(&self) -> &ConstPool {
+        &self.const_pool
+    }
+
+    /// Each declared field with its access flags and attributes.
+    pub fn field_info(&self) -> &[FieldInfo] {
+        &self.fields
+    }
+
+    /// Each declared method with its access flags and attributes, its code among them.
+    ///
+    /// ```
+    /// use rj::Opcode;
+    /// use rj::parser::{AccessFlags, Attribute};
+    ///
+    /// let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class")).unwrap();
+    /// let class_file = rj::parser::parse_bytes(&bytes).unwrap();
+    /// let add = class_file.method_info().iter()
+    ///     .find(|it| class_file.const_pool().utf8(it.name_index) == Some("add"))
+    ///     .unwrap();
+    /// assert_ne!(add.access_flags & AccessFlags::STATIC as u16, 0);
+    /// let codes = add.attributes.iter().find_map(|it| match it {
+    ///     Attribute::Code { codes, .. } => Some(codes),
+    ///     _ => None,
+    /// });
+    /// assert_eq!(codes.unwrap(), &[Opcode::iload_0, Opcode::iload_1, Opcode::iadd, Opcode::ireturn]);
+    /// ```
+    pub fn method_info(&self) -> &[MethodInfo] {
+        &self.methods
+    }
+
+    /// §4.7, the attributes of the class itself.
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    fn member(&self, name_index: u16, descriptor_index: u16) -> (&str, &str) {
+        (self.const_pool.utf8(name_index).unwrap_or(""), self.const_pool.utf8(descriptor_index).unwrap_or(""))
+    }
+}
+
+// §4.5
 #[derive(Debug)]
-pub(crate) struct FieldInfo {
-    access_flags: u16,
-    name_index: u16,
-    descriptor_index: u16,
-    attributes: Vec<Attribute>,
+pub struct FieldInfo {
+    pub access_flags: u16,
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub attributes: Vec<Attribute>,
 }
 
+// §4.6
 #[derive(Debug)]
-pub(crate) struct MethodInfo {
-    pub(crate) access_flags: u16,
-    pub(crate) name_index: u16,
-    pub(crate) descriptor_index: u16,
-    pub(crate) attributes: Vec<Attribute>,
+pub struct MethodInfo {
+    pub access_flags: u16,
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub attributes: Vec<Attribute>,
 }
 
-// §4.7
+// §4.7, constant pool references are kept as indices and code offsets are
+// indices into the `codes` of the enclosing `Code` attribute
 #[derive(Debug)]
-pub(crate) enum Attribute {
-    ConstValue,
+pub enum Attribute {
+    ConstantValue { constantvalue_index: u16 },
     Code {
         max_stack: u16,
         max_locals: u16,
         codes: Vec<Opcode>,
+        exception_table: Vec<ExceptionTableEntry>,
+        attributes: Vec<Attribute>,
     },
-    StackMapTable,
-    BootstrapMethods,
-    NestHost,
-    NestMembers,
+    StackMapTable(Vec<StackMapFrame>),
+    Exceptions(Vec<u16>),
+    InnerClasses(Vec<InnerClass>),
+    EnclosingMethod { class_index: u16, method_index: u16 },
+    Synthetic,
+    Signature { signature_index: u16 },
+    SourceFile { sourcefile_index: u16 },
+    SourceDebugExtension(Vec<u8>),
+    LineNumberTable(Vec<LineNumber>),
+    LocalVariableTable(Vec<LocalVariable>),
+    LocalVariableTypeTable(Vec<LocalVariable>),
+    Deprecated,
+    RuntimeVisibleAnnotations(Vec<Annotation>),
+    RuntimeInvisibleAnnotations(Vec<Annotation>),
+    RuntimeVisibleParameterAnnotations(Vec<Vec<Annotation>>),
+    RuntimeInvisibleParameterAnnotations(Vec<Vec<Annotation>>),
+    RuntimeVisibleTypeAnnotations(Vec<TypeAnnotation>),
+    RuntimeInvisibleTypeAnnotations(Vec<TypeAnnotation>),
+    AnnotationDefault(ElementValue),
+    BootstrapMethods(Vec<BootstrapMethod>),
+    MethodParameters(Vec<MethodParameter>),
+    Module(Module),
+    ModulePackages(Vec<u16>),
+    ModuleMainClass { main_class_index: u16 },
+    NestHost { host_class_index: u16 },
+    NestMembers(Vec<u16>),
+    Record(Vec<RecordComponent>),
+    PermittedSubclasses(Vec<u16>),
+    // §4.7.1, attributes the JVM does not recognize are kept so they can be written back
+    Unknown { name_index: u16, info: Vec<u8> },
+}
+
+// §4.7.3, `start_pc`, `end_pc` and `handler_pc` are indices into `Attribute::Code::codes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExceptionTableEntry {
+    pub start_pc: usize,
+    pub end_pc: usize,
+    pub handler_pc: usize,
+    pub catch_type: u16,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[repr(u16)]
-enum AccessFlags {
+pub enum AccessFlags {
     PUBLIC = 0x0001,
+    PRIVATE = 0x0002,
+    PROTECTED = 0x0004,
+    STATIC = 0x0008,
     FINAL = 0x0010,
     SUPER = 0x0020,
     INTERFACE = 0x0200,
@@ -65,92 +196,153 @@ enum AccessFlags {
     MODULE = 0x8000,
 }
 
+/// Cursor over the bytes of a class file; parsing borrows the input rather than copying it.
 pub(crate) struct Reader<'a> {
-    u1_buffer: [u8; 1],
-    u2_buffer: [u8; 2],
-    u4_buffer: [u8; 4],
-    handle: &'a mut File,
-}
-
-impl Reader<'_> {
-    fn new(file: &mut File) -> Reader {
-        Reader {
-            u1_buffer: [0; 1],
-            u2_buffer: [0; 2],
-            u4_buffer: [0; 4],
-            handle: file,
+    bytes: &'a [u8],
+    // bytes consumed so far
+    pub(crate) position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    pub(crate) fn error(&self, kind: ClassFormatErrorKind) -> ClassFormatError {
+        ClassFormatError::new(self.position, kind)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], ClassFormatError> {
+        let bytes = self.bytes;
+        match bytes.get(self.position..self.position + length) {
+            Some(taken) => {
+                self.position += length;
+                Ok(taken)
+            }
+            None => Err(self.error(ClassFormatErrorKind::Truncated)),
         }
     }
 
-    pub fn read_u8(&mut self) -> u8 {
-        self.handle.read(&mut self.u1_buffer);
-        u8::from_be_bytes(self.u1_buffer)
+    pub fn read_u8(&mut self) -> Result<u8, ClassFormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, ClassFormatError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
     }
 
-    pub fn read_u16(&mut self) -> u16 {
-        self.handle.read(&mut self.u2_buffer);
-        u16::from_be_bytes(self.u2_buffer)
+    pub fn read_u32(&mut self) -> Result<u32, ClassFormatError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
 
-    pub fn read_u32(&mut self) -> u32 {
-        self.handle.read(&mut self.u4_buffer);
-        u32::from_be_bytes(self.u4_buffer)
+    pub fn read_utf8(&mut self, length: u16) -> Result<String, ClassFormatError> {
+        let start = self.position;
+        let bytes = self.take(length as usize)?;
+        mutf8::decode(bytes).ok_or_else(|| ClassFormatError::new(start, ClassFormatErrorKind::InvalidUtf8))
     }
 
-    pub fn read_utf8(&mut self, length: u16) -> String {
-        let mut handle = self.handle.take(length as u64);
-        let mut string = String::new();
-        handle.read_to_string(&mut string);
-        string
+    pub fn read_bytes(&mut self, length: u32) -> Result<Vec<u8>, ClassFormatError> {
+        Ok(self.take(length as usize)?.to_vec())
     }
 }
 
-pub fn parse(handle: &mut File) -> ClassFile {
-    let mut reader = Reader::new(handle);
+const MAGIC: u32 = 0xCAFEBABE;
+
+/// Parses a class file from `input`, which is read to the end first.
+///
+/// To parse bytes that are already in memory, use [`parse_bytes`] instead.
+pub fn parse<R: Read>(mut input: R) -> Result<ClassFile, ClassFormatError> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)
+        .map_err(|e| ClassFormatError::new(bytes.len(), ClassFormatErrorKind::Io(e.kind())))?;
+    parse_bytes(&bytes)
+}
+
+/// Parses a class file, see JVMS §4.1.
+///
+/// ```
+/// let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class")).unwrap();
+/// let class_file = rj::parser::parse_bytes(&bytes).unwrap();
+/// assert_eq!(class_file.this_class(), "Adder");
+/// assert_eq!(class_file.super_class(), Some("java/lang/Object"));
+/// ```
+pub fn parse_bytes(bytes: &[u8]) -> Result<ClassFile, ClassFormatError> {
+    let mut reader = Reader::new(bytes);
 
-    // magic
-    reader.read_u32();
+    let magic = reader.read_u32()?;
+    if magic != MAGIC {
+        return Err(ClassFormatError::new(0, ClassFormatErrorKind::BadMagic(magic)));
+    }
 
-    let minor = reader.read_u16();
-    let major = reader.read_u16();
+    let minor = reader.read_u16()?;
+    let major = reader.read_u16()?;
+    format::check_version(major, minor).map_err(|kind| ClassFormatError::new(4, kind))?;
 
-    let const_pool = parser::const_pool::read_const_pool(&mut reader);
-    dbg!(&const_pool);
+    let const_pool = parser::const_pool::read_const_pool(&mut reader)?;
 
-    let access_flag = reader.read_u16();
-    dbg!(access_flag);
+    let class_flags = reader.read_u16()?;
+    checked(&reader, 2, format::check_class_flags(class_flags, major))?;
 
-    let this_class: u16 = reader.read_u16();
-    let this_class: &str = const_pool.resolve_utf8(this_class);
-    let this_class: String = String::from(this_class);
+    let this_class = read_class_name(&mut reader, &const_pool)?;
 
-    let super_class: u16 = reader.read_u16();
-    let super_class: &str = const_pool.resolve_utf8(super_class);
-    let super_class: String = String::from(super_class);
+    let super_class = match reader.read_u16()? {
+        // only java/lang/Object has no superclass
+        0 => None,
+        index => {
+            let name = class_name(&reader, &const_pool, index)?;
+            checked(&reader, 2, format::check_class_name(&name))?;
+            Some(name)
+        }
+    };
 
-    let interface_count = reader.read_u16();
-    dbg!(interface_count);
-    for i in 0..interface_count {
-        let const_pool_index = reader.read_u16();
-        // todo handle interfaces
+    let interface_count = reader.read_u16()?;
+    let mut interfaces = Vec::with_capacity(interface_count as usize);
+    for _ in 0..interface_count {
+        interfaces.push(read_class_name(&mut reader, &const_pool)?);
     }
 
-    let fields_count = reader.read_u16();
-    // todo handle fields
+    let fields_count = reader.read_u16()?;
+    let mut fields = Vec::with_capacity(fields_count as usize);
+    for _ in 0..fields_count {
+        let access_flags = reader.read_u16()?;
+        checked(&reader, 2, format::check_field_flags(access_flags, class_flags))?;
+        let name_index = read_utf8_index(&mut reader, &const_pool)?;
+        checked(&reader, 2, format::check_member_name(utf8(&const_pool, name_index), false))?;
+        let descriptor_index = read_utf8_index(&mut reader, &const_pool)?;
+        checked(&reader, 2, format::check_field_descriptor(utf8(&const_pool, descriptor_index)))?;
+        let attribute_count = reader.read_u16()?;
+        let mut attributes = Vec::with_capacity(attribute_count as usize);
+        for _ in 0..attribute_count {
+            attributes.push(parser::attribute::read_attribute(&mut reader, &const_pool)?);
+        }
+        fields.push(FieldInfo {
+            access_flags,
+            name_index,
+            descriptor_index,
+            attributes,
+        });
+    }
 
-    let methods_count = reader.read_u16();
+    let methods_count = reader.read_u16()?;
     let mut methods = Vec::with_capacity(methods_count as usize);
 
     for _ in 0..methods_count {
-        let access_flags = reader.read_u16();
-        let name_index = reader.read_u16();
-        let descriptor_index = reader.read_u16();
-        let attribute_count = reader.read_u16();
+        let access_flags = reader.read_u16()?;
+        let name_index = read_utf8_index(&mut reader, &const_pool)?;
+        let name = utf8(&const_pool, name_index);
+        checked(&reader, 2, format::check_member_name(name, true))?;
+        checked(&reader, 4, format::check_method_flags(access_flags, name, class_flags, major))?;
+        let descriptor_index = read_utf8_index(&mut reader, &const_pool)?;
+        let descriptor = utf8(&const_pool, descriptor_index);
+        checked(&reader, 2, format::check_method_descriptor(descriptor, name, access_flags, major))?;
+        let attribute_count = reader.read_u16()?;
 
         let mut attributes = Vec::new();
 
         for _ in 0..attribute_count {
-            let attribute = parser::attribute::read_attribute(&mut reader, &const_pool);
+            let attribute = parser::attribute::read_attribute(&mut reader, &const_pool)?;
             attributes.push(attribute)
         }
         let method = MethodInfo {
@@ -162,28 +354,366 @@ pub fn parse(handle: &mut File) -> ClassFile {
         methods.push(method);
     }
 
-    ClassFile {
+    let attribute_count = reader.read_u16()?;
+    let mut attributes = Vec::with_capacity(attribute_count as usize);
+    for _ in 0..attribute_count {
+        attributes.push(parser::attribute::read_attribute(&mut reader, &const_pool)?);
+    }
+
+    Ok(ClassFile {
         minor,
         major,
         const_pool,
+        access_flags: class_flags,
         this_class,
         super_class,
-        interfaces: &[],
-        fields: vec![],
+        interfaces,
+        fields,
         methods,
-        attributes: vec![],
+        attributes,
+    })
+}
+
+fn read_class_name(reader: &mut Reader, const_pool: &ConstPool) -> Result<String, ClassFormatError> {
+    let index = reader.read_u16()?;
+    let name = class_name(reader, const_pool, index)?;
+    checked(reader, 2, format::check_class_name(&name))?;
+    Ok(name)
+}
+
+/// Turns a failed §4.8 format check of the item `back` bytes before the reader into an error.
+fn checked(reader: &Reader, back: usize, result: Result<(), ClassFormatErrorKind>) -> Result<(), ClassFormatError> {
+    result.map_err(|kind| ClassFormatError::new(reader.position - back, kind))
+}
+
+// an index `read_utf8_index` returned
+fn utf8(const_pool: &ConstPool, index: u16) -> &str {
+    const_pool.utf8(index).expect("the index was checked when it was read")
+}
+
+/// Reads an index that must refer to a `CONSTANT_Utf8` entry.
+fn read_utf8_index(reader: &mut Reader, const_pool: &ConstPool) -> Result<u16, ClassFormatError> {
+    let index = reader.read_u16()?;
+    match const_pool.utf8(index) {
+        Some(_) => Ok(index),
+        None => Err(ClassFormatError::new(
+            reader.position - 2, ClassFormatErrorKind::InvalidConstantPoolIndex(index))),
     }
 }
 
+fn class_name(reader: &Reader, const_pool: &ConstPool, index: u16) -> Result<String, ClassFormatError> {
+    match const_pool.class_name(index) {
+        Some(name) => Ok(name.to_string()),
+        // the index was just read
+        None => Err(ClassFormatError::new(
+            reader.position - 2, ClassFormatErrorKind::InvalidConstantPoolIndex(index))),
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::parse;
+    use std::io;
+    use std::io::Read;
+
+    use crate::const_pool::CpInfo;
+    use crate::parser::{AccessFlags, Attribute, ClassFile, ClassFormatError, ClassFormatErrorKind, parse, parse_bytes};
+    use crate::parser::attribute::{ElementValue, StackMapFrame, VerificationTypeInfo};
 
     #[test]
     fn parse_test_file() {
-        let mut handle = std::fs::File::open("C:\\Users\\yujinyan\\code\\study\\java\\Adder.class").unwrap();
-        let class_file = parse(&mut handle);
-        dbg!(class_file);
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class");
+        let mut handle = std::fs::File::open(path).unwrap();
+        let class_file = parse(&mut handle).unwrap();
+        assert_eq!(class_file.this_class(), "Adder");
+        assert!(class_file.methods().any(|it| it == ("add", "(II)I")));
+    }
+
+    fn method_attributes<'a>(class_file: &'a ClassFile, name: &str, descriptor: &str) -> &'a [Attribute] {
+        let index = class_file.methods().position(|it| it == (name, descriptor)).unwrap();
+        &class_file.methods[index].attributes
+    }
+
+    #[test]
+    fn parse_method_attributes() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Attributes.class");
+        let class_file = parse(std::fs::File::open(path).unwrap()).unwrap();
+        let const_pool = &class_file.const_pool;
+        let attributes = method_attributes(&class_file, "compareTo", "(LAttributes;)I");
+
+        let code = attributes.iter().find_map(|it| match it {
+            Attribute::Code { attributes, .. } => Some(attributes),
+            _ => None,
+        }).unwrap();
+        assert!(code.iter().any(|it| matches!(it, Attribute::LineNumberTable(lines) if lines.len() == 2)));
+        let variables = code.iter().find_map(|it| match it {
+            Attribute::LocalVariableTable(variables) => Some(variables),
+            _ => None,
+        }).unwrap();
+        let result = variables.iter().find(|it| it.index == 2).unwrap();
+        assert_eq!(const_pool.utf8(result.name_index), Some("result"));
+        // live from `iload_2` to the end of the code
+        assert_eq!((result.start_pc, result.end_pc), (2, 4));
+        assert!(code.iter().any(|it| matches!(it, Attribute::LocalVariableTypeTable(_))));
+
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::Exceptions(exceptions)
+            if const_pool.class_name(exceptions[0]) == Some("java/lang/IllegalStateException"))));
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::Signature { signature_index }
+            if const_pool.utf8(*signature_index) == Some("(LAttributes<TT;>;)I"))));
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::MethodParameters(parameters)
+            if const_pool.utf8(parameters[0].name_index) == Some("other"))));
+        let annotation = attributes.iter().find_map(|it| match it {
+            Attribute::RuntimeVisibleAnnotations(annotations) => Some(&annotations[0]),
+            _ => None,
+        }).unwrap();
+        assert_eq!(const_pool.utf8(annotation.type_index), Some("LTag;"));
+        assert!(matches!(&annotation.element_value_pairs[1], (name, ElementValue::Array(ids))
+            if const_pool.utf8(*name) == Some("ids") && ids.len() == 2));
+
+        // the bridge method javac generates for Comparable<T>
+        assert!(method_attributes(&class_file, "compareTo", "(Ljava/lang/Object;)I").iter()
+            .any(|it| matches!(it, Attribute::Code { .. })));
+    }
+
+    fn stack_map<'a>(class_file: &'a ClassFile, name: &str, descriptor: &str) -> &'a [StackMapFrame] {
+        method_attributes(class_file, name, descriptor).iter().find_map(|it| match it {
+            Attribute::Code { attributes, .. } => attributes.iter().find_map(|it| match it {
+                Attribute::StackMapTable(frames) => Some(&frames[..]),
+                _ => None,
+            }),
+            _ => None,
+        }).unwrap()
+    }
+
+    #[test]
+    fn parse_stack_map_frames() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Branches.class");
+        let class_file = parse(std::fs::File::open(path).unwrap()).unwrap();
+        // frame offsets are instruction indices, byte offsets 6 and 14
+        assert_eq!(stack_map(&class_file, "chop", "(I)I"), [
+            StackMapFrame::Append { offset: 4, locals: vec![VerificationTypeInfo::Integer] },
+            StackMapFrame::Chop { offset: 10, k: 1 },
+        ]);
+        let uninitialized = VerificationTypeInfo::Uninitialized(0);
+        assert_eq!(stack_map(&class_file, "make", "(Z)Ljava/lang/Object;"), [
+            StackMapFrame::Full {
+                offset: 6,
+                locals: vec![VerificationTypeInfo::Integer],
+                stack: vec![uninitialized, uninitialized],
+            },
+            StackMapFrame::Full {
+                offset: 7,
+                locals: vec![VerificationTypeInfo::Integer],
+                stack: vec![uninitialized, uninitialized, VerificationTypeInfo::Integer],
+            },
+        ]);
+        assert!(matches!(stack_map(&class_file, "far", "(I)I"), [StackMapFrame::Same { offset: 27 }]));
+        let exception = class_file.const_pool.find_class("java/lang/ClassCastException").unwrap();
+        assert!(matches!(stack_map(&class_file, "guarded", "(Ljava/lang/Object;)I"),
+            [StackMapFrame::Full { stack, .. }, StackMapFrame::Same { .. }]
+            if stack[..] == [VerificationTypeInfo::Object(exception)]));
+    }
+
+    #[test]
+    fn parse_class_and_field_attributes() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Attributes.class");
+        let class_file = parse(std::fs::File::open(path).unwrap()).unwrap();
+        let const_pool = &class_file.const_pool;
+        assert_eq!(class_file.access_flags(), AccessFlags::PUBLIC as u16 | AccessFlags::SUPER as u16);
+        assert_eq!(class_file.interfaces(), ["java/lang/Comparable"]);
+        assert_eq!(class_file.fields().collect::<Vec<_>>(), vec![("ANSWER", "I"), ("value", "Ljava/lang/Object;")]);
+
+        assert!(matches!(class_file.fields[0].attributes[..], [Attribute::ConstantValue { constantvalue_index }]
+            if matches!(const_pool.get(constantvalue_index), Some(CpInfo::Integer(42)))));
+        assert!(class_file.fields[1].attributes.iter().any(|it| matches!(it, Attribute::Signature { signature_index }
+            if const_pool.utf8(*signature_index) == Some("TT;"))));
+
+        let attributes = &class_file.attributes;
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::SourceFile { sourcefile_index }
+            if const_pool.utf8(*sourcefile_index) == Some("Attributes.java"))));
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::Deprecated)));
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::NestMembers(members)
+            if const_pool.class_name(members[0]) == Some("Attributes$Inner"))));
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::InnerClasses(classes)
+            if const_pool.utf8(classes[0].inner_name_index) == Some("Inner"))));
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Shape.class");
+        let class_file = parse(std::fs::File::open(path).unwrap()).unwrap();
+        assert!(class_file.attributes.iter().any(|it| matches!(it, Attribute::PermittedSubclasses(classes)
+            if class_file.const_pool.class_name(classes[0]) == Some("Circle"))));
+    }
+
+    #[test]
+    fn parse_annotation_default() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Tag.class");
+        let class_file = parse(std::fs::File::open(path).unwrap()).unwrap();
+        let attributes = method_attributes(&class_file, "value", "()Ljava/lang/String;");
+        assert!(attributes.iter().any(|it| matches!(it, Attribute::AnnotationDefault(ElementValue::Const { tag: b's', const_value_index })
+            if class_file.const_pool.utf8(*const_value_index) == Some("none"))));
+    }
+
+    #[test]
+    fn keep_unknown_attributes() {
+        let mut bytes = class_with_code(13, &[0xb1]);
+        // rename the method's attribute from `Code` to `Cod!`
+        bytes[23] = b'!';
+        let class_file = parse_bytes(&bytes).unwrap();
+        assert!(matches!(&class_file.methods[0].attributes[..],
+            [Attribute::Unknown { name_index: 3, info }] if info.len() == 13));
+    }
+
+    struct FailingRead;
+
+    impl Read for FailingRead {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone"))
+        }
+    }
+
+    #[test]
+    fn parse_from_any_read() {
+        let bytes = class_with_code(13, &[0xb1]);
+        assert_eq!(parse(bytes.as_slice()).unwrap().this_class(), "A");
+        assert_eq!(
+            parse(FailingRead).unwrap_err(),
+            ClassFormatError { offset: 0, kind: ClassFormatErrorKind::Io(io::ErrorKind::ConnectionReset) }
+        );
+    }
+
+    /// A class `A` with a single method `m()V` whose `Code` attribute
+    /// declares `code_attribute_length` and contains `code`.
+    fn class_with_code(code_attribute_length: u32, code: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 52];
+        bytes.extend(&[0, 6]);
+        bytes.extend(&[1, 0, 1, b'A']);
+        bytes.extend(&[7, 0, 1]);
+        bytes.extend(&[1, 0, 4, b'C', b'o', b'd', b'e']);
+        bytes.extend(&[1, 0, 1, b'm']);
+        bytes.extend(&[1, 0, 3, b'(', b')', b'V']);
+        // access_flags, this_class, super_class, interfaces_count, fields_count
+        bytes.extend(&[0, 0x21, 0, 2, 0, 0, 0, 0, 0, 0]);
+        // methods_count, method_info
+        bytes.extend(&[0, 1, 0, 9, 0, 4, 0, 5, 0, 1]);
+        bytes.extend(&[0, 3]);
+        bytes.extend(&code_attribute_length.to_be_bytes());
+        bytes.extend(&[0, 1, 0, 1]);
+        bytes.extend(&(code.len() as u32).to_be_bytes());
+        bytes.extend(code);
+        // exception_table_length, attributes_count, then the class's attributes_count
+        bytes.extend(&[0, 0, 0, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn parse_minimal_class() {
+        let class_file = parse_bytes(&class_with_code(13, &[0xb1])).unwrap();
+        assert_eq!(class_file.this_class, "A");
+        assert_eq!(class_file.super_class, None);
+        assert_eq!(class_file.methods.len(), 1);
+    }
+
+    #[test]
+    fn reject_bad_magic() {
+        let mut bytes = class_with_code(13, &[0xb1]);
+        bytes[3] = 0xBF;
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 0, kind: ClassFormatErrorKind::BadMagic(0xCAFEBABF) }
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_version() {
+        let mut bytes = class_with_code(13, &[0xb1]);
+        bytes[7] = 62;
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 4, kind: ClassFormatErrorKind::UnsupportedClassVersion { major: 62, minor: 0 } }
+        );
+    }
+
+    #[test]
+    fn reject_illegal_flags_and_descriptors() {
+        let mut bytes = class_with_code(13, &[0xb1]);
+        // public final abstract class
+        bytes[35] = 0x31;
+        bytes[34] = 0x04;
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 34, kind: ClassFormatErrorKind::InvalidAccessFlags(0x0431) }
+        );
+        let mut bytes = class_with_code(13, &[0xb1]);
+        // public private static
+        bytes[47] = 0x0b;
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 46, kind: ClassFormatErrorKind::InvalidAccessFlags(0x000b) }
+        );
+        let mut bytes = class_with_code(13, &[0xb1]);
+        bytes[33] = b'Q';
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 50, kind: ClassFormatErrorKind::InvalidDescriptor("()Q".to_string()) }
+        );
+    }
+
+    #[test]
+    fn reject_truncated_constant_pool() {
+        let bytes = class_with_code(13, &[0xb1]);
+        assert_eq!(
+            parse_bytes(&bytes[..20]).unwrap_err(),
+            ClassFormatError { offset: 20, kind: ClassFormatErrorKind::Truncated }
+        );
+    }
+
+    #[test]
+    fn reject_invalid_constant_pool_tag() {
+        let mut bytes = class_with_code(13, &[0xb1]);
+        bytes[14] = 2;
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 14, kind: ClassFormatErrorKind::InvalidConstantPoolTag(2) }
+        );
+    }
+
+    #[test]
+    fn reject_raw_nul_in_utf8_entry() {
+        let mut bytes = class_with_code(13, &[0xb1]);
+        bytes[13] = 0;
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 13, kind: ClassFormatErrorKind::InvalidUtf8 }
+        );
+    }
+
+    #[test]
+    fn reject_invalid_this_class() {
+        let mut bytes = class_with_code(13, &[0xb1]);
+        // point this_class at the Utf8 entry instead of the Class entry
+        bytes[37] = 1;
+        assert_eq!(
+            parse_bytes(&bytes).unwrap_err(),
+            ClassFormatError { offset: 36, kind: ClassFormatErrorKind::InvalidConstantPoolIndex(1) }
+        );
+    }
+
+    #[test]
+    fn reject_wrong_attribute_length() {
+        assert_eq!(
+            parse_bytes(&class_with_code(14, &[0xb1])).unwrap_err().kind,
+            ClassFormatErrorKind::AttributeLength { name: "Code".to_string(), declared: 14, actual: 13 }
+        );
+    }
+
+    #[test]
+    fn reject_invalid_opcode_and_branch_target() {
+        assert_eq!(
+            parse_bytes(&class_with_code(13, &[0xff])).unwrap_err(),
+            ClassFormatError { offset: 68, kind: ClassFormatErrorKind::InvalidOpcode(0xff) }
+        );
+        // goto +1 lands inside its own operand
+        assert_eq!(
+            parse_bytes(&class_with_code(16, &[0xa7, 0, 1, 0xb1])).unwrap_err(),
+            ClassFormatError { offset: 68, kind: ClassFormatErrorKind::InvalidCodeOffset(1) }
+        );
     }
 }
@@ -0,0 +1,112 @@
+// Modified UTF-8 as used by CONSTANT_Utf8_info, §4.4.7.
+//
+// It differs from standard UTF-8 in two ways: the null character is encoded as the two bytes
+// `0xC0 0x80`, so no byte is ever zero, and supplementary characters are encoded as a
+// surrogate pair with each surrogate taking three bytes.
+
+/// Decodes `bytes`, returning `None` if they are not valid modified UTF-8.
+///
+/// A lone surrogate is well-formed modified UTF-8 but has no `char`, so it decodes to U+FFFD.
+pub(crate) fn decode(bytes: &[u8]) -> Option<String> {
+    // fast path, most names and literals are ASCII
+    if bytes.iter().all(|&b| b != 0 && b < 0x80) {
+        return std::str::from_utf8(bytes).ok().map(str::to_string);
+    }
+
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let x = bytes[i];
+        let unit = match x {
+            0x01..=0x7F => {
+                i += 1;
+                x as u16
+            }
+            0xC0..=0xDF => {
+                let y = continuation(bytes, i + 1)?;
+                i += 2;
+                ((x as u16 & 0x1F) << 6) | y
+            }
+            0xE0..=0xEF => {
+                let y = continuation(bytes, i + 1)?;
+                let z = continuation(bytes, i + 2)?;
+                i += 3;
+                ((x as u16 & 0x0F) << 12) | (y << 6) | z
+            }
+            // 0x00, stray continuation bytes and the four byte forms of standard UTF-8
+            _ => return None,
+        };
+        units.push(unit);
+    }
+    Some(std::char::decode_utf16(units)
+        .map(|it| it.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+        .collect())
+}
+
+fn continuation(bytes: &[u8], i: usize) -> Option<u16> {
+    match bytes.get(i) {
+        Some(&b) if b & 0xC0 == 0x80 => Some(b as u16 & 0x3F),
+        _ => None,
+    }
+}
+
+/// Encodes `s`, the inverse of [`decode`].
+pub(crate) fn encode(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut buffer = [0; 2];
+    for c in s.chars() {
+        for &unit in c.encode_utf16(&mut buffer).iter() {
+            match unit {
+                0x01..=0x7F => bytes.push(unit as u8),
+                0x00 | 0x80..=0x7FF => {
+                    bytes.push(0xC0 | (unit >> 6) as u8);
+                    bytes.push(0x80 | (unit & 0x3F) as u8);
+                }
+                _ => {
+                    bytes.push(0xE0 | (unit >> 12) as u8);
+                    bytes.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                    bytes.push(0x80 | (unit & 0x3F) as u8);
+                }
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::mutf8::{decode, encode};
+
+    #[test]
+    fn encode_null_and_supplementary_characters() {
+        assert_eq!(encode("a\0b"), vec![b'a', 0xC0, 0x80, b'b']);
+        assert_eq!(encode("é"), vec![0xC3, 0xA9]);
+        assert_eq!(encode("€"), vec![0xE2, 0x82, 0xAC]);
+        // U+1F600 is the surrogate pair D83D DE00
+        assert_eq!(encode("😀"), vec![0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80]);
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in ["", "java/lang/Object", "nul\0in the middle", "héllo wörld €", "emoji 😀 and 𝄞"].iter() {
+            assert_eq!(decode(&encode(s)).as_deref(), Some(*s));
+        }
+    }
+
+    #[test]
+    fn reject_standard_utf8_forms() {
+        // raw NUL
+        assert_eq!(decode(&[b'a', 0]), None);
+        // four byte encoding of U+1F600
+        assert_eq!(decode(&[0xF0, 0x9F, 0x98, 0x80]), None);
+        // truncated and bad continuation bytes
+        assert_eq!(decode(&[0xC3]), None);
+        assert_eq!(decode(&[0xE2, 0x82, b'a']), None);
+        assert_eq!(decode(&[0x80]), None);
+    }
+
+    #[test]
+    fn lone_surrogate_decodes_to_replacement_character() {
+        assert_eq!(decode(&[b'a', 0xED, 0xA0, 0xBD]).as_deref(), Some("a\u{FFFD}"));
+    }
+}
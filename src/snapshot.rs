@@ -0,0 +1,249 @@
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use crate::heap::{Heap, Object, Reference};
+
+// the format changes whenever what the VM keeps of classes or the heap does, so a snapshot is
+// only restored by the version that saved it
+const MAGIC: &[u8] = b"rj snapshot\0";
+const VERSION: u32 = 1;
+
+/// Where a class the application defined came from, which decides how a snapshot restores it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Origin {
+    // found on the classpath, where restoring checks it is still found as these bytes
+    Classpath(Vec<u8>),
+    // passed to the VM directly, as these bytes unless it couldn't be written as a class file
+    Defined(Option<Vec<u8>>),
+}
+
+/// A class the application defined, in the order the VM defined them.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Definition {
+    pub(crate) name: String,
+    pub(crate) origin: Origin,
+}
+
+/// The contents of a snapshot: the classpath it was saved with, the classes defined then and
+/// the heap with the static fields of the classes that were initialized.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Image {
+    pub(crate) classpath: Vec<String>,
+    pub(crate) definitions: Vec<Definition>,
+    pub(crate) objects: Vec<Object>,
+    pub(crate) statics: Vec<(String, Vec<i32>)>,
+    pub(crate) interned: Vec<(String, Reference)>,
+}
+
+/// Encodes a snapshot of the VM, or says which class it can't save.
+///
+/// Nothing in it refers to methods, so the decoded and quickened instructions, inline caches
+/// and native code of the methods are left for the VM to build again once restored. Objects
+/// keep their references, which is what static fields, interned strings and quickened
+/// instructions hold.
+pub(crate) fn save(classpath: &[PathBuf], definitions: &[Definition], heap: &Heap) -> Result<Vec<u8>, String> {
+    let mut out = Encoder(MAGIC.to_vec());
+    out.u32(VERSION);
+    out.u32(classpath.len() as u32);
+    for path in classpath {
+        out.string(&path.to_string_lossy());
+    }
+    out.u32(definitions.len() as u32);
+    for Definition { name, origin } in definitions {
+        out.string(name);
+        match origin {
+            Origin::Classpath(bytes) => {
+                out.u8(0);
+                out.bytes(bytes);
+            }
+            Origin::Defined(Some(bytes)) => {
+                out.u8(1);
+                out.bytes(bytes);
+            }
+            Origin::Defined(None) => return Err(format!("{} can't be written as a class file", name)),
+        }
+    }
+    out.u32(heap.objects().len() as u32);
+    for object in heap.objects() {
+        match object {
+            Object::Instance { class, fields } => {
+                out.u8(0);
+                out.string(class);
+                out.slots(fields);
+            }
+            Object::Array { class, elements } => {
+                out.u8(1);
+                out.string(class);
+                out.slots(elements);
+            }
+            Object::String(value) => {
+                out.u8(2);
+                out.string(value);
+            }
+        }
+    }
+    let initialized = heap.initialized();
+    out.u32(initialized.len() as u32);
+    for (class, fields) in initialized {
+        out.string(class);
+        out.slots(fields);
+    }
+    let interned = heap.interned();
+    out.u32(interned.len() as u32);
+    for (value, string) in interned {
+        out.string(value);
+        out.i32(string);
+    }
+    Ok(out.0)
+}
+
+/// Decodes a snapshot [`save`] encoded.
+pub(crate) fn load(bytes: &[u8]) -> Result<Image, String> {
+    if !bytes.starts_with(MAGIC) {
+        return Err("not a snapshot".to_string());
+    }
+    let mut input = Decoder { bytes, position: MAGIC.len() };
+    let version = input.u32()?;
+    if version != VERSION {
+        return Err(format!("snapshot version {} can't be restored by version {}", version, VERSION));
+    }
+    let classpath = input.list(Decoder::string)?;
+    let definitions = input.list(|input| {
+        let name = input.string()?;
+        let origin = match input.u8()? {
+            0 => Origin::Classpath(input.bytes()?.to_vec()),
+            1 => Origin::Defined(Some(input.bytes()?.to_vec())),
+            tag => return Err(format!("invalid class origin {}", tag)),
+        };
+        Ok(Definition { name, origin })
+    })?;
+    let objects = input.list(|input| {
+        Ok(match input.u8()? {
+            0 => Object::Instance { class: input.string()?, fields: input.slots()? },
+            1 => Object::Array { class: input.string()?, elements: input.slots()? },
+            2 => Object::String(input.string()?),
+            tag => return Err(format!("invalid object kind {}", tag)),
+        })
+    })?;
+    let statics = input.list(|input| Ok((input.string()?, input.slots()?)))?;
+    let interned = input.list(|input| Ok((input.string()?, input.i32()?)))?;
+    if input.position != bytes.len() {
+        return Err("trailing bytes after the snapshot".to_string());
+    }
+    Ok(Image { classpath, definitions, objects, statics, interned })
+}
+
+// little-endian, with lengths before strings, byte strings and lists
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    fn slots(&mut self, slots: &[i32]) {
+        self.u32(slots.len() as u32);
+        slots.iter().for_each(|it| self.i32(*it));
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self.position.checked_add(length)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or("snapshot is truncated")?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(<[u8; 4]>::try_from(self.take(4)?).unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(<[u8; 4]>::try_from(self.take(4)?).unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| "invalid string in snapshot".to_string())
+    }
+
+    fn slots(&mut self) -> Result<Vec<i32>, String> {
+        self.list(Decoder::i32)
+    }
+
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let count = self.u32()? as usize;
+        // not reserved up front, since a corrupt count could be huge
+        let mut items = Vec::new();
+        for _ in 0..count {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::heap::{Heap, Object};
+    use crate::snapshot::{Definition, Image, load, Origin, save};
+
+    #[test]
+    fn snapshot_round_trips() {
+        let mut heap = Heap::new();
+        heap.init_statics("A", 2);
+        let array = heap.allocate(Object::Array { class: "[I".to_string(), elements: vec![1, -2] });
+        heap.statics_mut("A")[1] = array;
+        let string = heap.intern("foo");
+        let definitions = vec![
+            Definition { name: "A".to_string(), origin: Origin::Classpath(vec![0xca, 0xfe]) },
+            Definition { name: "B".to_string(), origin: Origin::Defined(Some(vec![0xba, 0xbe])) },
+        ];
+        let bytes = save(&[PathBuf::from("classes")], &definitions, &heap).unwrap();
+        assert_eq!(load(&bytes), Ok(Image {
+            classpath: vec!["classes".to_string()],
+            definitions,
+            objects: vec![Object::Array { class: "[I".to_string(), elements: vec![1, -2] }, Object::String("foo".to_string())],
+            statics: vec![("A".to_string(), vec![0, array])],
+            interned: vec![("foo".to_string(), string)],
+        }));
+
+        assert_eq!(load(&bytes[..bytes.len() - 1]), Err("snapshot is truncated".to_string()));
+        assert_eq!(load(b"\xca\xfe\xba\xbe").unwrap_err(), "not a snapshot");
+        let unwritable = [Definition { name: "C".to_string(), origin: Origin::Defined(None) }];
+        assert_eq!(save(&[], &unwritable, &heap), Err("C can't be written as a class file".to_string()));
+    }
+}
@@ -0,0 +1,1384 @@
+use std::fmt::Write;
+
+use crate::const_pool::{ConstPool, CpInfo};
+use crate::descriptor::{FieldType, MethodDescriptor};
+use crate::disassembler::array_type;
+use crate::heap::slot_width;
+use crate::{branch_target, Opcode};
+use crate::parser::{AccessFlags, Attribute, ClassFile, ExceptionTableEntry};
+
+/// Renders each method of a class file in SSA form, as it is built from the bytecode and again
+/// after it is optimized, so that what the optimizations did can be seen.
+///
+/// ```
+/// let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class")).unwrap();
+/// let text = rj::ssa::dump(&rj::parser::parse_bytes(&bytes).unwrap());
+/// assert!(text.contains("Adder.add:(II)I\n  built:\n    b0:\n      v0 = argument 0\n"));
+/// ```
+pub fn dump(class_file: &ClassFile) -> String {
+    let constant_pool = &class_file.const_pool;
+    let mut out = String::new();
+    for method in &class_file.methods {
+        let code = method.attributes.iter().find_map(|it| match it {
+            Attribute::Code { max_locals, codes, exception_table, .. } => Some((*max_locals, codes, exception_table)),
+            _ => None,
+        });
+        let (local_size, codes, exception_table) = match code {
+            Some(code) => code,
+            None => continue,
+        };
+        let name = constant_pool.utf8(method.name_index).unwrap_or("");
+        let descriptor = constant_pool.utf8(method.descriptor_index).unwrap_or("");
+        let is_static = method.access_flags & AccessFlags::STATIC as u16 != 0;
+        writeln!(out, "{}.{}:{}", class_file.this_class, name, descriptor).unwrap();
+        match Function::build(codes, constant_pool, descriptor, is_static, local_size as usize, exception_table) {
+            Ok(mut function) => {
+                out.push_str("  built:\n");
+                function.print(constant_pool, &mut out);
+                function.optimize();
+                out.push_str("  optimized:\n");
+                function.print(constant_pool, &mut out);
+            }
+            Err(reason) => writeln!(out, "  not converted: {}", reason).unwrap(),
+        }
+    }
+    out
+}
+
+pub(crate) type Value = usize;
+pub(crate) type Block = usize;
+
+// what computes a value, from the values it takes as operands
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Op {
+    // the local at the index when the method is called, which holds an argument or `this`
+    Argument(usize),
+    // a local that isn't set when the method is called, which the verifier keeps from being used
+    Undefined,
+    Const(i32),
+    // the `String` of the string constant at the index
+    String(u16),
+    Copy(Value),
+    // the value that reaches the block from each of its predecessors
+    Phi(Vec<(Block, Value)>),
+    // the exception a handler catches
+    Exception,
+    Add(Value, Value),
+    GetStatic(u16),
+    PutStatic(u16, Value),
+    GetField(u16, Value),
+    PutField(u16, Value, Value),
+    Invoke { opcode: &'static str, index: u16, arguments: Vec<Value>, returns: bool },
+    New(u16),
+    NewArray(u8, Value),
+    ANewArray(u16, Value),
+    ArrayLength(Value),
+    // throws unless the array isn't null and the index is within it, so that the array access
+    // after it doesn't have to
+    BoundsCheck(Value, Value),
+    ArrayLoad(Value, Value),
+    ArrayStore(Value, Value, Value),
+    CheckCast(u16, Value),
+    InstanceOf(u16, Value),
+}
+
+impl Op {
+    fn operands(&self) -> Vec<Value> {
+        match self {
+            Op::Argument(_) | Op::Undefined | Op::Const(_) | Op::String(_) | Op::Exception | Op::GetStatic(_)
+            | Op::New(_) => vec![],
+            Op::Copy(a) | Op::PutStatic(_, a) | Op::GetField(_, a) | Op::NewArray(_, a) | Op::ANewArray(_, a)
+            | Op::ArrayLength(a) | Op::CheckCast(_, a) | Op::InstanceOf(_, a) => vec![*a],
+            Op::Add(a, b) | Op::PutField(_, a, b) | Op::BoundsCheck(a, b) | Op::ArrayLoad(a, b) => vec![*a, *b],
+            Op::ArrayStore(a, b, c) => vec![*a, *b, *c],
+            Op::Phi(operands) => operands.iter().map(|(_, it)| *it).collect(),
+            Op::Invoke { arguments, .. } => arguments.clone(),
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Op::Argument(_) | Op::Undefined | Op::Const(_) | Op::String(_) | Op::Exception | Op::GetStatic(_)
+            | Op::New(_) => vec![],
+            Op::Copy(a) | Op::PutStatic(_, a) | Op::GetField(_, a) | Op::NewArray(_, a) | Op::ANewArray(_, a)
+            | Op::ArrayLength(a) | Op::CheckCast(_, a) | Op::InstanceOf(_, a) => vec![a],
+            Op::Add(a, b) | Op::PutField(_, a, b) | Op::BoundsCheck(a, b) | Op::ArrayLoad(a, b) => vec![a, b],
+            Op::ArrayStore(a, b, c) => vec![a, b, c],
+            Op::Phi(operands) => operands.iter_mut().map(|(_, it)| it).collect(),
+            Op::Invoke { arguments, .. } => arguments.iter_mut().collect(),
+        }
+    }
+
+    // whether it can be removed once nothing uses its value, as it neither throws nor has
+    // effects other than computing it; not instanceof, which resolves its class for objects
+    fn is_pure(&self) -> bool {
+        matches!(self, Op::Argument(_) | Op::Undefined | Op::Const(_) | Op::String(_) | Op::Copy(_) | Op::Phi(_)
+            | Op::Exception | Op::Add(..))
+    }
+
+    fn has_value(&self) -> bool {
+        !matches!(self, Op::PutStatic(..) | Op::PutField(..) | Op::BoundsCheck(..) | Op::ArrayStore(..)
+            | Op::Invoke { returns: false, .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Condition {
+    Zero(Value),
+    NonZero(Value),
+    Less(Value, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Terminator {
+    Goto(Block),
+    // to the first block if the condition holds, else to the second
+    If(Condition, Block, Block),
+    Return(Option<Value>),
+    Throw(Value),
+}
+
+impl Terminator {
+    fn successors(&self) -> Vec<Block> {
+        match *self {
+            Terminator::Goto(target) => vec![target],
+            Terminator::If(_, then, otherwise) => vec![then, otherwise],
+            Terminator::Return(_) | Terminator::Throw(_) => vec![],
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Goto(_) | Terminator::Return(None) => vec![],
+            Terminator::If(Condition::Zero(a), ..) | Terminator::If(Condition::NonZero(a), ..)
+            | Terminator::Return(Some(a)) | Terminator::Throw(a) => vec![a],
+            Terminator::If(Condition::Less(a, b), ..) => vec![a, b],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct BasicBlock {
+    // the instruction the block starts at, None for the block that defines the arguments
+    pub(crate) pc: Option<usize>,
+    pub(crate) values: Vec<Value>,
+    pub(crate) end: Terminator,
+    // the handlers an exception thrown by the block's first instruction can go to
+    pub(crate) handlers: Vec<Block>,
+}
+
+impl BasicBlock {
+    fn successors(&self) -> Vec<Block> {
+        let mut successors = self.end.successors();
+        successors.extend(&self.handlers);
+        successors
+    }
+}
+
+/// A method in SSA form: each value is computed once by an [`Op`], with phis where the
+/// locals and operand stack slots of several blocks meet.
+///
+/// Blocks start where the bytecode can be branched to, and in the ranges exception handlers
+/// cover also at each instruction that can throw, so that a handler is only reached from
+/// block starts and sees the locals as the block starts with them.
+#[derive(Debug)]
+pub(crate) struct Function {
+    pub(crate) ops: Vec<Op>,
+    // None for blocks that can't be reached
+    pub(crate) blocks: Vec<Option<BasicBlock>>,
+}
+
+// the locals and operand stack slots at some point of the method
+type State = (Vec<Value>, Vec<Value>);
+
+impl Function {
+    /// Converts `codes` of a method taking `descriptor` arguments, besides `this` unless it is
+    /// static, into SSA form, or says why it can't.
+    ///
+    /// Every block first gets a phi for each local and operand stack slot, which copy
+    /// propagation then removes wherever a single value reaches the block.
+    pub(crate) fn build(
+        codes: &[Opcode], constant_pool: &ConstPool, descriptor: &str, is_static: bool, local_size: usize,
+        exception_table: &[ExceptionTableEntry],
+    ) -> Result<Function, &'static str> {
+        let descriptor = MethodDescriptor::parse(descriptor).ok_or("invalid descriptor")?;
+        if descriptor.parameters.iter().any(|it| it.slot_width() == 2) {
+            return Err("long or double arguments");
+        }
+        let arguments = descriptor.parameters.len() + !is_static as usize;
+        if arguments > local_size {
+            return Err("arguments don't fit in the locals");
+        }
+
+        let mut leaders = vec![false; codes.len() + 1];
+        leaders[0] = true;
+        for (pc, code) in codes.iter().enumerate() {
+            if matches!(code, Opcode::jsr(_) | Opcode::ret(_)) {
+                return Err("jsr and ret");
+            }
+            if let Some(target) = branch_target(code) {
+                *leaders.get_mut(target).ok_or("branch out of the code")? = true;
+            }
+            if ends_block(code) {
+                leaders[pc + 1] = true;
+            }
+            if can_throw(code) && exception_table.iter().any(|it| (it.start_pc..it.end_pc).contains(&pc)) {
+                leaders[pc] = true;
+                leaders[pc + 1] = true;
+            }
+        }
+        for entry in exception_table {
+            *leaders.get_mut(entry.handler_pc).ok_or("handler out of the code")? = true;
+        }
+        // block 0 defines the arguments and goes to block 1, which starts at the first leader
+        let starts: Vec<usize> = (0..codes.len()).filter(|&pc| leaders[pc]).collect();
+        let is_handler = |pc: usize| exception_table.iter().any(|it| it.handler_pc == pc);
+
+        let mut builder = Builder {
+            constant_pool,
+            function: Function { ops: vec![], blocks: (0..=starts.len()).map(|_| None).collect() },
+            entries: vec![None; starts.len() + 1],
+            starts,
+            edges: vec![],
+            pending: vec![],
+        };
+        builder.function.blocks[0] = Some(BasicBlock { pc: None, values: vec![], end: Terminator::Goto(1), handlers: vec![] });
+        let locals: Vec<Value> = (0..local_size)
+            .map(|local| builder.emit(0, if local < arguments { Op::Argument(local) } else { Op::Undefined }))
+            .collect();
+        builder.enter(0, 1, (locals, vec![]), false)?;
+
+        while let Some(block) = builder.pending.pop() {
+            let start = builder.starts[block - 1];
+            let (mut locals, mut stack) = builder.entries[block].clone().unwrap();
+            let mut handlers: Vec<Block> = Vec::new();
+            if can_throw(&codes[start]) {
+                for entry in exception_table.iter().filter(|it| (it.start_pc..it.end_pc).contains(&start)) {
+                    let handler = builder.block_at(entry.handler_pc)?;
+                    if !handlers.contains(&handler) {
+                        handlers.push(handler);
+                        builder.enter(block, handler, (locals.clone(), vec![]), true)?;
+                    }
+                }
+            }
+            let mut pc = start;
+            let end = loop {
+                if pc > start && leaders[pc] {
+                    let next = builder.block_at(pc)?;
+                    if is_handler(pc) {
+                        return Err("handler reached other than by an exception");
+                    }
+                    builder.enter(block, next, (locals, stack), false)?;
+                    break Terminator::Goto(next);
+                }
+                let code = codes.get(pc).ok_or("falls off the end of the code")?;
+                if let Some(end) = builder.translate(block, pc, code, &mut locals, &mut stack)? {
+                    for successor in end.successors() {
+                        if is_handler(builder.starts[successor - 1]) {
+                            return Err("handler reached other than by an exception");
+                        }
+                        builder.enter(block, successor, (locals.clone(), stack.clone()), false)?;
+                    }
+                    break end;
+                }
+                pc += 1;
+            };
+            let basic_block = builder.function.blocks[block].as_mut().unwrap();
+            basic_block.end = end;
+            basic_block.handlers = handlers;
+        }
+
+        let Builder { mut function, entries, edges, .. } = builder;
+        for (from, to, (locals, stack)) in edges {
+            let (phi_locals, phi_stack) = entries[to].as_ref().unwrap();
+            for (phi, value) in phi_locals.iter().zip(locals).chain(phi_stack.iter().zip(stack)) {
+                if let Op::Phi(operands) = &mut function.ops[*phi] {
+                    operands.push((from, value));
+                }
+            }
+        }
+        // the blocks of code that can't be reached were never entered
+        for (block, entry) in entries.iter().enumerate().skip(1) {
+            if entry.is_none() {
+                function.blocks[block] = None;
+            }
+        }
+        Ok(function)
+    }
+
+    /// Runs constant folding, copy propagation and bounds check elimination until none of them
+    /// finds anything more to do, then removes the values nothing uses.
+    pub(crate) fn optimize(&mut self) {
+        loop {
+            let mut changed = self.fold_constants();
+            changed |= self.propagate_copies();
+            changed |= self.eliminate_bounds_checks();
+            if !changed {
+                break;
+            }
+        }
+        self.eliminate_dead_code();
+    }
+
+    /// Lowers the function back to bytecode for a method taking `descriptor` arguments, besides
+    /// `this` unless it is static, so that the interpreter and the JIT can run it.
+    ///
+    /// Each value is kept in a local of its own past the arguments, which stay where the method
+    /// is called with them, and the operand stack is empty between blocks. The locals of a
+    /// block's phis are set on each branch to it. The bounds checks left aren't lowered, as the
+    /// array access after each of them checks the same, and the accesses whose checks were
+    /// eliminated are lowered to unchecked ones.
+    pub(crate) fn lower(&self, descriptor: &str, is_static: bool) -> Result<Lowered, &'static str> {
+        let descriptor = MethodDescriptor::parse(descriptor).ok_or("invalid descriptor")?;
+        let arguments = descriptor.parameters.len() + !is_static as usize;
+        let returns_reference = descriptor.return_type.as_ref().is_some_and(FieldType::is_reference);
+        let order = self.reverse_postorder();
+        let mut lowering = Lowering {
+            function: self,
+            slots: vec![None; self.ops.len()],
+            codes: vec![],
+            stack_size: 0,
+            branches: vec![],
+            checked: None,
+        };
+        let mut local_size = arguments;
+        for &block in &order {
+            let basic_block = self.blocks[block].as_ref().unwrap();
+            if !basic_block.handlers.is_empty() {
+                return Err("exception handlers");
+            }
+            for &value in &basic_block.values {
+                match self.ops[value] {
+                    Op::Argument(local) => lowering.slots[value] = Some(local),
+                    ref op if op.has_value() => {
+                        lowering.slots[value] = Some(local_size);
+                        local_size += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if local_size > u16::MAX as usize {
+            return Err("too many values for the locals");
+        }
+
+        let mut starts = vec![0; self.blocks.len()];
+        for (position, &block) in order.iter().enumerate() {
+            starts[block] = lowering.codes.len();
+            let next = order.get(position + 1).copied();
+            let basic_block = self.blocks[block].as_ref().unwrap();
+            for &value in &basic_block.values {
+                lowering.op(value)?;
+            }
+            match basic_block.end {
+                Terminator::Goto(target) => lowering.edge(block, target, next)?,
+                Terminator::If(condition, then, otherwise) => {
+                    let (operands, code) = match condition {
+                        Condition::Zero(value) => (vec![value], Opcode::ifeq(0)),
+                        Condition::NonZero(value) => (vec![value], Opcode::ifne(0)),
+                        Condition::Less(a, b) => (vec![a, b], Opcode::if_icmplt(0)),
+                    };
+                    lowering.load(&operands)?;
+                    if lowering.copies(block, then)?.is_empty() {
+                        lowering.branch(code, then);
+                        lowering.edge(block, otherwise, next)?;
+                    } else {
+                        // the branch goes to the copies to the phis of `then`, after those of
+                        // `otherwise`, which then can't fall through
+                        let branch = lowering.codes.len();
+                        lowering.codes.push(code);
+                        lowering.edge(block, otherwise, None)?;
+                        let copies = lowering.codes.len();
+                        set_branch_target(&mut lowering.codes[branch], copies);
+                        lowering.edge(block, then, next)?;
+                    }
+                }
+                Terminator::Return(Some(value)) => {
+                    lowering.load(&[value])?;
+                    lowering.codes.push(if returns_reference { Opcode::areturn } else { Opcode::ireturn });
+                }
+                Terminator::Return(None) => lowering.codes.push(Opcode::r#return),
+                Terminator::Throw(value) => {
+                    lowering.load(&[value])?;
+                    lowering.codes.push(Opcode::athrow);
+                }
+            }
+        }
+        let Lowering { mut codes, stack_size, branches, .. } = lowering;
+        for (pc, block) in branches {
+            set_branch_target(&mut codes[pc], starts[block]);
+        }
+        Ok(Lowered { codes, local_size, stack_size })
+    }
+
+    fn live_blocks(&self) -> impl Iterator<Item = (Block, &BasicBlock)> {
+        self.blocks.iter().enumerate().filter_map(|(block, it)| it.as_ref().map(|it| (block, it)))
+    }
+
+    fn constant(&self, value: Value) -> Option<i32> {
+        match self.ops[value] {
+            Op::Const(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // the blocks reachable from the start, in reverse postorder
+    fn reverse_postorder(&self) -> Vec<Block> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        // each block with the successors that are left to visit
+        let mut path = vec![(0, self.blocks[0].as_ref().unwrap().successors())];
+        visited[0] = true;
+        while let Some((block, successors)) = path.last_mut() {
+            match successors.pop() {
+                Some(successor) if !visited[successor] => {
+                    visited[successor] = true;
+                    let successors = self.blocks[successor].as_ref().unwrap().successors();
+                    path.push((successor, successors));
+                }
+                Some(_) => {}
+                None => {
+                    order.push(*block);
+                    path.pop();
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    fn predecessors(&self) -> Vec<Vec<Block>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (block, basic_block) in self.live_blocks() {
+            for successor in basic_block.successors() {
+                if !predecessors[successor].contains(&block) {
+                    predecessors[successor].push(block);
+                }
+            }
+        }
+        predecessors
+    }
+
+    // the immediate dominator of each block, None for the start and for blocks that can't be
+    // reached, see Cooper, Harvey and Kennedy's "A Simple, Fast Dominance Algorithm"
+    fn dominators(&self, predecessors: &[Vec<Block>]) -> Vec<Option<Block>> {
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (index, block) in order.iter().enumerate() {
+            rank[*block] = index;
+        }
+        let mut dominators = vec![None; self.blocks.len()];
+        dominators[0] = Some(0);
+        let intersect = |dominators: &[Option<Block>], mut a: Block, mut b: Block| {
+            while a != b {
+                while rank[a] > rank[b] {
+                    a = dominators[a].unwrap();
+                }
+                while rank[b] > rank[a] {
+                    b = dominators[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order[1..] {
+                let mut dominator = None;
+                for &predecessor in &predecessors[block] {
+                    if dominators[predecessor].is_some() {
+                        dominator = Some(dominator.map_or(predecessor, |it| intersect(&dominators, predecessor, it)));
+                    }
+                }
+                if dominators[block] != dominator {
+                    dominators[block] = dominator;
+                    changed = true;
+                }
+            }
+        }
+        dominators[0] = None;
+        dominators
+    }
+
+    fn remove_unreachable_blocks(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        for block in self.reverse_postorder() {
+            reachable[block] = true;
+        }
+        for (block, reachable) in reachable.into_iter().enumerate() {
+            if !reachable {
+                self.blocks[block] = None;
+            }
+        }
+        let predecessors = self.predecessors();
+        for (block, basic_block) in self.blocks.iter().enumerate() {
+            for value in basic_block.iter().flat_map(|it| &it.values) {
+                if let Op::Phi(operands) = &mut self.ops[*value] {
+                    operands.retain(|(from, _)| predecessors[block].contains(from));
+                }
+            }
+        }
+    }
+
+    fn fold_constants(&mut self) -> bool {
+        let mut changed = false;
+        let values: Vec<Value> = self.live_blocks().flat_map(|(_, it)| it.values.iter().copied()).collect();
+        for value in values {
+            let folded = match self.ops[value] {
+                Op::Add(a, b) => match (self.constant(a), self.constant(b)) {
+                    (Some(a), Some(b)) => Some(Op::Const(a.wrapping_add(b))),
+                    (_, Some(0)) => Some(Op::Copy(a)),
+                    (Some(0), _) => Some(Op::Copy(b)),
+                    _ => None,
+                },
+                // the array is only there once its length was found to be valid
+                Op::ArrayLength(array) => match self.ops[array] {
+                    Op::NewArray(_, length) | Op::ANewArray(_, length) => Some(Op::Copy(length)),
+                    _ => None,
+                },
+                Op::InstanceOf(_, object) if self.constant(object) == Some(0) => Some(Op::Const(0)),
+                Op::CheckCast(_, object) if self.constant(object) == Some(0) => Some(Op::Copy(object)),
+                _ => None,
+            };
+            if let Some(op) = folded {
+                self.ops[value] = op;
+                changed = true;
+            }
+        }
+
+        let mut branches_folded = false;
+        for block in 0..self.blocks.len() {
+            let end = match &self.blocks[block] {
+                Some(BasicBlock { end: Terminator::If(condition, then, otherwise), .. }) => {
+                    let taken = match *condition {
+                        Condition::Zero(value) => self.constant(value).map(|it| it == 0),
+                        Condition::NonZero(value) => self.constant(value).map(|it| it != 0),
+                        Condition::Less(a, b) if a == b => Some(false),
+                        Condition::Less(a, b) => self.constant(a).zip(self.constant(b)).map(|(a, b)| a < b),
+                    };
+                    taken.map(|taken| Terminator::Goto(if taken { *then } else { *otherwise }))
+                }
+                _ => None,
+            };
+            if let Some(end) = end {
+                self.blocks[block].as_mut().unwrap().end = end;
+                branches_folded = true;
+            }
+        }
+        if branches_folded {
+            self.remove_unreachable_blocks();
+        }
+        changed || branches_folded
+    }
+
+    fn propagate_copies(&mut self) -> bool {
+        let mut changed = false;
+        // a phi that merges a single value, or that value and itself around a loop, copies it
+        let values: Vec<Value> = self.live_blocks().flat_map(|(_, it)| it.values.iter().copied()).collect();
+        for &value in &values {
+            if let Op::Phi(operands) = &self.ops[value] {
+                let mut sources = operands.iter().map(|(_, it)| *it).filter(|it| *it != value);
+                if let Some(source) = sources.next() {
+                    if sources.all(|it| it == source) {
+                        self.ops[value] = Op::Copy(source);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        // what each value copies, following copies of copies
+        let sources: Vec<Value> = (0..self.ops.len())
+            .map(|mut value| {
+                // bounded, since copies that go around in a circle could only come from blocks
+                // that can't be reached
+                for _ in 0..self.ops.len() {
+                    match self.ops[value] {
+                        Op::Copy(source) => value = source,
+                        _ => break,
+                    }
+                }
+                value
+            })
+            .collect();
+        let mut replace = |operand: &mut Value| {
+            if sources[*operand] != *operand {
+                *operand = sources[*operand];
+                changed = true;
+            }
+        };
+        for value in values {
+            self.ops[value].operands_mut().into_iter().for_each(&mut replace);
+        }
+        for basic_block in self.blocks.iter_mut().flatten() {
+            basic_block.end.operands_mut().into_iter().for_each(&mut replace);
+        }
+        changed
+    }
+
+    fn eliminate_bounds_checks(&mut self) -> bool {
+        let predecessors = self.predecessors();
+        let dominators = self.dominators(&predecessors);
+        let mut places = vec![None; self.ops.len()];
+        for (block, basic_block) in self.live_blocks() {
+            for &value in &basic_block.values {
+                places[value] = Some(block);
+            }
+        }
+        let analysis = Analysis { function: self, predecessors, dominators, places };
+
+        let mut redundant = Vec::new();
+        for (block, basic_block) in self.live_blocks() {
+            for (position, &value) in basic_block.values.iter().enumerate() {
+                if let Op::BoundsCheck(array, index) = self.ops[value] {
+                    if analysis.within(array, index, block) || analysis.checked_before(value, block, position) {
+                        redundant.push((block, value));
+                    }
+                }
+            }
+        }
+        for (block, value) in &redundant {
+            self.blocks[*block].as_mut().unwrap().values.retain(|it| it != value);
+        }
+        !redundant.is_empty()
+    }
+
+    fn eliminate_dead_code(&mut self) {
+        let mut live = vec![false; self.ops.len()];
+        let mut pending: Vec<Value> = Vec::new();
+        for (_, basic_block) in self.live_blocks() {
+            pending.extend(basic_block.values.iter().filter(|it| !self.ops[**it].is_pure()));
+            pending.extend(basic_block.end.clone().operands_mut().into_iter().map(|it| *it));
+        }
+        while let Some(value) = pending.pop() {
+            if !live[value] {
+                live[value] = true;
+                pending.extend(self.ops[value].operands());
+            }
+        }
+        for basic_block in self.blocks.iter_mut().flatten() {
+            basic_block.values.retain(|it| live[*it]);
+        }
+    }
+
+    pub(crate) fn print(&self, constant_pool: &ConstPool, out: &mut String) {
+        let predecessors = self.predecessors();
+        for (block, basic_block) in self.live_blocks() {
+            let mut header = format!("    b{}", block);
+            if let Some(pc) = basic_block.pc {
+                write!(header, " (pc {})", pc).unwrap();
+            }
+            if !predecessors[block].is_empty() {
+                let predecessors: Vec<String> = predecessors[block].iter().map(|it| format!("b{}", it)).collect();
+                write!(header, " <- {}", predecessors.join(", ")).unwrap();
+            }
+            writeln!(out, "{}:", header).unwrap();
+            for &value in &basic_block.values {
+                let op = &self.ops[value];
+                if op.has_value() {
+                    writeln!(out, "      v{} = {}", value, op_text(op, constant_pool)).unwrap();
+                } else {
+                    writeln!(out, "      {}", op_text(op, constant_pool)).unwrap();
+                }
+            }
+            let end = match basic_block.end {
+                Terminator::Goto(target) => format!("goto b{}", target),
+                Terminator::If(condition, then, otherwise) => {
+                    let condition = match condition {
+                        Condition::Zero(value) => format!("v{} == 0", value),
+                        Condition::NonZero(value) => format!("v{} != 0", value),
+                        Condition::Less(a, b) => format!("v{} < v{}", a, b),
+                    };
+                    format!("if {} goto b{} else b{}", condition, then, otherwise)
+                }
+                Terminator::Return(Some(value)) => format!("return v{}", value),
+                Terminator::Return(None) => "return".to_string(),
+                Terminator::Throw(value) => format!("throw v{}", value),
+            };
+            writeln!(out, "      {}", end).unwrap();
+            if !basic_block.handlers.is_empty() {
+                let handlers: Vec<String> = basic_block.handlers.iter().map(|it| format!("b{}", it)).collect();
+                writeln!(out, "      throws to {}", handlers.join(", ")).unwrap();
+            }
+        }
+    }
+}
+
+struct Builder<'a> {
+    constant_pool: &'a ConstPool,
+    function: Function,
+    // the instruction each block but the start starts at
+    starts: Vec<usize>,
+    // the phis each block starts with once it is reached, except that the start defines the
+    // arguments instead and handlers have the exception on their operand stack
+    entries: Vec<Option<State>>,
+    // what each block passes to each of its successors
+    edges: Vec<(Block, Block, State)>,
+    pending: Vec<Block>,
+}
+
+impl Builder<'_> {
+    fn block_at(&self, pc: usize) -> Result<Block, &'static str> {
+        self.starts.binary_search(&pc).map(|it| it + 1).map_err(|_| "falls off the end of the code")
+    }
+
+    fn emit(&mut self, block: Block, op: Op) -> Value {
+        self.function.ops.push(op);
+        let value = self.function.ops.len() - 1;
+        self.function.blocks[block].as_mut().unwrap().values.push(value);
+        value
+    }
+
+    // passes `state` from `from` to `to`, giving `to` its phis when it is first reached
+    fn enter(&mut self, from: Block, to: Block, state: State, handler: bool) -> Result<(), &'static str> {
+        match &self.entries[to] {
+            Some((_, stack)) if !handler && stack.len() != state.1.len() => return Err("operand stack depths differ"),
+            Some(_) => {}
+            None => {
+                self.function.blocks[to] = Some(BasicBlock {
+                    pc: Some(self.starts[to - 1]),
+                    values: vec![],
+                    end: Terminator::Return(None),
+                    handlers: vec![],
+                });
+                let locals = state.0.iter().map(|_| self.emit(to, Op::Phi(vec![]))).collect();
+                let stack = if handler {
+                    vec![self.emit(to, Op::Exception)]
+                } else {
+                    state.1.iter().map(|_| self.emit(to, Op::Phi(vec![]))).collect()
+                };
+                self.entries[to] = Some((locals, stack));
+                self.pending.push(to);
+            }
+        }
+        self.edges.push((from, to, state));
+        Ok(())
+    }
+
+    // adds what `code` does to `block`, returning how the block ends if it does
+    fn translate(
+        &mut self, block: Block, pc: usize, code: &Opcode, locals: &mut [Value], stack: &mut Vec<Value>,
+    ) -> Result<Option<Terminator>, &'static str> {
+        const UNDERFLOW: &str = "operand stack underflow";
+        let constant_pool = self.constant_pool;
+        macro_rules! pop {
+            () => {
+                stack.pop().ok_or(UNDERFLOW)?
+            };
+        }
+        macro_rules! push {
+            ($op:expr) => {{
+                let value = self.emit(block, $op);
+                stack.push(value);
+            }};
+        }
+        let field_width = |index: u16| constant_pool.resolve_field_ref(index).map(|it| slot_width(&it.descriptor));
+        let narrow_field = |index: u16| match field_width(index) {
+            Some(1) => Ok(index),
+            Some(_) => Err("long or double fields"),
+            None => Err("not a field reference"),
+        };
+        match *code {
+            Opcode::aconst_null | Opcode::iconst_0 => push!(Op::Const(0)),
+            Opcode::iconst_1 => push!(Op::Const(1)),
+            Opcode::bipush(value) | Opcode::push(value) => push!(Op::Const(value)),
+            Opcode::ldc(index) => match constant_pool.get(index) {
+                Some(CpInfo::Integer(value)) => push!(Op::Const(*value)),
+                Some(CpInfo::Float(value)) => push!(Op::Const(value.to_bits() as i32)),
+                Some(CpInfo::String { .. }) => push!(Op::String(index)),
+                _ => return Err("unsupported constant"),
+            },
+            Opcode::iload(local) | Opcode::aload(local) => stack.push(*locals.get(local).ok_or("local out of range")?),
+            Opcode::istore(local) | Opcode::astore(local) => *locals.get_mut(local).ok_or("local out of range")? = pop!(),
+            Opcode::iload_0 | Opcode::aload_0 => stack.push(*locals.first().ok_or("local out of range")?),
+            Opcode::iload_1 | Opcode::aload_1 => stack.push(*locals.get(1).ok_or("local out of range")?),
+            Opcode::iload_2 | Opcode::aload_2 => stack.push(*locals.get(2).ok_or("local out of range")?),
+            Opcode::iload_3 | Opcode::aload_3 => stack.push(*locals.get(3).ok_or("local out of range")?),
+            Opcode::istore_0 | Opcode::astore_0 => *locals.get_mut(0).ok_or("local out of range")? = pop!(),
+            Opcode::istore_1 | Opcode::astore_1 => *locals.get_mut(1).ok_or("local out of range")? = pop!(),
+            Opcode::istore_2 | Opcode::astore_2 => *locals.get_mut(2).ok_or("local out of range")? = pop!(),
+            Opcode::istore_3 | Opcode::astore_3 => *locals.get_mut(3).ok_or("local out of range")? = pop!(),
+            Opcode::iinc(local, value) => {
+                let current = *locals.get(local).ok_or("local out of range")?;
+                let value = self.emit(block, Op::Const(value));
+                locals[local] = self.emit(block, Op::Add(current, value));
+            }
+            Opcode::iadd => {
+                let (b, a) = (pop!(), pop!());
+                push!(Op::Add(a, b));
+            }
+            Opcode::pop => {
+                pop!();
+            }
+            Opcode::dup => {
+                let value = *stack.last().ok_or(UNDERFLOW)?;
+                stack.push(value);
+            }
+            Opcode::goto(target) => return Ok(Some(Terminator::Goto(self.block_at(target)?))),
+            Opcode::ifeq(target) => {
+                let condition = Condition::Zero(pop!());
+                return Ok(Some(Terminator::If(condition, self.block_at(target)?, self.block_at(pc + 1)?)));
+            }
+            Opcode::ifne(target) => {
+                let condition = Condition::NonZero(pop!());
+                return Ok(Some(Terminator::If(condition, self.block_at(target)?, self.block_at(pc + 1)?)));
+            }
+            Opcode::if_icmplt(target) => {
+                let (b, a) = (pop!(), pop!());
+                return Ok(Some(Terminator::If(Condition::Less(a, b), self.block_at(target)?, self.block_at(pc + 1)?)));
+            }
+            Opcode::ireturn | Opcode::areturn => return Ok(Some(Terminator::Return(Some(pop!())))),
+            Opcode::r#return => return Ok(Some(Terminator::Return(None))),
+            Opcode::athrow => return Ok(Some(Terminator::Throw(pop!()))),
+            Opcode::jsr(_) | Opcode::ret(_) => return Err("jsr and ret"),
+            Opcode::getstatic(index) => push!(Op::GetStatic(narrow_field(index)?)),
+            Opcode::putstatic(index) => {
+                let value = pop!();
+                self.emit(block, Op::PutStatic(narrow_field(index)?, value));
+            }
+            Opcode::getfield(index) => {
+                let object = pop!();
+                push!(Op::GetField(narrow_field(index)?, object));
+            }
+            Opcode::putfield(index) => {
+                let (value, object) = (pop!(), pop!());
+                self.emit(block, Op::PutField(narrow_field(index)?, object, value));
+            }
+            Opcode::invokestatic(index) => self.invoke(block, stack, "invokestatic", index as u16, false)?,
+            Opcode::invokespecial(index) => self.invoke(block, stack, "invokespecial", index, true)?,
+            Opcode::invokevirtual(index) => self.invoke(block, stack, "invokevirtual", index, true)?,
+            Opcode::invokeinterface(index, _) => self.invoke(block, stack, "invokeinterface", index, true)?,
+            Opcode::new(index) => push!(Op::New(index)),
+            Opcode::newarray(atype) => {
+                let length = pop!();
+                push!(Op::NewArray(atype, length));
+            }
+            Opcode::anewarray(index) => {
+                let length = pop!();
+                push!(Op::ANewArray(index, length));
+            }
+            Opcode::arraylength => {
+                let array = pop!();
+                push!(Op::ArrayLength(array));
+            }
+            Opcode::iaload => {
+                let (index, array) = (pop!(), pop!());
+                self.emit(block, Op::BoundsCheck(array, index));
+                push!(Op::ArrayLoad(array, index));
+            }
+            Opcode::iastore => {
+                let (value, index, array) = (pop!(), pop!(), pop!());
+                self.emit(block, Op::BoundsCheck(array, index));
+                self.emit(block, Op::ArrayStore(array, index, value));
+            }
+            Opcode::iaload_unchecked => {
+                let (index, array) = (pop!(), pop!());
+                push!(Op::ArrayLoad(array, index));
+            }
+            Opcode::iastore_unchecked => {
+                let (value, index, array) = (pop!(), pop!(), pop!());
+                self.emit(block, Op::ArrayStore(array, index, value));
+            }
+            Opcode::checkcast(index) => {
+                let object = pop!();
+                push!(Op::CheckCast(index, object));
+            }
+            Opcode::instanceof(index) => {
+                let object = pop!();
+                push!(Op::InstanceOf(index, object));
+            }
+        }
+        Ok(None)
+    }
+
+    fn invoke(
+        &mut self, block: Block, stack: &mut Vec<Value>, opcode: &'static str, index: u16, receiver: bool,
+    ) -> Result<(), &'static str> {
+        let method = self.constant_pool.resolve_method_ref(index).ok_or("not a method reference")?;
+        let descriptor = MethodDescriptor::parse(&method.descriptor).ok_or("invalid descriptor")?;
+        let returned = descriptor.return_type.as_ref().map(|it| it.slot_width());
+        if returned == Some(2) || descriptor.parameters.iter().any(|it| it.slot_width() == 2) {
+            return Err("long or double arguments");
+        }
+        let count = descriptor.parameters.len() + receiver as usize;
+        let arguments = stack.split_off(stack.len().checked_sub(count).ok_or("operand stack underflow")?);
+        let value = self.emit(block, Op::Invoke { opcode, index, arguments, returns: returned.is_some() });
+        if returned.is_some() {
+            stack.push(value);
+        }
+        Ok(())
+    }
+}
+
+/// Bytecode a [`Function`] was lowered to, for a frame with `local_size` locals and
+/// `stack_size` operand stack slots.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Lowered {
+    pub(crate) codes: Vec<Opcode>,
+    pub(crate) local_size: usize,
+    pub(crate) stack_size: usize,
+}
+
+struct Lowering<'a> {
+    function: &'a Function,
+    // the local each value is kept in, None for those without a value or that aren't used
+    slots: Vec<Option<usize>>,
+    codes: Vec<Opcode>,
+    stack_size: usize,
+    // the branches to patch with where their target block starts once all are lowered
+    branches: Vec<(usize, Block)>,
+    // the array and index of the bounds check just lowered, which the array access after it
+    // makes
+    checked: Option<(Value, Value)>,
+}
+
+impl Lowering<'_> {
+    fn slot(&self, value: Value) -> Result<usize, &'static str> {
+        self.slots[value].ok_or("value used where it isn't computed")
+    }
+
+    // pushes `values` on the operand stack, the first at the bottom
+    fn load(&mut self, values: &[Value]) -> Result<(), &'static str> {
+        for &value in values {
+            // the slots hold ints and references alike, which the loads and stores don't tell apart
+            let slot = self.slot(value)?;
+            self.codes.push(Opcode::iload(slot));
+        }
+        self.stack_size = self.stack_size.max(values.len());
+        Ok(())
+    }
+
+    fn op(&mut self, value: Value) -> Result<(), &'static str> {
+        let op = &self.function.ops[value];
+        let checked = self.checked.take();
+        let (operands, code) = match *op {
+            // arguments are where they are passed, undefined locals are never used, and phis
+            // are set by the branches to their block
+            Op::Argument(_) | Op::Undefined | Op::Phi(_) => return Ok(()),
+            Op::BoundsCheck(array, index) => {
+                self.checked = Some((array, index));
+                return Ok(());
+            }
+            Op::Exception => return Err("exception handlers"),
+            Op::Const(constant) => (vec![], Some(match constant {
+                0 => Opcode::iconst_0,
+                1 => Opcode::iconst_1,
+                -128..=127 => Opcode::bipush(constant),
+                _ => Opcode::push(constant),
+            })),
+            Op::String(index) => (vec![], Some(Opcode::ldc(index))),
+            Op::Copy(a) => (vec![a], None),
+            Op::Add(a, b) => (vec![a, b], Some(Opcode::iadd)),
+            Op::GetStatic(index) => (vec![], Some(Opcode::getstatic(index))),
+            Op::PutStatic(index, a) => (vec![a], Some(Opcode::putstatic(index))),
+            Op::GetField(index, object) => (vec![object], Some(Opcode::getfield(index))),
+            Op::PutField(index, object, a) => (vec![object, a], Some(Opcode::putfield(index))),
+            Op::Invoke { opcode, index, ref arguments, .. } => {
+                let code = match opcode {
+                    "invokestatic" => Opcode::invokestatic(index as usize),
+                    "invokespecial" => Opcode::invokespecial(index),
+                    "invokevirtual" => Opcode::invokevirtual(index),
+                    _ => Opcode::invokeinterface(index, arguments.len() as u8),
+                };
+                (arguments.clone(), Some(code))
+            }
+            Op::New(index) => (vec![], Some(Opcode::new(index))),
+            Op::NewArray(atype, length) => (vec![length], Some(Opcode::newarray(atype))),
+            Op::ANewArray(index, length) => (vec![length], Some(Opcode::anewarray(index))),
+            Op::ArrayLength(array) => (vec![array], Some(Opcode::arraylength)),
+            Op::ArrayLoad(array, index) => {
+                let code = if checked == Some((array, index)) { Opcode::iaload } else { Opcode::iaload_unchecked };
+                (vec![array, index], Some(code))
+            }
+            Op::ArrayStore(array, index, a) => {
+                let code = if checked == Some((array, index)) { Opcode::iastore } else { Opcode::iastore_unchecked };
+                (vec![array, index, a], Some(code))
+            }
+            Op::CheckCast(index, object) => (vec![object], Some(Opcode::checkcast(index))),
+            Op::InstanceOf(index, object) => (vec![object], Some(Opcode::instanceof(index))),
+        };
+        self.load(&operands)?;
+        self.stack_size = self.stack_size.max(1);
+        self.codes.extend(code);
+        if op.has_value() {
+            let slot = self.slot(value)?;
+            self.codes.push(Opcode::istore(slot));
+        }
+        Ok(())
+    }
+
+    // the values the phis of `to` take when it is branched to from `from`, with the phis
+    fn copies(&self, from: Block, to: Block) -> Result<Vec<(Value, Value)>, &'static str> {
+        let mut copies = Vec::new();
+        for &phi in &self.function.blocks[to].as_ref().unwrap().values {
+            if let Op::Phi(operands) = &self.function.ops[phi] {
+                let (_, source) = operands.iter().find(|(block, _)| *block == from).ok_or("phi missing a predecessor")?;
+                copies.push((*source, phi));
+            }
+        }
+        Ok(copies)
+    }
+
+    // sets the phis of `to` for a branch from `from` and goes to it, unless it is `next`
+    fn edge(&mut self, from: Block, to: Block, next: Option<Block>) -> Result<(), &'static str> {
+        let copies = self.copies(from, to)?;
+        // all at once, as phis of a loop can take each other's values
+        let sources: Vec<Value> = copies.iter().map(|(source, _)| *source).collect();
+        self.load(&sources)?;
+        for (_, phi) in copies.iter().rev() {
+            let slot = self.slot(*phi)?;
+            self.codes.push(Opcode::istore(slot));
+        }
+        if next != Some(to) {
+            self.branch(Opcode::goto(0), to);
+        }
+        Ok(())
+    }
+
+    fn branch(&mut self, code: Opcode, to: Block) {
+        self.branches.push((self.codes.len(), to));
+        self.codes.push(code);
+    }
+}
+
+fn set_branch_target(code: &mut Opcode, pc: usize) {
+    match code {
+        Opcode::goto(target) | Opcode::ifeq(target) | Opcode::ifne(target) | Opcode::if_icmplt(target) => *target = pc,
+        _ => unreachable!("not a branch"),
+    }
+}
+
+// what the bounds check elimination knows about a function
+struct Analysis<'a> {
+    function: &'a Function,
+    predecessors: Vec<Vec<Block>>,
+    dominators: Vec<Option<Block>>,
+    // the block each value is computed in
+    places: Vec<Option<Block>>,
+}
+
+impl Analysis<'_> {
+    // whether `index` is known to be within `array` in `block`
+    fn within(&self, array: Value, index: Value, block: Block) -> bool {
+        let ops = &self.function.ops;
+        if !self.non_negative(index, &mut Vec::new()) {
+            return false;
+        }
+        let length = match ops[array] {
+            Op::NewArray(_, length) | Op::ANewArray(_, length) => Some(length),
+            _ => None,
+        };
+        if let (Some(index), Some(length)) = (self.function.constant(index), length.and_then(|it| self.function.constant(it))) {
+            return index < length;
+        }
+        self.upper_bounds(index, block).into_iter().any(|bound| Some(bound) == length || ops[bound] == Op::ArrayLength(array))
+    }
+
+    // whether the same check as the one at `position` in `block` always passed before it
+    fn checked_before(&self, check: Value, block: Block, position: usize) -> bool {
+        let mut current = Some(block);
+        while let Some(dominator) = current {
+            let values = &self.function.blocks[dominator].as_ref().unwrap().values;
+            let before = if dominator == block { &values[..position] } else { &values[..] };
+            if before.iter().any(|it| self.function.ops[*it] == self.function.ops[check]) {
+                return true;
+            }
+            current = self.dominators[dominator];
+        }
+        false
+    }
+
+    // the values `value` is known to be less than in `block`, as it is only reached when a
+    // branch on `value < bound` is taken
+    fn upper_bounds(&self, value: Value, block: Block) -> Vec<Value> {
+        let mut bounds = Vec::new();
+        let mut current = Some(block);
+        while let Some(dominator) = current {
+            if let [branch] = self.predecessors[dominator][..] {
+                let end = &self.function.blocks[branch].as_ref().unwrap().end;
+                if let Terminator::If(Condition::Less(a, bound), then, otherwise) = *end {
+                    if a == value && then == dominator && otherwise != dominator {
+                        bounds.push(bound);
+                    }
+                }
+            }
+            current = self.dominators[dominator];
+        }
+        bounds
+    }
+
+    // whether `value` is never negative, assuming so for the phis in `assumed`, which holds for
+    // them if it then holds for what they merge
+    fn non_negative(&self, value: Value, assumed: &mut Vec<Value>) -> bool {
+        match &self.function.ops[value] {
+            Op::Const(value) => *value >= 0,
+            Op::ArrayLength(_) => true,
+            Op::Phi(operands) => {
+                if assumed.contains(&value) {
+                    return true;
+                }
+                assumed.push(value);
+                operands.iter().all(|(_, it)| self.non_negative(*it, assumed))
+            }
+            // adding 1 can't overflow where the value is known to be less than another int
+            Op::Add(a, b) => match (self.function.constant(*a), self.function.constant(*b)) {
+                (_, Some(1)) => self.non_negative(*a, assumed) && self.places[value]
+                    .is_some_and(|block| !self.upper_bounds(*a, block).is_empty()),
+                (Some(1), _) => self.non_negative(*b, assumed) && self.places[value]
+                    .is_some_and(|block| !self.upper_bounds(*b, block).is_empty()),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+// whether the instruction after `code` starts a block, as it isn't reached from `code`, or only
+// when a branch isn't taken
+fn ends_block(code: &Opcode) -> bool {
+    branch_target(code).is_some()
+        || matches!(code, Opcode::ireturn | Opcode::areturn | Opcode::r#return | Opcode::athrow)
+}
+
+fn can_throw(code: &Opcode) -> bool {
+    !matches!(code, Opcode::aconst_null | Opcode::iconst_0 | Opcode::iconst_1 | Opcode::bipush(_) | Opcode::ldc(_)
+        | Opcode::iload(_) | Opcode::aload(_) | Opcode::istore(_) | Opcode::astore(_)
+        | Opcode::iload_0 | Opcode::iload_1 | Opcode::iload_2 | Opcode::iload_3 | Opcode::aload_0 | Opcode::aload_1
+        | Opcode::aload_2 | Opcode::aload_3 | Opcode::istore_0 | Opcode::istore_1 | Opcode::istore_2
+        | Opcode::istore_3 | Opcode::astore_0 | Opcode::astore_1 | Opcode::astore_2 | Opcode::astore_3
+        | Opcode::iinc(..) | Opcode::iadd | Opcode::pop | Opcode::dup | Opcode::goto(_) | Opcode::ifeq(_)
+        | Opcode::ifne(_) | Opcode::if_icmplt(_) | Opcode::instanceof(_))
+}
+
+fn op_text(op: &Op, constant_pool: &ConstPool) -> String {
+    let member = |index: u16| {
+        let member = constant_pool.resolve_field_ref(index).or_else(|| constant_pool.resolve_method_ref(index));
+        member.map_or_else(|| format!("#{}", index), |it| it.key.clone())
+    };
+    let class = |index: u16| constant_pool.resolve_class(index).map_or_else(|| format!("#{}", index), str::to_string);
+    let values = |values: &[Value]| values.iter().map(|it| format!("v{}", it)).collect::<Vec<_>>().join(", ");
+    match op {
+        Op::Argument(local) => format!("argument {}", local),
+        Op::Undefined => "undefined".to_string(),
+        Op::Const(value) => format!("const {}", value),
+        Op::String(index) => match constant_pool.get(*index) {
+            Some(CpInfo::String { string_index }) => format!("string {:?}", constant_pool.utf8(*string_index).unwrap_or("")),
+            _ => format!("string #{}", index),
+        },
+        Op::Copy(value) => format!("copy v{}", value),
+        Op::Phi(operands) => {
+            let operands: Vec<String> = operands.iter().map(|(block, value)| format!("b{}: v{}", block, value)).collect();
+            format!("phi {}", operands.join(", "))
+        }
+        Op::Exception => "exception".to_string(),
+        Op::Add(a, b) => format!("add v{}, v{}", a, b),
+        Op::GetStatic(index) => format!("getstatic {}", member(*index)),
+        Op::PutStatic(index, value) => format!("putstatic {} v{}", member(*index), value),
+        Op::GetField(index, object) => format!("getfield {} v{}", member(*index), object),
+        Op::PutField(index, object, value) => format!("putfield {} v{}, v{}", member(*index), object, value),
+        Op::Invoke { opcode, index, arguments, .. } => format!("{} {} {}", opcode, member(*index), values(arguments)),
+        Op::New(index) => format!("new {}", class(*index)),
+        Op::NewArray(atype, length) => format!("newarray {} v{}", array_type(*atype), length),
+        Op::ANewArray(index, length) => format!("anewarray {} v{}", class(*index), length),
+        Op::ArrayLength(array) => format!("arraylength v{}", array),
+        Op::BoundsCheck(array, index) => format!("boundscheck v{}, v{}", array, index),
+        Op::ArrayLoad(array, index) => format!("iaload v{}, v{}", array, index),
+        Op::ArrayStore(array, index, value) => format!("iastore v{}, v{}, v{}", array, index, value),
+        Op::CheckCast(index, object) => format!("checkcast {} v{}", class(*index), object),
+        Op::InstanceOf(index, object) => format!("instanceof {} v{}", class(*index), object),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::const_pool::tests::sample_const_pool;
+    use crate::Opcode;
+    use crate::ssa::{Function, Lowered};
+
+    // the function `codes` make up before and after it is optimized
+    fn optimized(codes: &[Opcode], descriptor: &str, local_size: usize) -> (String, String) {
+        let constant_pool = sample_const_pool();
+        let mut function = Function::build(codes, &constant_pool, descriptor, true, local_size, &[]).unwrap();
+        let mut built = String::new();
+        function.print(&constant_pool, &mut built);
+        function.optimize();
+        let mut optimized = String::new();
+        function.print(&constant_pool, &mut optimized);
+        (built, optimized)
+    }
+
+    fn lowered(codes: &[Opcode], descriptor: &str, local_size: usize) -> Vec<Opcode> {
+        let mut function = Function::build(codes, &sample_const_pool(), descriptor, true, local_size, &[]).unwrap();
+        function.optimize();
+        function.lower(descriptor, true).unwrap().codes
+    }
+
+    #[test]
+    fn fold_constants_and_the_branches_on_them() {
+        let codes = [
+            Opcode::iconst_1,
+            Opcode::bipush(2),
+            Opcode::iadd,
+            Opcode::istore_0,
+            Opcode::iload_0,
+            Opcode::ifeq(8),
+            Opcode::iload_0,
+            Opcode::ireturn,
+            Opcode::iconst_0,
+            Opcode::ireturn,
+        ];
+        let (_, optimized) = optimized(&codes, "()I", 1);
+        assert_eq!(optimized, "    b0:\n      goto b1\n\
+                               \x20   b1 (pc 0) <- b0:\n      v4 = const 3\n      goto b2\n\
+                               \x20   b2 (pc 6) <- b1:\n      return v4\n");
+    }
+
+    #[test]
+    fn lower_optimized_function_to_bytecode() {
+        // return 1 + 2 != 0 ? a : 0;
+        let codes = [
+            Opcode::iconst_1,
+            Opcode::bipush(2),
+            Opcode::iadd,
+            Opcode::ifeq(6),
+            Opcode::iload_0,
+            Opcode::ireturn,
+            Opcode::iconst_0,
+            Opcode::ireturn,
+        ];
+        let constant_pool = sample_const_pool();
+        let mut function = Function::build(&codes, &constant_pool, "(I)I", true, 1, &[]).unwrap();
+        function.optimize();
+        let lowered = function.lower("(I)I", true).unwrap();
+        // the constant is no longer used once the branch on it is folded
+        assert_eq!(lowered, Lowered { codes: vec![Opcode::iload(0), Opcode::ireturn], local_size: 1, stack_size: 1 });
+    }
+
+    #[test]
+    fn lower_constants_that_dont_fit_in_a_byte_to_push() {
+        // return a + (100 + 100);
+        let codes = [
+            Opcode::iload_0,
+            Opcode::bipush(100),
+            Opcode::bipush(100),
+            Opcode::iadd,
+            Opcode::iadd,
+            Opcode::ireturn,
+        ];
+        let constant_pool = sample_const_pool();
+        let mut function = Function::build(&codes, &constant_pool, "(I)I", true, 1, &[]).unwrap();
+        function.optimize();
+        let lowered = function.lower("(I)I", true).unwrap();
+        assert_eq!(lowered.codes[0], Opcode::push(200));
+    }
+
+    #[test]
+    fn lower_phis_to_copies_on_each_branch() {
+        // int a = 0, b = 1; while (a < n) { int t = a; a = b; b = t; } return a;
+        let codes = [
+            Opcode::iconst_0,
+            Opcode::istore_1,
+            Opcode::iconst_1,
+            Opcode::istore_2,
+            Opcode::goto(11),
+            Opcode::iload_1,
+            Opcode::istore_3,
+            Opcode::iload_2,
+            Opcode::istore_1,
+            Opcode::iload_3,
+            Opcode::istore_2,
+            Opcode::iload_1,
+            Opcode::iload_0,
+            Opcode::if_icmplt(5),
+            Opcode::iload_1,
+            Opcode::ireturn,
+        ];
+        let constant_pool = sample_const_pool();
+        let mut function = Function::build(&codes, &constant_pool, "(I)I", true, 4, &[]).unwrap();
+        function.optimize();
+        let lowered = function.lower("(I)I", true).unwrap();
+        // the phis of a and b swap their values all at once through the operand stack, and
+        // the loop's condition comes before its body
+        assert_eq!(lowered.codes, vec![
+            Opcode::iconst_0,
+            Opcode::istore(1),
+            Opcode::iconst_1,
+            Opcode::istore(2),
+            Opcode::iload(1),
+            Opcode::iload(2),
+            Opcode::istore(4),
+            Opcode::istore(3),
+            Opcode::iload(3),
+            Opcode::iload(0),
+            Opcode::if_icmplt(12),
+            Opcode::goto(17),
+            Opcode::iload(4),
+            Opcode::iload(3),
+            Opcode::istore(4),
+            Opcode::istore(3),
+            Opcode::goto(8),
+            Opcode::iload(3),
+            Opcode::ireturn,
+        ]);
+        assert_eq!((lowered.local_size, lowered.stack_size), (5, 2));
+    }
+
+    #[test]
+    fn eliminate_bounds_checks_within_counted_loops() {
+        // int sum = 0; for (int i = 0; i < a.length; i++) sum += a[i]; return sum;
+        let codes = [
+            Opcode::iconst_0,
+            Opcode::istore_1,
+            Opcode::iconst_0,
+            Opcode::istore_2,
+            Opcode::goto(12),
+            Opcode::iload_1,
+            Opcode::aload_0,
+            Opcode::iload_2,
+            Opcode::iaload,
+            Opcode::iadd,
+            Opcode::istore_1,
+            Opcode::iinc(2, 1),
+            Opcode::iload_2,
+            Opcode::aload_0,
+            Opcode::arraylength,
+            Opcode::if_icmplt(5),
+            Opcode::iload_1,
+            Opcode::ireturn,
+        ];
+        let (built, optimized) = optimized(&codes, "([I)I", 3);
+        assert!(built.contains("boundscheck"));
+        assert_eq!(optimized, "    b0:\n      v0 = argument 0\n      goto b1\n\
+                               \x20   b1 (pc 0) <- b0:\n      v6 = const 0\n      v7 = const 0\n      goto b3\n\
+                               \x20   b2 (pc 5) <- b3:\n      v19 = iaload v0, v10\n      v20 = add v9, v19\n\
+                               \x20     v21 = const 1\n      v22 = add v10, v21\n      goto b3\n\
+                               \x20   b3 (pc 12) <- b1, b2:\n      v9 = phi b1: v6, b2: v20\n\
+                               \x20     v10 = phi b1: v7, b2: v22\n      v11 = arraylength v0\n\
+                               \x20     if v10 < v11 goto b2 else b4\n\
+                               \x20   b4 (pc 16) <- b3:\n      return v9\n");
+        // which the lowered code runs without
+        let codes = lowered(&codes, "([I)I", 3);
+        assert!(codes.contains(&Opcode::iaload_unchecked) && !codes.contains(&Opcode::iaload));
+    }
+
+    #[test]
+    fn keep_bounds_checks_that_can_fail() {
+        // return a[i] + a[i];
+        let codes = [
+            Opcode::aload_0,
+            Opcode::iload_1,
+            Opcode::iaload,
+            Opcode::aload_0,
+            Opcode::iload_1,
+            Opcode::iaload,
+            Opcode::iadd,
+            Opcode::ireturn,
+        ];
+        let (_, optimized) = optimized(&codes, "([II)I", 2);
+        assert_eq!(optimized.matches("boundscheck v0, v1").count(), 1);
+        // the first load checks for the second
+        let codes = lowered(&codes, "([II)I", 2);
+        let loads: Vec<_> = codes.iter().filter(|it| matches!(it, Opcode::iaload | Opcode::iaload_unchecked)).collect();
+        assert_eq!(loads, [&Opcode::iaload, &Opcode::iaload_unchecked]);
+    }
+}
@@ -1,6 +0,0 @@
-use crate::*;
-use crate::const_pool::ConstPool;
-use crate::method_area::Class;
-
-#[cfg(test)]
-mod tests {}
\ No newline at end of file
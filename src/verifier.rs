@@ -0,0 +1,1118 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::const_pool::{ConstPool, CpInfo, MemberRef};
+use crate::descriptor::{FieldType, MethodDescriptor};
+use crate::method_area::{component_class, Method, MethodArea};
+use crate::{branch_target, Opcode};
+use crate::parser::AccessFlags;
+use crate::parser::attribute::{StackMapFrame, VerificationTypeInfo};
+use crate::writer::CodeOffsets;
+
+const OBJECT: &str = "java/lang/Object";
+const CLONEABLE: &str = "java/lang/Cloneable";
+const SERIALIZABLE: &str = "java/io/Serializable";
+const THROWABLE: &str = "java/lang/Throwable";
+
+/// Code that fails verification, see JVMS §4.10.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    /// `Class.name:descriptor` of the method
+    pub method: String,
+    /// Byte offset of the offending instruction
+    pub pc: usize,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    /// An instruction pops more values than the operand stack holds
+    StackUnderflow,
+    /// The operand stack grows past `max_stack`
+    StackOverflow,
+    /// A local variable at or past `max_locals`
+    LocalOutOfRange(usize),
+    /// A value whose type is not the one the instruction expects
+    TypeMismatch { expected: String, found: String },
+    /// A branch or exception handler whose target has no stack map frame
+    BadBranchTarget(usize),
+    /// The types at a branch target don't match its stack map frame, or when inferring
+    /// them, the operand stacks of two paths that join there can't be merged
+    FrameMismatch(usize),
+    /// An instruction that follows an unconditional branch has no stack map frame
+    MissingFrame,
+    /// A stack map frame that chops more locals than there are or refers to
+    /// something other than a `new` instruction
+    InvalidStackMapFrame,
+    /// Execution can continue past the last instruction
+    FallsOffEnd,
+    /// A constructor returns without calling another constructor on `this`
+    UninitializedThis,
+    /// A `jsr` or `ret` in a class file that is verified by type checking
+    Subroutine,
+    InvalidConstant(u16),
+    InvalidDescriptor(String),
+    /// An `invokeinterface` whose count isn't the slots its arguments and receiver take
+    InvalidCount(u8),
+    /// A `newarray` whose `atype` isn't one of the primitive types, §6.5.newarray
+    InvalidArrayType(u8),
+    /// An instruction only optimized methods run, which no opcode stands for
+    NotInClassFiles,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at pc {}: ", self.method, self.pc)?;
+        match &self.kind {
+            VerifyErrorKind::StackUnderflow => write!(f, "operand stack underflow"),
+            VerifyErrorKind::StackOverflow => write!(f, "operand stack exceeds max_stack"),
+            VerifyErrorKind::LocalOutOfRange(index) => write!(f, "local variable {} exceeds max_locals", index),
+            VerifyErrorKind::TypeMismatch { expected, found } => write!(f, "expected {}, found {}", expected, found),
+            VerifyErrorKind::BadBranchTarget(target) => write!(f, "no stack map frame at branch target {}", target),
+            VerifyErrorKind::FrameMismatch(target) => write!(f, "types do not match the frame at {}", target),
+            VerifyErrorKind::MissingFrame => write!(f, "expected a stack map frame"),
+            VerifyErrorKind::InvalidStackMapFrame => write!(f, "invalid stack map frame"),
+            VerifyErrorKind::FallsOffEnd => write!(f, "falling off the end of the code"),
+            VerifyErrorKind::UninitializedThis => write!(f, "constructor returns before this is initialized"),
+            VerifyErrorKind::Subroutine => write!(f, "jsr and ret are not allowed with stack map frames"),
+            VerifyErrorKind::InvalidConstant(index) => write!(f, "invalid constant pool index {}", index),
+            VerifyErrorKind::InvalidDescriptor(descriptor) => write!(f, "malformed descriptor {}", descriptor),
+            VerifyErrorKind::InvalidCount(count) => write!(f, "invokeinterface count {} does not match the arguments", count),
+            VerifyErrorKind::InvalidArrayType(atype) => write!(f, "invalid newarray type {}", atype),
+            VerifyErrorKind::NotInClassFiles => write!(f, "instruction can't be in a class file"),
+        }
+    }
+}
+
+impl Error for VerifyError {}
+
+// §4.10.1.2, a long or double takes two slots, the second one being `Top`
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Top,
+    Int,
+    Float,
+    Long,
+    Double,
+    Null,
+    UninitializedThis,
+    // index of the `new` instruction that created the object
+    Uninitialized(usize),
+    // class name in internal form, arrays as descriptors
+    Reference(String),
+    // §4.10.2.4, pushed by `jsr`, holding the index the subroutine starts at
+    ReturnAddress(usize),
+}
+
+impl Type {
+    fn of(field_type: &FieldType) -> Type {
+        match field_type {
+            FieldType::Byte | FieldType::Char | FieldType::Short | FieldType::Boolean | FieldType::Int => Type::Int,
+            FieldType::Float => Type::Float,
+            FieldType::Long => Type::Long,
+            FieldType::Double => Type::Double,
+            FieldType::Object(name) => Type::Reference(name.clone()),
+            FieldType::Array(_) => Type::Reference(field_type.to_string()),
+        }
+    }
+
+    fn is_wide(&self) -> bool {
+        matches!(self, Type::Long | Type::Double)
+    }
+
+    fn is_reference(&self) -> bool {
+        matches!(self, Type::Null | Type::UninitializedThis | Type::Uninitialized(_) | Type::Reference(_))
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Top => write!(f, "top"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Long => write!(f, "long"),
+            Type::Double => write!(f, "double"),
+            Type::Null => write!(f, "null"),
+            Type::UninitializedThis => write!(f, "uninitializedThis"),
+            Type::Uninitialized(_) => write!(f, "uninitialized"),
+            Type::Reference(name) => write!(f, "{}", name),
+            Type::ReturnAddress(_) => write!(f, "returnAddress"),
+        }
+    }
+}
+
+// §4.10.1.3, `locals` always holds `max_locals` slots
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    locals: Vec<Type>,
+    stack: Vec<Type>,
+}
+
+// §4.10.2.4, the frame inferred at an instruction along with the locals
+// stored to since entering the subroutine the instruction is in
+#[derive(Debug, Clone, PartialEq)]
+struct Inferred {
+    frame: Frame,
+    modified: Vec<bool>,
+}
+
+/// Verifies the code of each method of `class`, §4.10. From version 50 on, methods are type
+/// checked against their `StackMapTable` (§4.10.1). Older class files carry no stack maps, so
+/// their types are inferred (§4.10.2), which is also the fallback for version 50 as in HotSpot.
+pub(crate) fn verify(method_area: &MethodArea, class: &str) -> Result<(), VerifyError> {
+    let c = method_area.resolve_class(class);
+    for (key, method) in method_area.methods_of(class) {
+        let descriptor = MethodDescriptor::parse(&method.descriptor).ok_or_else(|| VerifyError {
+            method: key.to_string(),
+            pc: 0,
+            kind: VerifyErrorKind::InvalidDescriptor(method.descriptor.clone()),
+        })?;
+        let verifier = Verifier {
+            method_area,
+            const_pool: &c.const_pool,
+            class,
+            key,
+            method,
+            descriptor,
+            offsets: CodeOffsets::of(&method.codes),
+        };
+        match c.major {
+            0..=49 => verifier.infer()?,
+            50 => verifier.type_check().or_else(|_| verifier.infer())?,
+            _ => verifier.type_check()?,
+        }
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    method_area: &'a MethodArea,
+    const_pool: &'a ConstPool,
+    class: &'a str,
+    key: &'a str,
+    method: &'a Method,
+    descriptor: MethodDescriptor,
+    offsets: CodeOffsets,
+}
+
+impl Verifier<'_> {
+    fn error(&self, index: usize, kind: VerifyErrorKind) -> VerifyError {
+        VerifyError { method: self.key.to_string(), pc: self.pc(index), kind }
+    }
+
+    fn pc(&self, index: usize) -> usize {
+        self.offsets.pc(index.min(self.method.codes.len())) as usize
+    }
+
+    fn mismatch(&self, index: usize, expected: impl Display, found: impl Display) -> VerifyError {
+        self.error(index, VerifyErrorKind::TypeMismatch { expected: expected.to_string(), found: found.to_string() })
+    }
+
+    fn is_constructor(&self) -> bool {
+        self.method.name == "<init>"
+    }
+
+    fn type_check(&self) -> Result<(), VerifyError> {
+        let initial = self.initial_locals();
+        let frames = self.stack_map_frames(&initial)?;
+        let mut current = Some(Frame { locals: self.expand(0, &initial, self.method.local_size, true)?, stack: vec![] });
+        for (index, code) in self.method.codes.iter().enumerate() {
+            if let Some(declared) = frames.get(&index) {
+                if let Some(frame) = &current {
+                    if !self.is_frame_assignable(frame, declared) {
+                        return Err(self.error(index, VerifyErrorKind::FrameMismatch(self.pc(index))));
+                    }
+                }
+                current = Some(declared.clone());
+            }
+            let mut frame = current.take().ok_or_else(|| self.error(index, VerifyErrorKind::MissingFrame))?;
+            if let Opcode::jsr(_) | Opcode::ret(_) = code {
+                return Err(self.error(index, VerifyErrorKind::Subroutine));
+            }
+            self.check_handlers(index, &frame, &frames)?;
+            self.execute(index, code, &mut frame)?;
+            if let Some(target) = branch_target(code) {
+                self.branch(index, target, &frame, &frames)?;
+            }
+            if falls_through(code) {
+                current = Some(frame);
+            }
+        }
+        match current {
+            Some(_) => Err(self.error(self.method.codes.len().saturating_sub(1), VerifyErrorKind::FallsOffEnd)),
+            None => Ok(()),
+        }
+    }
+
+    // §4.10.2.2, a data-flow analysis that merges the frames of every path
+    // to an instruction until none of them change
+    fn infer(&self) -> Result<(), VerifyError> {
+        let codes = &self.method.codes;
+        if codes.is_empty() {
+            return Err(self.error(0, VerifyErrorKind::FallsOffEnd));
+        }
+        let locals = self.expand(0, &self.initial_locals(), self.method.local_size, true)?;
+        let mut inferred: Vec<Option<Inferred>> = vec![None; codes.len()];
+        let mut changed = vec![false; codes.len()];
+        inferred[0] = Some(Inferred { modified: vec![false; locals.len()], frame: Frame { locals, stack: vec![] } });
+        changed[0] = true;
+        // index a subroutine starts at to the `jsr`s that call it and the `ret`s that leave it
+        let mut callers: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut returns: HashMap<usize, Vec<usize>> = HashMap::new();
+
+        while let Some(index) = changed.iter().position(|it| *it) {
+            changed[index] = false;
+            let Inferred { mut frame, mut modified } = inferred[index].clone().expect("changed instructions have a frame");
+            let code = &codes[index];
+            for entry in self.method.exception_table.iter().filter(|it| (it.start_pc..it.end_pc).contains(&index)) {
+                let catch_type = match entry.catch_type {
+                    0 => THROWABLE,
+                    class => self.class_name(entry.handler_pc, class)?,
+                };
+                let handler = Frame { locals: frame.locals.clone(), stack: vec![Type::Reference(catch_type.to_string())] };
+                let handler = Inferred { frame: handler, modified: modified.clone() };
+                self.merge(index, entry.handler_pc, handler, &mut inferred, &mut changed)?;
+            }
+
+            let before = frame.locals.clone();
+            self.execute(index, code, &mut frame)?;
+            for (local, (old, new)) in before.iter().zip(frame.locals.iter()).enumerate() {
+                modified[local] |= old != new || stored_local(code) == Some(local);
+            }
+
+            match code {
+                Opcode::jsr(target) => {
+                    let jsrs = callers.entry(*target).or_default();
+                    if !jsrs.contains(&index) {
+                        jsrs.push(index);
+                    }
+                    let entry = Inferred { frame, modified: vec![false; modified.len()] };
+                    self.merge(index, *target, entry, &mut inferred, &mut changed)?;
+                    // the subroutine's returns have a new place to go back to
+                    for ret in returns.get(target).into_iter().flatten() {
+                        changed[*ret] = true;
+                    }
+                }
+                Opcode::ret(local) => {
+                    let subroutine = match frame.locals[*local] {
+                        Type::ReturnAddress(subroutine) => subroutine,
+                        _ => unreachable!("execute checks ret's local"),
+                    };
+                    let rets = returns.entry(subroutine).or_default();
+                    if !rets.contains(&index) {
+                        rets.push(index);
+                    }
+                    // locals the subroutine didn't touch are as they were at the `jsr`
+                    for caller in callers.get(&subroutine).into_iter().flatten() {
+                        let call = inferred[*caller].clone().expect("a jsr that was executed has a frame");
+                        let locals = (0..modified.len())
+                            .map(|it| if modified[it] { &frame.locals[it] } else { &call.frame.locals[it] })
+                            .cloned()
+                            .collect();
+                        let returned = Inferred {
+                            frame: Frame { locals, stack: frame.stack.clone() },
+                            modified: call.modified.iter().zip(modified.iter()).map(|(a, b)| *a || *b).collect(),
+                        };
+                        self.merge(index, caller + 1, returned, &mut inferred, &mut changed)?;
+                    }
+                }
+                _ => {
+                    if let Some(target) = branch_target(code) {
+                        let branch = Inferred { frame: frame.clone(), modified: modified.clone() };
+                        self.merge(index, target, branch, &mut inferred, &mut changed)?;
+                    }
+                    if falls_through(code) {
+                        if index + 1 == codes.len() {
+                            return Err(self.error(index, VerifyErrorKind::FallsOffEnd));
+                        }
+                        self.merge(index, index + 1, Inferred { frame, modified }, &mut inferred, &mut changed)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges `incoming` into what is inferred for `target` so far, marking it changed if that grows.
+    fn merge(
+        &self,
+        index: usize,
+        target: usize,
+        incoming: Inferred,
+        inferred: &mut [Option<Inferred>],
+        changed: &mut [bool],
+    ) -> Result<(), VerifyError> {
+        let mismatch = || self.error(index, VerifyErrorKind::FrameMismatch(self.pc(target)));
+        let slot = inferred.get_mut(target)
+            .ok_or_else(|| self.error(index, VerifyErrorKind::BadBranchTarget(self.pc(target))))?;
+        let merged = match slot {
+            None => incoming,
+            Some(existing) => {
+                if existing.frame.stack.len() != incoming.frame.stack.len() {
+                    return Err(mismatch());
+                }
+                let stack = existing.frame.stack.iter().zip(incoming.frame.stack.iter())
+                    .map(|(existing, incoming)| self.merge_type(existing, incoming).ok_or_else(mismatch))
+                    .collect::<Result<_, _>>()?;
+                // a local that differs between paths can't be used until it is stored to again
+                let locals = existing.frame.locals.iter().zip(incoming.frame.locals.iter())
+                    .map(|(existing, incoming)| self.merge_type(existing, incoming).unwrap_or(Type::Top))
+                    .collect();
+                let modified = existing.modified.iter().zip(incoming.modified.iter()).map(|(a, b)| *a || *b).collect();
+                Inferred { frame: Frame { locals, stack }, modified }
+            }
+        };
+        if slot.as_ref() != Some(&merged) {
+            *slot = Some(merged);
+            changed[target] = true;
+        }
+        Ok(())
+    }
+
+    // the most specific type both are assignable to, if there is one
+    fn merge_type(&self, existing: &Type, incoming: &Type) -> Option<Type> {
+        if self.is_assignable(incoming, existing) {
+            return Some(existing.clone());
+        }
+        match (existing, incoming) {
+            (Type::Null, Type::Reference(_)) => Some(incoming.clone()),
+            (Type::Reference(a), Type::Reference(b)) => Some(Type::Reference(self.common_superclass(a, b))),
+            _ => None,
+        }
+    }
+
+    // interfaces merge to `Object`, like classes whose only common superclass it is
+    fn common_superclass(&self, a: &str, b: &str) -> String {
+        if self.is_class_assignable(a, b) {
+            return b.to_string();
+        }
+        if self.is_class_assignable(b, a) {
+            return a.to_string();
+        }
+        match (a.strip_prefix('['), b.strip_prefix('[')) {
+            (Some(ac), Some(bc)) => match (component_class(ac), component_class(bc)) {
+                (Some(ac), Some(bc)) => array_of(&self.common_superclass(ac, bc)),
+                _ => OBJECT.to_string(),
+            },
+            (None, None) => {
+                // both are loaded, otherwise they'd have been assumed assignable
+                let mut current = self.method_area.resolve_class(a).super_class.as_deref();
+                while let Some(name) = current {
+                    if self.is_class_assignable(b, name) {
+                        return name.to_string();
+                    }
+                    current = self.method_area.resolve_class(name).super_class.as_deref();
+                }
+                OBJECT.to_string()
+            }
+            _ => OBJECT.to_string(),
+        }
+    }
+
+    // §4.10.1.6, `this` followed by the parameters, a long or double being one entry
+    fn initial_locals(&self) -> Vec<Type> {
+        let mut locals = Vec::new();
+        if self.method.access_flags & AccessFlags::STATIC as u16 == 0 {
+            if self.is_constructor() && self.class != OBJECT {
+                locals.push(Type::UninitializedThis);
+            } else {
+                locals.push(Type::Reference(self.class.to_string()));
+            }
+        }
+        locals.extend(self.descriptor.parameters.iter().map(Type::of));
+        locals
+    }
+
+    /// The frame at each instruction that has one in the `StackMapTable`, §4.7.4.
+    fn stack_map_frames(&self, initial: &[Type]) -> Result<HashMap<usize, Frame>, VerifyError> {
+        let mut locals = initial.to_vec();
+        let mut frames = HashMap::new();
+        for frame in self.method.stack_map.iter() {
+            let index = frame.offset();
+            let stack = match frame {
+                StackMapFrame::Same { .. } => vec![],
+                StackMapFrame::SameLocals1StackItem { stack, .. } => vec![self.verification_type(index, stack)?],
+                StackMapFrame::Chop { k, .. } => {
+                    let len = locals.len().checked_sub(*k as usize)
+                        .ok_or_else(|| self.error(index, VerifyErrorKind::InvalidStackMapFrame))?;
+                    locals.truncate(len);
+                    vec![]
+                }
+                StackMapFrame::Append { locals: appended, .. } => {
+                    for info in appended {
+                        locals.push(self.verification_type(index, info)?);
+                    }
+                    vec![]
+                }
+                StackMapFrame::Full { locals: full, stack, .. } => {
+                    locals = full.iter().map(|it| self.verification_type(index, it)).collect::<Result<_, _>>()?;
+                    stack.iter().map(|it| self.verification_type(index, it)).collect::<Result<_, _>>()?
+                }
+            };
+            frames.insert(index, Frame {
+                locals: self.expand(index, &locals, self.method.local_size, true)?,
+                stack: self.expand(index, &stack, self.method.stack_size, false)?,
+            });
+        }
+        Ok(frames)
+    }
+
+    fn verification_type(&self, index: usize, info: &VerificationTypeInfo) -> Result<Type, VerifyError> {
+        let verification_type = match info {
+            VerificationTypeInfo::Top => Type::Top,
+            VerificationTypeInfo::Integer => Type::Int,
+            VerificationTypeInfo::Float => Type::Float,
+            VerificationTypeInfo::Double => Type::Double,
+            VerificationTypeInfo::Long => Type::Long,
+            VerificationTypeInfo::Null => Type::Null,
+            VerificationTypeInfo::UninitializedThis => Type::UninitializedThis,
+            VerificationTypeInfo::Object(class) => Type::Reference(self.class_name(index, *class)?.to_string()),
+            VerificationTypeInfo::Uninitialized(offset) => match self.method.codes.get(*offset) {
+                Some(Opcode::new(_)) => Type::Uninitialized(*offset),
+                _ => return Err(self.error(index, VerifyErrorKind::InvalidStackMapFrame)),
+            },
+        };
+        Ok(verification_type)
+    }
+
+    /// Lays `types` out in slots, padding locals with `Top` up to `max`.
+    fn expand(&self, index: usize, types: &[Type], max: usize, pad: bool) -> Result<Vec<Type>, VerifyError> {
+        let mut slots = Vec::with_capacity(max);
+        for it in types {
+            slots.push(it.clone());
+            if it.is_wide() {
+                slots.push(Type::Top);
+            }
+        }
+        if slots.len() > max && pad {
+            return Err(self.error(index, VerifyErrorKind::LocalOutOfRange(slots.len() - 1)));
+        }
+        if slots.len() > max {
+            return Err(self.error(index, VerifyErrorKind::StackOverflow));
+        }
+        if pad {
+            slots.resize(max, Type::Top);
+        }
+        Ok(slots)
+    }
+
+    // §4.10.1.6, any instruction covered by a handler may transfer control to it
+    // with the locals it starts with and only the exception on the stack
+    fn check_handlers(&self, index: usize, frame: &Frame, frames: &HashMap<usize, Frame>) -> Result<(), VerifyError> {
+        for entry in self.method.exception_table.iter().filter(|it| (it.start_pc..it.end_pc).contains(&index)) {
+            let catch_type = match entry.catch_type {
+                0 => THROWABLE,
+                index => self.class_name(entry.handler_pc, index)?,
+            };
+            let handler = Frame { locals: frame.locals.clone(), stack: vec![Type::Reference(catch_type.to_string())] };
+            self.branch(index, entry.handler_pc, &handler, frames)?;
+        }
+        Ok(())
+    }
+
+    fn branch(&self, index: usize, target: usize, frame: &Frame, frames: &HashMap<usize, Frame>) -> Result<(), VerifyError> {
+        let declared = frames.get(&target)
+            .ok_or_else(|| self.error(index, VerifyErrorKind::BadBranchTarget(self.pc(target))))?;
+        if !self.is_frame_assignable(frame, declared) {
+            return Err(self.error(index, VerifyErrorKind::FrameMismatch(self.pc(target))));
+        }
+        Ok(())
+    }
+
+    fn is_frame_assignable(&self, frame: &Frame, to: &Frame) -> bool {
+        frame.locals.len() == to.locals.len() && frame.stack.len() == to.stack.len()
+            && frame.locals.iter().zip(to.locals.iter()).all(|(from, to)| self.is_assignable(from, to))
+            && frame.stack.iter().zip(to.stack.iter()).all(|(from, to)| self.is_assignable(from, to))
+    }
+
+    // §4.10.1.2
+    fn is_assignable(&self, from: &Type, to: &Type) -> bool {
+        match (from, to) {
+            _ if from == to => true,
+            (_, Type::Top) => true,
+            (Type::Null, Type::Reference(_)) => true,
+            (Type::Reference(s), Type::Reference(t)) => self.is_class_assignable(s, t),
+            _ => false,
+        }
+    }
+
+    // interfaces are treated like `Object`, and classes that aren't loaded
+    // are assumed to be assignable until the code actually uses them
+    fn is_class_assignable(&self, s: &str, t: &str) -> bool {
+        if s == t || t == OBJECT {
+            return true;
+        }
+        match (s.strip_prefix('['), t.strip_prefix('[')) {
+            (Some(sc), Some(tc)) => match (component_class(sc), component_class(tc)) {
+                (Some(sc), Some(tc)) => self.is_class_assignable(sc, tc),
+                _ => false,
+            },
+            (Some(_), None) => t == CLONEABLE || t == SERIALIZABLE,
+            (None, Some(_)) => false,
+            (None, None) => {
+                if !self.method_area.contains_class(t) || self.method_area.resolve_class(t).is_interface() {
+                    return true;
+                }
+                let mut current = Some(s);
+                while let Some(name) = current {
+                    if name == t || !self.method_area.contains_class(name) {
+                        return true;
+                    }
+                    current = self.method_area.resolve_class(name).super_class.as_deref();
+                }
+                false
+            }
+        }
+    }
+
+    fn class_name(&self, index: usize, class: u16) -> Result<&str, VerifyError> {
+        self.const_pool.resolve_class(class).ok_or_else(|| self.error(index, VerifyErrorKind::InvalidConstant(class)))
+    }
+
+    fn field_ref(&self, index: usize, field: u16) -> Result<(&MemberRef, Type), VerifyError> {
+        let member = self.const_pool.resolve_field_ref(field)
+            .ok_or_else(|| self.error(index, VerifyErrorKind::InvalidConstant(field)))?;
+        let field_type = FieldType::parse(&member.descriptor)
+            .ok_or_else(|| self.error(index, VerifyErrorKind::InvalidDescriptor(member.descriptor.clone())))?;
+        Ok((member, Type::of(&field_type)))
+    }
+
+    fn method_ref(&self, index: usize, method: u16) -> Result<(&MemberRef, MethodDescriptor), VerifyError> {
+        let member = self.const_pool.resolve_method_ref(method)
+            .ok_or_else(|| self.error(index, VerifyErrorKind::InvalidConstant(method)))?;
+        let descriptor = MethodDescriptor::parse(&member.descriptor)
+            .ok_or_else(|| self.error(index, VerifyErrorKind::InvalidDescriptor(member.descriptor.clone())))?;
+        Ok((member, descriptor))
+    }
+
+    fn push(&self, index: usize, frame: &mut Frame, value: Type) -> Result<(), VerifyError> {
+        let wide = value.is_wide();
+        frame.stack.push(value);
+        if wide {
+            frame.stack.push(Type::Top);
+        }
+        if frame.stack.len() > self.method.stack_size {
+            return Err(self.error(index, VerifyErrorKind::StackOverflow));
+        }
+        Ok(())
+    }
+
+    fn pop_slot(&self, index: usize, frame: &mut Frame) -> Result<Type, VerifyError> {
+        frame.stack.pop().ok_or_else(|| self.error(index, VerifyErrorKind::StackUnderflow))
+    }
+
+    /// Pops a value that must be assignable to `expected`.
+    fn pop(&self, index: usize, frame: &mut Frame, expected: &Type) -> Result<Type, VerifyError> {
+        if expected.is_wide() {
+            let top = self.pop_slot(index, frame)?;
+            let value = self.pop_slot(index, frame)?;
+            if top != Type::Top || value != *expected {
+                return Err(self.mismatch(index, expected, &value));
+            }
+            return Ok(value);
+        }
+        let value = self.pop_slot(index, frame)?;
+        if !self.is_assignable(&value, expected) {
+            return Err(self.mismatch(index, expected, &value));
+        }
+        Ok(value)
+    }
+
+    /// Pops a reference, which may be uninitialized.
+    fn pop_reference(&self, index: usize, frame: &mut Frame) -> Result<Type, VerifyError> {
+        let value = self.pop_slot(index, frame)?;
+        if !value.is_reference() {
+            return Err(self.mismatch(index, "reference", &value));
+        }
+        Ok(value)
+    }
+
+    /// Pops a value that takes a single slot, as `pop` and `dup` require.
+    fn pop_category1(&self, index: usize, frame: &mut Frame) -> Result<Type, VerifyError> {
+        let value = self.pop_slot(index, frame)?;
+        if value == Type::Top {
+            let wide = frame.stack.last().cloned().unwrap_or(Type::Top);
+            return Err(self.mismatch(index, "category 1 value", &wide));
+        }
+        Ok(value)
+    }
+
+    fn local(&self, index: usize, frame: &Frame, local: usize) -> Result<Type, VerifyError> {
+        frame.locals.get(local).cloned()
+            .ok_or_else(|| self.error(index, VerifyErrorKind::LocalOutOfRange(local)))
+    }
+
+    fn load(&self, index: usize, frame: &mut Frame, local: usize, expected: &Type) -> Result<(), VerifyError> {
+        let value = self.local(index, frame, local)?;
+        if !self.is_assignable(&value, expected) {
+            return Err(self.mismatch(index, expected, &value));
+        }
+        self.push(index, frame, value)
+    }
+
+    fn aload(&self, index: usize, frame: &mut Frame, local: usize) -> Result<(), VerifyError> {
+        let value = self.local(index, frame, local)?;
+        if !value.is_reference() {
+            return Err(self.mismatch(index, "reference", &value));
+        }
+        self.push(index, frame, value)
+    }
+
+    fn store(&self, index: usize, frame: &mut Frame, local: usize, value: Type) -> Result<(), VerifyError> {
+        self.local(index, frame, local)?;
+        // overwriting the second half of a long or double invalidates it
+        if local > 0 && frame.locals[local - 1].is_wide() {
+            frame.locals[local - 1] = Type::Top;
+        }
+        if frame.locals[local].is_wide() {
+            frame.locals[local + 1] = Type::Top;
+        }
+        frame.locals[local] = value;
+        Ok(())
+    }
+
+    fn pop_arguments(&self, index: usize, frame: &mut Frame, descriptor: &MethodDescriptor) -> Result<(), VerifyError> {
+        for parameter in descriptor.parameters.iter().rev() {
+            self.pop(index, frame, &Type::of(parameter))?;
+        }
+        Ok(())
+    }
+
+    fn push_return(&self, index: usize, frame: &mut Frame, descriptor: &MethodDescriptor) -> Result<(), VerifyError> {
+        match &descriptor.return_type {
+            Some(return_type) => self.push(index, frame, Type::of(return_type)),
+            None => Ok(()),
+        }
+    }
+
+    // §4.10.1.9, the return type of the method being verified
+    fn return_type(&self) -> Option<Type> {
+        self.descriptor.return_type.as_ref().map(Type::of)
+    }
+
+    /// Applies the instruction at `index` to `frame`, §4.10.1.9. Where control goes next
+    /// is up to the caller, see `branch_target` and `falls_through`.
+    fn execute(&self, index: usize, code: &Opcode, frame: &mut Frame) -> Result<(), VerifyError> {
+        match code {
+            Opcode::aconst_null => self.push(index, frame, Type::Null)?,
+            Opcode::aload_0 => self.aload(index, frame, 0)?,
+            Opcode::aload_1 => self.aload(index, frame, 1)?,
+            Opcode::aload_2 => self.aload(index, frame, 2)?,
+            Opcode::aload_3 => self.aload(index, frame, 3)?,
+            Opcode::aload(local) => self.aload(index, frame, *local)?,
+            Opcode::astore(_) | Opcode::astore_0 | Opcode::astore_1 | Opcode::astore_2 | Opcode::astore_3 => {
+                // a subroutine stores its return address first thing
+                let value = self.pop_slot(index, frame)?;
+                if !value.is_reference() && !matches!(value, Type::ReturnAddress(_)) {
+                    return Err(self.mismatch(index, "reference", &value));
+                }
+                self.store(index, frame, stored_local(code).unwrap_or(0), value)?;
+            }
+            Opcode::iconst_0 | Opcode::iconst_1 | Opcode::bipush(_) => self.push(index, frame, Type::Int)?,
+            Opcode::ldc(constant) => {
+                let value = match self.const_pool.get(*constant) {
+                    Some(CpInfo::Integer(_)) => Type::Int,
+                    Some(CpInfo::Float(_)) => Type::Float,
+                    Some(CpInfo::String { .. }) => Type::Reference("java/lang/String".to_string()),
+                    _ => return Err(self.error(index, VerifyErrorKind::InvalidConstant(*constant))),
+                };
+                self.push(index, frame, value)?;
+            }
+            Opcode::iload_0 => self.load(index, frame, 0, &Type::Int)?,
+            Opcode::iload_1 => self.load(index, frame, 1, &Type::Int)?,
+            Opcode::iload_2 => self.load(index, frame, 2, &Type::Int)?,
+            Opcode::iload_3 => self.load(index, frame, 3, &Type::Int)?,
+            Opcode::iload(local) => self.load(index, frame, *local, &Type::Int)?,
+            Opcode::istore(_) | Opcode::istore_0 | Opcode::istore_1 | Opcode::istore_2 | Opcode::istore_3 => {
+                self.pop(index, frame, &Type::Int)?;
+                self.store(index, frame, stored_local(code).unwrap_or(0), Type::Int)?;
+            }
+            Opcode::iadd => {
+                self.pop(index, frame, &Type::Int)?;
+                self.pop(index, frame, &Type::Int)?;
+                self.push(index, frame, Type::Int)?;
+            }
+            Opcode::pop => {
+                self.pop_category1(index, frame)?;
+            }
+            Opcode::dup => {
+                let value = self.pop_category1(index, frame)?;
+                self.push(index, frame, value.clone())?;
+                self.push(index, frame, value)?;
+            }
+            Opcode::ireturn => {
+                match self.return_type() {
+                    Some(Type::Int) => {}
+                    Some(expected) => return Err(self.mismatch(index, expected, &Type::Int)),
+                    None => return Err(self.mismatch(index, "void", &Type::Int)),
+                }
+                self.pop(index, frame, &Type::Int)?;
+            }
+            Opcode::areturn => {
+                let expected = match self.return_type() {
+                    Some(expected @ Type::Reference(_)) => expected,
+                    Some(expected) => return Err(self.mismatch(index, expected, "reference")),
+                    None => return Err(self.mismatch(index, "void", "reference")),
+                };
+                self.pop(index, frame, &expected)?;
+            }
+            Opcode::r#return => {
+                if let Some(expected) = self.return_type() {
+                    return Err(self.mismatch(index, expected, "void"));
+                }
+                if self.is_constructor() && frame.locals.contains(&Type::UninitializedThis) {
+                    return Err(self.error(index, VerifyErrorKind::UninitializedThis));
+                }
+            }
+            Opcode::goto(_) => {}
+            Opcode::jsr(target) => self.push(index, frame, Type::ReturnAddress(*target))?,
+            Opcode::ret(local) => {
+                let value = self.local(index, frame, *local)?;
+                if !matches!(value, Type::ReturnAddress(_)) {
+                    return Err(self.mismatch(index, "returnAddress", &value));
+                }
+            }
+            Opcode::iinc(local, _) => {
+                let value = self.local(index, frame, *local)?;
+                if value != Type::Int {
+                    return Err(self.mismatch(index, Type::Int, &value));
+                }
+            }
+            Opcode::ifeq(_) | Opcode::ifne(_) => {
+                self.pop(index, frame, &Type::Int)?;
+            }
+            Opcode::if_icmplt(_) => {
+                self.pop(index, frame, &Type::Int)?;
+                self.pop(index, frame, &Type::Int)?;
+            }
+            Opcode::getstatic(field) => {
+                let (_, value) = self.field_ref(index, *field)?;
+                self.push(index, frame, value)?;
+            }
+            Opcode::putstatic(field) => {
+                let (_, value) = self.field_ref(index, *field)?;
+                self.pop(index, frame, &value)?;
+            }
+            Opcode::getfield(field) => {
+                let (member, value) = self.field_ref(index, *field)?;
+                self.pop(index, frame, &Type::Reference(member.class.clone()))?;
+                self.push(index, frame, value)?;
+            }
+            Opcode::putfield(field) => {
+                let (member, value) = self.field_ref(index, *field)?;
+                self.pop(index, frame, &value)?;
+                // a constructor may set its own fields before calling `super()`
+                let object = self.pop_slot(index, frame)?;
+                let initializing = object == Type::UninitializedThis && member.class == self.class;
+                if !initializing && !self.is_assignable(&object, &Type::Reference(member.class.clone())) {
+                    return Err(self.mismatch(index, &member.class, &object));
+                }
+            }
+            Opcode::invokevirtual(method) => {
+                let (member, descriptor) = self.method_ref(index, *method)?;
+                self.pop_arguments(index, frame, &descriptor)?;
+                self.pop(index, frame, &Type::Reference(member.class.clone()))?;
+                self.push_return(index, frame, &descriptor)?;
+            }
+            Opcode::invokeinterface(method, count) => {
+                let (_, descriptor) = self.method_ref(index, *method)?;
+                if *count as usize != descriptor.parameter_slots() + 1 {
+                    return Err(self.error(index, VerifyErrorKind::InvalidCount(*count)));
+                }
+                self.pop_arguments(index, frame, &descriptor)?;
+                // interfaces are treated like `Object`
+                self.pop(index, frame, &Type::Reference(OBJECT.to_string()))?;
+                self.push_return(index, frame, &descriptor)?;
+            }
+            Opcode::invokestatic(method) => {
+                let (_, descriptor) = self.method_ref(index, *method as u16)?;
+                self.pop_arguments(index, frame, &descriptor)?;
+                self.push_return(index, frame, &descriptor)?;
+            }
+            Opcode::invokespecial(method) => {
+                let (member, descriptor) = self.method_ref(index, *method)?;
+                self.pop_arguments(index, frame, &descriptor)?;
+                if member.name == "<init>" {
+                    self.initialize(index, frame, member)?;
+                } else {
+                    self.pop(index, frame, &Type::Reference(self.class.to_string()))?;
+                    self.push_return(index, frame, &descriptor)?;
+                }
+            }
+            Opcode::new(class) => {
+                self.class_name(index, *class)?;
+                // a previous object created by this instruction can no longer be initialized
+                for local in frame.locals.iter_mut().filter(|it| **it == Type::Uninitialized(index)) {
+                    *local = Type::Top;
+                }
+                self.push(index, frame, Type::Uninitialized(index))?;
+            }
+            Opcode::newarray(atype) => {
+                self.pop(index, frame, &Type::Int)?;
+                let component = match atype {
+                    4 => "Z",
+                    5 => "C",
+                    6 => "F",
+                    7 => "D",
+                    8 => "B",
+                    9 => "S",
+                    10 => "I",
+                    11 => "J",
+                    _ => return Err(self.error(index, VerifyErrorKind::InvalidArrayType(*atype))),
+                };
+                self.push(index, frame, Type::Reference(format!("[{}", component)))?;
+            }
+            Opcode::anewarray(class) => {
+                self.pop(index, frame, &Type::Int)?;
+                let component = self.class_name(index, *class)?;
+                self.push(index, frame, Type::Reference(array_of(component)))?;
+            }
+            Opcode::arraylength => {
+                let array = self.pop_reference(index, frame)?;
+                match &array {
+                    Type::Null => {}
+                    Type::Reference(name) if name.starts_with('[') => {}
+                    _ => return Err(self.mismatch(index, "array", &array)),
+                }
+                self.push(index, frame, Type::Int)?;
+            }
+            Opcode::iaload => {
+                self.pop(index, frame, &Type::Int)?;
+                self.pop(index, frame, &Type::Reference("[I".to_string()))?;
+                self.push(index, frame, Type::Int)?;
+            }
+            Opcode::iastore => {
+                self.pop(index, frame, &Type::Int)?;
+                self.pop(index, frame, &Type::Int)?;
+                self.pop(index, frame, &Type::Reference("[I".to_string()))?;
+            }
+            Opcode::athrow => {
+                self.pop(index, frame, &Type::Reference(THROWABLE.to_string()))?;
+            }
+            Opcode::checkcast(class) => {
+                let class = self.class_name(index, *class)?;
+                self.pop(index, frame, &Type::Reference(OBJECT.to_string()))?;
+                self.push(index, frame, Type::Reference(class.to_string()))?;
+            }
+            Opcode::instanceof(class) => {
+                self.class_name(index, *class)?;
+                self.pop(index, frame, &Type::Reference(OBJECT.to_string()))?;
+                self.push(index, frame, Type::Int)?;
+            }
+            Opcode::push(_) | Opcode::iaload_unchecked | Opcode::iastore_unchecked => {
+                return Err(self.error(index, VerifyErrorKind::NotInClassFiles))
+            }
+        }
+        Ok(())
+    }
+
+    // §4.10.1.9.invokespecial, calling a constructor initializes every copy of the object
+    fn initialize(&self, index: usize, frame: &mut Frame, member: &MemberRef) -> Result<(), VerifyError> {
+        let object = self.pop_slot(index, frame)?;
+        let initialized = match &object {
+            Type::UninitializedThis => Type::Reference(self.class.to_string()),
+            Type::Uninitialized(new) => match self.method.codes[*new] {
+                Opcode::new(class) => {
+                    let class = self.class_name(index, class)?;
+                    if class != member.class {
+                        return Err(self.mismatch(index, &member.class, class));
+                    }
+                    Type::Reference(class.to_string())
+                }
+                _ => return Err(self.error(index, VerifyErrorKind::InvalidStackMapFrame)),
+            },
+            _ => return Err(self.mismatch(index, "uninitialized", &object)),
+        };
+        for it in frame.locals.iter_mut().chain(frame.stack.iter_mut()).filter(|it| **it == object) {
+            *it = initialized.clone();
+        }
+        Ok(())
+    }
+}
+
+/// Whether execution may continue with the instruction after `code`, a subroutine
+/// call only does so through a `ret`.
+fn falls_through(code: &Opcode) -> bool {
+    !matches!(code, Opcode::goto(_) | Opcode::jsr(_) | Opcode::ret(_) | Opcode::athrow
+        | Opcode::ireturn | Opcode::areturn | Opcode::r#return)
+}
+
+/// The local variable `code` stores to.
+fn stored_local(code: &Opcode) -> Option<usize> {
+    match code {
+        Opcode::istore_0 | Opcode::astore_0 => Some(0),
+        Opcode::istore_1 | Opcode::astore_1 => Some(1),
+        Opcode::istore_2 | Opcode::astore_2 => Some(2),
+        Opcode::istore_3 | Opcode::astore_3 => Some(3),
+        Opcode::istore(local) | Opcode::astore(local) => Some(*local),
+        _ => None,
+    }
+}
+
+/// Descriptor of an array of the class or array `component`.
+fn array_of(component: &str) -> String {
+    if component.starts_with('[') {
+        format!("[{}", component)
+    } else {
+        format!("[L{};", component)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::const_pool::ConstPool;
+    use crate::method_area::{Class, ClassLoader, Method, MethodArea};
+    use crate::Opcode;
+    use crate::parser::AccessFlags;
+    use crate::parser::attribute::{StackMapFrame, VerificationTypeInfo};
+    use crate::verifier::{verify, VerifyErrorKind};
+
+    fn verify_method(
+        descriptor: &str, max_stack: usize, codes: Vec<Opcode>, stack_map: Vec<StackMapFrame>,
+    ) -> Result<(), VerifyErrorKind> {
+        verify_version(52, descriptor, max_stack, 2, codes, stack_map)
+    }
+
+    fn infer_method(descriptor: &str, max_stack: usize, max_locals: usize, codes: Vec<Opcode>) -> Result<(), VerifyErrorKind> {
+        verify_version(49, descriptor, max_stack, max_locals, codes, vec![])
+    }
+
+    fn verify_version(
+        major: u16, descriptor: &str, max_stack: usize, max_locals: usize, codes: Vec<Opcode>,
+        stack_map: Vec<StackMapFrame>,
+    ) -> Result<(), VerifyErrorKind> {
+        let mut method_area = MethodArea::new();
+        method_area.put_class(Class {
+            name: "Test".to_string(),
+            access_flags: AccessFlags::PUBLIC as u16,
+            super_class: Some("java/lang/Object".to_string()),
+            interfaces: vec![],
+            fields: vec![],
+            const_pool: ConstPool::from_vec(vec![]),
+            loader: ClassLoader::App,
+            major,
+        });
+        method_area.put(&format!("Test.test:{}", descriptor), Method {
+            stack_size: max_stack,
+            local_size: max_locals,
+            codes,
+            class: "Test".to_string(),
+            name: "test".to_string(),
+            descriptor: descriptor.to_string(),
+            access_flags: AccessFlags::STATIC as u16,
+            stack_map,
+            ..Default::default()
+        });
+        verify(&method_area, "Test").map_err(|it| it.kind)
+    }
+
+    #[test]
+    fn accept_javac_output() {
+        for name in ["Adder", "Counter", "Attributes", "Branches"].iter() {
+            let path = format!("{}/tests/fixtures/{}.class", env!("CARGO_MANIFEST_DIR"), name);
+            let mut method_area = MethodArea::new();
+            method_area.define_class(crate::parser::parse(std::fs::File::open(path).unwrap()).unwrap());
+            assert_eq!(verify(&method_area, name), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reject_stack_underflow_and_overflow() {
+        assert_eq!(verify_method("(I)I", 2, vec![Opcode::iadd, Opcode::ireturn], vec![]),
+                   Err(VerifyErrorKind::StackUnderflow));
+        let codes = || vec![Opcode::iconst_0, Opcode::iconst_1, Opcode::iadd, Opcode::ireturn];
+        assert_eq!(verify_method("()I", 2, codes(), vec![]), Ok(()));
+        assert_eq!(verify_method("()I", 1, codes(), vec![]), Err(VerifyErrorKind::StackOverflow));
+    }
+
+    #[test]
+    fn reject_invalid_array_type() {
+        let codes = |atype| vec![Opcode::iconst_1, Opcode::newarray(atype), Opcode::pop, Opcode::r#return];
+        assert_eq!(verify_method("()V", 1, codes(11), vec![]), Ok(()));
+        assert_eq!(verify_method("()V", 1, codes(12), vec![]), Err(VerifyErrorKind::InvalidArrayType(12)));
+    }
+
+    #[test]
+    fn reject_type_confusion() {
+        assert_eq!(
+            verify_method("()Ljava/lang/Object;", 1, vec![Opcode::iconst_0, Opcode::areturn], vec![]),
+            Err(VerifyErrorKind::TypeMismatch { expected: "java/lang/Object".to_string(), found: "int".to_string() })
+        );
+        assert_eq!(
+            verify_method("(Ljava/lang/Object;)I", 1, vec![Opcode::iload_0, Opcode::ireturn], vec![]),
+            Err(VerifyErrorKind::TypeMismatch { expected: "int".to_string(), found: "java/lang/Object".to_string() })
+        );
+        assert_eq!(verify_method("(J)I", 1, vec![Opcode::iload_1, Opcode::ireturn], vec![]),
+                   Err(VerifyErrorKind::TypeMismatch { expected: "int".to_string(), found: "top".to_string() }));
+    }
+
+    #[test]
+    fn reject_bad_branch_targets() {
+        // if (n != 0) return 1; return n;
+        let codes = || vec![
+            Opcode::iload_0,
+            Opcode::ifeq(4),
+            Opcode::iconst_1,
+            Opcode::ireturn,
+            Opcode::iload_0,
+            Opcode::ireturn,
+        ];
+        assert_eq!(verify_method("(I)I", 1, codes(), vec![]), Err(VerifyErrorKind::BadBranchTarget(6)));
+        assert_eq!(verify_method("(I)I", 1, codes(), vec![StackMapFrame::Same { offset: 4 }]), Ok(()));
+        let item = StackMapFrame::SameLocals1StackItem { offset: 4, stack: VerificationTypeInfo::Integer };
+        assert_eq!(verify_method("(I)I", 1, codes(), vec![item]), Err(VerifyErrorKind::FrameMismatch(6)));
+
+        let codes = vec![Opcode::goto(2), Opcode::iconst_0, Opcode::iconst_1, Opcode::ireturn];
+        assert_eq!(verify_method("()I", 1, codes, vec![StackMapFrame::Same { offset: 2 }]),
+                   Err(VerifyErrorKind::MissingFrame));
+        assert_eq!(verify_method("(I)V", 1, vec![Opcode::iload_0, Opcode::pop], vec![]),
+                   Err(VerifyErrorKind::FallsOffEnd));
+    }
+
+    #[test]
+    fn infer_types_without_stack_map() {
+        let codes = vec![Opcode::iload_0, Opcode::ifeq(4), Opcode::iconst_1, Opcode::ireturn, Opcode::iload_0, Opcode::ireturn];
+        assert_eq!(infer_method("(I)I", 1, 1, codes), Ok(()));
+        assert_eq!(
+            infer_method("()Ljava/lang/Object;", 1, 0, vec![Opcode::iconst_0, Opcode::areturn]),
+            Err(VerifyErrorKind::TypeMismatch { expected: "java/lang/Object".to_string(), found: "int".to_string() })
+        );
+
+        // int x; if (n == 0) x = 0; else x = null; return x;
+        let codes = vec![
+            Opcode::iload_0,
+            Opcode::ifeq(5),
+            Opcode::iconst_0,
+            Opcode::istore_1,
+            Opcode::goto(7),
+            Opcode::aconst_null,
+            Opcode::astore_1,
+            Opcode::iload_1,
+            Opcode::ireturn,
+        ];
+        assert_eq!(infer_method("(I)I", 1, 2, codes),
+                   Err(VerifyErrorKind::TypeMismatch { expected: "int".to_string(), found: "top".to_string() }));
+        let codes = vec![Opcode::iload_0, Opcode::ifeq(3), Opcode::iconst_0, Opcode::iconst_1, Opcode::ireturn];
+        assert_eq!(infer_method("(I)I", 2, 1, codes), Err(VerifyErrorKind::FrameMismatch(5)));
+    }
+
+    #[test]
+    fn infer_subroutine_locals() {
+        // the subroutine at 3 stores n to local 1, which is read after it returns
+        let codes = |store| vec![
+            Opcode::jsr(3),
+            Opcode::iload_1,
+            Opcode::ireturn,
+            Opcode::astore_2,
+            Opcode::iload_0,
+            store,
+            Opcode::ret(2),
+        ];
+        assert_eq!(infer_method("(I)I", 1, 3, codes(Opcode::istore_1)), Ok(()));
+        assert_eq!(infer_method("(I)I", 1, 3, codes(Opcode::pop)),
+                   Err(VerifyErrorKind::TypeMismatch { expected: "int".to_string(), found: "top".to_string() }));
+        assert_eq!(verify_version(52, "(I)I", 1, 3, codes(Opcode::istore_1), vec![]), Err(VerifyErrorKind::Subroutine));
+    }
+}
@@ -0,0 +1,699 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::call_stack::{JvmStack, Stop, SuspendedFrame};
+use crate::descriptor::{FieldType, MethodDescriptor};
+use crate::heap::{Heap, NULL, Object, Reference};
+use crate::inline_cache::CallSiteStats;
+use crate::jit::JitThresholds;
+use crate::limits::{Budget, Limit, Limits};
+use crate::method_area::{component_class, Method, MethodArea};
+use crate::parser;
+use crate::parser::{AccessFlags, ClassFile, ClassFormatError};
+use crate::snapshot;
+use crate::snapshot::{Definition, Origin};
+use crate::verifier;
+use crate::verifier::VerifyError;
+use crate::writer;
+
+/// Frames a call into the [`Vm`] may nest unless [`Vm::set_stack_depth`] says otherwise.
+const DEFAULT_STACK_DEPTH: usize = 1024;
+
+/// Handle to an object on the heap of the [`Vm`] that created it. Calls given a handle that
+/// refers to no object on the heap of their `Vm` fail with [`VmError::InvalidReference`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectRef(Reference);
+
+/// A Java value passed into or returned out of the [`Vm`], see JVMS §2.2.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Byte(i8),
+    Char(u16),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Null,
+    Object(ObjectRef),
+}
+
+/// An exception thrown by Java code and not caught before returning to Rust.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JavaException {
+    pub object: ObjectRef,
+    /// Binary name, e.g. `java.lang.ClassCastException`
+    pub class_name: String,
+    pub message: Option<String>,
+}
+
+impl Display for JavaException {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.class_name, message),
+            None => write!(f, "{}", self.class_name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VmError {
+    ClassNotFound(String),
+    /// The class hierarchy could not be linked, e.g. it is circular
+    LinkageError(String),
+    NoSuchMethod(String),
+    NoSuchField(String),
+    /// Arguments or field values that don't match the declared types
+    IllegalArgument(String),
+    ClassFormat(ClassFormatError),
+    Verify(VerifyError),
+    Io(io::Error),
+    Exception(JavaException),
+    /// The call stopped at one of the [`Limits`] and can be [resumed](Vm::resume)
+    LimitExceeded(Limit),
+    /// [`Vm::resume`] was called without a call that stopped at a limit
+    NotSuspended,
+    /// A snapshot couldn't be saved, or can't be restored into this `Vm`
+    Snapshot(String),
+    /// An [`ObjectRef`] that refers to no object on the heap of this `Vm`, e.g. one taken
+    /// before a snapshot without the object was restored
+    InvalidReference(ObjectRef),
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::ClassNotFound(name) => write!(f, "class not found: {}", name),
+            VmError::LinkageError(message) => write!(f, "linkage error: {}", message),
+            VmError::NoSuchMethod(name) => write!(f, "no such method: {}", name),
+            VmError::NoSuchField(name) => write!(f, "no such field: {}", name),
+            VmError::IllegalArgument(message) => write!(f, "illegal argument: {}", message),
+            VmError::ClassFormat(e) => write!(f, "{}", e),
+            VmError::Verify(e) => write!(f, "verify error: {}", e),
+            VmError::Io(e) => write!(f, "{}", e),
+            VmError::Exception(e) => write!(f, "{}", e),
+            VmError::LimitExceeded(limit) => write!(f, "{}", limit),
+            VmError::NotSuspended => write!(f, "no call to resume"),
+            VmError::Snapshot(message) => write!(f, "snapshot error: {}", message),
+            VmError::InvalidReference(object) => write!(f, "invalid reference: {:?}", object),
+        }
+    }
+}
+
+impl Error for VmError {}
+
+impl From<ClassFormatError> for VmError {
+    fn from(e: ClassFormatError) -> Self {
+        VmError::ClassFormat(e)
+    }
+}
+
+impl From<VerifyError> for VmError {
+    fn from(e: VerifyError) -> Self {
+        VmError::Verify(e)
+    }
+}
+
+impl From<io::Error> for VmError {
+    fn from(e: io::Error) -> Self {
+        VmError::Io(e)
+    }
+}
+
+/// A Java virtual machine that Rust code can load classes into, call methods
+/// on and inspect objects of.
+///
+/// ```no_run
+/// use rj::{Value, Vm};
+///
+/// let mut vm = Vm::new();
+/// vm.add_classpath("target/classes");
+/// vm.load_class("com.example.Adder")?;
+/// let sum = vm.invoke_static("com.example.Adder", "add", "(II)I", &[Value::Int(1), Value::Int(2)])?;
+/// assert_eq!(sum, Some(Value::Int(3)));
+/// # Ok::<(), rj::VmError>(())
+/// ```
+pub struct Vm {
+    classpath: Vec<PathBuf>,
+    method_area: MethodArea,
+    heap: Heap,
+    stack_depth: usize,
+    limits: Limits,
+    jit: Option<JitThresholds>,
+    // the last call, if it stopped at a limit
+    suspended: Option<Suspended>,
+    // the classes defined so far besides the bootstrap ones, for snapshots
+    definitions: Vec<Definition>,
+}
+
+// the frames of a call that stopped at a limit and what the call returns once they complete
+struct Suspended {
+    frames: Vec<SuspendedFrame>,
+    returns: Returns,
+}
+
+enum Returns {
+    Value(Option<FieldType>),
+    // the object `new_object` constructs
+    Object(Reference),
+}
+
+// how a run within the `Limits` ended if its bottom frame didn't complete
+enum Stopped {
+    Thrown(Reference),
+    Limit(Limit, Vec<SuspendedFrame>),
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm {
+            classpath: Vec::new(),
+            method_area: MethodArea::new(),
+            heap: Heap::new(),
+            stack_depth: DEFAULT_STACK_DEPTH,
+            limits: Limits::default(),
+            jit: Some(JitThresholds::default()),
+            suspended: None,
+            definitions: Vec::new(),
+        }
+    }
+
+    /// Sets the limits each call into Java code runs within, none by default.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Continues the last call, which stopped with [`VmError::LimitExceeded`], with the limits
+    /// starting over. Returns what the call would have, or `None` if it stopped while initializing
+    /// a class before the method itself was called, in which case the call can simply be repeated.
+    ///
+    /// Calling into the `Vm` again instead drops the stopped call. Any class whose static
+    /// initializer it was running is left initialized as far as it got.
+    pub fn resume(&mut self) -> Result<Option<Value>, VmError> {
+        let Suspended { frames, returns } = self.suspended.take().ok_or(VmError::NotSuspended)?;
+        let stack = JvmStack::resume(self.stack_depth, frames, &self.method_area, &mut self.heap);
+        let result = run_within(stack, &self.limits, self.jit);
+        self.complete(result, returns)
+    }
+
+    /// Sets how hot methods get before they are compiled to native code, or with `None`, runs
+    /// everything in the interpreter, including methods already compiled. Methods are compiled
+    /// with the [default](JitThresholds::default) thresholds unless this says otherwise, on
+    /// x86-64 Linux only. Calls of a method made after it got that hot run it
+    /// [optimized](JitThresholds::optimize) on any platform.
+    pub fn set_jit(&mut self, jit: Option<JitThresholds>) {
+        self.jit = jit;
+    }
+
+    /// Limits how deeply Java methods may call each other within one call from Rust,
+    /// counted in frames rather than bytes as `-Xss` does. Going deeper throws a
+    /// `StackOverflowError` that Java code can catch.
+    pub fn set_stack_depth(&mut self, frames: usize) {
+        self.stack_depth = frames;
+    }
+
+    /// How the inline caches of the `invokevirtual` and `invokeinterface` instructions of every
+    /// method that has run so far did, ordered by method and instruction. Loading a class
+    /// empties the caches but keeps their counts.
+    pub fn call_sites(&self) -> Vec<CallSiteStats> {
+        self.method_area.call_site_stats()
+    }
+
+    /// Adds a directory that classes are looked up in, by their package path.
+    pub fn add_classpath<P: Into<PathBuf>>(&mut self, path: P) {
+        self.classpath.push(path.into());
+    }
+
+    /// Loads a class by its binary name (`a.b.C`) or internal name (`a/b/C`)
+    /// from the classpath, along with its superclasses and interfaces.
+    pub fn load_class(&mut self, name: &str) -> Result<(), VmError> {
+        self.load(&internal_name(name), &mut Vec::new())
+    }
+
+    /// Loads the class in the given class file regardless of the classpath,
+    /// returning its internal name. Classes it refers to are looked up on the classpath.
+    pub fn load_class_file<P: AsRef<Path>>(&mut self, path: P) -> Result<String, VmError> {
+        let bytes = fs::read(path)?;
+        let class_file = parser::parse_bytes(&bytes)?;
+        self.define_new(class_file, Origin::Defined(Some(bytes)))
+    }
+
+    /// Defines a class from its parsed or built form, returning its internal name.
+    /// Classes it refers to are looked up on the classpath.
+    pub fn define_class(&mut self, class_file: ClassFile) -> Result<String, VmError> {
+        // kept as a class file so that snapshots can define it again
+        let origin = Origin::Defined(writer::write(&class_file).ok());
+        self.define_new(class_file, origin)
+    }
+
+    fn define_new(&mut self, class_file: ClassFile, origin: Origin) -> Result<String, VmError> {
+        let name = class_file.this_class.clone();
+        if self.method_area.contains_class(&name) {
+            return Err(VmError::LinkageError(format!("duplicate class definition: {}", name)));
+        }
+        self.define(class_file, origin, &mut Vec::new())?;
+        Ok(name)
+    }
+
+    fn load(&mut self, name: &str, loading: &mut Vec<String>) -> Result<(), VmError> {
+        if self.method_area.contains_class(name) {
+            return Ok(());
+        }
+        if loading.iter().any(|it| it == name) {
+            return Err(VmError::LinkageError(format!("class circularity: {}", name)));
+        }
+        let path = self.find_class_file(name).ok_or_else(|| VmError::ClassNotFound(name.to_string()))?;
+        let bytes = fs::read(path)?;
+        let class_file = parser::parse_bytes(&bytes)?;
+        if class_file.this_class != name {
+            return Err(VmError::ClassNotFound(name.to_string()));
+        }
+        self.define(class_file, Origin::Classpath(bytes), loading)
+    }
+
+    fn find_class_file(&self, name: &str) -> Option<PathBuf> {
+        self.classpath.iter()
+            .map(|it| it.join(format!("{}.class", name)))
+            .find(|it| it.is_file())
+    }
+
+    /// §5.3.5, superclasses and interfaces are loaded first so that the class
+    /// hierarchy is complete once the class is defined.
+    fn define(&mut self, class_file: ClassFile, origin: Origin, loading: &mut Vec<String>) -> Result<(), VmError> {
+        let name = class_file.this_class.clone();
+        loading.push(name.clone());
+        if let Some(super_class) = &class_file.super_class {
+            self.load(super_class, loading)?;
+        }
+        for interface in class_file.interfaces.iter() {
+            self.load(interface, loading)?;
+        }
+        let referenced: Vec<String> = class_file.const_pool.class_names().iter()
+            .filter_map(|it| element_class(it))
+            .map(String::from)
+            .collect();
+        self.method_area.define_class(class_file);
+        loading.pop();
+        // referenced classes are loaded eagerly, those that can't be found
+        // only fail if they are actually used
+        for class in referenced {
+            let _ = self.load(&class, loading);
+        }
+        // §5.4.1, the class is linked once the classes it refers to are available
+        if let Err(e) = verifier::verify(&self.method_area, &name) {
+            self.method_area.remove_class(&name);
+            return Err(e.into());
+        }
+        self.definitions.push(Definition { name, origin });
+        Ok(())
+    }
+
+    /// Saves the classes loaded so far, the static fields of those that were initialized and
+    /// the heap, so that another `Vm` can [restore](Vm::restore_snapshot) them instead of
+    /// loading and initializing the same classes again. Fails while a call is
+    /// [suspended](Vm::resume).
+    pub fn save_snapshot<W: io::Write>(&self, mut out: W) -> Result<(), VmError> {
+        if self.suspended.is_some() {
+            return Err(VmError::Snapshot("a call is suspended".to_string()));
+        }
+        let image = snapshot::save(&self.classpath, &self.definitions, &self.heap).map_err(VmError::Snapshot)?;
+        out.write_all(&image)?;
+        Ok(())
+    }
+
+    /// Replaces the classes, static fields and heap of this `Vm` with those of a snapshot
+    /// [saved](Vm::save_snapshot) by a `Vm` with the same classpath, keeping its limits and JIT
+    /// settings. Objects keep the references they had in the `Vm` that saved it.
+    ///
+    /// Fails, leaving the `Vm` as it was, if the classpath differs or any class the snapshot
+    /// loaded from it isn't found there as the same class file anymore, or if the snapshot
+    /// doesn't hold classes that verify and a heap they could have built. Methods start out
+    /// interpreted and cold.
+    pub fn restore_snapshot<R: io::Read>(&mut self, mut input: R) -> Result<(), VmError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let image = snapshot::load(&bytes).map_err(VmError::Snapshot)?;
+        let classpath: Vec<String> = self.classpath.iter().map(|it| it.to_string_lossy().into_owned()).collect();
+        if image.classpath != classpath {
+            return Err(VmError::Snapshot(format!("saved with classpath {:?}", image.classpath)));
+        }
+        let mut method_area = MethodArea::new();
+        for Definition { name, origin } in image.definitions.iter() {
+            let bytes = match origin {
+                Origin::Classpath(bytes) => {
+                    let path = self.find_class_file(name)
+                        .ok_or_else(|| VmError::Snapshot(format!("{} is no longer on the classpath", name)))?;
+                    if fs::read(path)? != *bytes {
+                        return Err(VmError::Snapshot(format!("{} changed since the snapshot was saved", name)));
+                    }
+                    bytes
+                }
+                Origin::Defined(bytes) => bytes.as_ref().expect("snapshots only hold written classes"),
+            };
+            let class_file = parser::parse_bytes(bytes)?;
+            if class_file.this_class != *name || method_area.contains_class(name) {
+                return Err(VmError::Snapshot(format!("invalid definition of {}", name)));
+            }
+            method_area.define_class(class_file);
+        }
+        for Definition { name, .. } in image.definitions.iter() {
+            verifier::verify(&method_area, name)?;
+        }
+        // every reference must be null or one to an object of the type of the field, element or
+        // literal holding it, as the code that stored it would have made sure
+        let objects = &image.objects;
+        let check = |reference: Reference, t: &str, holder: &str| {
+            let valid = reference == NULL || reference > 0 && objects.get(reference as usize - 1)
+                .is_some_and(|it| method_area.is_assignable(it.class_name(), t));
+            if !valid {
+                return Err(VmError::Snapshot(format!("invalid reference {} in {}", reference, holder)));
+            }
+            Ok(())
+        };
+        for object in objects.iter() {
+            match object {
+                Object::Instance { class, fields } => {
+                    if !method_area.contains_class(class) || method_area.instance_slot_count(class) != fields.len() {
+                        return Err(VmError::Snapshot(format!("invalid instance of {}", class)));
+                    }
+                    for (slot, t) in method_area.reference_fields(class, false) {
+                        check(fields[slot], t, class)?;
+                    }
+                }
+                Object::Array { class, elements } => {
+                    match FieldType::parse(class) {
+                        Some(FieldType::Array(_)) => {}
+                        _ => return Err(VmError::Snapshot(format!("invalid array class {}", class))),
+                    }
+                    if let Some(t) = class.strip_prefix('[').and_then(component_class) {
+                        for element in elements {
+                            check(*element, t, class)?;
+                        }
+                    }
+                }
+                Object::String(_) => {}
+            }
+        }
+        for (class, fields) in image.statics.iter() {
+            if !method_area.contains_class(class) || method_area.static_slot_count(class) != fields.len() {
+                return Err(VmError::Snapshot(format!("invalid static fields of {}", class)));
+            }
+            for (slot, t) in method_area.reference_fields(class, true) {
+                check(fields[slot], t, class)?;
+            }
+        }
+        for (value, string) in image.interned.iter() {
+            check(*string, "java/lang/String", value)?;
+        }
+        self.method_area = method_area;
+        self.heap = Heap::restore(image.objects, image.statics, image.interned);
+        self.definitions = image.definitions;
+        self.suspended = None;
+        Ok(())
+    }
+
+    /// Calls a static method, `descriptor` being its JVM method descriptor,
+    /// e.g. `(ILjava/lang/String;)V`. Returns `None` for `void` methods.
+    pub fn invoke_static(
+        &mut self, class: &str, name: &str, descriptor: &str, args: &[Value],
+    ) -> Result<Option<Value>, VmError> {
+        let class = internal_name(class);
+        self.load_class(&class)?;
+        self.execute(&format!("{}.{}:{}", class, name, descriptor), None, args)
+    }
+
+    /// Calls an instance method, selecting the implementation from the class of `object`.
+    pub fn invoke_virtual(
+        &mut self, object: ObjectRef, name: &str, descriptor: &str, args: &[Value],
+    ) -> Result<Option<Value>, VmError> {
+        let class = self.object(object)?.class_name().to_string();
+        self.execute(&format!("{}.{}:{}", class, name, descriptor), Some(object.0), args)
+    }
+
+    /// Creates an instance of `class` by calling the constructor with the given descriptor.
+    /// Fails for interfaces and abstract classes, which have no instances of their own.
+    pub fn new_object(
+        &mut self, class: &str, descriptor: &str, args: &[Value],
+    ) -> Result<ObjectRef, VmError> {
+        let class = internal_name(class);
+        self.load_class(&class)?;
+        if !self.method_area.is_instantiable(&class) {
+            return Err(VmError::IllegalArgument(format!("{} is abstract", class)));
+        }
+        self.initialize(&class)?;
+        let object = self.heap.new_instance(&self.method_area, &class);
+        let result = self.execute(&format!("{}.<init>:{}", class, descriptor), Some(object), args);
+        if let (Err(VmError::LimitExceeded(_)), Some(suspended)) = (&result, &mut self.suspended) {
+            suspended.returns = Returns::Object(object);
+        }
+        result?;
+        Ok(ObjectRef(object))
+    }
+
+    pub fn new_string(&mut self, value: &str) -> ObjectRef {
+        ObjectRef(self.heap.new_string(value))
+    }
+
+    pub fn new_string_array(&mut self, values: &[&str]) -> ObjectRef {
+        let elements = values.iter().map(|it| self.heap.new_string(it)).collect();
+        ObjectRef(self.heap.allocate(Object::Array { class: "[Ljava/lang/String;".to_string(), elements }))
+    }
+
+    /// Contents of a `java.lang.String`, `None` for any other object.
+    pub fn string_value(&self, object: ObjectRef) -> Result<Option<&str>, VmError> {
+        Ok(match self.object(object)? {
+            Object::String(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Internal name of the class of `object`, e.g. `java/lang/String` or `[I`.
+    pub fn class_name(&self, object: ObjectRef) -> Result<&str, VmError> {
+        Ok(self.object(object)?.class_name())
+    }
+
+    // handles can outlive the heap they were taken from, e.g. when a snapshot is restored
+    fn object(&self, object: ObjectRef) -> Result<&Object, VmError> {
+        self.heap.find(object.0).ok_or(VmError::InvalidReference(object))
+    }
+
+    pub fn get_field(&self, object: ObjectRef, name: &str) -> Result<Value, VmError> {
+        let (slot, field_type) = self.instance_field(object, name)?;
+        match self.heap.get(object.0) {
+            Object::Instance { fields, .. } => Ok(to_value(&field_type, &fields[slot..])),
+            _ => Err(VmError::NoSuchField(name.to_string())),
+        }
+    }
+
+    pub fn set_field(&mut self, object: ObjectRef, name: &str, value: Value) -> Result<(), VmError> {
+        let (slot, field_type) = self.instance_field(object, name)?;
+        let slots = self.to_slots(&field_type, value)?;
+        if let Object::Instance { fields, .. } = self.heap.get_mut(object.0) {
+            fields[slot..slot + slots.len()].copy_from_slice(&slots);
+        }
+        Ok(())
+    }
+
+    /// Reads a static field, initializing its class first if needed.
+    pub fn get_static(&mut self, class: &str, name: &str) -> Result<Value, VmError> {
+        let (owner, slot, field_type) = self.static_field(class, name)?;
+        Ok(to_value(&field_type, &self.heap.statics(&owner)[slot..]))
+    }
+
+    /// Writes a static field, initializing its class first if needed.
+    pub fn set_static(&mut self, class: &str, name: &str, value: Value) -> Result<(), VmError> {
+        let (owner, slot, field_type) = self.static_field(class, name)?;
+        let slots = self.to_slots(&field_type, value)?;
+        self.heap.statics_mut(&owner)[slot..slot + slots.len()].copy_from_slice(&slots);
+        Ok(())
+    }
+
+    fn instance_field(&self, object: ObjectRef, name: &str) -> Result<(usize, FieldType), VmError> {
+        let class = self.object(object)?.class_name();
+        let (slot, field) = self.method_area.instance_field(class, name)
+            .ok_or_else(|| VmError::NoSuchField(format!("{}.{}", class, name)))?;
+        Ok((slot, FieldType::parse(&field.descriptor).expect("malformed field descriptor")))
+    }
+
+    fn static_field(&mut self, class: &str, name: &str) -> Result<(String, usize, FieldType), VmError> {
+        let class = internal_name(class);
+        self.load_class(&class)?;
+        let (owner, slot, field) = self.method_area.static_field(&class, name)
+            .ok_or_else(|| VmError::NoSuchField(format!("{}.{}", class, name)))?;
+        let field_type = FieldType::parse(&field.descriptor).expect("malformed field descriptor");
+        let owner = owner.to_string();
+        self.initialize(&owner)?;
+        Ok((owner, slot, field_type))
+    }
+
+    /// Runs the static initializers of `class` if they haven't run yet, §5.5.
+    fn initialize(&mut self, class: &str) -> Result<(), VmError> {
+        if self.heap.is_initialized(class) {
+            return Ok(());
+        }
+        self.suspended = None;
+        let mut stack = JvmStack::new(self.stack_depth, &self.method_area, &mut self.heap);
+        stack.initialize(class);
+        let result = run_within(stack, &self.limits, self.jit);
+        self.complete(result, Returns::Value(None)).map(|_| ())
+    }
+
+    fn execute(&mut self, key: &str, receiver: Option<Reference>, args: &[Value]) -> Result<Option<Value>, VmError> {
+        let method: &Method = self.method_area.find_method(key)
+            .ok_or_else(|| VmError::NoSuchMethod(key.to_string()))?;
+        // §6.5.invokestatic and §6.5.invokevirtual, each only calls the one kind of method
+        let is_static = method.access_flags & AccessFlags::STATIC as u16 != 0;
+        if is_static != receiver.is_none() {
+            let kind = if is_static { "static" } else { "an instance method" };
+            return Err(VmError::IllegalArgument(format!("{} is {}", key, kind)));
+        }
+        let descriptor = MethodDescriptor::parse(&key[key.rfind(':').unwrap() + 1..])
+            .ok_or_else(|| VmError::IllegalArgument(format!("malformed descriptor in {}", key)))?;
+        if descriptor.parameters.len() != args.len() {
+            return Err(VmError::IllegalArgument(format!(
+                "{} takes {} arguments, got {}", key, descriptor.parameters.len(), args.len())));
+        }
+
+        // §2.6.1, `this` goes in local 0 followed by the parameters
+        let mut locals = Vec::with_capacity(method.local_size);
+        locals.extend(receiver);
+        for (parameter, arg) in descriptor.parameters.iter().zip(args) {
+            locals.extend(self.to_slots(parameter, *arg)?);
+        }
+
+        let class = method.class.clone();
+        let const_pool = &self.method_area.resolve_class(&class).const_pool;
+        self.suspended = None;
+        let mut stack = JvmStack::new(self.stack_depth, &self.method_area, &mut self.heap);
+        stack.push_frame(method, const_pool, &locals);
+        stack.initialize(&class);
+        let result = run_within(stack, &self.limits, self.jit);
+        self.complete(result, Returns::Value(descriptor.return_type))
+    }
+
+    fn complete(&mut self, result: Result<Option<i32>, Stopped>, returns: Returns) -> Result<Option<Value>, VmError> {
+        match result {
+            Ok(v) => Ok(match returns {
+                Returns::Value(return_type) => return_type.map(|it| to_value(&it, &[v.unwrap_or(NULL)])),
+                Returns::Object(object) => Some(Value::Object(ObjectRef(object))),
+            }),
+            Err(Stopped::Thrown(e)) => Err(self.exception(e)),
+            Err(Stopped::Limit(limit, frames)) => {
+                self.suspended = Some(Suspended { frames, returns });
+                Err(VmError::LimitExceeded(limit))
+            }
+        }
+    }
+
+    fn exception(&self, exception: Reference) -> VmError {
+        VmError::Exception(JavaException {
+            object: ObjectRef(exception),
+            class_name: self.heap.get(exception).class_name().replace('/', "."),
+            message: self.heap.throwable_message(&self.method_area, exception).map(String::from),
+        })
+    }
+
+    fn to_slots(&self, field_type: &FieldType, value: Value) -> Result<Vec<i32>, VmError> {
+        let slots = match (field_type, value) {
+            (FieldType::Boolean, Value::Boolean(v)) => vec![v as i32],
+            (FieldType::Byte, Value::Byte(v)) => vec![v as i32],
+            (FieldType::Char, Value::Char(v)) => vec![v as i32],
+            (FieldType::Short, Value::Short(v)) => vec![v as i32],
+            (FieldType::Int, Value::Int(v)) => vec![v],
+            (FieldType::Float, Value::Float(v)) => vec![v.to_bits() as i32],
+            (FieldType::Long, Value::Long(v)) => long_to_slots(v).to_vec(),
+            (FieldType::Double, Value::Double(v)) => long_to_slots(v.to_bits() as i64).to_vec(),
+            (t, Value::Null) if t.is_reference() => vec![NULL],
+            (t, Value::Object(object)) if t.is_reference() => {
+                let class = self.object(object)?.class_name();
+                let expected = t.class_name().unwrap();
+                if !self.method_area.is_assignable(class, &expected) {
+                    return Err(VmError::IllegalArgument(format!("{} is not a {}", class, expected)));
+                }
+                vec![object.0]
+            }
+            (t, v) => return Err(VmError::IllegalArgument(format!("{:?} is not a {}", v, t))),
+        };
+        Ok(slots)
+    }
+}
+
+/// Runs `stack` within `limits`, keeping its frames if it stops at one so that it can be resumed.
+fn run_within(mut stack: JvmStack, limits: &Limits, jit: Option<JitThresholds>) -> Result<Option<i32>, Stopped> {
+    stack.set_budget(Budget::new(limits));
+    stack.set_jit(jit);
+    match stack.run() {
+        Ok(v) => Ok(v),
+        Err(Stop::Thrown(exception)) => Err(Stopped::Thrown(exception)),
+        Err(Stop::Limit(limit)) => Err(Stopped::Limit(limit, stack.suspend())),
+    }
+}
+
+fn to_value(field_type: &FieldType, slots: &[i32]) -> Value {
+    match field_type {
+        FieldType::Boolean => Value::Boolean(slots[0] != 0),
+        FieldType::Byte => Value::Byte(slots[0] as i8),
+        FieldType::Char => Value::Char(slots[0] as u16),
+        FieldType::Short => Value::Short(slots[0] as i16),
+        FieldType::Int => Value::Int(slots[0]),
+        FieldType::Float => Value::Float(f32::from_bits(slots[0] as u32)),
+        FieldType::Long => Value::Long(slots_to_long(slots)),
+        FieldType::Double => Value::Double(f64::from_bits(slots_to_long(slots) as u64)),
+        FieldType::Object(_) | FieldType::Array(_) => match slots[0] {
+            NULL => Value::Null,
+            reference => Value::Object(ObjectRef(reference)),
+        },
+    }
+}
+
+/// Category 2 values take two slots, high bits first.
+fn long_to_slots(value: i64) -> [i32; 2] {
+    [(value >> 32) as i32, value as i32]
+}
+
+fn slots_to_long(slots: &[i32]) -> i64 {
+    ((slots[0] as i64) << 32) | (slots[1] as u32 as i64)
+}
+
+fn internal_name(name: &str) -> String {
+    name.replace('.', "/")
+}
+
+/// The class an array class is made of, or the class itself; `None` for primitive arrays.
+fn element_class(name: &str) -> Option<&str> {
+    let element = name.trim_start_matches('[');
+    if element.len() == name.len() {
+        Some(name)
+    } else {
+        element.strip_prefix('L').and_then(|it| it.strip_suffix(';'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{element_class, long_to_slots, slots_to_long};
+
+    #[test]
+    fn long_slots_round_trip() {
+        for value in [0, -1, i64::MAX, i64::MIN, 0x1234_5678_9abc_def0].iter() {
+            assert_eq!(slots_to_long(&long_to_slots(*value)), *value);
+        }
+    }
+
+    #[test]
+    fn element_class_of_arrays() {
+        assert_eq!(element_class("Foo"), Some("Foo"));
+        assert_eq!(element_class("[[Ljava/lang/String;"), Some("java/lang/String"));
+        assert_eq!(element_class("[I"), None);
+    }
+}
@@ -0,0 +1,833 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::const_pool::{ConstPool, CpInfo};
+use crate::Opcode;
+use crate::parser::{Attribute, ClassFile, ExceptionTableEntry, FieldInfo, MethodInfo};
+use crate::parser::attribute::{
+    Annotation, ElementValue, LocalVariable, StackMapFrame, TargetInfo, TypeAnnotation, VerificationTypeInfo,
+};
+use crate::parser::mutf8;
+
+/// A `ClassFile` that cannot be represented in the class file format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteError {
+    /// The constant pool has no entry for a class or attribute name that must be written
+    MissingConstant(String),
+    /// A branch whose target is too far away for a 16-bit offset, at instruction `pc`
+    BranchOutOfRange { pc: usize, target: usize },
+    /// Code longer than the 65535 bytes allowed by §4.7.3
+    CodeTooLong(usize),
+    /// An operand of instruction `pc` too large even for its `wide` form, §6.5.wide
+    OperandOutOfRange { pc: usize },
+    /// Instruction `pc` is one of those only optimized methods run, which no opcode stands for
+    NotInClassFiles { pc: usize },
+}
+
+impl Display for WriteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::MissingConstant(name) => write!(f, "no constant pool entry for {}", name),
+            WriteError::BranchOutOfRange { pc, target } => write!(f, "branch at {} to {} is out of range", pc, target),
+            WriteError::CodeTooLong(length) => write!(f, "code is {} bytes long", length),
+            WriteError::OperandOutOfRange { pc } => write!(f, "operand of the instruction at {} is out of range", pc),
+            WriteError::NotInClassFiles { pc } => write!(f, "instruction at {} can't be in a class file", pc),
+        }
+    }
+}
+
+impl Error for WriteError {}
+
+/// Serializes a class file, see JVMS §4.1.
+///
+/// Branch offsets and code offsets in attributes are recomputed from the decoded
+/// instructions, so a parsed class can be modified and written back out.
+///
+/// ```
+/// let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class")).unwrap();
+/// let class_file = rj::parser::parse_bytes(&bytes).unwrap();
+/// assert_eq!(rj::writer::write(&class_file).unwrap(), bytes);
+/// ```
+pub fn write(class_file: &ClassFile) -> Result<Vec<u8>, WriteError> {
+    let mut writer = Writer { bytes: Vec::new(), const_pool: &class_file.const_pool };
+    writer.write_class_file(class_file)?;
+    Ok(writer.bytes)
+}
+
+/// Serializes a class file into `output`.
+pub fn write_to<W: io::Write>(class_file: &ClassFile, mut output: W) -> io::Result<()> {
+    let bytes = write(class_file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    output.write_all(&bytes)
+}
+
+struct Writer<'a> {
+    bytes: Vec<u8>,
+    const_pool: &'a ConstPool,
+}
+
+// byte offset of each instruction, and of the end of the code
+pub(crate) struct CodeOffsets(Vec<usize>);
+
+impl CodeOffsets {
+    pub(crate) fn of(codes: &[Opcode]) -> CodeOffsets {
+        let mut offsets = Vec::with_capacity(codes.len() + 1);
+        let mut offset = 0;
+        offsets.push(offset);
+        for code in codes {
+            offset += instruction_length(code);
+            offsets.push(offset);
+        }
+        CodeOffsets(offsets)
+    }
+
+    pub(crate) fn pc(&self, index: usize) -> u16 {
+        self.0[index] as u16
+    }
+
+    pub(crate) fn length(&self, start: usize, end: usize) -> u16 {
+        (self.0[end] - self.0[start]) as u16
+    }
+}
+
+impl Writer<'_> {
+    fn u1(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u2(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u4(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn indices(&mut self, indices: &[u16]) {
+        self.u2(indices.len() as u16);
+        for index in indices {
+            self.u2(*index);
+        }
+    }
+
+    fn write_class_file(&mut self, class_file: &ClassFile) -> Result<(), WriteError> {
+        self.u4(0xCAFEBABE);
+        self.u2(class_file.minor);
+        self.u2(class_file.major);
+        self.write_const_pool();
+        self.u2(class_file.access_flags);
+        let this_class = self.class_index(&class_file.this_class)?;
+        self.u2(this_class);
+        let super_class = match &class_file.super_class {
+            Some(name) => self.class_index(name)?,
+            None => 0,
+        };
+        self.u2(super_class);
+        let interfaces = class_file.interfaces.iter()
+            .map(|it| self.class_index(it))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.indices(&interfaces);
+
+        self.u2(class_file.fields.len() as u16);
+        for FieldInfo { access_flags, name_index, descriptor_index, attributes } in &class_file.fields {
+            self.u2(*access_flags);
+            self.u2(*name_index);
+            self.u2(*descriptor_index);
+            self.write_attributes(attributes, None)?;
+        }
+
+        self.u2(class_file.methods.len() as u16);
+        for MethodInfo { access_flags, name_index, descriptor_index, attributes } in &class_file.methods {
+            self.u2(*access_flags);
+            self.u2(*name_index);
+            self.u2(*descriptor_index);
+            self.write_attributes(attributes, None)?;
+        }
+
+        self.write_attributes(&class_file.attributes, None)
+    }
+
+    fn class_index(&self, name: &str) -> Result<u16, WriteError> {
+        self.const_pool.find_class(name).ok_or_else(|| WriteError::MissingConstant(name.to_string()))
+    }
+
+    fn write_const_pool(&mut self) {
+        let entries = self.const_pool.entries();
+        self.u2(entries.len() as u16);
+        for entry in entries {
+            match *entry {
+                // index 0 and the second half of a Long or Double
+                CpInfo::Placeholder => {}
+                CpInfo::Utf8(ref value) => {
+                    let encoded = mutf8::encode(value);
+                    self.u1(1);
+                    self.u2(encoded.len() as u16);
+                    self.bytes.extend(encoded);
+                }
+                CpInfo::Integer(value) => {
+                    self.u1(3);
+                    self.u4(value as u32);
+                }
+                CpInfo::Float(value) => {
+                    self.u1(4);
+                    self.u4(value.to_bits());
+                }
+                CpInfo::Long(value) => {
+                    self.u1(5);
+                    self.bytes.extend_from_slice(&value.to_be_bytes());
+                }
+                CpInfo::Double(value) => {
+                    self.u1(6);
+                    self.bytes.extend_from_slice(&value.to_bits().to_be_bytes());
+                }
+                CpInfo::Class { name_index } => {
+                    self.u1(7);
+                    self.u2(name_index);
+                }
+                CpInfo::String { string_index } => {
+                    self.u1(8);
+                    self.u2(string_index);
+                }
+                CpInfo::FieldRef { class_index, name_and_type_index } => {
+                    self.u1(9);
+                    self.u2(class_index);
+                    self.u2(name_and_type_index);
+                }
+                CpInfo::MethodRef { class_index, name_and_type_index } => {
+                    self.u1(10);
+                    self.u2(class_index);
+                    self.u2(name_and_type_index);
+                }
+                CpInfo::InterfaceMethodRef { class_index, name_and_type_index } => {
+                    self.u1(11);
+                    self.u2(class_index);
+                    self.u2(name_and_type_index);
+                }
+                CpInfo::NameAndTuple { name_index, descriptor_index } => {
+                    self.u1(12);
+                    self.u2(name_index);
+                    self.u2(descriptor_index);
+                }
+                CpInfo::MethodHandle { reference_kind, reference_index } => {
+                    self.u1(15);
+                    self.u1(reference_kind);
+                    self.u2(reference_index);
+                }
+                CpInfo::MethodType { descriptor_index } => {
+                    self.u1(16);
+                    self.u2(descriptor_index);
+                }
+                CpInfo::Dynamic { bootstrap_method_attr_index, name_and_type_index } => {
+                    self.u1(17);
+                    self.u2(bootstrap_method_attr_index);
+                    self.u2(name_and_type_index);
+                }
+                CpInfo::InvokeDynamic { bootstrap_method_attr_index, name_and_type_index } => {
+                    self.u1(18);
+                    self.u2(bootstrap_method_attr_index);
+                    self.u2(name_and_type_index);
+                }
+                CpInfo::Module { name_index } => {
+                    self.u1(19);
+                    self.u2(name_index);
+                }
+                CpInfo::Package { name_index } => {
+                    self.u1(20);
+                    self.u2(name_index);
+                }
+            }
+        }
+    }
+
+    fn write_attributes(&mut self, attributes: &[Attribute], code: Option<&CodeOffsets>) -> Result<(), WriteError> {
+        self.u2(attributes.len() as u16);
+        for attribute in attributes {
+            self.write_attribute(attribute, code)?;
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, attribute: &Attribute, code: Option<&CodeOffsets>) -> Result<(), WriteError> {
+        let name_index = match attribute {
+            Attribute::Unknown { name_index, .. } => *name_index,
+            _ => {
+                let name = attribute_name(attribute);
+                self.const_pool.find_utf8(name).ok_or_else(|| WriteError::MissingConstant(name.to_string()))?
+            }
+        };
+        self.u2(name_index);
+        // attribute_length is patched in once the attribute is written
+        let length_at = self.bytes.len();
+        self.u4(0);
+
+        match attribute {
+            Attribute::ConstantValue { constantvalue_index } => self.u2(*constantvalue_index),
+            Attribute::Code { max_stack, max_locals, codes, exception_table, attributes } => {
+                self.write_code(*max_stack, *max_locals, codes, exception_table, attributes)?;
+            }
+            Attribute::StackMapTable(frames) => {
+                let code = code.expect("StackMapTable outside of Code");
+                self.u2(frames.len() as u16);
+                let mut previous = None;
+                for frame in frames {
+                    self.stack_map_frame(frame, previous, code);
+                    previous = Some(code.pc(frame.offset()));
+                }
+            }
+            Attribute::SourceDebugExtension(info) | Attribute::Unknown { info, .. } => {
+                self.bytes.extend_from_slice(info);
+            }
+            Attribute::Exceptions(indices)
+            | Attribute::ModulePackages(indices)
+            | Attribute::NestMembers(indices)
+            | Attribute::PermittedSubclasses(indices) => self.indices(indices),
+            Attribute::InnerClasses(classes) => {
+                self.u2(classes.len() as u16);
+                for class in classes {
+                    self.u2(class.inner_class_info_index);
+                    self.u2(class.outer_class_info_index);
+                    self.u2(class.inner_name_index);
+                    self.u2(class.inner_class_access_flags);
+                }
+            }
+            Attribute::EnclosingMethod { class_index, method_index } => {
+                self.u2(*class_index);
+                self.u2(*method_index);
+            }
+            Attribute::Synthetic | Attribute::Deprecated => {}
+            Attribute::Signature { signature_index: index }
+            | Attribute::SourceFile { sourcefile_index: index }
+            | Attribute::ModuleMainClass { main_class_index: index }
+            | Attribute::NestHost { host_class_index: index } => self.u2(*index),
+            Attribute::LineNumberTable(lines) => {
+                let code = code.expect("LineNumberTable outside of Code");
+                self.u2(lines.len() as u16);
+                for line in lines {
+                    self.u2(code.pc(line.start_pc));
+                    self.u2(line.line_number);
+                }
+            }
+            Attribute::LocalVariableTable(variables) | Attribute::LocalVariableTypeTable(variables) => {
+                let code = code.expect("LocalVariableTable outside of Code");
+                self.u2(variables.len() as u16);
+                for LocalVariable { start_pc, end_pc, name_index, descriptor_index, index } in variables {
+                    self.u2(code.pc(*start_pc));
+                    self.u2(code.length(*start_pc, *end_pc));
+                    self.u2(*name_index);
+                    self.u2(*descriptor_index);
+                    self.u2(*index);
+                }
+            }
+            Attribute::RuntimeVisibleAnnotations(annotations)
+            | Attribute::RuntimeInvisibleAnnotations(annotations) => self.annotations(annotations),
+            Attribute::RuntimeVisibleParameterAnnotations(parameters)
+            | Attribute::RuntimeInvisibleParameterAnnotations(parameters) => {
+                self.u1(parameters.len() as u8);
+                for annotations in parameters {
+                    self.annotations(annotations);
+                }
+            }
+            Attribute::RuntimeVisibleTypeAnnotations(annotations)
+            | Attribute::RuntimeInvisibleTypeAnnotations(annotations) => {
+                self.u2(annotations.len() as u16);
+                for annotation in annotations {
+                    self.type_annotation(annotation, code);
+                }
+            }
+            Attribute::AnnotationDefault(value) => self.element_value(value),
+            Attribute::BootstrapMethods(methods) => {
+                self.u2(methods.len() as u16);
+                for method in methods {
+                    self.u2(method.bootstrap_method_ref);
+                    self.indices(&method.bootstrap_arguments);
+                }
+            }
+            Attribute::MethodParameters(parameters) => {
+                self.u1(parameters.len() as u8);
+                for parameter in parameters {
+                    self.u2(parameter.name_index);
+                    self.u2(parameter.access_flags);
+                }
+            }
+            Attribute::Module(module) => {
+                self.u2(module.module_name_index);
+                self.u2(module.module_flags);
+                self.u2(module.module_version_index);
+                self.u2(module.requires.len() as u16);
+                for requires in &module.requires {
+                    self.u2(requires.requires_index);
+                    self.u2(requires.requires_flags);
+                    self.u2(requires.requires_version_index);
+                }
+                for exports in [&module.exports, &module.opens].iter() {
+                    self.u2(exports.len() as u16);
+                    for export in exports.iter() {
+                        self.u2(export.index);
+                        self.u2(export.flags);
+                        self.indices(&export.to);
+                    }
+                }
+                self.indices(&module.uses);
+                self.u2(module.provides.len() as u16);
+                for provides in &module.provides {
+                    self.u2(provides.provides_index);
+                    self.indices(&provides.with);
+                }
+            }
+            Attribute::Record(components) => {
+                self.u2(components.len() as u16);
+                for component in components {
+                    self.u2(component.name_index);
+                    self.u2(component.descriptor_index);
+                    self.write_attributes(&component.attributes, None)?;
+                }
+            }
+        }
+
+        let length = (self.bytes.len() - length_at - 4) as u32;
+        self.bytes[length_at..length_at + 4].copy_from_slice(&length.to_be_bytes());
+        Ok(())
+    }
+
+    fn write_code(
+        &mut self,
+        max_stack: u16,
+        max_locals: u16,
+        codes: &[Opcode],
+        exception_table: &[ExceptionTableEntry],
+        attributes: &[Attribute],
+    ) -> Result<(), WriteError> {
+        let offsets = CodeOffsets::of(codes);
+        let code_length = offsets.0[codes.len()];
+        if code_length > u16::MAX as usize {
+            return Err(WriteError::CodeTooLong(code_length));
+        }
+        self.u2(max_stack);
+        self.u2(max_locals);
+        self.u4(code_length as u32);
+        for (pc, code) in codes.iter().enumerate() {
+            self.instruction(pc, code, &offsets)?;
+        }
+        self.u2(exception_table.len() as u16);
+        for entry in exception_table {
+            self.u2(offsets.pc(entry.start_pc));
+            self.u2(offsets.pc(entry.end_pc));
+            self.u2(offsets.pc(entry.handler_pc));
+            self.u2(entry.catch_type);
+        }
+        self.write_attributes(attributes, Some(&offsets))
+    }
+
+    fn instruction(&mut self, pc: usize, code: &Opcode, offsets: &CodeOffsets) -> Result<(), WriteError> {
+        let branch = |writer: &mut Writer, opcode: u8, target: usize| {
+            let offset = offsets.0[target] as isize - offsets.0[pc] as isize;
+            if offset < i16::MIN as isize || offset > i16::MAX as isize {
+                return Err(WriteError::BranchOutOfRange { pc, target });
+            }
+            writer.u1(opcode);
+            writer.u2(offset as i16 as u16);
+            Ok(())
+        };
+        let indexed = |writer: &mut Writer, opcode: u8, index: u16| {
+            writer.u1(opcode);
+            writer.u2(index);
+        };
+        let local = |writer: &mut Writer, opcode: u8, index: usize| {
+            match u8::try_from(index) {
+                Ok(index) => {
+                    writer.u1(opcode);
+                    writer.u1(index);
+                }
+                Err(_) => {
+                    let index = u16::try_from(index).map_err(|_| WriteError::OperandOutOfRange { pc })?;
+                    writer.u1(0xc4);
+                    writer.u1(opcode);
+                    writer.u2(index);
+                }
+            }
+            Ok(())
+        };
+        match *code {
+            Opcode::aconst_null => self.u1(0x01),
+            Opcode::iconst_0 => self.u1(0x03),
+            Opcode::iconst_1 => self.u1(0x04),
+            Opcode::bipush(value) => {
+                let value = i8::try_from(value).map_err(|_| WriteError::OperandOutOfRange { pc })?;
+                self.u1(0x10);
+                self.u1(value as u8);
+            }
+            Opcode::ldc(index) if index <= u8::MAX as u16 => {
+                self.u1(0x12);
+                self.u1(index as u8);
+            }
+            Opcode::ldc(index) => indexed(self, 0x13, index),
+            Opcode::iload(index) => local(self, 0x15, index)?,
+            Opcode::aload(index) => local(self, 0x19, index)?,
+            Opcode::iload_0 => self.u1(0x1a),
+            Opcode::iload_1 => self.u1(0x1b),
+            Opcode::iload_2 => self.u1(0x1c),
+            Opcode::iload_3 => self.u1(0x1d),
+            Opcode::aload_0 => self.u1(0x2a),
+            Opcode::aload_1 => self.u1(0x2b),
+            Opcode::aload_2 => self.u1(0x2c),
+            Opcode::aload_3 => self.u1(0x2d),
+            Opcode::iaload => self.u1(0x2e),
+            Opcode::istore(index) => local(self, 0x36, index)?,
+            Opcode::astore(index) => local(self, 0x3a, index)?,
+            Opcode::istore_0 => self.u1(0x3b),
+            Opcode::istore_1 => self.u1(0x3c),
+            Opcode::istore_2 => self.u1(0x3d),
+            Opcode::istore_3 => self.u1(0x3e),
+            Opcode::astore_0 => self.u1(0x4b),
+            Opcode::astore_1 => self.u1(0x4c),
+            Opcode::astore_2 => self.u1(0x4d),
+            Opcode::astore_3 => self.u1(0x4e),
+            Opcode::iastore => self.u1(0x4f),
+            Opcode::pop => self.u1(0x57),
+            Opcode::dup => self.u1(0x59),
+            Opcode::iadd => self.u1(0x60),
+            Opcode::iinc(index, value) => match (u8::try_from(index), i8::try_from(value)) {
+                (Ok(index), Ok(value)) => {
+                    self.u1(0x84);
+                    self.u1(index);
+                    self.u1(value as u8);
+                }
+                _ => {
+                    let index = u16::try_from(index).map_err(|_| WriteError::OperandOutOfRange { pc })?;
+                    let value = i16::try_from(value).map_err(|_| WriteError::OperandOutOfRange { pc })?;
+                    self.u1(0xc4);
+                    self.u1(0x84);
+                    self.u2(index);
+                    self.u2(value as u16);
+                }
+            },
+            Opcode::ifeq(target) => branch(self, 0x99, target)?,
+            Opcode::ifne(target) => branch(self, 0x9a, target)?,
+            Opcode::if_icmplt(target) => branch(self, 0xa1, target)?,
+            Opcode::goto(target) => branch(self, 0xa7, target)?,
+            Opcode::jsr(target) => branch(self, 0xa8, target)?,
+            Opcode::ret(index) => local(self, 0xa9, index)?,
+            Opcode::ireturn => self.u1(0xac),
+            Opcode::areturn => self.u1(0xb0),
+            Opcode::r#return => self.u1(0xb1),
+            Opcode::getstatic(index) => indexed(self, 0xb2, index),
+            Opcode::putstatic(index) => indexed(self, 0xb3, index),
+            Opcode::getfield(index) => indexed(self, 0xb4, index),
+            Opcode::putfield(index) => indexed(self, 0xb5, index),
+            Opcode::invokevirtual(index) => indexed(self, 0xb6, index),
+            Opcode::invokespecial(index) => indexed(self, 0xb7, index),
+            Opcode::invokestatic(index) => indexed(self, 0xb8, index as u16),
+            Opcode::invokeinterface(index, count) => {
+                indexed(self, 0xb9, index);
+                self.u1(count);
+                self.u1(0);
+            }
+            Opcode::new(index) => indexed(self, 0xbb, index),
+            Opcode::newarray(atype) => {
+                self.u1(0xbc);
+                self.u1(atype);
+            }
+            Opcode::anewarray(index) => indexed(self, 0xbd, index),
+            Opcode::arraylength => self.u1(0xbe),
+            Opcode::athrow => self.u1(0xbf),
+            Opcode::checkcast(index) => indexed(self, 0xc0, index),
+            Opcode::instanceof(index) => indexed(self, 0xc1, index),
+            Opcode::push(_) | Opcode::iaload_unchecked | Opcode::iastore_unchecked => {
+                return Err(WriteError::NotInClassFiles { pc })
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a frame in its most compact form, `previous` is the byte offset of the frame before it.
+    fn stack_map_frame(&mut self, frame: &StackMapFrame, previous: Option<u16>, code: &CodeOffsets) {
+        let delta = match previous {
+            Some(previous) => code.pc(frame.offset()) - previous - 1,
+            None => code.pc(frame.offset()),
+        };
+        let frame_type = frame.frame_type(delta);
+        self.u1(frame_type);
+        if frame_type >= 247 {
+            self.u2(delta);
+        }
+        match frame {
+            StackMapFrame::Same { .. } | StackMapFrame::Chop { .. } => {}
+            StackMapFrame::SameLocals1StackItem { stack, .. } => self.verification_type(stack, code),
+            StackMapFrame::Append { locals, .. } => {
+                for local in locals {
+                    self.verification_type(local, code);
+                }
+            }
+            StackMapFrame::Full { locals, stack, .. } => {
+                for types in [locals, stack] {
+                    self.u2(types.len() as u16);
+                    for info in types {
+                        self.verification_type(info, code);
+                    }
+                }
+            }
+        }
+    }
+
+    fn verification_type(&mut self, info: &VerificationTypeInfo, code: &CodeOffsets) {
+        match info {
+            VerificationTypeInfo::Top => self.u1(0),
+            VerificationTypeInfo::Integer => self.u1(1),
+            VerificationTypeInfo::Float => self.u1(2),
+            VerificationTypeInfo::Double => self.u1(3),
+            VerificationTypeInfo::Long => self.u1(4),
+            VerificationTypeInfo::Null => self.u1(5),
+            VerificationTypeInfo::UninitializedThis => self.u1(6),
+            VerificationTypeInfo::Object(index) => {
+                self.u1(7);
+                self.u2(*index);
+            }
+            VerificationTypeInfo::Uninitialized(offset) => {
+                self.u1(8);
+                self.u2(code.pc(*offset));
+            }
+        }
+    }
+
+    fn annotations(&mut self, annotations: &[Annotation]) {
+        self.u2(annotations.len() as u16);
+        for annotation in annotations {
+            self.annotation(annotation);
+        }
+    }
+
+    fn annotation(&mut self, annotation: &Annotation) {
+        self.u2(annotation.type_index);
+        self.u2(annotation.element_value_pairs.len() as u16);
+        for (name_index, value) in &annotation.element_value_pairs {
+            self.u2(*name_index);
+            self.element_value(value);
+        }
+    }
+
+    fn element_value(&mut self, value: &ElementValue) {
+        match value {
+            ElementValue::Const { tag, const_value_index } => {
+                self.u1(*tag);
+                self.u2(*const_value_index);
+            }
+            ElementValue::Enum { type_name_index, const_name_index } => {
+                self.u1(b'e');
+                self.u2(*type_name_index);
+                self.u2(*const_name_index);
+            }
+            ElementValue::Class { class_info_index } => {
+                self.u1(b'c');
+                self.u2(*class_info_index);
+            }
+            ElementValue::Annotation(annotation) => {
+                self.u1(b'@');
+                self.annotation(annotation);
+            }
+            ElementValue::Array(values) => {
+                self.u1(b'[');
+                self.u2(values.len() as u16);
+                for value in values {
+                    self.element_value(value);
+                }
+            }
+        }
+    }
+
+    fn type_annotation(&mut self, annotation: &TypeAnnotation, code: Option<&CodeOffsets>) {
+        self.u1(annotation.target_type);
+        match &annotation.target_info {
+            TargetInfo::TypeParameter { type_parameter_index } => self.u1(*type_parameter_index),
+            TargetInfo::Supertype { supertype_index } => self.u2(*supertype_index),
+            TargetInfo::TypeParameterBound { type_parameter_index, bound_index } => {
+                self.u1(*type_parameter_index);
+                self.u1(*bound_index);
+            }
+            TargetInfo::Empty => {}
+            TargetInfo::FormalParameter { formal_parameter_index } => self.u1(*formal_parameter_index),
+            TargetInfo::Throws { throws_type_index } => self.u2(*throws_type_index),
+            TargetInfo::LocalVar(table) => {
+                let code = code.expect("localvar target outside of Code");
+                self.u2(table.len() as u16);
+                for target in table {
+                    self.u2(code.pc(target.start_pc));
+                    self.u2(code.length(target.start_pc, target.end_pc));
+                    self.u2(target.index);
+                }
+            }
+            TargetInfo::Catch { exception_table_index } => self.u2(*exception_table_index),
+            TargetInfo::Offset(pc) => self.u2(code.expect("offset target outside of Code").pc(*pc)),
+            TargetInfo::TypeArgument { offset, type_argument_index } => {
+                self.u2(code.expect("type_argument target outside of Code").pc(*offset));
+                self.u1(*type_argument_index);
+            }
+        }
+        self.u1(annotation.target_path.len() as u8);
+        for (type_path_kind, type_argument_index) in &annotation.target_path {
+            self.u1(*type_path_kind);
+            self.u1(*type_argument_index);
+        }
+        self.annotation(&annotation.annotation);
+    }
+}
+
+/// Encoded length in bytes of an instruction, see §6.5.
+pub(crate) fn instruction_length(code: &Opcode) -> usize {
+    match code {
+        Opcode::bipush(_) | Opcode::newarray(_) => 2,
+        Opcode::ldc(index) if *index <= u8::MAX as u16 => 2,
+        Opcode::ldc(_) => 3,
+        Opcode::iload(index) | Opcode::aload(index) | Opcode::istore(index) | Opcode::astore(index)
+        | Opcode::ret(index) if *index <= u8::MAX as usize => 2,
+        Opcode::iload(_) | Opcode::aload(_) | Opcode::istore(_) | Opcode::astore(_) | Opcode::ret(_) => 4,
+        Opcode::iinc(index, value) if *index <= u8::MAX as usize && i8::try_from(*value).is_ok() => 3,
+        Opcode::iinc(..) => 6,
+        Opcode::invokeinterface(..) => 5,
+        Opcode::ifeq(_) | Opcode::ifne(_) | Opcode::if_icmplt(_) | Opcode::goto(_) | Opcode::jsr(_)
+        | Opcode::getstatic(_) | Opcode::putstatic(_) | Opcode::getfield(_) | Opcode::putfield(_)
+        | Opcode::invokevirtual(_) | Opcode::invokespecial(_) | Opcode::invokestatic(_)
+        | Opcode::new(_) | Opcode::anewarray(_) | Opcode::checkcast(_) | Opcode::instanceof(_) => 3,
+        _ => 1,
+    }
+}
+
+fn attribute_name(attribute: &Attribute) -> &'static str {
+    match attribute {
+        Attribute::ConstantValue { .. } => "ConstantValue",
+        Attribute::Code { .. } => "Code",
+        Attribute::StackMapTable(_) => "StackMapTable",
+        Attribute::Exceptions(_) => "Exceptions",
+        Attribute::InnerClasses(_) => "InnerClasses",
+        Attribute::EnclosingMethod { .. } => "EnclosingMethod",
+        Attribute::Synthetic => "Synthetic",
+        Attribute::Signature { .. } => "Signature",
+        Attribute::SourceFile { .. } => "SourceFile",
+        Attribute::SourceDebugExtension(_) => "SourceDebugExtension",
+        Attribute::LineNumberTable(_) => "LineNumberTable",
+        Attribute::LocalVariableTable(_) => "LocalVariableTable",
+        Attribute::LocalVariableTypeTable(_) => "LocalVariableTypeTable",
+        Attribute::Deprecated => "Deprecated",
+        Attribute::RuntimeVisibleAnnotations(_) => "RuntimeVisibleAnnotations",
+        Attribute::RuntimeInvisibleAnnotations(_) => "RuntimeInvisibleAnnotations",
+        Attribute::RuntimeVisibleParameterAnnotations(_) => "RuntimeVisibleParameterAnnotations",
+        Attribute::RuntimeInvisibleParameterAnnotations(_) => "RuntimeInvisibleParameterAnnotations",
+        Attribute::RuntimeVisibleTypeAnnotations(_) => "RuntimeVisibleTypeAnnotations",
+        Attribute::RuntimeInvisibleTypeAnnotations(_) => "RuntimeInvisibleTypeAnnotations",
+        Attribute::AnnotationDefault(_) => "AnnotationDefault",
+        Attribute::BootstrapMethods(_) => "BootstrapMethods",
+        Attribute::MethodParameters(_) => "MethodParameters",
+        Attribute::Module(_) => "Module",
+        Attribute::ModulePackages(_) => "ModulePackages",
+        Attribute::ModuleMainClass { .. } => "ModuleMainClass",
+        Attribute::NestHost { .. } => "NestHost",
+        Attribute::NestMembers(_) => "NestMembers",
+        Attribute::Record(_) => "Record",
+        Attribute::PermittedSubclasses(_) => "PermittedSubclasses",
+        Attribute::Unknown { .. } => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Opcode;
+    use crate::parser::{Attribute, ClassFile, parse_bytes};
+    use crate::writer::{WriteError, write};
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/tests/fixtures/{}.class", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    fn code_mut<'a>(class_file: &'a mut ClassFile, name: &str) -> (&'a mut Vec<Opcode>, &'a mut Vec<Attribute>) {
+        let index = class_file.methods().position(|(it, _)| it == name).unwrap();
+        match class_file.methods[index].attributes.iter_mut().find(|it| matches!(it, Attribute::Code { .. })) {
+            Some(Attribute::Code { codes, attributes, .. }) => (codes, attributes),
+            _ => panic!("{} has no code", name),
+        }
+    }
+
+    #[test]
+    fn round_trip_fixtures() {
+        for name in ["Adder", "Counter", "Attributes", "Attributes$Inner", "Tag", "Shape", "Circle", "Branches"].iter() {
+            let bytes = fixture(name);
+            assert_eq!(write(&parse_bytes(&bytes).unwrap()).unwrap(), bytes, "{}", name);
+        }
+    }
+
+    #[test]
+    fn recompute_branch_offsets() {
+        let mut class_file = parse_bytes(&fixture("Adder")).unwrap();
+        let (codes, attributes) = code_mut(&mut class_file, "add");
+        attributes.clear();
+        *codes = vec![
+            Opcode::bipush(-2),
+            Opcode::pop,
+            Opcode::iload_0,
+            Opcode::ifeq(5),
+            Opcode::goto(2),
+            Opcode::iload_1,
+            Opcode::ireturn,
+        ];
+        let bytes = write(&class_file).unwrap();
+        let code = [0x10, 0xFE, 0x57, 0x1a, 0x99, 0x00, 0x06, 0xa7, 0xFF, 0xFC, 0x1b, 0xac];
+        assert!(bytes.windows(code.len()).any(|it| it == code));
+
+        let mut class_file = parse_bytes(&bytes).unwrap();
+        let (codes, _) = code_mut(&mut class_file, "add");
+        assert_eq!(format!("{:?}", codes), "[bipush(-2), pop, iload_0, ifeq(5), goto(2), iload_1, ireturn]");
+    }
+
+    #[test]
+    fn widen_local_operands_that_do_not_fit_in_a_byte() {
+        let mut class_file = parse_bytes(&fixture("Adder")).unwrap();
+        let (codes, attributes) = code_mut(&mut class_file, "add");
+        attributes.clear();
+        *codes = vec![
+            Opcode::iload(5),
+            Opcode::istore(300),
+            Opcode::iinc(2, -3),
+            Opcode::iinc(2, 200),
+            Opcode::aload(256),
+            Opcode::astore(4),
+            Opcode::ret(1000),
+        ];
+        let bytes = write(&class_file).unwrap();
+        let code = [
+            0x15, 0x05, 0xc4, 0x36, 0x01, 0x2c, 0x84, 0x02, 0xfd, 0xc4, 0x84, 0x00, 0x02, 0x00, 0xc8,
+            0xc4, 0x19, 0x01, 0x00, 0x3a, 0x04, 0xc4, 0xa9, 0x03, 0xe8,
+        ];
+        assert!(bytes.windows(code.len()).any(|it| it == code));
+
+        let mut class_file = parse_bytes(&bytes).unwrap();
+        let (codes, _) = code_mut(&mut class_file, "add");
+        assert_eq!(format!("{:?}", codes),
+            "[iload(5), istore(300), iinc(2, -3), iinc(2, 200), aload(256), astore(4), ret(1000)]");
+
+        codes.push(Opcode::iload(70000));
+        assert_eq!(write(&class_file), Err(WriteError::OperandOutOfRange { pc: 7 }));
+    }
+
+    #[test]
+    fn reject_bipush_value_out_of_range() {
+        let mut class_file = parse_bytes(&fixture("Adder")).unwrap();
+        let (codes, attributes) = code_mut(&mut class_file, "add");
+        attributes.clear();
+        *codes = vec![Opcode::bipush(-128), Opcode::bipush(127), Opcode::bipush(128), Opcode::ireturn];
+        assert_eq!(write(&class_file), Err(WriteError::OperandOutOfRange { pc: 2 }));
+        // only optimized methods push ints that don't fit
+        let (codes, _) = code_mut(&mut class_file, "add");
+        codes[2] = Opcode::push(128);
+        assert_eq!(write(&class_file), Err(WriteError::NotInClassFiles { pc: 2 }));
+    }
+
+    #[test]
+    fn reject_branch_out_of_range() {
+        let mut class_file = parse_bytes(&fixture("Adder")).unwrap();
+        let (codes, attributes) = code_mut(&mut class_file, "add");
+        attributes.clear();
+        codes.clear();
+        codes.extend((0..40000).map(|_| Opcode::pop));
+        codes.push(Opcode::goto(0));
+        assert_eq!(write(&class_file), Err(WriteError::BranchOutOfRange { pc: 40000, target: 0 }));
+    }
+}
@@ -0,0 +1,9 @@
+public class Adder {
+    public static int add(int x, int y) {
+        return x + y;
+    }
+
+    public static void main(String[] args) {
+        int s = add(1, 1);
+    }
+}
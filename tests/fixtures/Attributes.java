@@ -0,0 +1,31 @@
+import java.lang.annotation.Retention;
+import java.lang.annotation.RetentionPolicy;
+
+@Retention(RetentionPolicy.RUNTIME)
+@interface Tag {
+    String value() default "none";
+    int[] ids() default {};
+}
+
+sealed interface Shape permits Circle {
+}
+
+final class Circle implements Shape {
+}
+
+@Deprecated
+public class Attributes<T> implements Comparable<Attributes<T>> {
+    public static final int ANSWER = 42;
+
+    @Tag("field")
+    T value;
+
+    class Inner {
+    }
+
+    @Tag(value = "method", ids = {1, 2})
+    public int compareTo(Attributes<T> other) throws IllegalStateException {
+        int result = 0;
+        return result;
+    }
+}
@@ -0,0 +1,105 @@
+public class Branches {
+    static int sum(int n) {
+        int sum = 0;
+        int i = 0;
+        do {
+            sum += i;
+            i++;
+        } while (i < n);
+        return sum;
+    }
+
+    static Object pick(boolean b, Object x) {
+        return b ? x : null;
+    }
+
+    static int length(Object o) {
+        if (o instanceof int[]) {
+            int[] a = (int[]) o;
+            return a.length;
+        }
+        return 0;
+    }
+
+    static int scoped(int n) {
+        if (n != 0) {
+            int k = 1;
+            n += k;
+        }
+        return n;
+    }
+
+    static int guarded(Object o) {
+        int result = 1;
+        try {
+            String s = (String) o;
+        } catch (ClassCastException e) {
+            result = 0;
+        }
+        return result;
+    }
+
+    static int wide(long l, int i) {
+        Object o = null;
+        if (i != 0) {
+            o = new Object();
+        }
+        return length(o);
+    }
+
+    static int chop(int n) {
+        if (n != 0) {
+            int y = 1;
+            do {
+                n = n + y;
+            } while (n != 0);
+        }
+        return n;
+    }
+
+    static int far(int n) {
+        if (n != 0) {
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+            n++;
+        }
+        return n;
+    }
+
+    static int farItem(int n) {
+        return n == 0 ? 0 : n + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1;
+    }
+
+    Branches(int x) {
+    }
+
+    Branches(boolean b) {
+        this(b ? 1 : 0);
+    }
+
+    static Object make(boolean b) {
+        return new Branches(b ? 1 : 0);
+    }
+}
@@ -0,0 +1,30 @@
+public class Counter implements Comparable {
+    static int instances;
+    static int base = 40;
+    int count;
+    long total;
+
+    public Counter() {
+        instances++;
+    }
+
+    public void add(int n) {
+        count += n;
+    }
+
+    public int get() {
+        return count;
+    }
+
+    public int compareTo(Object o) {
+        return ((Counter) o).count;
+    }
+
+    public static int pick(int a, long b, int c) {
+        return c;
+    }
+
+    public static Object id(Object o) {
+        return o;
+    }
+}
@@ -0,0 +1,752 @@
+use std::time::Duration;
+
+use rj::{CacheState, JitThresholds, Limit, Limits, Value, Vm, VmError};
+use rj::builder::ClassBuilder;
+use rj::parser::AccessFlags;
+
+fn vm() -> Vm {
+    let mut vm = Vm::new();
+    vm.add_classpath(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+    vm
+}
+
+#[test]
+fn invoke_static_method() {
+    let mut vm = vm();
+    let result = vm.invoke_static("Adder", "add", "(II)I", &[Value::Int(2), Value::Int(3)]);
+    assert_eq!(result.unwrap(), Some(Value::Int(5)));
+}
+
+#[test]
+fn wide_arguments_take_two_local_slots() {
+    let mut vm = vm();
+    let result = vm.invoke_static("Counter", "pick", "(IJI)I", &[Value::Int(1), Value::Long(2), Value::Int(3)]);
+    assert_eq!(result.unwrap(), Some(Value::Int(3)));
+}
+
+#[test]
+fn construct_and_invoke_instance_methods() {
+    let mut vm = vm();
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+    assert_eq!(vm.invoke_virtual(counter, "add", "(I)V", &[Value::Int(7)]).unwrap(), None);
+    assert_eq!(vm.invoke_virtual(counter, "get", "()I", &[]).unwrap(), Some(Value::Int(7)));
+    assert_eq!(vm.class_name(counter).unwrap(), "Counter");
+}
+
+#[test]
+fn read_and_write_fields() {
+    let mut vm = vm();
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+    vm.set_field(counter, "count", Value::Int(41)).unwrap();
+    vm.invoke_virtual(counter, "add", "(I)V", &[Value::Int(1)]).unwrap();
+    assert_eq!(vm.get_field(counter, "count").unwrap(), Value::Int(42));
+
+    vm.set_field(counter, "total", Value::Long(-1 << 40)).unwrap();
+    assert_eq!(vm.get_field(counter, "total").unwrap(), Value::Long(-1 << 40));
+    assert_eq!(vm.get_field(counter, "count").unwrap(), Value::Int(42));
+}
+
+#[test]
+fn static_fields_are_initialized() {
+    let mut vm = vm();
+    assert_eq!(vm.get_static("Counter", "base").unwrap(), Value::Int(40));
+    vm.new_object("Counter", "()V", &[]).unwrap();
+    vm.new_object("Counter", "()V", &[]).unwrap();
+    assert_eq!(vm.get_static("Counter", "instances").unwrap(), Value::Int(2));
+    vm.set_static("Counter", "instances", Value::Int(0)).unwrap();
+    assert_eq!(vm.get_static("Counter", "instances").unwrap(), Value::Int(0));
+}
+
+#[test]
+fn constant_value_initializes_static_final_fields() {
+    let mut vm = vm();
+    assert_eq!(vm.get_static("Attributes", "ANSWER").unwrap(), Value::Int(42));
+}
+
+#[test]
+fn pass_and_return_references() {
+    let mut vm = vm();
+    let hello = vm.new_string("hello");
+    let result = vm.invoke_static("Counter", "id", "(Ljava/lang/Object;)Ljava/lang/Object;", &[Value::Object(hello)]);
+    assert_eq!(result.unwrap(), Some(Value::Object(hello)));
+    assert_eq!(vm.string_value(hello).unwrap(), Some("hello"));
+
+    let result = vm.invoke_static("Counter", "id", "(Ljava/lang/Object;)Ljava/lang/Object;", &[Value::Null]);
+    assert_eq!(result.unwrap(), Some(Value::Null));
+}
+
+#[test]
+fn thrown_exception_is_returned_as_error() {
+    let mut vm = vm();
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+    let hello = vm.new_string("hello");
+    let result = vm.invoke_virtual(counter, "compareTo", "(Ljava/lang/Object;)I", &[Value::Object(hello)]);
+    match result {
+        Err(VmError::Exception(exception)) => {
+            assert_eq!(exception.class_name, "java.lang.ClassCastException");
+            assert_eq!(exception.message.as_deref(), Some(
+                "class java.lang.String cannot be cast to class Counter \
+                (java.lang.String is in module java.base of loader 'bootstrap'; \
+                Counter is in unnamed module of loader 'app')"));
+            assert_eq!(vm.class_name(exception.object).unwrap(), "java/lang/ClassCastException");
+        }
+        result => panic!("expected exception, got {:?}", result),
+    }
+}
+
+#[test]
+fn reject_mismatched_arguments() {
+    let mut vm = vm();
+    let result = vm.invoke_static("Adder", "add", "(II)I", &[Value::Int(2)]);
+    assert!(matches!(result, Err(VmError::IllegalArgument(_))));
+    let result = vm.invoke_static("Adder", "add", "(II)I", &[Value::Int(2), Value::Long(3)]);
+    assert!(matches!(result, Err(VmError::IllegalArgument(_))));
+    let array = vm.new_string_array(&[]);
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+    let result = vm.invoke_virtual(counter, "compareTo", "(Ljava/lang/Object;)I", &[Value::Int(1)]);
+    assert!(matches!(result, Err(VmError::IllegalArgument(_))));
+    assert!(matches!(vm.set_field(counter, "count", Value::Object(array)), Err(VmError::IllegalArgument(_))));
+}
+
+#[test]
+fn reject_calls_of_the_wrong_kind_of_method() {
+    let mut vm = vm();
+    let result = vm.invoke_static("Counter", "add", "(I)V", &[Value::Int(5)]);
+    assert_eq!(result.unwrap_err().to_string(), "illegal argument: Counter.add:(I)V is an instance method");
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+    let result = vm.invoke_virtual(counter, "pick", "(IJI)I", &[Value::Int(1), Value::Long(2), Value::Int(3)]);
+    assert_eq!(result.unwrap_err().to_string(), "illegal argument: Counter.pick:(IJI)I is static");
+}
+
+#[test]
+fn reject_references_to_objects_of_another_heap() {
+    let mut vm = vm();
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+    let mut other = self::vm();
+    let mut image = Vec::new();
+    other.save_snapshot(&mut image).unwrap();
+
+    let invalid = |error: Option<VmError>| matches!(error, Some(VmError::InvalidReference(it)) if it == counter);
+    assert!(invalid(other.invoke_virtual(counter, "get", "()I", &[]).err()));
+    assert!(invalid(other.class_name(counter).err()));
+    assert!(invalid(other.string_value(counter).err()));
+    assert!(invalid(other.get_field(counter, "count").err()));
+    assert!(invalid(other.set_field(counter, "count", Value::Int(1)).err()));
+    assert!(invalid(other.invoke_static("Counter", "id", "(Ljava/lang/Object;)Ljava/lang/Object;",
+        &[Value::Object(counter)]).err()));
+    // a snapshot without the object replaces the heap it is on
+    vm.restore_snapshot(image.as_slice()).unwrap();
+    assert!(invalid(vm.class_name(counter).err()));
+}
+
+#[test]
+fn reject_instances_of_abstract_classes_and_interfaces() {
+    let mut base = ClassBuilder::new("Base");
+    base.access_flags(AccessFlags::PUBLIC as u16 | AccessFlags::ABSTRACT as u16);
+    let mut init = base.method(AccessFlags::PUBLIC as u16, "<init>", "()V");
+    init.aload(0).invokespecial("java/lang/Object", "<init>", "()V").r#return();
+    init.finish().unwrap();
+    let mut factory = ClassBuilder::new("Factory");
+    let mut make = factory.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "make", "()LBase;");
+    make.new("Base").dup().invokespecial("Base", "<init>", "()V").areturn();
+    make.finish().unwrap();
+    let mut vm = vm();
+    vm.define_class(base.build()).unwrap();
+    vm.define_class(factory.build()).unwrap();
+
+    assert_eq!(vm.new_object("Base", "()V", &[]).unwrap_err().to_string(), "illegal argument: Base is abstract");
+    assert_eq!(vm.new_object("Shape", "()V", &[]).unwrap_err().to_string(), "illegal argument: Shape is abstract");
+    match vm.invoke_static("Factory", "make", "()LBase;", &[]) {
+        Err(VmError::Exception(exception)) => assert_eq!(exception.to_string(), "java.lang.InstantiationError: Base"),
+        result => panic!("expected an exception, got {:?}", result),
+    }
+}
+
+#[test]
+fn report_missing_classes_and_members() {
+    let mut vm = vm();
+    assert!(matches!(vm.load_class("com.example.Missing"), Err(VmError::ClassNotFound(_))));
+    let result = vm.invoke_static("Adder", "sub", "(II)I", &[Value::Int(2), Value::Int(3)]);
+    assert!(matches!(result, Err(VmError::NoSuchMethod(_))));
+    assert!(matches!(vm.get_static("Counter", "missing"), Err(VmError::NoSuchField(_))));
+}
+
+#[test]
+fn unresolved_references_throw_linkage_errors() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Broken");
+    let mut method = class.method(static_flags, "staticMethod", "()I");
+    method.invokestatic("Broken", "missing", "()I").ireturn();
+    method.finish().unwrap();
+    let mut method = class.method(static_flags, "virtualMethod", "(LCounter;)I");
+    method.aload(0).invokevirtual("Counter", "missing", "()I").ireturn();
+    method.finish().unwrap();
+    let mut method = class.method(static_flags, "field", "(LCounter;)I");
+    method.aload(0).getfield("Counter", "missing", "I").ireturn();
+    method.finish().unwrap();
+    let mut method = class.method(static_flags, "class", "()V");
+    method.new("com/example/Missing").pop().r#return();
+    method.finish().unwrap();
+    let mut vm = vm();
+    vm.define_class(class.build()).unwrap();
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+
+    let mut thrown = |name: &str, descriptor: &str, args: &[Value]| match vm.invoke_static("Broken", name, descriptor, args) {
+        Err(VmError::Exception(exception)) => exception.to_string(),
+        result => panic!("expected an exception, got {:?}", result),
+    };
+    assert_eq!(thrown("staticMethod", "()I", &[]), "java.lang.NoSuchMethodError: Broken.missing()I");
+    assert_eq!(thrown("virtualMethod", "(LCounter;)I", &[Value::Object(counter)]),
+               "java.lang.NoSuchMethodError: Counter.missing()I");
+    assert_eq!(thrown("field", "(LCounter;)I", &[Value::Object(counter)]), "java.lang.NoSuchFieldError: missing");
+    assert_eq!(thrown("class", "()V", &[]), "java.lang.NoClassDefFoundError: com/example/Missing");
+}
+
+#[test]
+fn define_built_class() {
+    let mut class = ClassBuilder::new("Sum");
+    let mut sum = class.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "sum", "(I)I");
+    let (body, condition) = (sum.new_label(), sum.new_label());
+    sum.iconst_0().istore(1).iconst_0().istore(2).goto(condition);
+    sum.bind(body).iload(1).iload(2).iadd().istore(1).iinc(2, 1);
+    sum.bind(condition).iload(2).iload(0).if_icmplt(body);
+    sum.iload(1).ireturn();
+    sum.finish().unwrap();
+    let class_file = class.build();
+
+    // the built class survives a trip through the writer and parser
+    let bytes = rj::writer::write(&class_file).unwrap();
+    let mut vm = vm();
+    assert_eq!(vm.define_class(rj::parser::parse_bytes(&bytes).unwrap()).unwrap(), "Sum");
+    assert_eq!(vm.invoke_static("Sum", "sum", "(I)I", &[Value::Int(5)]).unwrap(), Some(Value::Int(10)));
+    assert!(matches!(vm.define_class(class_file), Err(VmError::LinkageError(_))));
+}
+
+#[test]
+fn patch_parsed_class() {
+    let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Adder.class")).unwrap();
+    let mut class = ClassBuilder::from_class_file(rj::parser::parse_bytes(&bytes).unwrap());
+    assert!(class.remove_method("add", "(II)I"));
+    assert!(!class.remove_method("add", "(II)I"));
+    let mut add = class.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "add", "(II)I");
+    add.iload(0).iload(1).iadd().iconst_1().iadd().ireturn();
+    add.finish().unwrap();
+    class.field(AccessFlags::STATIC as u16, "calls", "I");
+
+    let patched = rj::parser::parse_bytes(&rj::writer::write(&class.build()).unwrap()).unwrap();
+    assert_eq!(patched.methods().count(), 3);
+    assert!(patched.attributes().iter().any(|it| matches!(it, rj::parser::Attribute::SourceFile { .. })));
+    let mut vm = Vm::new();
+    assert_eq!(vm.define_class(patched).unwrap(), "Adder");
+    assert_eq!(vm.invoke_static("Adder", "add", "(II)I", &[Value::Int(2), Value::Int(3)]).unwrap(), Some(Value::Int(6)));
+    assert_eq!(vm.get_static("Adder", "calls").unwrap(), Value::Int(0));
+}
+
+#[test]
+fn load_and_store_locals_past_the_fourth() {
+    let mut class = ClassBuilder::new("Locals");
+    let mut sum = class.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "sum", "(I)I");
+    let (body, condition) = (sum.new_label(), sum.new_label());
+    // the total is kept in a local that needs `wide`, the counter in one that doesn't
+    sum.iconst_0().istore(300).iconst_0().istore(4).goto(condition);
+    sum.bind(body).iload(300).iload(4).iadd().istore(300).iinc(4, 1).iinc(300, 1000);
+    sum.bind(condition).iload(4).iload(0).if_icmplt(body);
+    sum.iload(300).ireturn();
+    sum.finish().unwrap();
+    let class_file = rj::writer::write(&class.build()).unwrap();
+
+    for jit in [None, Some(JitThresholds { invocations: 1, backedges: 1, optimize: true })].iter() {
+        let mut vm = vm();
+        vm.set_jit(*jit);
+        vm.define_class(rj::parser::parse_bytes(&class_file).unwrap()).unwrap();
+        for _ in 0..3 {
+            let result = vm.invoke_static("Locals", "sum", "(I)I", &[Value::Int(5)]);
+            assert_eq!(result.unwrap(), Some(Value::Int(10 + 5000)));
+        }
+    }
+}
+
+#[test]
+fn define_assembled_class() {
+    let source = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Loop.j")).unwrap();
+    let class_file = rj::assembler::assemble(&source).unwrap();
+    let bytes = rj::writer::write(&class_file).unwrap();
+    let mut vm = vm();
+    assert_eq!(vm.define_class(rj::parser::parse_bytes(&bytes).unwrap()).unwrap(), "Loop");
+    assert_eq!(vm.invoke_static("Loop", "sum", "(I)I", &[Value::Int(5)]).unwrap(), Some(Value::Int(10)));
+    assert_eq!(vm.invoke_static("Loop", "sum", "(I)I", &[Value::Int(0)]).unwrap(), Some(Value::Int(0)));
+    assert_eq!(vm.invoke_static("Loop", "calls", "()I", &[]).unwrap(), Some(Value::Int(2)));
+    assert_eq!(vm.invoke_static("Loop", "twice", "(I)I", &[Value::Int(21)]).unwrap(), Some(Value::Int(42)));
+}
+
+#[test]
+fn reject_class_that_fails_verification() {
+    let mut class = ClassBuilder::new("Bad");
+    class.version(52, 0);
+    let mut bad = class.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "bad", "()Ljava/lang/Object;");
+    bad.iconst_0().areturn();
+    bad.finish().unwrap();
+    let mut vm = vm();
+    match vm.define_class(class.build()) {
+        Err(VmError::Verify(e)) => {
+            assert_eq!((e.method.as_str(), e.pc), ("Bad.bad:()Ljava/lang/Object;", 1));
+            assert!(matches!(e.kind, rj::VerifyErrorKind::TypeMismatch { .. }));
+        }
+        result => panic!("expected verify error, got {:?}", result),
+    }
+    assert!(matches!(vm.load_class("Bad"), Err(VmError::ClassNotFound(_))));
+}
+
+#[test]
+fn deep_recursion_throws_stack_overflow_error() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Recursion");
+    let mut down = class.method(static_flags, "down", "(I)I");
+    down.iload(0).invokestatic("Recursion", "down", "(I)I").ireturn();
+    down.finish().unwrap();
+    // returns how many frames deep the overflow was caught
+    let mut depth = class.method(static_flags, "depth", "(I)I");
+    let (start, end, handler) = (depth.new_label(), depth.new_label(), depth.new_label());
+    depth.bind(start).iload(0).iconst_1().iadd().invokestatic("Recursion", "depth", "(I)I").bind(end).ireturn();
+    depth.bind(handler).pop().iload(0).ireturn();
+    depth.try_catch(start, end, handler, Some("java/lang/StackOverflowError"));
+    depth.finish().unwrap();
+    let mut vm = vm();
+    vm.define_class(class.build()).unwrap();
+
+    match vm.invoke_static("Recursion", "down", "(I)I", &[Value::Int(0)]) {
+        Err(VmError::Exception(e)) => assert_eq!((e.class_name.as_str(), e.message), ("java.lang.StackOverflowError", None)),
+        result => panic!("expected StackOverflowError, got {:?}", result),
+    }
+    vm.set_stack_depth(100);
+    assert_eq!(vm.invoke_static("Recursion", "depth", "(I)I", &[Value::Int(1)]).unwrap(), Some(Value::Int(100)));
+}
+
+#[test]
+fn stop_at_stack_depth_limit_that_java_code_cannot_catch() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Deep");
+    // counts down to zero, catching StackOverflowError to return how deep it got instead
+    let mut down = class.method(static_flags, "down", "(I)I");
+    let (start, end, handler, recurse) = (down.new_label(), down.new_label(), down.new_label(), down.new_label());
+    down.bind(start).iload(0).ifne(recurse).iconst_0().ireturn();
+    down.bind(recurse).iload(0).ldc_int(-1).iadd().invokestatic("Deep", "down", "(I)I").iconst_1().iadd().bind(end).ireturn();
+    down.bind(handler).pop().iconst_0().ireturn();
+    down.try_catch(start, end, handler, Some("java/lang/StackOverflowError"));
+    down.finish().unwrap();
+    let mut vm = vm();
+    vm.define_class(class.build()).unwrap();
+
+    vm.set_limits(Limits { stack_depth: Some(50), ..Limits::default() });
+    assert!(matches!(vm.invoke_static("Deep", "down", "(I)I", &[Value::Int(80)]),
+                     Err(VmError::LimitExceeded(Limit::StackDepth))));
+    // the call resumes at the invoke that went too deep once the limit allows it
+    vm.set_limits(Limits { stack_depth: Some(100), ..Limits::default() });
+    assert_eq!(vm.resume().unwrap(), Some(Value::Int(80)));
+    assert_eq!(vm.invoke_static("Deep", "down", "(I)I", &[Value::Int(99)]).unwrap(), Some(Value::Int(99)));
+    assert!(matches!(vm.invoke_static("Deep", "down", "(I)I", &[Value::Int(100)]),
+                     Err(VmError::LimitExceeded(Limit::StackDepth))));
+}
+
+#[test]
+fn stop_at_limits_and_resume() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Sandboxed");
+    let mut count = class.method(static_flags, "count", "(I)I");
+    let body = count.new_label();
+    count.iconst_0().istore(1);
+    count.bind(body).iinc(1, 1).iload(1).iload(0).if_icmplt(body);
+    count.iload(1).ireturn();
+    count.finish().unwrap();
+    let mut allocate = class.method(static_flags, "allocate", "()V");
+    let again = allocate.new_label();
+    allocate.bind(again).bipush(127).newarray(10).pop().goto(again);
+    allocate.finish().unwrap();
+    let mut spin = class.method(static_flags, "spin", "()V");
+    let forever = spin.new_label();
+    spin.bind(forever).goto(forever);
+    spin.finish().unwrap();
+    let mut vm = vm();
+    vm.define_class(class.build()).unwrap();
+
+    vm.set_limits(Limits { instructions: Some(1000), ..Limits::default() });
+    let mut result = vm.invoke_static("Sandboxed", "count", "(I)I", &[Value::Int(10_000)]);
+    let mut resumed = 0;
+    while let Err(VmError::LimitExceeded(Limit::Instructions)) = result {
+        resumed += 1;
+        result = vm.resume();
+    }
+    assert_eq!(result.unwrap(), Some(Value::Int(10_000)));
+    assert!(resumed >= 40, "resumed {} times", resumed);
+    assert!(matches!(vm.resume(), Err(VmError::NotSuspended)));
+
+    vm.set_limits(Limits { time: Some(Duration::from_millis(10)), ..Limits::default() });
+    assert!(matches!(vm.invoke_static("Sandboxed", "spin", "()V", &[]),
+                     Err(VmError::LimitExceeded(Limit::Time))));
+    vm.set_limits(Limits { heap_bytes: Some(64 * 1024), ..Limits::default() });
+    assert!(matches!(vm.invoke_static("Sandboxed", "allocate", "()V", &[]),
+                     Err(VmError::LimitExceeded(Limit::HeapBytes))));
+}
+
+#[test]
+fn load_constants() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Constants");
+    class.field(static_flags, "count", "I");
+    let mut next = class.method(static_flags, "next", "()I");
+    next.getstatic("Constants", "count", "I").ldc_int(100_000).iadd().dup().putstatic("Constants", "count", "I").ireturn();
+    next.finish().unwrap();
+    for name in ["greeting", "salutation"].iter() {
+        let mut greeting = class.method(static_flags, name, "()Ljava/lang/String;");
+        greeting.ldc_string("hello, world").areturn();
+        greeting.finish().unwrap();
+    }
+    let mut vm = vm();
+    vm.define_class(class.build()).unwrap();
+
+    assert_eq!(vm.invoke_static("Constants", "next", "()I", &[]).unwrap(), Some(Value::Int(100_000)));
+    assert_eq!(vm.invoke_static("Constants", "next", "()I", &[]).unwrap(), Some(Value::Int(200_000)));
+    let greeting = match vm.invoke_static("Constants", "greeting", "()Ljava/lang/String;", &[]).unwrap() {
+        Some(Value::Object(greeting)) => greeting,
+        result => panic!("expected a string, got {:?}", result),
+    };
+    assert_eq!(vm.string_value(greeting).unwrap(), Some("hello, world"));
+    // string literals are interned
+    let salutation = vm.invoke_static("Constants", "salutation", "()Ljava/lang/String;", &[]).unwrap();
+    assert_eq!(salutation, Some(Value::Object(greeting)));
+}
+
+#[test]
+fn load_and_store_int_array_elements() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Elements");
+    // int[] a = new int[n]; a[i] = 5; return a[i] + a[0];
+    let mut store = class.method(static_flags, "store", "(II)I");
+    store.iload(0).newarray(10).astore(2).aload(2).iload(1).bipush(5).iastore();
+    store.aload(2).iload(1).iaload().aload(2).iconst_0().iaload().iadd().ireturn();
+    store.finish().unwrap();
+    let mut null = class.method(static_flags, "null", "()I");
+    null.aconst_null().iconst_0().iaload().ireturn();
+    null.finish().unwrap();
+    let class_file = rj::writer::write(&class.build()).unwrap();
+    let mut vm = vm();
+    vm.define_class(rj::parser::parse_bytes(&class_file).unwrap()).unwrap();
+
+    let mut run = |name: &str, descriptor: &str, args: &[Value]| match vm.invoke_static("Elements", name, descriptor, args) {
+        Err(VmError::Exception(exception)) => exception.to_string(),
+        result => format!("{:?}", result.unwrap()),
+    };
+    assert_eq!(run("store", "(II)I", &[Value::Int(3), Value::Int(1)]), "Some(Int(5))");
+    assert_eq!(run("store", "(II)I", &[Value::Int(3), Value::Int(0)]), "Some(Int(10))");
+    assert_eq!(run("store", "(II)I", &[Value::Int(3), Value::Int(3)]),
+               "java.lang.ArrayIndexOutOfBoundsException: Index 3 out of bounds for length 3");
+    assert_eq!(run("store", "(II)I", &[Value::Int(3), Value::Int(-1)]),
+               "java.lang.ArrayIndexOutOfBoundsException: Index -1 out of bounds for length 3");
+    assert_eq!(run("null", "()I", &[]), "java.lang.NullPointerException: array is null");
+
+    let mut class = ClassBuilder::new("NotAnArray");
+    let mut method = class.method(static_flags, "load", "()I");
+    method.iconst_0().iconst_0().iaload().ireturn();
+    method.finish().unwrap();
+    match vm.define_class(class.build()) {
+        Err(VmError::Verify(e)) => assert!(matches!(e.kind, rj::VerifyErrorKind::TypeMismatch { .. })),
+        result => panic!("expected verify error, got {:?}", result),
+    }
+}
+
+#[test]
+fn inline_caches_count_hits_per_call_site() {
+    let public = AccessFlags::PUBLIC as u16;
+    let static_flags = public | AccessFlags::STATIC as u16;
+    let mut shape = ClassBuilder::new("Shape");
+    shape.access_flags(public | AccessFlags::INTERFACE as u16 | AccessFlags::ABSTRACT as u16);
+    shape.method_without_code(public | AccessFlags::ABSTRACT as u16, "sides", "()I");
+    let shape_class = |name: &str, sides: i8| {
+        let mut class = ClassBuilder::new(name);
+        class.interface("Shape");
+        let mut init = class.method(public, "<init>", "()V");
+        init.aload(0).invokespecial("java/lang/Object", "<init>", "()V").r#return();
+        init.finish().unwrap();
+        let mut method = class.method(public, "sides", "()I");
+        method.bipush(sides).ireturn();
+        method.finish().unwrap();
+        class.build()
+    };
+    let mut shapes = ClassBuilder::new("Shapes");
+    let mut sides = shapes.method(static_flags, "sides", "(LShape;)I");
+    sides.aload(0).invokeinterface("Shape", "sides", "()I").ireturn();
+    sides.finish().unwrap();
+    let mut vm = vm();
+    vm.define_class(shape.build()).unwrap();
+    vm.define_class(shape_class("Square", 4)).unwrap();
+    vm.define_class(shape_class("Triangle", 3)).unwrap();
+    // invokeinterface survives a trip through the writer and parser
+    let bytes = rj::writer::write(&shapes.build()).unwrap();
+    vm.define_class(rj::parser::parse_bytes(&bytes).unwrap()).unwrap();
+
+    let square = vm.new_object("Square", "()V", &[]).unwrap();
+    let triangle = vm.new_object("Triangle", "()V", &[]).unwrap();
+    for (shape, expected) in [(square, 4), (square, 4), (triangle, 3), (square, 4), (triangle, 3)].iter() {
+        let result = vm.invoke_static("Shapes", "sides", "(LShape;)I", &[Value::Object(*shape)]);
+        assert_eq!(result.unwrap(), Some(Value::Int(*expected)));
+    }
+    let stats = vm.call_sites();
+    assert_eq!(stats.len(), 1);
+    let site = &stats[0];
+    assert_eq!((site.method.as_str(), site.pc, site.target.as_str()), ("Shapes.sides:(LShape;)I", 1, "Shape.sides:()I"));
+    assert_eq!((site.state, site.hits, site.misses), (CacheState::Polymorphic, 3, 2));
+    assert_eq!(site.hit_rate(), 0.6);
+
+    // loading a class flushes the cache
+    vm.define_class(shape_class("Pentagon", 5)).unwrap();
+    assert_eq!(vm.call_sites()[0].state, CacheState::Empty);
+    let result = vm.invoke_static("Shapes", "sides", "(LShape;)I", &[Value::Object(square)]);
+    assert_eq!(result.unwrap(), Some(Value::Int(4)));
+    assert_eq!((vm.call_sites()[0].state, vm.call_sites()[0].misses), (CacheState::Monomorphic, 3));
+}
+
+#[test]
+fn compiled_methods_run_like_interpreted_ones() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Hot");
+    // adds up 0 to 99 n times, calling a method each time round the outer loop, which the
+    // native code leaves the inner loop for
+    let mut sums = class.method(static_flags, "sums", "(I)I");
+    let (outer, outer_condition) = (sums.new_label(), sums.new_label());
+    let (inner, inner_condition) = (sums.new_label(), sums.new_label());
+    sums.iconst_0().istore(1).iconst_0().istore(2).goto(outer_condition);
+    sums.bind(outer).iload(1).invokestatic("Hot", "id", "(I)I").istore(1).iconst_0().istore(3).goto(inner_condition);
+    sums.bind(inner).iload(1).iload(3).iadd().istore(1).iinc(3, 1);
+    sums.bind(inner_condition).iload(3).bipush(100).if_icmplt(inner);
+    sums.iinc(2, 1);
+    sums.bind(outer_condition).iload(2).iload(0).if_icmplt(outer);
+    sums.iload(1).ireturn();
+    sums.finish().unwrap();
+    let mut id = class.method(static_flags, "id", "(I)I");
+    id.iload(0).ireturn();
+    id.finish().unwrap();
+    let class_file = rj::writer::write(&class.build()).unwrap();
+
+    let run = |jit: Option<JitThresholds>, limits: Limits| {
+        let mut vm = vm();
+        vm.define_class(rj::parser::parse_bytes(&class_file).unwrap()).unwrap();
+        vm.set_jit(jit);
+        vm.set_limits(limits);
+        let mut results = Vec::new();
+        for n in [1, 10, 1000].iter() {
+            let mut result = vm.invoke_static("Hot", "sums", "(I)I", &[Value::Int(*n)]);
+            let mut resumed = 0;
+            while let Err(VmError::LimitExceeded(Limit::Instructions)) = result {
+                resumed += 1;
+                result = vm.resume();
+            }
+            results.push((result.unwrap(), resumed));
+        }
+        results
+    };
+    let interpreted = run(None, Limits::default());
+    assert_eq!(interpreted[2].0, Some(Value::Int(4950 * 1000)));
+    let hot = JitThresholds { invocations: 2, backedges: 50, optimize: true };
+    assert_eq!(run(Some(hot), Limits::default()), interpreted);
+    // the native code stops at the same instruction limits, which the optimized code, running
+    // other instructions, doesn't
+    let limits = Limits { instructions: Some(777), ..Limits::default() };
+    assert_eq!(run(Some(JitThresholds { optimize: false, ..hot }), limits.clone()), run(None, limits));
+}
+
+#[test]
+fn optimized_methods_run_like_interpreted_ones() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Tiers");
+    class.field(static_flags, "total", "I");
+    // an array of 1 to n
+    let mut fill = class.method(static_flags, "fill", "(I)[I");
+    let (body, condition) = (fill.new_label(), fill.new_label());
+    fill.iload(0).newarray(10).astore(1).iconst_0().istore(2).goto(condition);
+    fill.bind(body).aload(1).iload(2).iload(2).iconst_1().iadd().iastore().iinc(2, 1);
+    fill.bind(condition).iload(2).aload(1).arraylength().if_icmplt(body);
+    fill.aload(1).areturn();
+    fill.finish().unwrap();
+    // whose bounds checks are eliminated
+    let mut sum = class.method(static_flags, "sum", "([I)I");
+    let (body, condition) = (sum.new_label(), sum.new_label());
+    sum.iconst_0().istore(1).iconst_0().istore(2).goto(condition);
+    sum.bind(body).iload(1).aload(0).iload(2).iaload().iadd().istore(1).iinc(2, 1);
+    sum.bind(condition).iload(2).aload(0).arraylength().if_icmplt(body);
+    sum.iload(1).ireturn();
+    sum.finish().unwrap();
+    // adds the sum of 1 to 10 to `total` n times, calling `sum` each time
+    let mut run = class.method(static_flags, "run", "(I)I");
+    let (body, condition) = (run.new_label(), run.new_label());
+    run.bipush(10).invokestatic("Tiers", "fill", "(I)[I").astore(1).iconst_0().istore(2).goto(condition);
+    run.bind(body).getstatic("Tiers", "total", "I").aload(1).invokestatic("Tiers", "sum", "([I)I").iadd()
+        .putstatic("Tiers", "total", "I").iinc(2, 1);
+    run.bind(condition).iload(2).iload(0).if_icmplt(body);
+    run.getstatic("Tiers", "total", "I").ireturn();
+    run.finish().unwrap();
+    let mut at = class.method(static_flags, "at", "([II)I");
+    at.aload(0).iload(1).iaload().ireturn();
+    at.finish().unwrap();
+    let mut get = class.method(static_flags, "get", "([II)I");
+    get.aload(0).iload(1).invokestatic("Tiers", "at", "([II)I").ireturn();
+    get.finish().unwrap();
+    let class_file = rj::writer::write(&class.build()).unwrap();
+
+    let run = |jit: Option<JitThresholds>, limits: Limits| {
+        let mut vm = vm();
+        vm.define_class(rj::parser::parse_bytes(&class_file).unwrap()).unwrap();
+        vm.set_jit(jit);
+        vm.set_limits(limits);
+        let mut results = Vec::new();
+        for n in [1, 20].iter() {
+            let mut result = vm.invoke_static("Tiers", "run", "(I)I", &[Value::Int(*n)]);
+            while let Err(VmError::LimitExceeded(Limit::Instructions)) = result {
+                result = vm.resume();
+            }
+            results.push(format!("{:?}", result.unwrap()));
+        }
+        // `at` is hot after its first call, so the later ones, and the exception, come from
+        // its optimized code
+        let array = vm.invoke_static("Tiers", "fill", "(I)[I", &[Value::Int(3)]).unwrap().unwrap();
+        for index in [2, 0, 3].iter() {
+            match vm.invoke_static("Tiers", "get", "([II)I", &[array, Value::Int(*index)]) {
+                Err(VmError::Exception(exception)) => results.push(exception.to_string()),
+                result => results.push(format!("{:?}", result.unwrap())),
+            }
+        }
+        results
+    };
+    let interpreted = run(None, Limits::default());
+    assert_eq!(interpreted, ["Some(Int(55))", "Some(Int(1155))", "Some(Int(3))", "Some(Int(1))",
+        "java.lang.ArrayIndexOutOfBoundsException: Index 3 out of bounds for length 3"]);
+    let hot = JitThresholds { invocations: 1, backedges: 1, optimize: true };
+    assert_eq!(run(Some(hot), Limits::default()), interpreted);
+    // calls suspended at a limit while running optimized code resume in it
+    assert_eq!(run(Some(hot), Limits { instructions: Some(97), ..Limits::default() }), interpreted);
+}
+
+#[test]
+fn optimized_methods_resolve_classes_of_unused_type_checks() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Check");
+    let mut check = class.method(static_flags, "check", "(Ljava/lang/Object;)I");
+    check.aload(0).instanceof("com/example/Missing").pop().iconst_1().ireturn();
+    check.finish().unwrap();
+    let mut run = class.method(static_flags, "run", "(Ljava/lang/Object;)I");
+    run.aload(0).invokestatic("Check", "check", "(Ljava/lang/Object;)I").ireturn();
+    run.finish().unwrap();
+    let class_file = rj::writer::write(&class.build()).unwrap();
+
+    let run = |jit: Option<JitThresholds>| {
+        let mut vm = vm();
+        vm.define_class(rj::parser::parse_bytes(&class_file).unwrap()).unwrap();
+        vm.set_jit(jit);
+        let object = vm.new_object("Counter", "()V", &[]).unwrap();
+        // null is never an instance, so only the later calls, which run the optimized code of
+        // `check`, resolve the class
+        [Value::Null, Value::Object(object), Value::Object(object)].iter()
+            .map(|it| match vm.invoke_static("Check", "run", "(Ljava/lang/Object;)I", &[*it]) {
+                Err(VmError::Exception(exception)) => exception.to_string(),
+                result => format!("{:?}", result.unwrap()),
+            })
+            .collect::<Vec<_>>()
+    };
+    let interpreted = run(None);
+    assert_eq!(interpreted, ["Some(Int(1))", "java.lang.NoClassDefFoundError: com/example/Missing",
+        "java.lang.NoClassDefFoundError: com/example/Missing"]);
+    assert_eq!(run(Some(JitThresholds { invocations: 1, backedges: 1, optimize: true })), interpreted);
+}
+
+#[test]
+fn restore_snapshot_of_loaded_classes_and_heap() {
+    let mut vm = vm();
+    let counter = vm.new_object("Counter", "()V", &[]).unwrap();
+    vm.new_object("Counter", "()V", &[]).unwrap();
+    vm.set_field(counter, "count", Value::Int(7)).unwrap();
+    let mut class = ClassBuilder::new("Seven");
+    let mut seven = class.method(AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16, "seven", "()I");
+    seven.bipush(7).ireturn();
+    seven.finish().unwrap();
+    vm.define_class(class.build()).unwrap();
+    let mut image = Vec::new();
+    vm.save_snapshot(&mut image).unwrap();
+
+    let mut restored = self::vm();
+    restored.restore_snapshot(image.as_slice()).unwrap();
+    // `<clinit>` doesn't run again, or it would be back to no instances
+    assert_eq!(restored.get_static("Counter", "instances").unwrap(), Value::Int(2));
+    assert_eq!(restored.invoke_virtual(counter, "get", "()I", &[]).unwrap(), Some(Value::Int(7)));
+    assert_eq!(restored.invoke_static("Seven", "seven", "()I", &[]).unwrap(), Some(Value::Int(7)));
+    assert!(matches!(restored.load_class_file(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Counter.class")), Err(VmError::LinkageError(_))));
+
+    let mut elsewhere = Vm::new();
+    assert!(matches!(elsewhere.restore_snapshot(image.as_slice()), Err(VmError::Snapshot(_))));
+    assert!(matches!(elsewhere.restore_snapshot(&b"not a snapshot"[..]), Err(VmError::Snapshot(_))));
+}
+
+#[test]
+fn reject_corrupted_snapshot() {
+    let static_flags = AccessFlags::PUBLIC as u16 | AccessFlags::STATIC as u16;
+    let mut class = ClassBuilder::new("Holder");
+    class.field(static_flags, "name", "Ljava/lang/String;").field(static_flags, "names", "[Ljava/lang/String;");
+    let mut seven = class.method(static_flags, "seven", "()I");
+    seven.bipush(7).ireturn();
+    seven.finish().unwrap();
+    let mut vm = vm();
+    vm.define_class(class.build()).unwrap();
+    let name = vm.new_string("name");
+    let names = vm.new_string_array(&["a", "b"]);
+    vm.set_static("Holder", "name", Value::Object(name)).unwrap();
+    vm.set_static("Holder", "names", Value::Object(names)).unwrap();
+    let mut image = Vec::new();
+    vm.save_snapshot(&mut image).unwrap();
+    self::vm().restore_snapshot(image.as_slice()).unwrap();
+
+    // the static fields of Holder are saved as its name, the number of slots and the slots
+    let statics = b"\x06\x00\x00\x00Holder\x02\x00\x00\x00";
+    let slots = image.windows(statics.len()).rposition(|it| it == statics).unwrap() + statics.len();
+    let restore = |patch: &dyn Fn(&mut Vec<u8>)| {
+        let mut corrupted = image.clone();
+        patch(&mut corrupted);
+        self::vm().restore_snapshot(corrupted.as_slice()).err().map(|it| it.to_string())
+    };
+    let invalid = Some("snapshot error: invalid reference 999 in Holder".to_string());
+    assert_eq!(restore(&|it| it[slots..slots + 4].copy_from_slice(&999i32.to_le_bytes())), invalid);
+    // references to objects of the wrong type
+    let swapped = restore(&|it| {
+        let (name, names) = (it[slots..slots + 4].to_vec(), it[slots + 4..slots + 8].to_vec());
+        it[slots..slots + 4].copy_from_slice(&names);
+        it[slots + 4..slots + 8].copy_from_slice(&name);
+    });
+    assert!(swapped.is_some_and(|it| it.starts_with("snapshot error: invalid reference")));
+    // a class that no longer verifies, returning the int as a reference
+    let code = image.windows(3).position(|it| it == [0x10, 0x07, 0xac]).unwrap();
+    let unverified = restore(&|it| it[code + 2] = 0xb0);
+    assert!(unverified.as_ref().is_some_and(|it| it.contains("Holder.seven:()I")), "{:?}", unverified);
+}
+
+#[test]
+fn reject_snapshot_once_a_class_changed() {
+    let classpath = std::env::temp_dir().join(format!("rj-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&classpath).unwrap();
+    let counter = classpath.join("Counter.class");
+    std::fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/Counter.class"), &counter).unwrap();
+    let mut vm = Vm::new();
+    vm.add_classpath(&classpath);
+    vm.get_static("Counter", "base").unwrap();
+    let mut image = Vec::new();
+    vm.save_snapshot(&mut image).unwrap();
+
+    let mut bytes = std::fs::read(&counter).unwrap();
+    bytes.push(0);
+    std::fs::write(&counter, bytes).unwrap();
+    let mut restored = Vm::new();
+    restored.add_classpath(&classpath);
+    let result = restored.restore_snapshot(image.as_slice());
+    std::fs::remove_dir_all(&classpath).unwrap();
+    match result {
+        Err(VmError::Snapshot(message)) => assert_eq!(message, "Counter changed since the snapshot was saved"),
+        other => panic!("expected a snapshot error, got {:?}", other.err()),
+    }
+}